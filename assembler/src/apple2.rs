@@ -0,0 +1,82 @@
+use crate::{Block, Error};
+use alloc::vec::Vec;
+use portal_solutions_mos6502_model::{address, Address};
+
+/// The Disk II boot ROM reads track 0 sector 0 here and jumps to the byte after.
+pub const BOOT_SECTOR_ADDRESS: Address = 0x0800;
+pub const BOOT_ENTRY_ADDRESS: Address = BOOT_SECTOR_ADDRESS + 1;
+pub const SECTOR_SIZE: usize = 256;
+
+/// Wraps an already assembled image in a DOS 3.3 "B" file header:
+/// the load address then the length, both little-endian. The image must
+/// end by $FFFF, and its length must fit in the header.
+pub fn binary_file_from_image(base: Address, image: &[u8]) -> Result<Vec<u8>, Error> {
+    let length = Address::try_from(image.len())
+        .ok()
+        .filter(|&length| length == 0 || base.checked_add(length - 1).is_some());
+    let Some(length) = length else {
+        // The first byte which doesn't fit.
+        let offset = (0x10000 - base as usize).min(Address::MAX as usize);
+        return Err(Error::OffsetOutOfBounds {
+            offset: offset as Address,
+            label: None,
+        });
+    };
+    let mut file = Vec::with_capacity(image.len() + 4);
+    file.push(address::lo(base));
+    file.push(address::hi(base));
+    file.push(address::lo(length));
+    file.push(address::hi(length));
+    file.extend_from_slice(image);
+    Ok(file)
+}
+
+/// Assembles `block` at `base` and produces a DOS 3.3 "B" file image.
+pub fn binary_file(block: &Block, base: Address, size: usize) -> Result<Vec<u8>, Error> {
+    let mut image = Vec::new();
+    block.assemble(base, size, &mut image)?;
+    binary_file_from_image(base, &image)
+}
+
+/// Builds a bootable track 0 sector 0 from `block`. The block is assembled
+/// to run from `BOOT_ENTRY_ADDRESS`, and the first byte of the sector tells
+/// the boot ROM to load only this sector.
+pub fn boot_sector(block: &Block) -> Result<[u8; SECTOR_SIZE], Error> {
+    let mut image = Vec::new();
+    block.assemble(BOOT_ENTRY_ADDRESS, SECTOR_SIZE - 1, &mut image)?;
+    let mut sector = [0; SECTOR_SIZE];
+    sector[0] = 1;
+    sector[1..].copy_from_slice(&image);
+    Ok(sector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_file_header() {
+        let file = binary_file_from_image(0x0803, &[0xEA, 0x60]).unwrap();
+        assert_eq!(file, [0x03, 0x08, 0x02, 0x00, 0xEA, 0x60]);
+    }
+
+    #[test]
+    fn binary_file_ending_at_end_of_address_space() {
+        let file = binary_file_from_image(0xFFFE, &[1, 2]).unwrap();
+        assert_eq!(file, [0xFE, 0xFF, 0x02, 0x00, 1, 2]);
+        assert!(binary_file_from_image(0xFFFF, &[]).is_ok());
+    }
+
+    #[test]
+    fn binary_file_past_end_of_address_space() {
+        match binary_file_from_image(0xFFFE, &[1, 2, 3]) {
+            Err(Error::OffsetOutOfBounds { offset, .. }) => assert_eq!(offset, 2),
+            other => panic!("expected OffsetOutOfBounds, got {:?}", other),
+        }
+        let image = alloc::vec![0; 0x10000];
+        match binary_file_from_image(0, &image) {
+            Err(Error::OffsetOutOfBounds { offset, .. }) => assert_eq!(offset, 0xFFFF),
+            other => panic!("expected OffsetOutOfBounds, got {:?}", other),
+        }
+    }
+}
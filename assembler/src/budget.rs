@@ -0,0 +1,85 @@
+use crate::{Block, Data};
+use alloc::{string::String, vec::Vec};
+use portal_solutions_mos6502_model::{debug::Instruction, Address};
+
+/// Size and straight-line timing of the code between one label and the
+/// next. Cycle counts are the sums over every instruction in the region
+/// run once, in order, so loops and skipped code aren't accounted for.
+#[derive(Debug, Clone)]
+pub struct RegionBudget {
+    /// Labels at the start of the region, empty for code before the first label.
+    pub labels: Vec<String>,
+    pub offset: Address,
+    pub bytes: usize,
+    pub min_cycles: usize,
+    pub max_cycles: usize,
+}
+
+impl RegionBudget {
+    pub fn fits_in(&self, cycles: usize) -> bool {
+        self.max_cycles <= cycles
+    }
+}
+
+impl Block {
+    /// Splits the program at every label and totals each region, with the
+    /// layout it would have if assembled at `base`.
+    pub fn region_budgets(&self, base: Address) -> Vec<RegionBudget> {
+        let layout = self.layout(base);
+        let mut starts = self
+            .labels
+            .iter()
+            .filter(|(_, label)| !label.absolute)
+            .map(|(name, label)| (label.index, name.clone()))
+            .collect::<Vec<_>>();
+        starts.sort();
+        let mut regions: Vec<RegionBudget> = Vec::new();
+        let mut starts = starts.into_iter().peekable();
+        for index in 0..=self.program.len() {
+            let mut labels = Vec::new();
+            while let Some((_, name)) = starts.next_if(|&(start, _)| start == index) {
+                labels.push(name);
+            }
+            if !labels.is_empty() || (index == 0 && !self.program.is_empty()) {
+                let offset = layout
+                    .offsets
+                    .get(index)
+                    .cloned()
+                    .unwrap_or(self.cursor_offset);
+                regions.push(RegionBudget {
+                    labels,
+                    offset,
+                    bytes: 0,
+                    min_cycles: 0,
+                    max_cycles: 0,
+                });
+            }
+            let (Some(item), Some(region)) = (self.program.get(index), regions.last_mut()) else {
+                continue;
+            };
+            let size = layout.sizes[index];
+            region.bytes += size as usize;
+            let (min, max) = match item.data {
+                Data::ZeroPageOrAbsolute {
+                    zero_page,
+                    absolute,
+                    ..
+                } => opcode_cycles(if size == 2 { zero_page } else { absolute }),
+                // The inverted branch is either taken, or falls through to the `JMP`.
+                Data::Branch { .. } if size == 5 => (3, 5),
+                Data::Branch { opcode, .. } => opcode_cycles(opcode),
+                Data::LiteralByte(opcode) if item.instruction => opcode_cycles(opcode),
+                _ => (0, 0),
+            };
+            region.min_cycles += min as usize;
+            region.max_cycles += max as usize;
+        }
+        regions
+    }
+}
+
+fn opcode_cycles(opcode: u8) -> (u8, u8) {
+    Instruction::from_opcode(opcode)
+        .map(|instruction| instruction.cycles())
+        .unwrap_or((0, 0))
+}
@@ -0,0 +1,96 @@
+use crate::{write_bytes, Block, Data, Error, Layout};
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
+use portal_solutions_mos6502_model::{address, Address};
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy)]
+pub enum Checksum {
+    /// Wrapping sum of the bytes, as one byte.
+    Sum8,
+    /// Wrapping sum of the bytes, as a little-endian word.
+    Sum16,
+    /// Exclusive or of the bytes, as one byte.
+    Xor8,
+    /// CRC-16/CCITT-FALSE (polynomial $1021, initial value $FFFF), as a
+    /// little-endian word.
+    Crc16,
+}
+
+impl Checksum {
+    pub fn num_bytes(self) -> Address {
+        match self {
+            Checksum::Sum8 | Checksum::Xor8 => 1,
+            Checksum::Sum16 | Checksum::Crc16 => 2,
+        }
+    }
+    pub fn compute(self, bytes: &[u8]) -> u16 {
+        match self {
+            Checksum::Sum8 => bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) as u16,
+            Checksum::Sum16 => bytes
+                .iter()
+                .fold(0u16, |sum, &b| sum.wrapping_add(b as u16)),
+            Checksum::Xor8 => bytes.iter().fold(0u8, |sum, &b| sum ^ b) as u16,
+            Checksum::Crc16 => bytes.iter().fold(0xFFFF, |mut crc: u16, &b| {
+                crc ^= (b as u16) << 8;
+                for _ in 0..8 {
+                    crc = if crc & 0x8000 != 0 {
+                        (crc << 1) ^ 0x1021
+                    } else {
+                        crc << 1
+                    };
+                }
+                crc
+            }),
+        }
+    }
+}
+
+impl Block {
+    /// Reserves space for a checksum of the assembled bytes at the
+    /// addresses `over`, which is filled in by `assemble`. The range is
+    /// inclusive so that it can end at $FFFF, and is empty if it starts
+    /// after it ends. Checksums are computed in program order after
+    /// everything else is written, so a checksum can cover earlier ones,
+    /// and covers any later ones (including itself) as zeroes.
+    pub fn checksum_here(&mut self, over: RangeInclusive<Address>, kind: Checksum) {
+        self.emit(Data::Checksum { over, kind });
+    }
+    // Writes every checksum into `buffer`, which holds the rest of the
+    // assembled image.
+    pub(crate) fn fill_checksums(
+        &self,
+        layout: &Layout,
+        base: Address,
+        buffer: &mut [u8],
+        errors: &mut Vec<Error>,
+    ) {
+        for (index, item) in self.program.iter().enumerate() {
+            let Data::Checksum { ref over, kind } = item.data else {
+                continue;
+            };
+            let start = over.start().wrapping_sub(base) as usize;
+            let len = if over.is_empty() {
+                0
+            } else {
+                (over.end() - over.start()) as usize + 1
+            };
+            let Some(bytes) = buffer.get(start..start + len) else {
+                let error = Error::OffsetOutOfBounds {
+                    offset: over.start().wrapping_sub(base),
+                    label: None,
+                };
+                errors.push(self.locate(layout, index, error));
+                continue;
+            };
+            let value = kind.compute(bytes);
+            let bytes = [address::lo(value), address::hi(value)];
+            let bytes = &bytes[..kind.num_bytes() as usize];
+            if let Err(error) = write_bytes(buffer, layout.offsets[index], bytes, None) {
+                errors.push(self.locate(layout, index, error));
+            }
+        }
+    }
+}
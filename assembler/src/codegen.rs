@@ -0,0 +1,68 @@
+use crate::{AssembledBlock, Error};
+use alloc::{collections::BTreeSet, string::String};
+use core::fmt::Write;
+
+/// Labels starting with `_` or `.` are local to the program and aren't
+/// exported.
+pub fn is_public_label(label: &str) -> bool {
+    !label.starts_with('_') && !label.starts_with('.')
+}
+
+/// `mainLoop` and `main-loop` both become `MAIN_LOOP`.
+pub fn constant_name(label: &str) -> String {
+    let mut name = String::new();
+    let mut previous_lower = false;
+    for c in label.chars() {
+        if c.is_ascii_alphanumeric() {
+            if c.is_ascii_uppercase() && previous_lower {
+                name.push('_');
+            }
+            name.push(c.to_ascii_uppercase());
+            previous_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        } else {
+            name.push('_');
+            previous_lower = false;
+        }
+    }
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+/// Rust source with a `pub const` address for every public label, for
+/// inclusion into the host program with `include!`. Two labels which map
+/// to the same constant name are reported as a `DuplicateLabel`.
+pub fn rust_constants(assembled: &AssembledBlock) -> Result<String, Error> {
+    let mut source = String::from("// Generated from assembled labels. Do not edit.\n");
+    let mut names = BTreeSet::new();
+    for (label, address) in assembled.labels.iter() {
+        if !is_public_label(label) {
+            continue;
+        }
+        let name = constant_name(label);
+        if !names.insert(name.clone()) {
+            return Err(Error::DuplicateLabel(name));
+        }
+        let _ = writeln!(source, "pub const {}: u16 = 0x{:04X};", name, address);
+    }
+    Ok(source)
+}
+
+/// Writes `rust_constants` to `file_name` in `OUT_DIR`, for use from a
+/// build script:
+/// `include!(concat!(env!("OUT_DIR"), "/labels.rs"));`
+#[cfg(feature = "std")]
+pub fn write_rust_constants_to_out_dir(
+    assembled: &AssembledBlock,
+    file_name: &str,
+) -> std::io::Result<std::path::PathBuf> {
+    use std::io::{Error as IoError, ErrorKind};
+    let source = rust_constants(assembled)
+        .map_err(|error| IoError::new(ErrorKind::InvalidData, std::format!("{:?}", error)))?;
+    let out_dir =
+        std::env::var_os("OUT_DIR").ok_or_else(|| IoError::new(ErrorKind::NotFound, "OUT_DIR"))?;
+    let path = std::path::Path::new(&out_dir).join(file_name);
+    std::fs::write(&path, source)?;
+    Ok(path)
+}
@@ -0,0 +1,279 @@
+use crate::AssembledBlock;
+use alloc::{string::String, vec::Vec};
+use core::{fmt, ops::Range};
+use portal_solutions_mos6502_model::{
+    debug::InstructionWithOperand, machine::MemoryReadOnly, Address,
+};
+
+// Number of bytes either side of a mismatch that are included in its disassembly.
+const CONTEXT_BYTES: usize = 4;
+// Mismatches closer together than this are reported as a single range.
+const MERGE_DISTANCE: usize = 8;
+const COLUMN_WIDTH: usize = 40;
+
+struct Image<'a> {
+    base: Address,
+    bytes: &'a [u8],
+}
+
+impl MemoryReadOnly for Image<'_> {
+    fn read_u8_read_only(&self, address: Address) -> u8 {
+        let offset = address.wrapping_sub(self.base) as usize;
+        self.bytes.get(offset).cloned().unwrap_or(0)
+    }
+}
+
+impl Image<'_> {
+    // Linear sweep from the start of the image, treating undecodable bytes
+    // as single byte data. Returns the offset and length of each item.
+    fn items(&self) -> Vec<Range<usize>> {
+        let mut items = Vec::new();
+        let mut offset = 0;
+        while offset < self.bytes.len() {
+            let address = self.base.wrapping_add(offset as Address);
+            let size = InstructionWithOperand::decode(address, self)
+                .map(|instruction| instruction.instruction().size())
+                .unwrap_or(1);
+            let end = (offset + size).min(self.bytes.len());
+            items.push(offset..end);
+            offset = end;
+        }
+        items
+    }
+    fn line(&self, item: &Range<usize>) -> String {
+        use fmt::Write;
+        let address = self.base.wrapping_add(item.start as Address);
+        let mut line = String::new();
+        match InstructionWithOperand::decode(address, self) {
+            Ok(instruction) if instruction.instruction().size() == item.len() => {
+                let _ = write!(line, "{}", instruction);
+            }
+            _ => {
+                let _ = write!(line, "{:04X}  .byte", address);
+                for &byte in &self.bytes[item.clone()] {
+                    let _ = write!(line, " {:02X}", byte);
+                }
+            }
+        }
+        line
+    }
+    fn lines_overlapping(&self, window: &Range<usize>) -> Vec<String> {
+        self.items()
+            .iter()
+            .filter(|item| item.start < window.end && window.start < item.end)
+            .map(|item| self.line(item))
+            .collect()
+    }
+}
+
+/// A failed comparison between an assembled image and a reference image.
+/// Its `Display` output lists each differing range with both sides
+/// disassembled next to each other.
+pub struct ImageDiff<'a> {
+    actual: Image<'a>,
+    expected: Image<'a>,
+    symbols: Option<&'a AssembledBlock>,
+    ranges: Vec<Range<usize>>,
+}
+
+impl ImageDiff<'_> {
+    /// Offset ranges (relative to the base address) which differ.
+    pub fn ranges(&self) -> &[Range<usize>] {
+        &self.ranges
+    }
+    fn symbol_context(&self, address: Address) -> Option<(&str, Address)> {
+        self.symbols?
+            .labels
+            .iter()
+            .filter(|&(_, &label_address)| label_address <= address)
+            .max_by_key(|&(_, &label_address)| label_address)
+            .map(|(label, &label_address)| (label.as_str(), address - label_address))
+    }
+}
+
+impl fmt::Display for ImageDiff<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.actual.bytes.len() != self.expected.bytes.len() {
+            writeln!(
+                f,
+                "image is {} bytes but reference is {} bytes",
+                self.actual.bytes.len(),
+                self.expected.bytes.len()
+            )?;
+        }
+        for range in self.ranges.iter() {
+            let address = self.actual.base.wrapping_add(range.start as Address);
+            write!(f, "mismatch at {:04X}", address)?;
+            if let Some((label, delta)) = self.symbol_context(address) {
+                write!(f, " ({}+{})", label, delta)?;
+            }
+            writeln!(f, ", {} bytes", range.len())?;
+            let window = range.start.saturating_sub(CONTEXT_BYTES)..range.end + CONTEXT_BYTES;
+            let actual = self.actual.lines_overlapping(&window);
+            let expected = self.expected.lines_overlapping(&window);
+            writeln!(f, "  {:<width$} | expected", "actual", width = COLUMN_WIDTH)?;
+            for i in 0..actual.len().max(expected.len()) {
+                writeln!(
+                    f,
+                    "  {:<width$} | {}",
+                    actual.get(i).map(String::as_str).unwrap_or(""),
+                    expected.get(i).map(String::as_str).unwrap_or(""),
+                    width = COLUMN_WIDTH
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ImageDiff<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Compares `actual` against `expected` byte for byte, both loaded at
+/// `base`. Returns `None` if they are identical.
+pub fn compare_images<'a>(
+    base: Address,
+    actual: &'a [u8],
+    expected: &'a [u8],
+    symbols: Option<&'a AssembledBlock>,
+) -> Option<ImageDiff<'a>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for offset in 0..actual.len().max(expected.len()) {
+        if actual.get(offset) == expected.get(offset) {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(range) if offset - range.end < MERGE_DISTANCE => range.end = offset + 1,
+            _ => ranges.push(offset..offset + 1),
+        }
+    }
+    if ranges.is_empty() {
+        None
+    } else {
+        Some(ImageDiff {
+            actual: Image {
+                base,
+                bytes: actual,
+            },
+            expected: Image {
+                base,
+                bytes: expected,
+            },
+            symbols,
+            ranges,
+        })
+    }
+}
+
+/// Panics with a disassembled diff if two images differ.
+/// `assert_image_eq!(base, actual, expected)` or
+/// `assert_image_eq!(base, actual, expected, &assembled_block)`.
+#[macro_export]
+macro_rules! assert_image_eq {
+    ($base:expr, $actual:expr, $expected:expr) => {
+        if let Some(diff) = $crate::compare::compare_images($base, &$actual, &$expected, None) {
+            panic!("assembled image differs from reference\n{}", diff);
+        }
+    };
+    ($base:expr, $actual:expr, $expected:expr, $symbols:expr) => {
+        if let Some(diff) =
+            $crate::compare::compare_images($base, &$actual, &$expected, Some($symbols))
+        {
+            panic!("assembled image differs from reference\n{}", diff);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Addr, Block};
+    use alloc::format;
+    use portal_solutions_mos6502_model::{addressing_mode::*, assembler_instruction::*};
+
+    // LDA #1; STA $0300; RTS at $0200, with its labels.
+    fn program() -> (Vec<u8>, AssembledBlock) {
+        let mut block = Block::new();
+        block.label("start");
+        block.inst(Lda(Immediate), 1);
+        block.label("store");
+        block.inst(Sta(Absolute), Addr(0x0300));
+        block.inst(Rts, ());
+        let mut image = Vec::new();
+        let assembled = block.assemble(0x0200, 6, &mut image).unwrap();
+        (image, assembled)
+    }
+
+    // The diff's lines with trailing spaces trimmed.
+    fn lines(diff: &ImageDiff) -> Vec<String> {
+        format!("{}", diff)
+            .lines()
+            .map(|line| line.trim_end().into())
+            .collect()
+    }
+
+    #[test]
+    fn equal_images() {
+        let (image, assembled) = program();
+        assert!(compare_images(0x0200, &image, &image, Some(&assembled)).is_none());
+        assert_image_eq!(0x0200, image, image.clone());
+    }
+
+    #[test]
+    #[should_panic(expected = "mismatch at 0203")]
+    fn assert_image_eq_panics_on_a_difference() {
+        let (image, _) = program();
+        let mut expected = image.clone();
+        expected[3] = 0x04;
+        assert_image_eq!(0x0200, image, expected);
+    }
+
+    #[test]
+    fn single_byte_difference() {
+        let (image, _) = program();
+        let mut expected = image.clone();
+        expected[3] = 0x04;
+        let diff = compare_images(0x0200, &image, &expected, None).unwrap();
+        assert_eq!(diff.ranges(), &[Range { start: 3, end: 4 }]);
+    }
+
+    #[test]
+    fn nearby_differences_are_merged() {
+        let actual = [0; 32];
+        let mut expected = [0; 32];
+        expected[2] = 1;
+        expected[6] = 1;
+        expected[20] = 1;
+        let diff = compare_images(0, &actual, &expected, None).unwrap();
+        assert_eq!(diff.ranges(), &[2..7, 20..21]);
+    }
+
+    #[test]
+    fn differing_lengths() {
+        let (image, _) = program();
+        let diff = compare_images(0x0200, &image, &image[..5], None).unwrap();
+        assert_eq!(diff.ranges(), &[Range { start: 5, end: 6 }]);
+        assert_eq!(lines(&diff)[0], "image is 6 bytes but reference is 5 bytes");
+    }
+
+    #[test]
+    fn disassembled_diff() {
+        let (image, assembled) = program();
+        let mut expected = image.clone();
+        expected[3] = 0x04;
+        let diff = compare_images(0x0200, &image, &expected, Some(&assembled)).unwrap();
+        assert_eq!(
+            lines(&diff),
+            [
+                "mismatch at 0203 (store+1), 1 bytes",
+                "  actual                                   | expected",
+                "  0200  Lda(Immediate) 01                  | 0200  Lda(Immediate) 01",
+                "  0202  Sta(Absolute) 0300                 | 0202  Sta(Absolute) 0304",
+                "  0205  Rts(Implied)                       | 0205  Rts(Implied)",
+            ]
+        );
+    }
+}
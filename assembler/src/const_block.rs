@@ -0,0 +1,246 @@
+//! A small assembler that runs in `const` contexts, for baking boot stubs
+//! and vector tables into `static` byte arrays. It has none of `Block`'s
+//! layout features: the image is a fixed-size array of `N` bytes starting
+//! at `base`, instructions are emitted as raw opcodes (see
+//! `portal_solutions_mos6502_model::opcode`), and at most `L` labels and `L`
+//! label references are supported. Errors are reported by panicking, which
+//! in a `const` or `static` initializer fails the build.
+//!
+//! ```ignore
+//! use portal_solutions_mos6502_model::opcode::*;
+//! static BOOT: [u8; 8] = ConstBlock::<8>::new(0xFF00, 0xFF)
+//!     .label("reset")
+//!     .op_u8(ldx::IMMEDIATE, 0xFF)
+//!     .op(txs::IMPLIED)
+//!     .op_label(jmp::ABSOLUTE, "reset")
+//!     .finish();
+//! ```
+use portal_solutions_mos6502_model::Address;
+
+#[derive(Clone, Copy)]
+enum Ref {
+    Word,
+    Lo,
+    Hi,
+    Relative,
+}
+
+#[derive(Clone, Copy)]
+struct Fixup {
+    kind: Ref,
+    label: &'static str,
+    at: usize,
+}
+
+pub struct ConstBlock<const N: usize, const L: usize = 8> {
+    base: Address,
+    bytes: [u8; N],
+    cursor: usize,
+    labels: [(&'static str, usize); L],
+    num_labels: usize,
+    fixups: [Fixup; L],
+    num_fixups: usize,
+}
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+impl<const N: usize, const L: usize> ConstBlock<N, L> {
+    /// An image of `N` bytes at `base`, with every byte that isn't written
+    /// set to `fill`.
+    pub const fn new(base: Address, fill: u8) -> Self {
+        Self {
+            base,
+            bytes: [fill; N],
+            cursor: 0,
+            labels: [("", 0); L],
+            num_labels: 0,
+            fixups: [Fixup {
+                kind: Ref::Word,
+                label: "",
+                at: 0,
+            }; L],
+            num_fixups: 0,
+        }
+    }
+    /// Moves the cursor to `address`, which must be inside the image.
+    pub const fn org(mut self, address: Address) -> Self {
+        if address < self.base || (address - self.base) as usize > N {
+            panic!("org address outside the image");
+        }
+        self.cursor = (address - self.base) as usize;
+        self
+    }
+    /// Address the next byte will be written at.
+    pub const fn here(&self) -> Address {
+        self.base.wrapping_add(self.cursor as Address)
+    }
+    pub const fn byte(mut self, byte: u8) -> Self {
+        if self.cursor >= N {
+            panic!("program does not fit in the image");
+        }
+        self.bytes[self.cursor] = byte;
+        self.cursor += 1;
+        self
+    }
+    pub const fn bytes(mut self, bytes: &[u8]) -> Self {
+        let mut i = 0;
+        while i < bytes.len() {
+            self = self.byte(bytes[i]);
+            i += 1;
+        }
+        self
+    }
+    pub const fn word(self, word: Address) -> Self {
+        self.byte(word as u8).byte((word >> 8) as u8)
+    }
+    pub const fn label(mut self, name: &'static str) -> Self {
+        let mut i = 0;
+        while i < self.num_labels {
+            if str_eq(self.labels[i].0, name) {
+                panic!("label defined more than once");
+            }
+            i += 1;
+        }
+        if self.num_labels >= L {
+            panic!("too many labels");
+        }
+        self.labels[self.num_labels] = (name, self.cursor);
+        self.num_labels += 1;
+        self
+    }
+    const fn reference(mut self, kind: Ref, label: &'static str, size: usize) -> Self {
+        if self.num_fixups >= L {
+            panic!("too many label references");
+        }
+        self.fixups[self.num_fixups] = Fixup {
+            kind,
+            label,
+            at: self.cursor,
+        };
+        self.num_fixups += 1;
+        let mut i = 0;
+        while i < size {
+            self = self.byte(0);
+            i += 1;
+        }
+        self
+    }
+    /// The address of `label`, little-endian.
+    pub const fn word_label(self, label: &'static str) -> Self {
+        self.reference(Ref::Word, label, 2)
+    }
+    pub const fn lo_label(self, label: &'static str) -> Self {
+        self.reference(Ref::Lo, label, 1)
+    }
+    pub const fn hi_label(self, label: &'static str) -> Self {
+        self.reference(Ref::Hi, label, 1)
+    }
+    /// An implied or accumulator mode instruction.
+    pub const fn op(self, opcode: u8) -> Self {
+        self.byte(opcode)
+    }
+    /// An instruction with a one byte operand.
+    pub const fn op_u8(self, opcode: u8, operand: u8) -> Self {
+        self.byte(opcode).byte(operand)
+    }
+    /// An instruction with a two byte operand.
+    pub const fn op_u16(self, opcode: u8, operand: Address) -> Self {
+        self.byte(opcode).word(operand)
+    }
+    /// An instruction whose two byte operand is the address of `label`.
+    pub const fn op_label(self, opcode: u8, label: &'static str) -> Self {
+        self.byte(opcode).word_label(label)
+    }
+    /// A branch to `label`, which must be within range.
+    pub const fn branch(self, opcode: u8, label: &'static str) -> Self {
+        self.byte(opcode).reference(Ref::Relative, label, 1)
+    }
+    const fn resolve(&self, label: &str) -> usize {
+        let mut i = 0;
+        while i < self.num_labels {
+            if str_eq(self.labels[i].0, label) {
+                return self.labels[i].1;
+            }
+            i += 1;
+        }
+        panic!("undefined label")
+    }
+    /// Resolves label references and returns the image.
+    pub const fn finish(mut self) -> [u8; N] {
+        let mut i = 0;
+        while i < self.num_fixups {
+            let Fixup { kind, label, at } = self.fixups[i];
+            let target = self.resolve(label);
+            let address = self.base.wrapping_add(target as Address);
+            match kind {
+                Ref::Word => {
+                    self.bytes[at] = address as u8;
+                    self.bytes[at + 1] = (address >> 8) as u8;
+                }
+                Ref::Lo => self.bytes[at] = address as u8,
+                Ref::Hi => self.bytes[at] = (address >> 8) as u8,
+                Ref::Relative => {
+                    let delta = target as isize - (at as isize + 1);
+                    if delta < i8::MIN as isize || delta > i8::MAX as isize {
+                        panic!("branch target out of range");
+                    }
+                    self.bytes[at] = delta as i8 as u8;
+                }
+            }
+            i += 1;
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use portal_solutions_mos6502_model::opcode::*;
+
+    static BOOT: [u8; 12] = ConstBlock::<12>::new(0xFF00, 0xEA)
+        .label("reset")
+        .op_u8(ldx::IMMEDIATE, 0xFF)
+        .op(txs::IMPLIED)
+        .branch(bne::RELATIVE, "done")
+        .op_label(jmp::ABSOLUTE, "reset")
+        .label("done")
+        .op_label(jmp::ABSOLUTE, "done")
+        .finish();
+
+    #[test]
+    fn static_with_forward_branch() {
+        assert_eq!(
+            BOOT,
+            [
+                0xA2, 0xFF, // LDX #$FF
+                0x9A, // TXS
+                0xD0, 0x03, // BNE done
+                0x4C, 0x00, 0xFF, // JMP reset
+                0x4C, 0x08, 0xFF, // done: JMP done
+                0xEA, // unwritten
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "undefined label")]
+    fn undefined_label() {
+        ConstBlock::<3>::new(0, 0)
+            .op_label(jmp::ABSOLUTE, "nowhere")
+            .finish();
+    }
+}
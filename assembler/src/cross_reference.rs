@@ -0,0 +1,58 @@
+use crate::{Block, Location};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use portal_solutions_mos6502_model::Address;
+
+#[derive(Debug, Clone, Default)]
+pub struct LabelReferences {
+    /// Offset of the definition, or `None` if the label is referenced but
+    /// never defined.
+    pub definition: Option<Address>,
+    pub references: Vec<Location>,
+}
+
+/// Where every label is defined and used, in label name order. Offsets are
+/// as emitted, before any zero-page selection or branch relaxation.
+#[derive(Debug, Clone, Default)]
+pub struct CrossReference {
+    pub labels: BTreeMap<String, LabelReferences>,
+}
+
+impl CrossReference {
+    /// Labels that are defined but never referenced.
+    pub fn unreferenced(&self) -> impl Iterator<Item = &str> {
+        self.labels
+            .iter()
+            .filter(|(_, refs)| refs.definition.is_some() && refs.references.is_empty())
+            .map(|(label, _)| label.as_str())
+    }
+    /// Labels that are referenced but never defined.
+    pub fn undefined(&self) -> impl Iterator<Item = &str> {
+        self.labels
+            .iter()
+            .filter(|(_, refs)| refs.definition.is_none())
+            .map(|(label, _)| label.as_str())
+    }
+}
+
+impl Block {
+    pub fn cross_reference(&self) -> CrossReference {
+        let mut labels = BTreeMap::<String, LabelReferences>::new();
+        for (name, label) in self.labels.iter() {
+            labels.entry(name.clone()).or_default().definition = Some(label.offset);
+        }
+        for (index, item) in self.program.iter().enumerate() {
+            if let Some(label) = item.data.label() {
+                labels
+                    .entry(self.symbols.resolve(label).into())
+                    .or_default()
+                    .references
+                    .push(Location {
+                        index,
+                        offset: item.offset,
+                        source_hint: item.source_hint.map(|hint| self.source_hints[hint].clone()),
+                    });
+            }
+        }
+        CrossReference { labels }
+    }
+}
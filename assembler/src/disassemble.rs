@@ -0,0 +1,157 @@
+//! Turning an existing binary back into a `Block`, to patch or instrument
+//! it with the rest of the assembler. The binary is disassembled either in
+//! one sweep from the start, or from entry points with
+//! `control_flow::Analysis` so that data isn't mistaken for code. Every
+//! branch, `JMP` or `JSR` whose target is the start of a line gets a label,
+//! so code can be added between lines and those references still reach
+//! their targets. Everything else, data included, is reproduced byte for
+//! byte.
+//!
+//! ```ignore
+//! let disassembly = Disassembly::new(0xC000, &rom);
+//! let mut block = Block::new();
+//! disassembly.emit(&mut block, ..0xC123);
+//! block.inst(Jsr(Absolute), "trace");
+//! disassembly.emit(&mut block, 0xC123..);
+//! ```
+use crate::Block;
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+use core::ops::RangeBounds;
+use portal_solutions_mos6502_model::{
+    control_flow::Analysis,
+    debug::{decode_all_at, AddressingMode, Disassembled, InstructionType, InstructionWithOperand},
+    Address,
+};
+
+/// The name given to the label at `address`, as `L_C012`.
+pub fn label_name(address: Address) -> String {
+    format!("L_{:04X}", address)
+}
+
+// Where a branch, `JMP` or `JSR` goes.
+fn target(instruction: &InstructionWithOperand) -> Option<Address> {
+    let operand = instruction.operand();
+    match instruction.instruction().addressing_mode() {
+        AddressingMode::Relative => Some(
+            instruction
+                .address()
+                .wrapping_add(2)
+                .wrapping_add(operand[0] as i8 as Address),
+        ),
+        AddressingMode::Absolute => match instruction.instruction().instruction_type() {
+            InstructionType::Jmp | InstructionType::Jsr => instruction.operand_u16_le(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Disassembly {
+    base: Address,
+    lines: Vec<Disassembled>,
+    labels: BTreeMap<Address, String>,
+}
+
+impl Disassembly {
+    /// Disassembles `bytes` loaded at `base`, up to $FFFF.
+    pub fn new(base: Address, bytes: &[u8]) -> Self {
+        Self::from_lines(base, decode_all_at(base, bytes), &[])
+    }
+    /// Disassembles only the code reachable from `entry_points`, which are
+    /// labelled too, and keeps everything else as bytes.
+    pub fn with_entry_points(base: Address, bytes: &[u8], entry_points: &[Address]) -> Self {
+        let bytes = &bytes[..bytes.len().min(0x10000 - base as usize)];
+        let analysis = Analysis::new(base, bytes, entry_points);
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let address = base + offset as Address;
+            let line = match analysis.instruction(address) {
+                Some(instruction) if offset + instruction.instruction().size() <= bytes.len() => {
+                    Disassembled::Instruction(instruction.clone())
+                }
+                _ => Disassembled::Byte {
+                    address,
+                    value: bytes[offset],
+                },
+            };
+            offset += line.size();
+            lines.push(line);
+        }
+        Self::from_lines(base, lines, entry_points)
+    }
+    fn from_lines(base: Address, lines: Vec<Disassembled>, entry_points: &[Address]) -> Self {
+        let starts = lines.iter().map(Disassembled::address).collect::<Vec<_>>();
+        let labels = lines
+            .iter()
+            .filter_map(|line| match line {
+                Disassembled::Instruction(instruction) => target(instruction),
+                Disassembled::Byte { .. } => None,
+            })
+            .chain(entry_points.iter().copied())
+            .filter(|address| starts.binary_search(address).is_ok())
+            .map(|address| (address, label_name(address)))
+            .collect();
+        Self {
+            base,
+            lines,
+            labels,
+        }
+    }
+    pub fn base(&self) -> Address {
+        self.base
+    }
+    pub fn lines(&self) -> &[Disassembled] {
+        &self.lines
+    }
+    /// Every generated label, in address order.
+    pub fn labels(&self) -> impl Iterator<Item = (Address, &str)> {
+        self.labels
+            .iter()
+            .map(|(&address, label)| (address, label.as_str()))
+    }
+    pub fn label_at(&self, address: Address) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+    /// Appends the lines starting in `range` to `block`, each preceded by
+    /// its label if it has one. Instructions go through the same path as
+    /// `Block::inst`, so with `auto_zero_page` enabled absolute operands
+    /// below $0100 shrink, and with `branch_relaxation` enabled branches to
+    /// labels are lengthened as needed.
+    pub fn emit<R: RangeBounds<Address>>(&self, block: &mut Block, range: R) {
+        for line in self.lines.iter() {
+            if !range.contains(&line.address()) {
+                continue;
+            }
+            if let Some(label) = self.label_at(line.address()) {
+                block.label(label);
+            }
+            let instruction = match line {
+                Disassembled::Instruction(instruction) => instruction,
+                Disassembled::Byte { value, .. } => {
+                    block.literal_byte(*value);
+                    continue;
+                }
+            };
+            let label = target(instruction).and_then(|target| self.label_at(target));
+            let relative = instruction.instruction().addressing_mode() == AddressingMode::Relative;
+            block.inst_from_opcode(instruction.opcode(), |block| {
+                match (label, instruction.operand()) {
+                    (Some(label), _) if relative => block.label_relative_offset(label),
+                    (Some(label), _) => block.label_offset_le(label),
+                    (None, &[byte]) => block.literal_byte(byte),
+                    (None, &[lo, hi]) => block.literal_address_le(u16::from_le_bytes([lo, hi])),
+                    _ => (),
+                }
+            });
+        }
+    }
+    /// A block holding the whole binary, which reassembles at the base
+    /// address to the same bytes.
+    pub fn to_block(&self) -> Block {
+        let mut block = Block::new();
+        self.emit(&mut block, ..);
+        block
+    }
+}
@@ -0,0 +1,235 @@
+//! A driver for fuzzing `Block`: a sequence of operations is read from
+//! raw bytes, applied to a block, and the block assembled every way it can
+//! be. Whatever the bytes, this must only ever return errors, never panic.
+//!
+//! ```ignore
+//! fuzz_target!(|data: &[u8]| {
+//!     let _ = fuzz::drive(data);
+//! });
+//! ```
+use crate::checksum::Checksum;
+use crate::jump_table::Dispatch;
+use crate::vectors::Vectors;
+use crate::{Addr, Block, Error, LabelRef};
+use alloc::vec::Vec;
+use core::ops::{Range, RangeInclusive};
+use portal_solutions_mos6502_model::generate::Source;
+use portal_solutions_mos6502_model::{addressing_mode::*, assembler_instruction::*, Address};
+
+// A few names, so that operations often refer to the same labels. Some
+// are local, and some clash with the labels the block defines itself.
+const NAMES: &[&str] = &["a", "b", "c", ".a", ".b", "_relocate_pc", "__reloc0"];
+
+// How deeply `Repeat` and `Include` nest, and how many operations each
+// holds, so that a short input can't build an enormous block.
+const MAX_DEPTH: usize = 3;
+const MAX_NESTED: u8 = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Reference {
+    Le(i16),
+    Lo(i16),
+    Hi(i16),
+    Relative,
+    Symbol,
+    Reloc,
+}
+
+#[derive(Debug, Clone)]
+pub enum Op {
+    LiteralByte(u8),
+    LiteralOffset(Address),
+    LiteralAddress(Address),
+    Label(&'static str),
+    Constant(&'static str, Address),
+    Reference(&'static str, Reference),
+    /// `LDA` absolute to a label, which may shrink to zero page.
+    LoadAbsolute(&'static str),
+    /// `LDA` absolute with a literal address.
+    LoadLiteral(Address),
+    /// `BNE` to a label, which may be lengthened.
+    Branch(&'static str),
+    SetOffset(Address),
+    AutoZeroPage(bool),
+    BranchRelaxation(bool),
+    InfiniteLoop,
+    Checksum(RangeInclusive<Address>, Checksum),
+    ZeroPagePool(Range<Address>),
+    ZeroPageVariable(&'static str, Address),
+    SourceHint(u8),
+    ClearSourceHint,
+    GetPc(&'static str, Address, u8),
+    RelocationStub(Address, u8),
+    RelocationTable,
+    JumpTable(&'static str, Vec<&'static str>, Dispatch),
+    Vectors(Address),
+    Repeat(usize, Vec<Op>),
+    Append(Vec<Op>),
+    Include(Address, bool, Vec<Op>),
+}
+
+impl Op {
+    /// Reads operations until `source` runs out.
+    pub fn list(source: &mut Source) -> Vec<Op> {
+        Self::list_at(source, 0)
+    }
+    fn list_at(source: &mut Source, depth: usize) -> Vec<Op> {
+        let mut ops = Vec::new();
+        while !source.is_empty() {
+            ops.push(Self::read(source, depth));
+        }
+        ops
+    }
+    fn nested(source: &mut Source, depth: usize) -> Vec<Op> {
+        let len = source.u8() % MAX_NESTED;
+        (0..len).map(|_| Self::read(source, depth + 1)).collect()
+    }
+    fn read(source: &mut Source, depth: usize) -> Op {
+        let kind = source.u8() % if depth < MAX_DEPTH { 26 } else { 23 };
+        match kind {
+            0 => Op::LiteralByte(source.u8()),
+            1 => Op::LiteralOffset(source.u16()),
+            2 => Op::LiteralAddress(source.u16()),
+            3 => Op::Label(source.choose(NAMES)),
+            4 => Op::Constant(source.choose(NAMES), source.u16()),
+            5 => {
+                let name = source.choose(NAMES);
+                let reference = match source.u8() % 6 {
+                    0 => Reference::Le(source.u16() as i16),
+                    1 => Reference::Lo(source.u16() as i16),
+                    2 => Reference::Hi(source.u16() as i16),
+                    3 => Reference::Relative,
+                    4 => Reference::Symbol,
+                    _ => Reference::Reloc,
+                };
+                Op::Reference(name, reference)
+            }
+            6 => Op::LoadAbsolute(source.choose(NAMES)),
+            7 => Op::LoadLiteral(source.u16()),
+            8 => Op::Branch(source.choose(NAMES)),
+            9 => Op::SetOffset(source.u16()),
+            10 => Op::AutoZeroPage(source.bool()),
+            11 => Op::BranchRelaxation(source.bool()),
+            12 => Op::InfiniteLoop,
+            13 => {
+                let over = source.u16()..=source.u16();
+                let kind = source.choose(&[
+                    Checksum::Sum8,
+                    Checksum::Sum16,
+                    Checksum::Xor8,
+                    Checksum::Crc16,
+                ]);
+                Op::Checksum(over, kind)
+            }
+            14 => Op::ZeroPagePool(source.u16()..source.u16()),
+            15 => Op::ZeroPageVariable(source.choose(NAMES), source.u16()),
+            16 => Op::SourceHint(source.u8()),
+            17 => Op::ClearSourceHint,
+            18 => Op::GetPc(source.choose(NAMES), source.u16(), source.u8()),
+            19 => Op::RelocationStub(source.u16(), source.u8()),
+            20 => Op::RelocationTable,
+            21 => {
+                let name = source.choose(NAMES);
+                let targets = (0..source.u8() % MAX_NESTED)
+                    .map(|_| source.choose(NAMES))
+                    .collect();
+                let dispatch = if source.bool() {
+                    Dispatch::Rts
+                } else {
+                    Dispatch::IndirectJmp {
+                        vector: source.u16(),
+                    }
+                };
+                Op::JumpTable(name, targets, dispatch)
+            }
+            22 => Op::Vectors(source.u16()),
+            23 => Op::Repeat((source.u8() % 4) as usize, Self::nested(source, depth)),
+            24 => Op::Append(Self::nested(source, depth)),
+            _ => Op::Include(source.u16(), source.bool(), Self::nested(source, depth)),
+        }
+    }
+    pub fn apply(&self, block: &mut Block) {
+        match self {
+            Op::LiteralByte(byte) => block.literal_byte(*byte),
+            Op::LiteralOffset(offset) => block.literal_offset_le(*offset),
+            Op::LiteralAddress(address) => block.literal_address_le(*address),
+            Op::Label(name) => block.label(name),
+            Op::Constant(name, address) => block.constant(name, *address),
+            Op::Reference(name, reference) => match *reference {
+                Reference::Le(delta) => block.label_offset_le_add(name, delta),
+                Reference::Lo(delta) => block.label_offset_lo_add(name, delta),
+                Reference::Hi(delta) => block.label_offset_hi_add(name, delta),
+                Reference::Relative => block.label_relative_offset(name),
+                Reference::Symbol => {
+                    let symbol = block.symbol(name);
+                    block.inst(Jmp(Absolute), symbol);
+                }
+                Reference::Reloc => block.relocatable_le(name),
+            },
+            Op::LoadAbsolute(name) => block.inst(Lda(Absolute), LabelRef::new(name)),
+            Op::LoadLiteral(address) => block.inst(Lda(Absolute), Addr(*address)),
+            Op::Branch(name) => block.inst(Bne, LabelRef::new(name).relative()),
+            Op::SetOffset(offset) => block.set_offset(*offset),
+            Op::AutoZeroPage(enabled) => block.auto_zero_page(*enabled),
+            Op::BranchRelaxation(enabled) => block.branch_relaxation(*enabled),
+            Op::InfiniteLoop => block.infinite_loop(),
+            Op::Checksum(over, kind) => block.checksum_here(over.clone(), *kind),
+            Op::ZeroPagePool(pool) => block.set_zero_page_pool(pool.clone()),
+            Op::ZeroPageVariable(name, size) => {
+                block.zp_var(name, *size);
+            }
+            Op::SourceHint(hint) => block.source_hint(alloc::format!("{}", hint)),
+            Op::ClearSourceHint => block.clear_source_hint(),
+            Op::GetPc(anchor, rts, zp) => block.get_pc(anchor, *rts, *zp),
+            Op::RelocationStub(rts, zp) => block.relocation_stub(*rts, *zp),
+            Op::RelocationTable => block.relocation_table(),
+            Op::JumpTable(name, targets, dispatch) => block.jump_table(name, targets, *dispatch),
+            Op::Vectors(base) => block.vectors(
+                *base,
+                Vectors {
+                    nmi: NAMES[0],
+                    reset: NAMES[1],
+                    irq: NAMES[2],
+                },
+            ),
+            Op::Repeat(count, ops) => block.repeat(*count, |block, _| apply(block, ops)),
+            Op::Append(ops) => block.append(&build(ops)),
+            Op::Include(offset, prefix, ops) => {
+                block.include_at(*offset, &build(ops), prefix.then_some("inc_"))
+            }
+        }
+    }
+}
+
+fn apply(block: &mut Block, ops: &[Op]) {
+    for op in ops {
+        op.apply(block);
+    }
+}
+
+/// A new block with `ops` applied.
+pub fn build(ops: &[Op]) -> Block {
+    let mut block = Block::new();
+    apply(&mut block, ops);
+    block
+}
+
+/// Builds a block from `data`, then assembles it at a base also taken from
+/// `data` into a full 64KiB image, into a smaller buffer, and as bytes,
+/// and works out its cross reference and timing. Returns the image, or the
+/// errors from assembling it.
+pub fn drive(data: &[u8]) -> Result<Vec<u8>, Vec<Error>> {
+    let mut source = Source::new(data);
+    let base = source.u16();
+    let small = source.u16() as usize;
+    let block = build(&Op::list(&mut source));
+    let _ = block.cross_reference();
+    let _ = block.region_budgets(base);
+    let _ = block.assembled_bytes(base).map(|bytes| bytes.count());
+    let _ = block.assemble_into(base, &mut alloc::vec![0; small]);
+    let mut image = Vec::new();
+    block.assemble_all_errors(base, 0x10000, &mut image)?;
+    let block = block.finish()?;
+    let _ = block.assemble(base, small, &mut Vec::new());
+    Ok(image)
+}
@@ -0,0 +1,430 @@
+//! Running a block as a unit test: assemble it into a fresh machine, set
+//! up memory and registers, run it until a label or a `BRK`, then check
+//! the result. Failed checks panic with the registers and a disassembled
+//! trace of the last instructions run, each marked with the program item
+//! it was assembled from.
+//!
+//! ```ignore
+//! let mut block = Block::new();
+//! block.label("start");
+//! block.inst(Lda(ZeroPage), 0x10);
+//! block.inst(Adc(Immediate), 1);
+//! block.inst(Brk, ());
+//! Test::new(&block, 0x0200)
+//!     .memory(0x10, &[41])
+//!     .run()
+//!     .unwrap()
+//!     .assert_a(42)
+//!     .assert_flag(flag::CARRY, false);
+//! ```
+use crate::{AssembledBlock, Block, Error};
+use alloc::{format, string::String, vec::Vec};
+use core::fmt::{self, Write as _};
+use portal_solutions_mos6502_model::{
+    machine::{Cpu, Fuel, Machine, Memory, MemoryReadOnly, Ram, RunReport, StepError, Stopped},
+    opcode,
+    status::Register,
+    Address,
+};
+
+/// How many instructions are kept for the trace in failure messages.
+pub const DEFAULT_TRACE: usize = 32;
+pub const DEFAULT_MAX_CYCLES: usize = 1_000_000;
+
+pub struct Test<'a> {
+    block: &'a Block,
+    base: Address,
+    entry: Option<String>,
+    until: Option<String>,
+    cpu: Cpu,
+    memory: Vec<(Address, Vec<u8>)>,
+    max_cycles: usize,
+    trace: usize,
+}
+
+impl<'a> Test<'a> {
+    /// Runs `block` assembled at `base`, from `base`, until a `BRK` or
+    /// `DEFAULT_MAX_CYCLES` cycles.
+    pub fn new(block: &'a Block, base: Address) -> Self {
+        Self {
+            block,
+            base,
+            entry: None,
+            until: None,
+            cpu: Cpu::new(),
+            memory: Vec::new(),
+            max_cycles: DEFAULT_MAX_CYCLES,
+            trace: DEFAULT_TRACE,
+        }
+    }
+    /// Starts at `label` rather than `base`.
+    pub fn entry(mut self, label: &str) -> Self {
+        self.entry = Some(label.into());
+        self
+    }
+    /// Also stops when the program counter reaches `label`.
+    pub fn until(mut self, label: &str) -> Self {
+        self.until = Some(label.into());
+        self
+    }
+    /// Writes `bytes` at `address` after the program is loaded.
+    pub fn memory(mut self, address: Address, bytes: &[u8]) -> Self {
+        self.memory.push((address, bytes.into()));
+        self
+    }
+    pub fn a(mut self, value: u8) -> Self {
+        self.cpu.acc = value;
+        self
+    }
+    pub fn x(mut self, value: u8) -> Self {
+        self.cpu.x = value;
+        self
+    }
+    pub fn y(mut self, value: u8) -> Self {
+        self.cpu.y = value;
+        self
+    }
+    pub fn sp(mut self, value: u8) -> Self {
+        self.cpu.sp = value;
+        self
+    }
+    pub fn status(mut self, value: u8) -> Self {
+        self.cpu.status = Register::from(value);
+        self
+    }
+    /// Sets or clears the bits of `flag`, one of `status::flag`.
+    pub fn flag(mut self, flag: u8, set: bool) -> Self {
+        let status = u8::from(self.cpu.status);
+        self.cpu.status = Register::from(if set { status | flag } else { status & !flag });
+        self
+    }
+    /// Fails if the program hasn't stopped after `cycles` cycles.
+    pub fn max_cycles(mut self, cycles: usize) -> Self {
+        self.max_cycles = cycles;
+        self
+    }
+    /// Keeps the last `instructions` for the trace, rather than
+    /// `DEFAULT_TRACE`.
+    pub fn trace(mut self, instructions: usize) -> Self {
+        self.trace = instructions;
+        self
+    }
+    /// Assembles, loads and runs the program. Stops before running a `BRK`,
+    /// so the registers are as the program left them, or once the program
+    /// counter reaches the `until` label after at least one instruction.
+    pub fn run(self) -> Result<Outcome, Failure> {
+        let mut machine = Machine::new(self.cpu, Ram::new());
+        machine.cpu.pc = self.base;
+        let assembled = self
+            .block
+            .assemble_into_machine(self.base, &mut machine, self.entry.as_deref())
+            .map_err(Failure::Assemble)?;
+        let until = self
+            .until
+            .map(|label| {
+                assembled
+                    .address_of_label(&label)
+                    .ok_or(Failure::Assemble(Error::UndeclaredLabel(label)))
+            })
+            .transpose()?;
+        for (address, bytes) in &self.memory {
+            machine.memory.load(*address, bytes);
+        }
+        machine.enable_history(self.trace);
+        let at_brk = |machine: &Machine<Ram>| {
+            machine.memory.read_u8_read_only(machine.cpu.pc) == opcode::brk::IMPLIED
+        };
+        let report = if at_brk(&machine) {
+            RunReport {
+                instructions: 0,
+                cycles: 0,
+                stopped: Stopped::Condition,
+            }
+        } else {
+            machine
+                .run_until_with_fuel(Fuel::cycles(self.max_cycles), |machine| {
+                    Some(machine.cpu.pc) == until || at_brk(machine)
+                })
+                .map_err(|error| Failure::Step {
+                    error,
+                    state: state(&machine, &assembled),
+                })?
+        };
+        if report.stopped != Stopped::Condition {
+            return Err(Failure::Stopped {
+                stopped: report.stopped,
+                cycles: report.cycles,
+                state: state(&machine, &assembled),
+            });
+        }
+        Ok(Outcome {
+            machine,
+            assembled,
+            report,
+        })
+    }
+}
+
+// The registers and trace, for failure messages, with each instruction
+// marked with the item it came from.
+fn state(machine: &Machine<Ram>, assembled: &AssembledBlock) -> String {
+    let mut state = String::new();
+    let _ = writeln!(state, "registers: {}", machine.cpu);
+    if let Some(history) = machine.history() {
+        let _ = writeln!(state, "last instructions:");
+        let _ = history.write_annotated(&mut state, &assembled.source_map().annotations());
+    }
+    state
+}
+
+/// Why a `Test` couldn't run to the end.
+pub enum Failure {
+    Assemble(Error),
+    Step {
+        error: StepError,
+        state: String,
+    },
+    /// Running stopped for some reason other than reaching a `BRK` or the
+    /// `until` label, such as running out of cycles.
+    Stopped {
+        stopped: Stopped,
+        cycles: usize,
+        state: String,
+    },
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::Assemble(error) => write!(f, "failed to assemble: {:?}", error),
+            Failure::Step { error, state } => write!(f, "{:?}\n{}", error, state),
+            Failure::Stopped {
+                stopped: Stopped::OutOfFuel,
+                cycles,
+                state,
+            } => write!(f, "still running after {} cycles\n{}", cycles, state),
+            Failure::Stopped {
+                stopped,
+                cycles,
+                state,
+            } => write!(
+                f,
+                "stopped ({:?}) after {} cycles\n{}",
+                stopped, cycles, state
+            ),
+        }
+    }
+}
+
+impl fmt::Debug for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// A program which ran to the end, to check the results of. Each check
+/// panics if it fails, and returns the outcome so checks can be chained.
+pub struct Outcome {
+    pub machine: Machine<Ram>,
+    pub assembled: AssembledBlock,
+    pub report: RunReport,
+}
+
+impl Outcome {
+    #[track_caller]
+    fn check(&self, ok: bool, what: &str, expected: &dyn fmt::Display, actual: &dyn fmt::Display) {
+        if !ok {
+            panic!(
+                "expected {} to be {}, but it was {}\n{}",
+                what,
+                expected,
+                actual,
+                state(&self.machine, &self.assembled)
+            );
+        }
+    }
+    #[track_caller]
+    fn check_register(&self, name: &str, expected: u8, actual: u8) -> &Self {
+        self.check(
+            expected == actual,
+            name,
+            &format!("${:02X}", expected),
+            &format!("${:02X}", actual),
+        );
+        self
+    }
+    #[track_caller]
+    pub fn assert_a(&self, expected: u8) -> &Self {
+        self.check_register("A", expected, self.machine.cpu.acc)
+    }
+    #[track_caller]
+    pub fn assert_x(&self, expected: u8) -> &Self {
+        self.check_register("X", expected, self.machine.cpu.x)
+    }
+    #[track_caller]
+    pub fn assert_y(&self, expected: u8) -> &Self {
+        self.check_register("Y", expected, self.machine.cpu.y)
+    }
+    #[track_caller]
+    pub fn assert_sp(&self, expected: u8) -> &Self {
+        self.check_register("SP", expected, self.machine.cpu.sp)
+    }
+    /// Checks the bits of `flag`, one of `status::flag`, are all set or
+    /// all clear.
+    #[track_caller]
+    pub fn assert_flag(&self, flag: u8, set: bool) -> &Self {
+        let actual = u8::from(self.machine.cpu.status) & flag;
+        let expected = if set { flag } else { 0 };
+        let name = |bits: u8| match bits {
+            0 => "clear",
+            bits if bits == flag => "set",
+            _ => "partly set",
+        };
+        self.check(
+            actual == expected,
+            &format!("flag ${:02X}", flag),
+            &name(expected),
+            &name(actual),
+        );
+        self
+    }
+    #[track_caller]
+    pub fn assert_pc(&self, expected: Address) -> &Self {
+        let actual = self.machine.cpu.pc;
+        self.check(
+            expected == actual,
+            "PC",
+            &format!("${:04X}", expected),
+            &format!("${:04X}", actual),
+        );
+        self
+    }
+    /// Checks the program stopped at `label`.
+    #[track_caller]
+    pub fn assert_at(&self, label: &str) -> &Self {
+        match self.assembled.address_of_label(label) {
+            Some(address) => self.assert_pc(address),
+            None => panic!("no label {}", label),
+        }
+    }
+    /// Checks the bytes at `address`.
+    #[track_caller]
+    pub fn assert_memory(&self, address: Address, expected: &[u8]) -> &Self {
+        let actual = (0..expected.len())
+            .map(|i| {
+                self.machine
+                    .memory
+                    .read_u8_read_only(address.wrapping_add(i as Address))
+            })
+            .collect::<Vec<_>>();
+        self.check(
+            expected == actual.as_slice(),
+            &format!("memory at ${:04X}", address),
+            &Hex(expected),
+            &Hex(&actual),
+        );
+        self
+    }
+    /// Checks the little-endian word at `address`.
+    #[track_caller]
+    pub fn assert_word(&self, address: Address, expected: u16) -> &Self {
+        self.assert_memory(address, &expected.to_le_bytes())
+    }
+}
+
+// Bytes as `$12 $34`.
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes = self
+            .0
+            .iter()
+            .map(|byte| format!("${:02X}", byte))
+            .collect::<Vec<_>>();
+        write!(f, "{}", bytes.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use portal_solutions_mos6502_model::{
+        addressing_mode::*, assembler_instruction::*, status::flag,
+    };
+
+    fn increment() -> Block {
+        let mut block = Block::new();
+        block.source_hint("add");
+        block.label("start");
+        block.inst(Lda(ZeroPage), 0x10);
+        block.inst(Clc, ());
+        block.inst(Adc(Immediate), 1);
+        block.inst(Sta(ZeroPage), 0x11);
+        block.label("done");
+        block.inst(Brk, ());
+        block
+    }
+
+    #[test]
+    fn passing_checks_chain() {
+        Test::new(&increment(), 0x0200)
+            .memory(0x10, &[41])
+            .run()
+            .unwrap()
+            .assert_a(42)
+            .assert_flag(flag::CARRY, false)
+            .assert_memory(0x11, &[42])
+            .assert_at("done");
+    }
+
+    #[test]
+    fn runs_until_a_label() {
+        Test::new(&increment(), 0x0200)
+            .until("done")
+            .a(7)
+            .run()
+            .unwrap()
+            .assert_a(1)
+            .assert_pc(0x0207);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected A to be $2A, but it was $2B
+registers: 0207  A:2B X:00 Y:00 P:24 SP:FF nv-bdIzc
+last instructions:
+0200  A5 10     LDA $10                         A:00 X:00 Y:00 P:24 SP:FF CYC:0  ; item 0: add
+0202  18        CLC                             A:2A X:00 Y:00 P:24 SP:FF CYC:3  ; item 2: add
+0203  69 01     ADC #$01                        A:2A X:00 Y:00 P:24 SP:FF CYC:5  ; item 3: add
+0205  85 11     STA $11                         A:2B X:00 Y:00 P:24 SP:FF CYC:7  ; item 5: add
+")]
+    fn failing_checks_report_the_state() {
+        Test::new(&increment(), 0x0200)
+            .memory(0x10, &[42])
+            .run()
+            .unwrap()
+            .assert_a(42);
+    }
+
+    #[test]
+    fn failures_report_the_trace() {
+        let mut block = Block::new();
+        block.label("loop");
+        block.inst(Jmp(Absolute), "loop");
+        let failure = Test::new(&block, 0x0200)
+            .max_cycles(9)
+            .trace(2)
+            .run()
+            .err()
+            .unwrap();
+        assert_eq!(
+            format!("{}", failure),
+            "still running after 9 cycles
+registers: 0200  A:00 X:00 Y:00 P:24 SP:FF nv-bdIzc
+last instructions:
+0200  4C 00 02  JMP $0200                       A:00 X:00 Y:00 P:24 SP:FF CYC:3  ; item 0
+0200  4C 00 02  JMP $0200                       A:00 X:00 Y:00 P:24 SP:FF CYC:6  ; item 0
+"
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{Block, DataAtOffset, Error, Label};
+use alloc::{format, string::String};
+use portal_solutions_mos6502_model::Address;
+
+impl Block {
+    /// Copies `other` to the cursor, as if its items had been emitted
+    /// here, and moves the cursor past it.
+    pub fn append(&mut self, other: &Block) {
+        self.include(self.cursor_offset, other, None, true);
+    }
+    /// Copies the program, labels and build errors of `other`, with its
+    /// offset 0 placed at `offset`. With a `prefix`, every label defined in
+    /// `other` is renamed to start with it (references to labels it doesn't
+    /// define are left alone), so one routine can be included several times.
+    /// Afterwards the cursor is where `other`'s cursor was, relative to `offset`.
+    pub fn include_at(&mut self, offset: Address, other: &Block, prefix: Option<&str>) {
+        self.include(offset, other, prefix, false);
+    }
+    // When appending, the start of `other` carries on the current segment,
+    // so it moves along with any items before it that change size.
+    fn include(&mut self, offset: Address, other: &Block, prefix: Option<&str>, append: bool) {
+        let rename = |label: &str| -> String {
+            match prefix {
+                Some(prefix) if other.labels.contains_key(label) => format!("{}{}", prefix, label),
+                _ => label.into(),
+            }
+        };
+        let first_index = self.program.len();
+        let current_segment = self.segment_start;
+        let segment = |start: usize| {
+            if append && start == 0 {
+                current_segment
+            } else {
+                start + first_index
+            }
+        };
+        let first_hint = self.source_hints.len();
+        self.source_hints.extend(other.source_hints.iter().cloned());
+        for item in other.program.iter() {
+            // Symbols are per block, so references are interned again by name.
+            let data = item
+                .data
+                .map_label(|symbol| self.symbols.intern(&rename(other.symbols.resolve(symbol))));
+            self.program.push(DataAtOffset {
+                data,
+                offset: item.offset.wrapping_add(offset),
+                source_hint: item.source_hint.map(|hint| hint + first_hint),
+                segment_start: segment(item.segment_start),
+                instruction: item.instruction,
+            });
+        }
+        for (name, label) in other.labels.iter() {
+            let name = rename(name);
+            if self.labels.contains_key(&name) {
+                self.build_error(Error::DuplicateLabel(name));
+                continue;
+            }
+            let symbol = self.symbols.intern(&name);
+            let label = if label.absolute {
+                Label {
+                    symbol,
+                    index: label.index + first_index,
+                    segment_start: segment(label.segment_start),
+                    ..*label
+                }
+            } else {
+                Label {
+                    symbol,
+                    offset: label.offset.wrapping_add(offset),
+                    index: label.index + first_index,
+                    segment_start: segment(label.segment_start),
+                    absolute: false,
+                }
+            };
+            self.labels.insert(name, label);
+        }
+        self.errors.extend(other.errors.iter().cloned());
+        self.cursor_offset = other.cursor_offset.wrapping_add(offset);
+        self.segment_start = segment(other.segment_start);
+        self.wrapped = other.wrapped;
+    }
+}
@@ -0,0 +1,53 @@
+use crate::{Addr, Block};
+use alloc::format;
+use portal_solutions_mos6502_model::{addressing_mode::*, assembler_instruction::*, Address};
+
+/// How a jump table's dispatcher transfers control.
+#[derive(Debug, Clone, Copy)]
+pub enum Dispatch {
+    /// Pushes the target address minus one and returns into it. Needs no
+    /// memory, but uses two bytes of stack until the `RTS`.
+    Rts,
+    /// Stores the target at the 2-byte `vector` and jumps through it.
+    IndirectJmp { vector: Address },
+}
+
+impl Block {
+    /// Emits a dispatcher at `name` which jumps to `targets[A]`, followed
+    /// by its tables at `{name}_lo` and `{name}_hi`. X is clobbered.
+    pub fn jump_table<S: AsRef<str>>(&mut self, name: &str, targets: &[S], dispatch: Dispatch) {
+        let lo = format!("{}_lo", name);
+        let hi = format!("{}_hi", name);
+        self.label(name);
+        self.inst(Tax, ());
+        match dispatch {
+            Dispatch::Rts => {
+                self.inst(Lda(AbsoluteXIndexed), hi.clone());
+                self.inst(Pha, ());
+                self.inst(Lda(AbsoluteXIndexed), lo.clone());
+                self.inst(Pha, ());
+                self.inst(Rts, ());
+            }
+            Dispatch::IndirectJmp { vector } => {
+                self.inst(Lda(AbsoluteXIndexed), lo.clone());
+                self.inst(Sta(Absolute), Addr(vector));
+                self.inst(Lda(AbsoluteXIndexed), hi.clone());
+                self.inst(Sta(Absolute), Addr(vector.wrapping_add(1)));
+                self.inst(Jmp(Indirect), Addr(vector));
+            }
+        }
+        // `RTS` resumes at the byte after the address it pulls.
+        let delta = match dispatch {
+            Dispatch::Rts => -1,
+            Dispatch::IndirectJmp { .. } => 0,
+        };
+        self.label(&lo);
+        for target in targets {
+            self.label_offset_lo_add(target, delta);
+        }
+        self.label(&hi);
+        for target in targets {
+            self.label_offset_hi_add(target, delta);
+        }
+    }
+}
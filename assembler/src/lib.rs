@@ -1,29 +1,189 @@
 #![no_std]
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
-use alloc::{collections::btree_map::BTreeMap, string::{String, ToString}, vec::Vec};
-use portal_solutions_mos6502_model::*;
+pub mod apple2;
+pub mod budget;
+pub mod checksum;
+pub mod codegen;
+pub mod compare;
+pub mod const_block;
+pub mod cross_reference;
+pub mod disassemble;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+pub mod harness;
+pub mod include;
+pub mod jump_table;
+pub mod load;
+pub mod mnemonics;
+pub mod operands;
+pub mod patch;
+pub mod pic;
+pub mod rom;
+pub mod runtime;
+pub mod source_map;
+pub mod symbol;
+pub mod vectors;
+pub mod zero_page;
 
+use alloc::{
+    boxed::Box,
+    collections::btree_map::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use portal_solutions_mos6502_model::*;
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+use symbol::{Interner, Symbol};
 
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Clone)]
 enum Data {
     LiteralByte(u8),
-    LabelOffsetLe(String),
+    LabelOffsetLe(Symbol, i16),
     LiteralOffsetLe(Address),
     LiteralAddressLe(Address),
-    LabelOffsetLo(String),
-    LabelOffsetHi(String),
-    LabelRelativeOffset(String),
+    LabelOffsetLo(Symbol, i16),
+    LabelOffsetHi(Symbol, i16),
+    LabelRelativeOffset(Symbol),
+    // An absolute (or absolute indexed) instruction that is shrunk to its
+    // zero-page form if the label turns out to be in the zero page.
+    ZeroPageOrAbsolute {
+        zero_page: u8,
+        absolute: u8,
+        label: Symbol,
+        delta: i16,
+    },
+    // A relative branch that becomes the opposite branch over a `JMP` if
+    // the label is out of range.
+    Branch {
+        opcode: u8,
+        label: Symbol,
+    },
+    // Filled in once everything else has been assembled.
+    Checksum {
+        over: core::ops::RangeInclusive<Address>,
+        kind: checksum::Checksum,
+    },
+}
+
+impl Data {
+    fn num_bytes(&self) -> Address {
+        match self {
+            Data::LiteralByte(_)
+            | Data::LabelOffsetLo(..)
+            | Data::LabelOffsetHi(..)
+            | Data::LabelRelativeOffset(_) => 1,
+            Data::LabelOffsetLe(..)
+            | Data::LiteralOffsetLe(_)
+            | Data::LiteralAddressLe(_)
+            | Data::Branch { .. } => 2,
+            Data::ZeroPageOrAbsolute { .. } => 3,
+            Data::Checksum { kind, .. } => kind.num_bytes(),
+        }
+    }
+    fn map_label<F: FnMut(Symbol) -> Symbol>(&self, mut f: F) -> Data {
+        match self {
+            Data::LabelOffsetLe(label, delta) => Data::LabelOffsetLe(f(*label), *delta),
+            Data::LabelOffsetLo(label, delta) => Data::LabelOffsetLo(f(*label), *delta),
+            Data::LabelOffsetHi(label, delta) => Data::LabelOffsetHi(f(*label), *delta),
+            Data::LabelRelativeOffset(label) => Data::LabelRelativeOffset(f(*label)),
+            Data::ZeroPageOrAbsolute {
+                zero_page,
+                absolute,
+                label,
+                delta,
+            } => Data::ZeroPageOrAbsolute {
+                zero_page: *zero_page,
+                absolute: *absolute,
+                label: f(*label),
+                delta: *delta,
+            },
+            Data::Branch { opcode, label } => Data::Branch {
+                opcode: *opcode,
+                label: f(*label),
+            },
+            data => data.clone(),
+        }
+    }
+    fn label(&self) -> Option<Symbol> {
+        match *self {
+            Data::LabelOffsetLe(label, _)
+            | Data::LabelOffsetLo(label, _)
+            | Data::LabelOffsetHi(label, _)
+            | Data::LabelRelativeOffset(label)
+            | Data::ZeroPageOrAbsolute { label, .. }
+            | Data::Branch { label, .. } => Some(label),
+            Data::LiteralByte(_)
+            | Data::LiteralOffsetLe(_)
+            | Data::LiteralAddressLe(_)
+            | Data::Checksum { .. } => None,
+        }
+    }
 }
 
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 struct DataAtOffset {
     data: Data,
     offset: Address,
+    source_hint: Option<usize>,
+    // Index of the first item placed after the most recent `set_offset`.
+    // Items resized during layout only move the rest of their own segment.
+    segment_start: usize,
+    // Set on the opcode byte of everything emitted through `inst`.
+    instruction: bool,
+}
+
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy)]
+struct Label {
+    symbol: Symbol,
+    offset: Address,
+    index: usize,
+    segment_start: usize,
+    // Constants hold an address rather than an offset within the block.
+    absolute: bool,
+}
+
+// Final offsets and sizes of every item once zero-page selection and
+// branch relaxation have been resolved, and the offsets of labels after
+// any items before them changed size.
+struct Layout {
+    offsets: Vec<Address>,
+    sizes: Vec<Address>,
+    // Offsets of labels, indexed by symbol.
+    labels: Vec<Option<Address>>,
 }
 
+impl Layout {
+    fn label(&self, symbol: Symbol) -> Option<Address> {
+        self.labels.get(symbol.index()).copied().flatten()
+    }
+}
+
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct Block {
     cursor_offset: Address,
     program: Vec<DataAtOffset>,
-    labels: BTreeMap<String, Address>,
+    labels: BTreeMap<String, Label>,
+    symbols: Interner,
+    size: Option<usize>,
+    wrapped: bool,
+    segment_start: usize,
+    auto_zero_page: bool,
+    branch_relaxation: bool,
+    zero_page: zero_page::ZeroPageAllocator,
+    local_scope: Option<String>,
+    repeat_count: usize,
+    // Indices of the items emitted for `Reloc` references.
+    relocations: Vec<usize>,
+    errors: Vec<Error>,
+    source_hints: Vec<String>,
+    current_source_hint: Option<usize>,
 }
 
 pub trait ArgOperand {
@@ -45,6 +205,7 @@ impl ArgOperand for String {
     }
 }
 
+// `Address` is `u16`, so this also covers plain `u16` literals.
 impl ArgOperand for Address {
     type Operand = operand::Address;
     fn program(self, block: &mut Block) {
@@ -83,7 +244,9 @@ impl ArgOperand for i32 {
     fn program(self, block: &mut Block) {
         // Allow the union of signed and unsigned byte ranges. This is to
         // prevent mistakes such as writing 0x011011010 instead of 0b011011010.
-        assert!(self >= -128 && self <= 255, "{} is not a valid byte", self);
+        if !(-128..=255).contains(&self) {
+            block.build_error(Error::InvalidByte(self));
+        }
         block.literal_byte((self as i8) as u8);
     }
 }
@@ -98,6 +261,32 @@ pub struct LabelOffsetHi(pub &'static str);
 pub struct LabelRelativeOffset(pub &'static str);
 pub struct LabelRelativeOffsetOwned(pub String);
 
+// The address of a label plus a constant, e.g. `table+1`.
+pub struct LabelOffsetLeAdd(pub &'static str, pub i16);
+pub struct LabelOffsetLoAdd(pub &'static str, pub i16);
+pub struct LabelOffsetHiAdd(pub &'static str, pub i16);
+
+impl ArgOperand for LabelOffsetLeAdd {
+    type Operand = operand::Address;
+    fn program(self, block: &mut Block) {
+        block.label_offset_le_add(self.0, self.1);
+    }
+}
+
+impl ArgOperand for LabelOffsetLoAdd {
+    type Operand = operand::Byte;
+    fn program(self, block: &mut Block) {
+        block.label_offset_lo_add(self.0, self.1);
+    }
+}
+
+impl ArgOperand for LabelOffsetHiAdd {
+    type Operand = operand::Byte;
+    fn program(self, block: &mut Block) {
+        block.label_offset_hi_add(self.0, self.1);
+    }
+}
+
 impl ArgOperand for LabelOffsetLo {
     type Operand = operand::Byte;
     fn program(self, block: &mut Block) {
@@ -126,11 +315,109 @@ impl ArgOperand for LabelRelativeOffsetOwned {
     }
 }
 
+/// An owned label name, for labels built at runtime. On its own it is the
+/// label's address, and `lo`, `hi` and `relative` give the other positions
+/// a label can be used in.
+pub struct LabelRef(pub String);
+pub struct LabelRefLo(pub String);
+pub struct LabelRefHi(pub String);
+
+impl LabelRef {
+    pub fn new<S: AsRef<str>>(label: S) -> Self {
+        Self(label.as_ref().into())
+    }
+    pub fn lo(self) -> LabelRefLo {
+        LabelRefLo(self.0)
+    }
+    pub fn hi(self) -> LabelRefHi {
+        LabelRefHi(self.0)
+    }
+    pub fn relative(self) -> LabelRelativeOffsetOwned {
+        LabelRelativeOffsetOwned(self.0)
+    }
+}
+
+impl ArgOperand for LabelRef {
+    type Operand = operand::Address;
+    fn program(self, block: &mut Block) {
+        block.label_offset_le(self.0);
+    }
+}
+
+impl ArgOperand for LabelRefLo {
+    type Operand = operand::Byte;
+    fn program(self, block: &mut Block) {
+        block.label_offset_lo(self.0);
+    }
+}
+
+impl ArgOperand for LabelRefHi {
+    type Operand = operand::Byte;
+    fn program(self, block: &mut Block) {
+        block.label_offset_hi(self.0);
+    }
+}
+
+/// Where in a block's program an error was found.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+pub struct Location {
+    /// Index of the program item, counting every emitted item in order.
+    pub index: usize,
+    pub offset: Address,
+    pub source_hint: Option<String>,
+}
+
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone)]
 pub enum Error {
-    OffsetOutOfBounds,
+    OffsetOutOfBounds {
+        offset: Address,
+        label: Option<String>,
+    },
     UndeclaredLabel(String),
     BranchTargetOutOfRange(String),
+    ZeroPageOutOfRange(String),
+    /// Not enough of the zero-page pool was left for the named variable.
+    ZeroPageExhausted(String),
+    DuplicateLabel(String),
+    /// More `Reloc` references than the relocation stub can handle.
+    TooManyRelocations(usize),
+    InvalidByte(i32),
+    SetOffsetOutOfRange(Address),
+    CursorWrapped(Address),
+    /// Program items `first` and `second` (in emission order) both write to `offset`.
+    Overlap {
+        offset: Address,
+        first: usize,
+        second: usize,
+    },
+    Located {
+        location: Location,
+        error: Box<Error>,
+    },
+}
+
+impl Error {
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            Error::Located { location, .. } => Some(location),
+            _ => None,
+        }
+    }
+    /// The underlying error, with any location stripped.
+    pub fn kind(&self) -> &Error {
+        match self {
+            Error::Located { error, .. } => error.kind(),
+            error => error,
+        }
+    }
+}
+
+impl Default for Block {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Block {
@@ -139,68 +426,227 @@ impl Block {
             cursor_offset: 0,
             program: Vec::new(),
             labels: BTreeMap::new(),
+            symbols: Interner::default(),
+            size: None,
+            wrapped: false,
+            segment_start: 0,
+            auto_zero_page: false,
+            branch_relaxation: false,
+            zero_page: zero_page::ZeroPageAllocator::default(),
+            local_scope: None,
+            repeat_count: 0,
+            relocations: Vec::new(),
+            errors: Vec::new(),
+            source_hints: Vec::new(),
+            current_source_hint: None,
+        }
+    }
+    /// Creates a block whose output is known to be `size` bytes, so that
+    /// `set_offset` past the end can be reported by `finish`.
+    pub fn with_size(size: usize) -> Self {
+        Self {
+            size: Some(size),
+            ..Self::new()
         }
     }
     pub fn set_offset(&mut self, offset: Address) {
+        if let Some(size) = self.size {
+            if offset as usize >= size {
+                self.build_error(Error::SetOffsetOutOfRange(offset));
+            }
+        }
         self.cursor_offset = offset;
+        self.wrapped = false;
+        self.segment_start = self.program.len();
     }
-    pub fn literal_byte(&mut self, byte: u8) {
+    /// While enabled, `inst` with an absolute or absolute indexed addressing
+    /// mode uses the zero-page form instead whenever the operand is below
+    /// $0100 and the instruction has one. Labels that are not yet known are
+    /// resolved during assembly, so `current_offset`, `len_bytes` and
+    /// `labels` report the unshrunk layout until then.
+    pub fn auto_zero_page(&mut self, enabled: bool) {
+        self.auto_zero_page = enabled;
+    }
+    /// While enabled, relative branches to labels emitted with `inst` whose
+    /// targets turn out to be out of range are assembled as the opposite
+    /// branch skipping over a `JMP` to the label, growing from 2 to 5
+    /// bytes. Like `auto_zero_page`, this is only known during assembly.
+    pub fn branch_relaxation(&mut self, enabled: bool) {
+        self.branch_relaxation = enabled;
+    }
+    pub(crate) fn emit(&mut self, data: Data) {
+        let num_bytes = data.num_bytes();
+        let end = self.cursor_offset as u32 + num_bytes as u32;
+        // Emitting the final byte at $FFFF is fine, but anything placed after
+        // the cursor has wrapped (or straddling the wrap) would overlap the
+        // start of the block. Each wrap is reported once.
+        let wrapped = self.wrapped || end > 0x10000;
+        if wrapped {
+            self.build_error(Error::CursorWrapped(self.cursor_offset));
+        }
+        self.wrapped = !wrapped && end >= 0x10000;
         self.program.push(DataAtOffset {
-            data: Data::LiteralByte(byte),
+            data,
             offset: self.cursor_offset,
+            source_hint: self.current_source_hint,
+            segment_start: self.segment_start,
+            instruction: false,
         });
-        self.cursor_offset = self.cursor_offset.wrapping_add(1);
+        self.cursor_offset = self.cursor_offset.wrapping_add(num_bytes);
+    }
+    pub fn literal_byte(&mut self, byte: u8) {
+        self.emit(Data::LiteralByte(byte));
     }
     pub fn literal_offset_le(&mut self, offset: Address) {
-        self.program.push(DataAtOffset {
-            data: Data::LiteralOffsetLe(offset),
-            offset: self.cursor_offset,
-        });
-        self.cursor_offset = self.cursor_offset.wrapping_add(2);
+        self.emit(Data::LiteralOffsetLe(offset));
     }
     pub fn literal_address_le(&mut self, offset: Address) {
-        self.program.push(DataAtOffset {
-            data: Data::LiteralAddressLe(offset),
-            offset: self.cursor_offset,
-        });
-        self.cursor_offset = self.cursor_offset.wrapping_add(2);
+        self.emit(Data::LiteralAddressLe(offset));
     }
     pub fn label_offset_le<S: AsRef<str>>(&mut self, label: S) {
-        let string = label.as_ref().to_string();
-        self.program.push(DataAtOffset {
-            data: Data::LabelOffsetLe(string),
-            offset: self.cursor_offset,
-        });
-        self.cursor_offset = self.cursor_offset.wrapping_add(2);
+        let symbol = self.intern_label(label.as_ref());
+        self.emit(Data::LabelOffsetLe(symbol, 0));
     }
     pub fn label_offset_lo<S: AsRef<str>>(&mut self, label: S) {
-        let string = label.as_ref().to_string();
-        self.program.push(DataAtOffset {
-            data: Data::LabelOffsetLo(string),
-            offset: self.cursor_offset,
-        });
-        self.cursor_offset = self.cursor_offset.wrapping_add(1);
+        let symbol = self.intern_label(label.as_ref());
+        self.emit(Data::LabelOffsetLo(symbol, 0));
     }
     pub fn label_offset_hi<S: AsRef<str>>(&mut self, label: S) {
-        let string = label.as_ref().to_string();
-        self.program.push(DataAtOffset {
-            data: Data::LabelOffsetHi(string),
-            offset: self.cursor_offset,
-        });
-        self.cursor_offset = self.cursor_offset.wrapping_add(1);
+        let symbol = self.intern_label(label.as_ref());
+        self.emit(Data::LabelOffsetHi(symbol, 0));
+    }
+    pub fn label_offset_le_add<S: AsRef<str>>(&mut self, label: S, delta: i16) {
+        let symbol = self.intern_label(label.as_ref());
+        self.emit(Data::LabelOffsetLe(symbol, delta));
+    }
+    pub fn label_offset_lo_add<S: AsRef<str>>(&mut self, label: S, delta: i16) {
+        let symbol = self.intern_label(label.as_ref());
+        self.emit(Data::LabelOffsetLo(symbol, delta));
+    }
+    pub fn label_offset_hi_add<S: AsRef<str>>(&mut self, label: S, delta: i16) {
+        let symbol = self.intern_label(label.as_ref());
+        self.emit(Data::LabelOffsetHi(symbol, delta));
     }
     pub fn label_relative_offset<S: AsRef<str>>(&mut self, label: S) {
-        let string = label.as_ref().to_string();
-        self.program.push(DataAtOffset {
-            data: Data::LabelRelativeOffset(string),
+        let symbol = self.intern_label(label.as_ref());
+        self.emit(Data::LabelRelativeOffset(symbol));
+    }
+    fn define_label(&mut self, name: &str, offset: Address, absolute: bool) {
+        let string = self.label_name(name);
+        if self.labels.contains_key(&string) {
+            self.build_error(Error::DuplicateLabel(string));
+        } else {
+            let label = Label {
+                symbol: self.symbols.intern(&string),
+                offset,
+                index: self.program.len(),
+                segment_start: self.segment_start,
+                absolute,
+            };
+            self.labels.insert(string, label);
+        }
+    }
+    pub fn label<S: AsRef<str>>(&mut self, s: S) {
+        self.define_label(s.as_ref(), self.cursor_offset, false);
+    }
+    /// Defines a label for a fixed address, which isn't moved by the base
+    /// address the block is assembled at.
+    pub fn constant<S: AsRef<str>>(&mut self, s: S, address: Address) {
+        self.define_label(s.as_ref(), address, true);
+    }
+    pub fn current_offset(&self) -> Address {
+        self.cursor_offset
+    }
+    /// Total number of bytes emitted so far, regardless of where they were placed.
+    pub fn len_bytes(&self) -> usize {
+        self.program
+            .iter()
+            .map(|item| item.data.num_bytes() as usize)
+            .sum()
+    }
+    /// Labels and their offsets within the block, in name order.
+    pub fn labels(&self) -> impl Iterator<Item = (&str, Address)> {
+        self.labels
+            .iter()
+            .map(|(name, label)| (name.as_str(), label.offset))
+    }
+    pub fn contains_label(&self, label: &str) -> bool {
+        self.labels.contains_key(&self.label_name(label))
+    }
+    // Labels starting with `.` are local to the current `repeat` iteration.
+    fn label_name(&self, label: &str) -> String {
+        match &self.local_scope {
+            Some(scope) if label.starts_with('.') => format!("{}{}", scope, label),
+            _ => label.to_string(),
+        }
+    }
+    // Only scoped labels need a new name built before they are looked up.
+    fn intern_label(&mut self, label: &str) -> Symbol {
+        match &self.local_scope {
+            Some(scope) if label.starts_with('.') => {
+                let name = format!("{}{}", scope, label);
+                self.symbols.intern(&name)
+            }
+            _ => self.symbols.intern(label),
+        }
+    }
+    /// Emits `body` `n` times, passing the iteration index. Labels starting
+    /// with `.` that are defined or referenced inside `body` are local to
+    /// each iteration, so e.g. an unrolled loop can reuse `.skip`.
+    pub fn repeat<F: FnMut(&mut Block, usize)>(&mut self, n: usize, mut body: F) {
+        let id = self.repeat_count;
+        self.repeat_count += 1;
+        let outer_scope = self.local_scope.take();
+        for i in 0..n {
+            self.local_scope = Some(format!("__repeat{}_{}", id, i));
+            body(self, i);
+        }
+        self.local_scope = outer_scope;
+    }
+    /// Tags every item emitted from now on with `hint`, which is included in
+    /// the location of any error they cause.
+    pub fn source_hint<S: AsRef<str>>(&mut self, hint: S) {
+        self.source_hints.push(hint.as_ref().to_string());
+        self.current_source_hint = Some(self.source_hints.len() - 1);
+    }
+    pub fn clear_source_hint(&mut self) {
+        self.current_source_hint = None;
+    }
+    fn build_error(&mut self, error: Error) {
+        let location = Location {
+            index: self.program.len(),
             offset: self.cursor_offset,
+            source_hint: self
+                .current_source_hint
+                .map(|hint| self.source_hints[hint].clone()),
+        };
+        self.errors.push(Error::Located {
+            location,
+            error: Box::new(error),
         });
-        self.cursor_offset = self.cursor_offset.wrapping_add(1);
     }
-    pub fn label<S: AsRef<str>>(&mut self, s: S) {
-        let string = s.as_ref().to_string();
-        if self.labels.insert(string, self.cursor_offset).is_some() {
-            panic!("Multiple definitions of label {}", s.as_ref());
+    fn locate(&self, layout: &Layout, index: usize, error: Error) -> Error {
+        let item = &self.program[index];
+        Error::Located {
+            location: Location {
+                index,
+                offset: layout.offsets[index],
+                source_hint: item.source_hint.map(|hint| self.source_hints[hint].clone()),
+            },
+            error: Box::new(error),
+        }
+    }
+    /// Errors recorded while building the block, in the order they occurred.
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+    /// Ends the build phase, returning the block if it was built without
+    /// errors, or every error recorded during construction otherwise.
+    pub fn finish(self) -> Result<Self, Vec<Error>> {
+        if self.errors.is_empty() {
+            Ok(self)
+        } else {
+            Err(self.errors)
         }
     }
     pub fn inst<
@@ -212,102 +658,626 @@ impl Block {
         arg: A,
     ) {
         let _ = instruction;
-        self.literal_byte(I::opcode());
-        arg.program(self);
+        self.inst_from_opcode(I::opcode(), |block| arg.program(block));
+    }
+    // Like `inst`, for an instruction only known at run time, with
+    // `operand` emitting whatever follows the opcode.
+    pub(crate) fn inst_from_opcode<F: FnOnce(&mut Self)>(&mut self, opcode: u8, operand: F) {
+        let index = self.program.len();
+        self.literal_byte(opcode);
+        self.program[index].instruction = true;
+        operand(self);
+        if self.auto_zero_page && self.program.len() == index + 2 {
+            self.select_zero_page(index);
+        }
+        if self.branch_relaxation && self.program.len() == index + 2 {
+            self.allow_long_branch(index);
+        }
+    }
+    fn allow_long_branch(&mut self, index: usize) {
+        let Data::LiteralByte(opcode) = self.program[index].data else {
+            return;
+        };
+        // Every branch opcode is xxy10000, and flipping y inverts it.
+        if opcode & 0x1F != 0x10
+            || !matches!(self.program[index + 1].data, Data::LabelRelativeOffset(_))
+        {
+            return;
+        }
+        let Some(Data::LabelRelativeOffset(label)) = self.program.pop().map(|item| item.data)
+        else {
+            unreachable!()
+        };
+        self.program[index].data = Data::Branch { opcode, label };
+    }
+    fn select_zero_page(&mut self, index: usize) {
+        let Data::LiteralByte(absolute) = self.program[index].data else {
+            return;
+        };
+        let Some(zero_page) = zero_page_opcode(absolute) else {
+            return;
+        };
+        // Relocated words have to stay words.
+        if self.relocations.last() == Some(&(index + 1)) {
+            return;
+        }
+        match self.program[index + 1].data {
+            Data::LiteralAddressLe(address) if address < 0x100 => {
+                self.program[index].data = Data::LiteralByte(zero_page);
+                self.program[index + 1].data = Data::LiteralByte(address as u8);
+                self.cursor_offset = self.program[index + 1].offset.wrapping_add(1);
+            }
+            Data::LabelOffsetLe(..) => {
+                let Some(Data::LabelOffsetLe(label, delta)) =
+                    self.program.pop().map(|item| item.data)
+                else {
+                    unreachable!()
+                };
+                self.program[index].data = Data::ZeroPageOrAbsolute {
+                    zero_page,
+                    absolute,
+                    label,
+                    delta,
+                };
+            }
+            _ => (),
+        }
     }
     pub fn infinite_loop(&mut self) {
         let offset = self.cursor_offset;
         self.literal_byte(assembler_instruction::Jmp::<addressing_mode::Absolute>::opcode());
+        if let Some(item) = self.program.last_mut() {
+            item.instruction = true;
+        }
         self.literal_offset_le(offset);
     }
-    pub fn assemble(
-        &self,
-        base: Address,
-        size: usize,
-        buffer: &mut Vec<u8>,
-    ) -> Result<AssembledBlock, Error> {
-        let mut labels = BTreeMap::new();
-        for (label, address) in self.labels.iter() {
-            labels.insert(label.clone(), address + base);
+    fn layout_with_sizes(&self, base: Address, sizes: Vec<Address>) -> Layout {
+        // grown[i] is the total change in size of all items before item i.
+        let mut grown = Vec::with_capacity(sizes.len() + 1);
+        let mut total = 0i32;
+        grown.push(total);
+        for (item, &size) in self.program.iter().zip(sizes.iter()) {
+            total += size as i32 - item.data.num_bytes() as i32;
+            grown.push(total);
         }
-        buffer.resize(size, 0);
-        for &DataAtOffset { offset, ref data } in self.program.iter() {
-            match data {
-                &Data::LiteralByte(byte) => {
-                    if offset as usize >= size {
-                        return Err(Error::OffsetOutOfBounds);
+        let shift = |offset: Address, index: usize, segment_start: usize| {
+            (offset as i32 + grown[index] - grown[segment_start]) as Address
+        };
+        let offsets = self
+            .program
+            .iter()
+            .enumerate()
+            .map(|(index, item)| shift(item.offset, index, item.segment_start))
+            .collect();
+        let mut labels = Vec::new();
+        labels.resize(self.symbols.len(), None);
+        for label in self.labels.values() {
+            labels[label.symbol.index()] = Some(if label.absolute {
+                label.offset.wrapping_sub(base)
+            } else {
+                shift(label.offset, label.index, label.segment_start)
+            });
+        }
+        Layout {
+            offsets,
+            sizes,
+            labels,
+        }
+    }
+    fn layout(&self, base: Address) -> Layout {
+        let mut sizes = self
+            .program
+            .iter()
+            .map(|item| item.data.num_bytes())
+            .collect::<Vec<_>>();
+        // Branches are never shortened again once lengthened, so they settle
+        // after at most one pass each. Zero-page selection is redone every
+        // pass because longer branches can push a label out of the zero
+        // page. Anything still wrong after the last pass is reported by
+        // `assemble_item`.
+        for _ in 0..=self.program.len() {
+            let layout = self.layout_with_sizes(base, sizes);
+            sizes = layout.sizes.clone();
+            let mut changed = false;
+            for (index, item) in self.program.iter().enumerate() {
+                let size = match &item.data {
+                    Data::ZeroPageOrAbsolute { label, delta, .. } => {
+                        let in_zero_page = layout.label(*label).is_some_and(|offset| {
+                            offset.wrapping_add(base).wrapping_add_signed(*delta) < 0x100
+                        });
+                        if in_zero_page {
+                            2
+                        } else {
+                            3
+                        }
                     }
-                    buffer[offset as usize] = byte;
-                }
-                Data::LabelOffsetLe(label) => {
-                    if let Some(&label_offset) = self.labels.get(label) {
-                        if offset as usize + 1 >= size {
-                            return Err(Error::OffsetOutOfBounds);
+                    Data::Branch { label, .. } => {
+                        let out_of_range = layout.label(*label).is_some_and(|target| {
+                            let delta = target as i32 - layout.offsets[index] as i32 - 2;
+                            !(-128..=127).contains(&delta)
+                        });
+                        if out_of_range || sizes[index] == 5 {
+                            5
+                        } else {
+                            2
                         }
-                        let address = label_offset + base;
-                        buffer[offset as usize] = address::lo(address);
-                        buffer[offset as usize + 1] = address::hi(address);
-                    } else {
-                        return Err(Error::UndeclaredLabel(label.clone()));
                     }
+                    _ => continue,
+                };
+                if size != sizes[index] {
+                    sizes[index] = size;
+                    changed = true;
                 }
-                Data::LiteralOffsetLe(literal_offset) => {
-                    if offset as usize + 1 >= size {
-                        return Err(Error::OffsetOutOfBounds);
+            }
+            if !changed {
+                return layout;
+            }
+        }
+        self.layout_with_sizes(base, sizes)
+    }
+    fn check_overlap(&self, layout: &Layout, errors: &mut Vec<Error>) {
+        let mut items = layout
+            .offsets
+            .iter()
+            .zip(layout.sizes.iter())
+            .enumerate()
+            .map(|(index, (&offset, &num_bytes))| (offset as u32, num_bytes as u32, index))
+            .collect::<Vec<_>>();
+        items.sort_unstable();
+        let mut furthest: Option<(u32, usize)> = None;
+        for (offset, num_bytes, index) in items {
+            if let Some((end, owner)) = furthest {
+                if offset < end {
+                    let error = Error::Overlap {
+                        offset: offset as Address,
+                        first: owner.min(index),
+                        second: owner.max(index),
+                    };
+                    errors.push(self.locate(layout, owner.max(index), error));
+                }
+            }
+            if furthest.is_none_or(|(end, _)| offset + num_bytes > end) {
+                furthest = Some((offset + num_bytes, index));
+            }
+        }
+    }
+    fn item_bytes(&self, layout: &Layout, base: Address, index: usize) -> Result<ItemBytes, Error> {
+        let data = &self.program[index].data;
+        let offset = layout.offsets[index];
+        let name = |label: Symbol| String::from(self.symbols.resolve(label));
+        let label_offset = |label: Symbol| {
+            layout
+                .label(label)
+                .ok_or_else(|| Error::UndeclaredLabel(name(label)))
+        };
+        let label_address = |label: Symbol| Ok(label_offset(label)?.wrapping_add(base));
+        let bytes = match *data {
+            Data::LiteralByte(byte) => ItemBytes::new(&[byte]),
+            Data::LabelOffsetLe(label, delta) => {
+                let address = label_address(label)?.wrapping_add_signed(delta);
+                ItemBytes::new(&[address::lo(address), address::hi(address)])
+            }
+            Data::LiteralOffsetLe(literal_offset) => {
+                let address = literal_offset.wrapping_add(base);
+                ItemBytes::new(&[address::lo(address), address::hi(address)])
+            }
+            Data::LiteralAddressLe(address) => {
+                ItemBytes::new(&[address::lo(address), address::hi(address)])
+            }
+            Data::LabelOffsetLo(label, delta) => {
+                let address = label_address(label)?.wrapping_add_signed(delta);
+                ItemBytes::new(&[address::lo(address)])
+            }
+            Data::LabelOffsetHi(label, delta) => {
+                let address = label_address(label)?.wrapping_add_signed(delta);
+                ItemBytes::new(&[address::hi(address)])
+            }
+            Data::ZeroPageOrAbsolute {
+                zero_page,
+                absolute,
+                label,
+                delta,
+            } => {
+                let address = label_address(label)?.wrapping_add_signed(delta);
+                if layout.sizes[index] == 2 {
+                    // Only reachable if the address wrapped around $FFFF
+                    // after it was found to be in the zero page.
+                    if address >= 0x100 {
+                        return Err(Error::ZeroPageOutOfRange(name(label)));
                     }
-                    let address = literal_offset + base;
-                    buffer[offset as usize] = address::lo(address);
-                    buffer[offset as usize + 1] = address::hi(address);
+                    ItemBytes::new(&[zero_page, address as u8])
+                } else {
+                    ItemBytes::new(&[absolute, address::lo(address), address::hi(address)])
                 }
-                &Data::LiteralAddressLe(address) => {
-                    buffer[offset as usize] = address::lo(address);
-                    buffer[offset as usize + 1] = address::hi(address);
+            }
+            Data::Branch { opcode, label } => {
+                if layout.sizes[index] == 5 {
+                    let address = label_address(label)?;
+                    let jmp = assembler_instruction::Jmp::<addressing_mode::Absolute>::opcode();
+                    return Ok(ItemBytes::new(&[
+                        opcode ^ 0x20,
+                        3,
+                        jmp,
+                        address::lo(address),
+                        address::hi(address),
+                    ]));
                 }
-                Data::LabelOffsetLo(label) => {
-                    if let Some(&label_offset) = self.labels.get(label) {
-                        if offset as usize + 1 >= size {
-                            return Err(Error::OffsetOutOfBounds);
-                        }
-                        let address = label_offset + base;
-                        buffer[offset as usize] = address::lo(address);
-                    } else {
-                        return Err(Error::UndeclaredLabel(label.clone()));
-                    }
+                let delta = label_offset(label)? as i32 - offset as i32 - 2;
+                if !(-128..=127).contains(&delta) {
+                    return Err(Error::BranchTargetOutOfRange(name(label)));
                 }
-                Data::LabelOffsetHi(label) => {
-                    if let Some(&label_offset) = self.labels.get(label) {
-                        if offset as usize + 1 >= size {
-                            return Err(Error::OffsetOutOfBounds);
-                        }
-                        let address = label_offset + base;
-                        buffer[offset as usize] = address::hi(address);
-                    } else {
-                        return Err(Error::UndeclaredLabel(label.clone()));
-                    }
+                ItemBytes::new(&[opcode, (delta as i8) as u8])
+            }
+            Data::LabelRelativeOffset(label) => {
+                let delta = label_offset(label)? as i32 - offset as i32 - 1;
+                if !(-128..=127).contains(&delta) {
+                    return Err(Error::BranchTargetOutOfRange(name(label)));
                 }
-                Data::LabelRelativeOffset(label) => {
-                    if let Some(&label_offset) = self.labels.get(label) {
-                        let delta = label_offset as i16 - offset as i16 - 1;
-                        if delta < -128 || delta > 127 {
-                            return Err(Error::BranchTargetOutOfRange(label.clone()));
-                        }
-                        buffer[offset as usize] = (delta as i8) as u8;
-                    } else {
-                        return Err(Error::UndeclaredLabel(label.clone()));
-                    }
+                ItemBytes::new(&[(delta as i8) as u8])
+            }
+            Data::Checksum { kind, .. } => ItemBytes::new(&[0; 2][..kind.num_bytes() as usize]),
+        };
+        Ok(bytes)
+    }
+    fn assemble_item(
+        &self,
+        layout: &Layout,
+        base: Address,
+        buffer: &mut [u8],
+        index: usize,
+    ) -> Result<(), Error> {
+        let bytes = self.item_bytes(layout, base, index)?;
+        write_bytes(
+            buffer,
+            layout.offsets[index],
+            bytes.as_slice(),
+            self.program[index]
+                .data
+                .label()
+                .map(|label| self.symbols.resolve(label)),
+        )
+    }
+    pub fn assemble(
+        &self,
+        base: Address,
+        size: usize,
+        buffer: &mut Vec<u8>,
+    ) -> Result<AssembledBlock, Error> {
+        self.assemble_all_errors(base, size, buffer)
+            .map_err(|mut errors| errors.swap_remove(0))
+    }
+    /// Like `assemble`, but keeps going after an error so that every
+    /// problem in the program is reported at once.
+    pub fn assemble_all_errors(
+        &self,
+        base: Address,
+        size: usize,
+        buffer: &mut Vec<u8>,
+    ) -> Result<AssembledBlock, Vec<Error>> {
+        let mut errors = self.errors.clone();
+        let layout = self.layout(base);
+        self.check_overlap(&layout, &mut errors);
+        buffer.resize(size, 0);
+        self.assemble_with_layout(&layout, base, buffer, errors)
+    }
+    /// Assembles into `buffer`, which is the whole output: anything placed
+    /// past its end is an `OffsetOutOfBounds` error. Bytes not covered by
+    /// the program are left as they were.
+    pub fn assemble_into(&self, base: Address, buffer: &mut [u8]) -> Result<AssembledBlock, Error> {
+        let mut errors = self.errors.clone();
+        let layout = self.layout(base);
+        self.check_overlap(&layout, &mut errors);
+        self.assemble_with_layout(&layout, base, buffer, errors)
+            .map_err(|mut errors| errors.swap_remove(0))
+    }
+    /// The assembled image as `(address, byte)` pairs, in program order,
+    /// without building the image itself. Every item is checked up front, so
+    /// the iterator itself can't fail. If there are any checksums, the whole
+    /// image is assembled to compute them, with gaps counted as zeroes.
+    pub fn assembled_bytes(
+        &self,
+        base: Address,
+    ) -> Result<impl Iterator<Item = (Address, u8)> + '_, Vec<Error>> {
+        let mut errors = self.errors.clone();
+        let layout = self.layout(base);
+        self.check_overlap(&layout, &mut errors);
+        for index in 0..self.program.len() {
+            if let Err(error) = self.item_bytes(&layout, base, index) {
+                errors.push(self.locate(&layout, index, error));
+            }
+        }
+        let has_checksum = self
+            .program
+            .iter()
+            .any(|item| matches!(item.data, Data::Checksum { .. }));
+        let image = if has_checksum && errors.is_empty() {
+            let mut image = alloc::vec![0; 0x10000];
+            if let Err(image_errors) =
+                self.assemble_with_layout(&layout, base, &mut image, Vec::new())
+            {
+                errors.extend(image_errors);
+            }
+            Some(image)
+        } else {
+            None
+        };
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok((0..self.program.len()).flat_map(move |index| {
+            let address = layout.offsets[index].wrapping_add(base);
+            let bytes = match (&self.program[index].data, &image) {
+                (Data::Checksum { kind, .. }, Some(image)) => {
+                    let start = layout.offsets[index] as usize;
+                    Some(ItemBytes::new(
+                        &image[start..start + kind.num_bytes() as usize],
+                    ))
                 }
+                _ => self.item_bytes(&layout, base, index).ok(),
+            };
+            bytes.into_iter().flat_map(move |bytes| {
+                (0..bytes.len).map(move |i| (address.wrapping_add(i as Address), bytes.bytes[i]))
+            })
+        }))
+    }
+    fn assemble_with_layout(
+        &self,
+        layout: &Layout,
+        base: Address,
+        buffer: &mut [u8],
+        mut errors: Vec<Error>,
+    ) -> Result<AssembledBlock, Vec<Error>> {
+        for index in 0..self.program.len() {
+            if let Err(error) = self.assemble_item(layout, base, buffer, index) {
+                errors.push(self.locate(layout, index, error));
+            }
+        }
+        self.fill_checksums(layout, base, buffer, &mut errors);
+        if errors.is_empty() {
+            let labels = self
+                .labels
+                .iter()
+                .filter_map(|(name, label)| {
+                    let address = layout.label(label.symbol)?.wrapping_add(base);
+                    Some((name.clone(), address))
+                })
+                .collect();
+            let end = layout
+                .offsets
+                .iter()
+                .zip(layout.sizes.iter())
+                .map(|(&offset, &size)| offset as usize + size as usize)
+                .max()
+                .unwrap_or(0);
+            let len_bytes = layout.sizes.iter().map(|&size| size as usize).sum();
+            let mut source_map = source_map::SourceMap::new(self.source_hints.clone());
+            for (index, item) in self.program.iter().enumerate() {
+                source_map.push(
+                    layout.offsets[index].wrapping_add(base),
+                    layout.sizes[index],
+                    index,
+                    item.source_hint,
+                    item.instruction,
+                );
             }
+            source_map.sort();
+            Ok(AssembledBlock {
+                base,
+                labels,
+                end,
+                len_bytes,
+                source_map,
+            })
+        } else {
+            Err(errors)
         }
-        Ok(AssembledBlock { labels })
     }
 }
 
+// The zero-page form of an absolute or absolute indexed opcode, if the
+// instruction has one.
+fn zero_page_opcode(absolute: u8) -> Option<u8> {
+    use debug::{AddressingMode, Instruction};
+    let instruction = Instruction::from_opcode(absolute).ok()?;
+    let addressing_mode = match instruction.addressing_mode() {
+        AddressingMode::Absolute => AddressingMode::ZeroPage,
+        AddressingMode::AbsoluteXIndexed => AddressingMode::ZeroPageXIndexed,
+        AddressingMode::AbsoluteYIndexed => AddressingMode::ZeroPageYIndexed,
+        _ => return None,
+    };
+    (0..=u8::MAX).find(|&opcode| {
+        Instruction::from_opcode(opcode).is_ok_and(|candidate| {
+            candidate.instruction_type() == instruction.instruction_type()
+                && candidate.addressing_mode() == addressing_mode
+        })
+    })
+}
+
+// The encoding of a single program item, which is at most 5 bytes long.
+struct ItemBytes {
+    bytes: [u8; 5],
+    len: usize,
+}
+
+impl ItemBytes {
+    fn new(bytes: &[u8]) -> Self {
+        let mut item = Self {
+            bytes: [0; 5],
+            len: bytes.len(),
+        };
+        item.bytes[..bytes.len()].copy_from_slice(bytes);
+        item
+    }
+    fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+// Every write into the output buffer goes through here so that all data
+// variants share the same bounds check.
+fn write_bytes(
+    buffer: &mut [u8],
+    offset: Address,
+    bytes: &[u8],
+    label: Option<&str>,
+) -> Result<(), Error> {
+    let start = offset as usize;
+    match buffer.get_mut(start..start + bytes.len()) {
+        Some(destination) => {
+            destination.copy_from_slice(bytes);
+            Ok(())
+        }
+        None => Err(Error::OffsetOutOfBounds {
+            offset,
+            label: label.map(String::from),
+        }),
+    }
+}
+
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct AssembledBlock {
+    base: Address,
     labels: BTreeMap<String, Address>,
+    end: usize,
+    len_bytes: usize,
+    source_map: source_map::SourceMap,
 }
 
 impl AssembledBlock {
     pub fn address_of_label(&self, label: &str) -> Option<Address> {
         self.labels.get(label).cloned()
     }
+    pub fn base(&self) -> Address {
+        self.base
+    }
+    /// One past the highest offset written to, or 0 if nothing was.
+    pub fn end(&self) -> usize {
+        self.end
+    }
+    pub fn highest_offset(&self) -> Option<Address> {
+        self.end.checked_sub(1).map(|offset| offset as Address)
+    }
+    /// Number of bytes the program wrote, not counting any gaps.
+    pub fn len_bytes(&self) -> usize {
+        self.len_bytes
+    }
+    /// Every label and its address, in name order.
+    pub fn labels(&self) -> impl Iterator<Item = (&str, Address)> {
+        self.labels
+            .iter()
+            .map(|(label, &address)| (label.as_str(), address))
+    }
+    /// The labels as bookmarks, for the model's disassembler and
+    /// profiler. Where labels share an address, the last by name is kept.
+    pub fn annotations(&self) -> annotation::Annotations {
+        let mut annotations = annotation::Annotations::new();
+        for (label, address) in self.labels() {
+            annotations.set_bookmark(address, label);
+        }
+        annotations
+    }
+    /// Which program item each byte came from.
+    pub fn source_map(&self) -> &source_map::SourceMap {
+        &self.source_map
+    }
+    /// Offset of a label within the buffer the block was assembled into.
+    pub fn offset_of_label(&self, label: &str) -> Option<Address> {
+        Some(self.address_of_label(label)?.wrapping_sub(self.base))
+    }
+    fn patch(&self, buffer: &mut [u8], label: &str, bytes: &[u8]) -> Result<(), Error> {
+        let offset = self
+            .offset_of_label(label)
+            .ok_or_else(|| Error::UndeclaredLabel(label.into()))?;
+        write_bytes(buffer, offset, bytes, Some(label))
+    }
+    /// Overwrites the byte at `label` in an image produced by assembling
+    /// this block, e.g. to build variants of a ROM with different settings.
+    pub fn patch_byte_at_label(
+        &self,
+        buffer: &mut [u8],
+        label: &str,
+        value: u8,
+    ) -> Result<(), Error> {
+        self.patch(buffer, label, &[value])
+    }
+    /// Overwrites the little-endian word at `label`.
+    pub fn patch_word(&self, buffer: &mut [u8], label: &str, value: u16) -> Result<(), Error> {
+        self.patch(buffer, label, &[address::lo(value), address::hi(value)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn out_of_bounds(result: Result<(), Error>) -> (Address, Option<String>) {
+        match result {
+            Err(Error::OffsetOutOfBounds { offset, label }) => (offset, label),
+            other => panic!("expected OffsetOutOfBounds, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn write_bytes_in_bounds() {
+        let mut buffer = [0; 4];
+        write_bytes(&mut buffer, 1, &[1, 2], None).unwrap();
+        assert_eq!(buffer, [0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn write_bytes_ending_at_end() {
+        let mut buffer = [0; 4];
+        write_bytes(&mut buffer, 2, &[1, 2], None).unwrap();
+        assert_eq!(buffer, [0, 0, 1, 2]);
+        write_bytes(&mut buffer, 4, &[], None).unwrap();
+    }
+
+    #[test]
+    fn write_bytes_one_past_end() {
+        let mut buffer = [0; 4];
+        let result = write_bytes(&mut buffer, 3, &[1, 2], Some("word"));
+        assert_eq!(out_of_bounds(result), (3, Some("word".into())));
+        assert_eq!(buffer, [0; 4]);
+        let result = write_bytes(&mut buffer, 4, &[1], None);
+        assert_eq!(out_of_bounds(result), (4, None));
+    }
+
+    #[test]
+    fn write_bytes_past_address_space() {
+        let mut buffer = alloc::vec![0; 0x10000];
+        let result = write_bytes(&mut buffer, 0xFFFF, &[1, 2, 3], None);
+        assert_eq!(out_of_bounds(result), (0xFFFF, None));
+        assert!(buffer.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn assemble_into_reports_overrun() {
+        let mut block = Block::new();
+        block.literal_byte(0xEA);
+        block.label("word");
+        block.literal_address_le(0x1234);
+        let mut buffer = [0; 3];
+        block.assemble_into(0x8000, &mut buffer).unwrap();
+        assert_eq!(buffer, [0xEA, 0x34, 0x12]);
+        let mut buffer = [0; 2];
+        match block.assemble_into(0x8000, &mut buffer) {
+            Err(Error::Located { location, error }) => {
+                assert_eq!(location.index, 1);
+                assert_eq!(out_of_bounds(Err(*error)), (1, None));
+            }
+            other => panic!("expected a located error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn checksum_reaches_end_of_address_space() {
+        let mut block = Block::new();
+        block.checksum_here(0xFFFD..=0xFFFF, checksum::Checksum::Sum8);
+        block.literal_byte(2);
+        block.literal_byte(3);
+        block.literal_byte(4);
+        let mut buffer = [0; 4];
+        block.assemble_into(0xFFFC, &mut buffer).unwrap();
+        assert_eq!(buffer, [9, 2, 3, 4]);
+    }
 }
@@ -0,0 +1,50 @@
+use crate::{AssembledBlock, Block, Error};
+use alloc::vec::Vec;
+use portal_solutions_mos6502_model::{
+    machine::{Machine, Memory},
+    Address,
+};
+
+impl AssembledBlock {
+    /// Loads `image`, as produced by assembling this block, into the memory
+    /// of `machine` at the base address, and sets the program counter to
+    /// `entry` if there is one. Only the part of `image` the program wrote
+    /// up to is copied.
+    pub fn load_into_machine<M: Memory>(
+        &self,
+        image: &[u8],
+        machine: &mut Machine<M>,
+        entry: Option<&str>,
+    ) -> Result<(), Error> {
+        let pc = entry
+            .map(|label| {
+                self.address_of_label(label)
+                    .ok_or_else(|| Error::UndeclaredLabel(label.into()))
+            })
+            .transpose()?;
+        machine
+            .memory
+            .load(self.base, &image[..self.end.min(image.len())]);
+        if let Some(pc) = pc {
+            machine.cpu.pc = pc;
+        }
+        Ok(())
+    }
+}
+
+impl Block {
+    /// Assembles at `base` and loads the result into `machine` with
+    /// `AssembledBlock::load_into_machine`.
+    pub fn assemble_into_machine<M: Memory>(
+        &self,
+        base: Address,
+        machine: &mut Machine<M>,
+        entry: Option<&str>,
+    ) -> Result<AssembledBlock, Error> {
+        let mut image = Vec::new();
+        let size = 0x10000 - base as usize;
+        let assembled = self.assemble(base, size, &mut image)?;
+        assembled.load_into_machine(&image, machine, entry)?;
+        Ok(assembled)
+    }
+}
@@ -0,0 +1,208 @@
+//! One method per official instruction and addressing mode, so that
+//! `b.inst(Lda(Immediate), 0x10)` can be written `b.lda_imm(0x10)`.
+//! Methods are named after the mnemonic with a suffix for the addressing
+//! mode: `imm`, `zp`, `zp_x`, `zp_y`, `abs`, `abs_x`, `abs_y`, `ind`,
+//! `ind_x` for `(zp,X)` and `ind_y` for `(zp),Y`. Implied instructions have
+//! no suffix and take no argument, accumulator forms end in `acc`, and
+//! branches take the name of their target label.
+use crate::{ArgOperand, Block, LabelRelativeOffsetOwned};
+use portal_solutions_mos6502_model::{addressing_mode::*, assembler_instruction::*};
+
+macro_rules! operand_methods {
+    ($($method:ident: $inst:ident($mode:ident),)*) => {
+        impl Block {
+            $(
+                pub fn $method<A>(&mut self, arg: A)
+                where
+                    A: ArgOperand<Operand = <$mode as addressing_mode::Trait>::Operand>,
+                {
+                    self.inst($inst($mode), arg);
+                }
+            )*
+        }
+    };
+}
+
+macro_rules! implied_methods {
+    ($($method:ident: $inst:expr,)*) => {
+        impl Block {
+            $(
+                pub fn $method(&mut self) {
+                    self.inst($inst, ());
+                }
+            )*
+        }
+    };
+}
+
+macro_rules! branch_methods {
+    ($($method:ident: $inst:ident,)*) => {
+        impl Block {
+            $(
+                pub fn $method<S: AsRef<str>>(&mut self, label: S) {
+                    self.inst($inst, LabelRelativeOffsetOwned(label.as_ref().into()));
+                }
+            )*
+        }
+    };
+}
+
+operand_methods! {
+    adc_abs: Adc(Absolute),
+    adc_abs_x: Adc(AbsoluteXIndexed),
+    adc_abs_y: Adc(AbsoluteYIndexed),
+    adc_imm: Adc(Immediate),
+    adc_ind_y: Adc(IndirectYIndexed),
+    adc_ind_x: Adc(XIndexedIndirect),
+    adc_zp: Adc(ZeroPage),
+    adc_zp_x: Adc(ZeroPageXIndexed),
+    and_abs: And(Absolute),
+    and_abs_x: And(AbsoluteXIndexed),
+    and_abs_y: And(AbsoluteYIndexed),
+    and_imm: And(Immediate),
+    and_ind_y: And(IndirectYIndexed),
+    and_ind_x: And(XIndexedIndirect),
+    and_zp: And(ZeroPage),
+    and_zp_x: And(ZeroPageXIndexed),
+    asl_abs: Asl(Absolute),
+    asl_abs_x: Asl(AbsoluteXIndexed),
+    asl_zp: Asl(ZeroPage),
+    asl_zp_x: Asl(ZeroPageXIndexed),
+    bit_abs: Bit(Absolute),
+    bit_zp: Bit(ZeroPage),
+    cmp_abs: Cmp(Absolute),
+    cmp_abs_x: Cmp(AbsoluteXIndexed),
+    cmp_abs_y: Cmp(AbsoluteYIndexed),
+    cmp_imm: Cmp(Immediate),
+    cmp_ind_y: Cmp(IndirectYIndexed),
+    cmp_ind_x: Cmp(XIndexedIndirect),
+    cmp_zp: Cmp(ZeroPage),
+    cmp_zp_x: Cmp(ZeroPageXIndexed),
+    dec_abs: Dec(Absolute),
+    dec_abs_x: Dec(AbsoluteXIndexed),
+    dec_zp: Dec(ZeroPage),
+    dec_zp_x: Dec(ZeroPageXIndexed),
+    cpx_abs: Cpx(Absolute),
+    cpx_imm: Cpx(Immediate),
+    cpx_zp: Cpx(ZeroPage),
+    cpy_abs: Cpy(Absolute),
+    cpy_imm: Cpy(Immediate),
+    cpy_zp: Cpy(ZeroPage),
+    eor_abs: Eor(Absolute),
+    eor_abs_x: Eor(AbsoluteXIndexed),
+    eor_abs_y: Eor(AbsoluteYIndexed),
+    eor_imm: Eor(Immediate),
+    eor_ind_y: Eor(IndirectYIndexed),
+    eor_ind_x: Eor(XIndexedIndirect),
+    eor_zp: Eor(ZeroPage),
+    eor_zp_x: Eor(ZeroPageXIndexed),
+    inc_abs: Inc(Absolute),
+    inc_abs_x: Inc(AbsoluteXIndexed),
+    inc_zp: Inc(ZeroPage),
+    inc_zp_x: Inc(ZeroPageXIndexed),
+    jmp_abs: Jmp(Absolute),
+    jmp_ind: Jmp(Indirect),
+    jsr_abs: Jsr(Absolute),
+    lda_abs: Lda(Absolute),
+    lda_abs_x: Lda(AbsoluteXIndexed),
+    lda_abs_y: Lda(AbsoluteYIndexed),
+    lda_imm: Lda(Immediate),
+    lda_ind_y: Lda(IndirectYIndexed),
+    lda_ind_x: Lda(XIndexedIndirect),
+    lda_zp: Lda(ZeroPage),
+    lda_zp_x: Lda(ZeroPageXIndexed),
+    ldx_abs: Ldx(Absolute),
+    ldx_abs_y: Ldx(AbsoluteYIndexed),
+    ldx_imm: Ldx(Immediate),
+    ldx_zp: Ldx(ZeroPage),
+    ldx_zp_y: Ldx(ZeroPageYIndexed),
+    ldy_abs: Ldy(Absolute),
+    ldy_abs_x: Ldy(AbsoluteXIndexed),
+    ldy_imm: Ldy(Immediate),
+    ldy_zp: Ldy(ZeroPage),
+    ldy_zp_x: Ldy(ZeroPageXIndexed),
+    lsr_abs: Lsr(Absolute),
+    lsr_abs_x: Lsr(AbsoluteXIndexed),
+    lsr_zp: Lsr(ZeroPage),
+    lsr_zp_x: Lsr(ZeroPageXIndexed),
+    ora_abs: Ora(Absolute),
+    ora_abs_x: Ora(AbsoluteXIndexed),
+    ora_abs_y: Ora(AbsoluteYIndexed),
+    ora_imm: Ora(Immediate),
+    ora_ind_y: Ora(IndirectYIndexed),
+    ora_ind_x: Ora(XIndexedIndirect),
+    ora_zp: Ora(ZeroPage),
+    ora_zp_x: Ora(ZeroPageXIndexed),
+    rol_abs: Rol(Absolute),
+    rol_abs_x: Rol(AbsoluteXIndexed),
+    rol_zp: Rol(ZeroPage),
+    rol_zp_x: Rol(ZeroPageXIndexed),
+    ror_abs: Ror(Absolute),
+    ror_abs_x: Ror(AbsoluteXIndexed),
+    ror_zp: Ror(ZeroPage),
+    ror_zp_x: Ror(ZeroPageXIndexed),
+    sbc_abs: Sbc(Absolute),
+    sbc_abs_x: Sbc(AbsoluteXIndexed),
+    sbc_abs_y: Sbc(AbsoluteYIndexed),
+    sbc_imm: Sbc(Immediate),
+    sbc_ind_y: Sbc(IndirectYIndexed),
+    sbc_ind_x: Sbc(XIndexedIndirect),
+    sbc_zp: Sbc(ZeroPage),
+    sbc_zp_x: Sbc(ZeroPageXIndexed),
+    sta_abs: Sta(Absolute),
+    sta_abs_x: Sta(AbsoluteXIndexed),
+    sta_abs_y: Sta(AbsoluteYIndexed),
+    sta_ind_y: Sta(IndirectYIndexed),
+    sta_ind_x: Sta(XIndexedIndirect),
+    sta_zp: Sta(ZeroPage),
+    sta_zp_x: Sta(ZeroPageXIndexed),
+    stx_abs: Stx(Absolute),
+    stx_zp: Stx(ZeroPage),
+    stx_zp_y: Stx(ZeroPageYIndexed),
+    sty_abs: Sty(Absolute),
+    sty_zp: Sty(ZeroPage),
+    sty_zp_x: Sty(ZeroPageXIndexed),
+}
+
+implied_methods! {
+    asl_acc: Asl(Accumulator),
+    brk: Brk,
+    clc: Clc,
+    cld: Cld,
+    cli: Cli,
+    clv: Clv,
+    dex: Dex,
+    dey: Dey,
+    inx: Inx,
+    iny: Iny,
+    lsr_acc: Lsr(Accumulator),
+    nop: Nop,
+    pha: Pha,
+    php: Php,
+    pla: Pla,
+    plp: Plp,
+    rol_acc: Rol(Accumulator),
+    ror_acc: Ror(Accumulator),
+    rti: Rti,
+    rts: Rts,
+    sec: Sec,
+    sed: Sed,
+    sei: Sei,
+    tax: Tax,
+    tay: Tay,
+    tsx: Tsx,
+    txa: Txa,
+    txs: Txs,
+    tya: Tya,
+}
+
+branch_methods! {
+    bcc: Bcc,
+    bcs: Bcs,
+    beq: Beq,
+    bmi: Bmi,
+    bne: Bne,
+    bpl: Bpl,
+    bvc: Bvc,
+    bvs: Bvs,
+}
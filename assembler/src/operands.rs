@@ -0,0 +1,69 @@
+//! Operands that name the addressing mode they are meant for. With
+//! `Block::op` the pairing is checked at compile time, so e.g.
+//! `b.op(Lda(Immediate), Zp(0x10))` doesn't build. They can also be passed
+//! to `inst`, where only the operand size is checked.
+use crate::{ArgOperand, Block};
+use portal_solutions_mos6502_model::{
+    addressing_mode::{self, *},
+    operand, Address, AssemblerInstruction,
+};
+
+pub struct Imm(pub u8);
+pub struct Zp(pub u8);
+pub struct AbsAddr(pub Address);
+pub struct Rel<'a>(pub &'a str);
+
+/// Implemented by operands for each addressing mode they suit.
+pub trait OperandFor<M: addressing_mode::Trait>: ArgOperand<Operand = M::Operand> {}
+
+impl ArgOperand for Imm {
+    type Operand = operand::Byte;
+    fn program(self, block: &mut Block) {
+        block.literal_byte(self.0);
+    }
+}
+
+impl ArgOperand for Zp {
+    type Operand = operand::Byte;
+    fn program(self, block: &mut Block) {
+        block.literal_byte(self.0);
+    }
+}
+
+impl ArgOperand for AbsAddr {
+    type Operand = operand::Address;
+    fn program(self, block: &mut Block) {
+        block.literal_address_le(self.0);
+    }
+}
+
+impl ArgOperand for Rel<'_> {
+    type Operand = operand::Byte;
+    fn program(self, block: &mut Block) {
+        block.label_relative_offset(self.0);
+    }
+}
+
+impl OperandFor<Immediate> for Imm {}
+impl OperandFor<ZeroPage> for Zp {}
+impl OperandFor<ZeroPageXIndexed> for Zp {}
+impl OperandFor<ZeroPageYIndexed> for Zp {}
+impl OperandFor<XIndexedIndirect> for Zp {}
+impl OperandFor<IndirectYIndexed> for Zp {}
+impl OperandFor<Absolute> for AbsAddr {}
+impl OperandFor<AbsoluteXIndexed> for AbsAddr {}
+impl OperandFor<AbsoluteYIndexed> for AbsAddr {}
+impl OperandFor<Indirect> for AbsAddr {}
+impl OperandFor<Relative> for Rel<'_> {}
+
+impl Block {
+    /// Like `inst`, but only accepts operands meant for the instruction's
+    /// addressing mode.
+    pub fn op<I: AssemblerInstruction, A: OperandFor<I::AddressingMode>>(
+        &mut self,
+        instruction: I,
+        arg: A,
+    ) {
+        self.inst(instruction, arg);
+    }
+}
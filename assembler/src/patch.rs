@@ -0,0 +1,486 @@
+//! Patches between two assembled images, for distributing changes to a ROM
+//! without the ROM itself. IPS is understood by nearly every patching tool.
+//! BPS also checks that it's applied to the right image, and isn't limited
+//! to 16MiB.
+//!
+//! ```ignore
+//! let mut original = Vec::new();
+//! let mut hacked = Vec::new();
+//! original_block.assemble(0x8000, 0x8000, &mut original)?;
+//! hacked_block.assemble(0x8000, 0x8000, &mut hacked)?;
+//! let ips = patch::ips(&original, &hacked)?;
+//! assert_eq!(patch::apply_ips(&original, &ips)?, hacked);
+//! std::fs::write("hack.ips", ips)?;
+//! ```
+use alloc::vec::Vec;
+use core::fmt;
+
+const IPS_HEADER: &[u8] = b"PATCH";
+const IPS_FOOTER: &[u8] = b"EOF";
+// A record at this offset would read as the footer.
+const IPS_FOOTER_OFFSET: usize = 0x454F46;
+// Offsets and the truncated size are 24-bit.
+const IPS_MAX_SIZE: usize = 0xFF_FFFF;
+const IPS_MAX_RECORD: usize = 0xFFFF;
+const IPS_RECORD_HEADER: usize = 5;
+// A run of this many identical bytes is worth its own RLE record, even in
+// the middle of a normal record, which costs two headers and a run.
+const IPS_MIN_RUN: usize = 14;
+const BPS_HEADER: &[u8] = b"BPS1";
+const BPS_FOOTER_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The patch doesn't start with `PATCH`, or `BPS1` for BPS.
+    BadHeader,
+    Truncated,
+    /// IPS offsets are 24-bit, so it can't patch past the first 16MiB.
+    TooLarge,
+    /// A BPS action reads or writes past the end of an image.
+    OutOfRange,
+    /// The image a BPS patch is applied to isn't the one it was made from.
+    SourceMismatch,
+    /// The patched image isn't what the BPS patch says it should be.
+    TargetMismatch,
+    /// The BPS patch itself is corrupt.
+    PatchMismatch,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::BadHeader => write!(f, "not a patch"),
+            Error::Truncated => write!(f, "patch is truncated"),
+            Error::TooLarge => write!(f, "image is too large for IPS"),
+            Error::OutOfRange => write!(f, "patch action is out of range"),
+            Error::SourceMismatch => write!(f, "patch is for a different image"),
+            Error::TargetMismatch => write!(f, "patched image has the wrong checksum"),
+            Error::PatchMismatch => write!(f, "patch has the wrong checksum"),
+        }
+    }
+}
+
+// Reads through a patch, failing with `Truncated` at the end.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() < len {
+            return Err(Error::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+    fn be(&mut self, len: usize) -> Result<usize, Error> {
+        Ok(self
+            .take(len)?
+            .iter()
+            .fold(0, |value, &byte| value << 8 | byte as usize))
+    }
+    fn varint(&mut self) -> Result<usize, Error> {
+        let mut value = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let byte = self.take(1)?[0];
+            value = ((byte & 0x7F) as usize)
+                .checked_mul(shift)
+                .and_then(|digit| value.checked_add(digit))
+                .ok_or(Error::OutOfRange)?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            // `checked_shl` only fails for shifts past the width, not when
+            // bits are shifted out.
+            shift = shift.checked_mul(0x80).ok_or(Error::OutOfRange)?;
+            value = value.checked_add(shift).ok_or(Error::OutOfRange)?;
+        }
+    }
+}
+
+fn push_be(out: &mut Vec<u8>, value: usize, len: usize) {
+    out.extend((0..len).rev().map(|i| (value >> (i * 8)) as u8));
+}
+
+// Whether `target[offset..]` starts with a run worth an RLE record.
+fn is_run(target: &[u8], offset: usize, end: usize) -> bool {
+    end - offset >= IPS_MIN_RUN
+        && target[offset..offset + IPS_MIN_RUN]
+            .iter()
+            .all(|&byte| byte == target[offset])
+}
+
+// Writes the records for `target[start..end]`, using RLE for long runs.
+fn ips_records(out: &mut Vec<u8>, target: &[u8], mut start: usize, end: usize) {
+    while start < end {
+        if is_run(target, start, end) && start != IPS_FOOTER_OFFSET {
+            let value = target[start];
+            let len = target[start..end]
+                .iter()
+                .take(IPS_MAX_RECORD)
+                .take_while(|&&byte| byte == value)
+                .count();
+            push_be(out, start, 3);
+            push_be(out, 0, 2);
+            push_be(out, len, 2);
+            out.push(value);
+            start += len;
+            continue;
+        }
+        // Starting a byte early rewrites one which is already right.
+        let offset = if start == IPS_FOOTER_OFFSET {
+            start - 1
+        } else {
+            start
+        };
+        let mut stop = start + 1;
+        while stop < end && stop - offset < IPS_MAX_RECORD && !is_run(target, stop, end) {
+            stop += 1;
+        }
+        push_be(out, offset, 3);
+        push_be(out, stop - offset, 2);
+        out.extend_from_slice(&target[offset..stop]);
+        start = stop;
+    }
+}
+
+/// An IPS patch which turns `source` into `target`. Bytes past the end of
+/// `source` count as changed, and if `target` is shorter the patch ends
+/// with the common truncation extension.
+pub fn ips(source: &[u8], target: &[u8]) -> Result<Vec<u8>, Error> {
+    if target.len() > IPS_MAX_SIZE {
+        return Err(Error::TooLarge);
+    }
+    let differs = |offset: usize| source.get(offset) != Some(&target[offset]);
+    let mut out = IPS_HEADER.to_vec();
+    let mut offset = 0;
+    while offset < target.len() {
+        if !differs(offset) {
+            offset += 1;
+            continue;
+        }
+        // Changes closer together than a record header share a record.
+        let start = offset;
+        let mut end = start + 1;
+        while offset < target.len() && offset < end + IPS_RECORD_HEADER {
+            if differs(offset) {
+                end = offset + 1;
+            }
+            offset += 1;
+        }
+        ips_records(&mut out, target, start, end);
+        offset = end;
+    }
+    out.extend_from_slice(IPS_FOOTER);
+    if target.len() < source.len() {
+        push_be(&mut out, target.len(), 3);
+    }
+    Ok(out)
+}
+
+/// Applies an IPS patch to `source`. Records past the end of `source`
+/// extend it, with any gap filled with zeroes.
+pub fn apply_ips(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut reader = Reader { bytes: patch };
+    if reader.take(IPS_HEADER.len()).ok() != Some(IPS_HEADER) {
+        return Err(Error::BadHeader);
+    }
+    let mut out = source.to_vec();
+    loop {
+        let offset = reader.take(3)?;
+        if offset == IPS_FOOTER {
+            if let Ok(len) = reader.be(3) {
+                out.truncate(len);
+            }
+            return Ok(out);
+        }
+        let offset = offset
+            .iter()
+            .fold(0, |value, &byte| value << 8 | byte as usize);
+        let (len, data) = match reader.be(2)? {
+            0 => (reader.be(2)?, None),
+            len => (len, Some(reader.take(len)?)),
+        };
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+        match data {
+            Some(data) => out[offset..offset + len].copy_from_slice(data),
+            None => out[offset..offset + len].fill(reader.take(1)?[0]),
+        }
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |mut crc: u32, &byte| {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+        crc
+    })
+}
+
+fn push_varint(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let digit = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(0x80 | digit);
+            return;
+        }
+        out.push(digit);
+        value -= 1;
+    }
+}
+
+/// A BPS patch which turns `source` into `target`, with no metadata. It
+/// only ever reads from `source` at the same offset or from the patch, so
+/// it's no smaller than the bytes which changed.
+pub fn bps(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let same = |offset: usize| source.get(offset) == Some(&target[offset]);
+    let mut out = BPS_HEADER.to_vec();
+    push_varint(&mut out, source.len());
+    push_varint(&mut out, target.len());
+    push_varint(&mut out, 0);
+    let mut offset = 0;
+    while offset < target.len() {
+        let read_source = same(offset);
+        let start = offset;
+        while offset < target.len() && same(offset) == read_source {
+            offset += 1;
+        }
+        push_varint(&mut out, (offset - start - 1) << 2 | !read_source as usize);
+        if !read_source {
+            out.extend_from_slice(&target[start..offset]);
+        }
+    }
+    out.extend(crc32(source).to_le_bytes());
+    out.extend(crc32(target).to_le_bytes());
+    out.extend(crc32(&out).to_le_bytes());
+    out
+}
+
+// Moves a BPS copy offset by the signed delta which follows.
+fn bps_seek(reader: &mut Reader, offset: &mut usize) -> Result<(), Error> {
+    let delta = reader.varint()?;
+    *offset = if delta & 1 != 0 {
+        offset.checked_sub(delta >> 1)
+    } else {
+        offset.checked_add(delta >> 1)
+    }
+    .ok_or(Error::OutOfRange)?;
+    Ok(())
+}
+
+/// Applies a BPS patch to `source`, checking the checksums of the patch,
+/// `source` and the result.
+pub fn apply_bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, Error> {
+    if !patch.starts_with(BPS_HEADER) {
+        return Err(Error::BadHeader);
+    }
+    if patch.len() < BPS_HEADER.len() + BPS_FOOTER_SIZE {
+        return Err(Error::Truncated);
+    }
+    let (body, footer) = patch.split_at(patch.len() - BPS_FOOTER_SIZE);
+    let crc = |at: usize| u32::from_le_bytes(footer[at..at + 4].try_into().unwrap());
+    if crc32(&patch[..patch.len() - 4]) != crc(8) {
+        return Err(Error::PatchMismatch);
+    }
+    let mut reader = Reader {
+        bytes: &body[BPS_HEADER.len()..],
+    };
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    let metadata_size = reader.varint()?;
+    reader.take(metadata_size)?;
+    if source.len() != source_size || crc32(source) != crc(0) {
+        return Err(Error::SourceMismatch);
+    }
+    let mut out = Vec::new();
+    let (mut source_offset, mut target_offset) = (0, 0);
+    while !reader.bytes.is_empty() {
+        let action = reader.varint()?;
+        let len = (action >> 2) + 1;
+        if target_size - out.len() < len {
+            return Err(Error::OutOfRange);
+        }
+        match action & 3 {
+            0 => out.extend_from_slice(
+                source
+                    .get(out.len()..out.len() + len)
+                    .ok_or(Error::OutOfRange)?,
+            ),
+            1 => out.extend_from_slice(reader.take(len)?),
+            2 => {
+                bps_seek(&mut reader, &mut source_offset)?;
+                let end = source_offset.checked_add(len).ok_or(Error::OutOfRange)?;
+                out.extend_from_slice(source.get(source_offset..end).ok_or(Error::OutOfRange)?);
+                source_offset = end;
+            }
+            _ => {
+                // The copy may overlap what it's writing, to repeat a pattern.
+                bps_seek(&mut reader, &mut target_offset)?;
+                if target_offset >= out.len() {
+                    return Err(Error::OutOfRange);
+                }
+                for _ in 0..len {
+                    out.push(out[target_offset]);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    if out.len() != target_size || crc32(&out) != crc(4) {
+        return Err(Error::TargetMismatch);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(bytes: &[u8]) -> Result<usize, Error> {
+        Reader { bytes }.varint()
+    }
+
+    #[test]
+    fn varint_round_trips() {
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, 0x1234_5678, usize::MAX] {
+            let mut bytes = Vec::new();
+            push_varint(&mut bytes, value);
+            assert_eq!(varint(&bytes), Ok(value));
+        }
+    }
+
+    #[test]
+    fn varint_overflow_is_out_of_range() {
+        // Each continuation adds the next power of 128, which runs out of
+        // bits before any digit does.
+        let mut bytes = alloc::vec![0; 10];
+        bytes.push(0x80);
+        assert_eq!(varint(&bytes), Err(Error::OutOfRange));
+        let mut bytes = Vec::new();
+        push_varint(&mut bytes, usize::MAX);
+        let last = bytes.pop().unwrap();
+        bytes.extend([last & 0x7F, 0x80]);
+        assert_eq!(varint(&bytes), Err(Error::OutOfRange));
+    }
+
+    // Pairs of images covering changes, runs, growing and shrinking.
+    fn pairs() -> Vec<(Vec<u8>, Vec<u8>)> {
+        let source: Vec<u8> = (0..0x300).map(|i| (i * 7) as u8).collect();
+        let mut changed = source.clone();
+        changed[0] ^= 0xFF;
+        changed[10] ^= 0xFF;
+        changed[12] ^= 0xFF;
+        changed[0x100..0x180].fill(0xAA);
+        changed[0x2FF] ^= 0xFF;
+        let mut longer = source.clone();
+        longer.extend([1, 2, 3]);
+        alloc::vec![
+            (source.clone(), source.clone()),
+            (source.clone(), changed),
+            (source.clone(), longer),
+            (source.clone(), source[..0x123].to_vec()),
+            (Vec::new(), source.clone()),
+            (source, Vec::new()),
+        ]
+    }
+
+    #[test]
+    fn ips_round_trips() {
+        for (source, target) in pairs() {
+            let patch = ips(&source, &target).unwrap();
+            assert_eq!(apply_ips(&source, &patch), Ok(target));
+        }
+    }
+
+    #[test]
+    fn bps_round_trips() {
+        for (source, target) in pairs() {
+            let patch = bps(&source, &target);
+            assert_eq!(apply_bps(&source, &patch), Ok(target));
+        }
+    }
+
+    #[test]
+    fn ips_writes_runs_as_rle_records() {
+        let source = alloc::vec![0; 0x40];
+        let mut target = source.clone();
+        target[0x10..0x30].fill(0xAA);
+        let patch = ips(&source, &target).unwrap();
+        assert_eq!(patch, b"PATCH\x00\x00\x10\x00\x00\x00\x20\xAAEOF");
+        assert_eq!(apply_ips(&source, &patch), Ok(target));
+    }
+
+    #[test]
+    fn ips_applies_rle_past_the_end() {
+        // A run of 4 $55 at $08, past the end of a 4-byte image.
+        let patch = b"PATCH\x00\x00\x08\x00\x00\x00\x04\x55EOF";
+        assert_eq!(
+            apply_ips(&[1, 2, 3, 4], patch),
+            Ok(alloc::vec![1, 2, 3, 4, 0, 0, 0, 0, 0x55, 0x55, 0x55, 0x55])
+        );
+    }
+
+    #[test]
+    fn ips_truncates_shorter_targets() {
+        let source: Vec<u8> = (0..0x40).collect();
+        let patch = ips(&source, &source[..0x20]).unwrap();
+        assert_eq!(patch, b"PATCHEOF\x00\x00\x20");
+        assert_eq!(apply_ips(&source, &patch), Ok(source[..0x20].to_vec()));
+        // Without the extension the image keeps its length.
+        assert_eq!(apply_ips(&source, b"PATCHEOF"), Ok(source));
+    }
+
+    #[test]
+    fn bps_rejects_mismatched_checksums() {
+        let (source, target) = pairs().swap_remove(1);
+        let patch = bps(&source, &target);
+        let mut other = source.clone();
+        other[0x20] ^= 1;
+        assert_eq!(apply_bps(&other, &patch), Err(Error::SourceMismatch));
+        assert_eq!(apply_bps(&source[1..], &patch), Err(Error::SourceMismatch));
+        let mut corrupt = patch.clone();
+        corrupt[BPS_HEADER.len() + 4] ^= 1;
+        assert_eq!(apply_bps(&source, &corrupt), Err(Error::PatchMismatch));
+        // A wrong target checksum, with the patch's own checksum fixed up.
+        let mut corrupt = patch.clone();
+        let footer = corrupt.len() - BPS_FOOTER_SIZE;
+        corrupt[footer + 4] ^= 1;
+        let crc = crc32(&corrupt[..corrupt.len() - 4]);
+        let len = corrupt.len();
+        corrupt[len - 4..].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(apply_bps(&source, &corrupt), Err(Error::TargetMismatch));
+    }
+
+    #[test]
+    fn truncated_patches_are_rejected() {
+        let (source, target) = pairs().swap_remove(1);
+        let patch = ips(&source, &target).unwrap();
+        for len in 0..patch.len() {
+            let expected = if len < IPS_HEADER.len() {
+                Error::BadHeader
+            } else {
+                Error::Truncated
+            };
+            assert_eq!(apply_ips(&source, &patch[..len]), Err(expected), "{}", len);
+        }
+        let patch = bps(&source, &target);
+        for len in 0..patch.len() {
+            assert!(apply_bps(&source, &patch[..len]).is_err(), "{}", len);
+        }
+        assert_eq!(
+            apply_bps(&source, &patch[..BPS_HEADER.len() + 1]),
+            Err(Error::Truncated)
+        );
+    }
+}
@@ -0,0 +1,206 @@
+//! Helpers for code that has to run from whatever address it is loaded at.
+//! The block is assembled at some base as usual, absolute references to
+//! its own labels are emitted with `Reloc`, and `relocation_stub` is run
+//! once after loading to add the difference between the load address and
+//! the base to each of them.
+use crate::{
+    Addr, ArgOperand, Block, Error, LabelOffsetHi, LabelOffsetLo, LabelRelativeOffset,
+    LabelRelativeOffsetOwned,
+};
+use alloc::{format, string::String};
+use portal_solutions_mos6502_model::{
+    addressing_mode::*, assembler_instruction::*, operand, Address,
+};
+
+/// The address of a label, added to the table written by
+/// `relocation_table` so that the relocation stub can fix it up.
+pub struct Reloc(pub &'static str);
+
+impl ArgOperand for Reloc {
+    type Operand = operand::Address;
+    fn program(self, block: &mut Block) {
+        block.relocatable_le(self.0);
+    }
+}
+
+fn relocation_label(index: usize) -> String {
+    format!("__reloc{}", index)
+}
+
+impl Block {
+    /// Emits a `JSR` to the `RTS` instruction at the fixed address `rts`
+    /// (e.g. one in ROM), defines `anchor` right after it, and leaves the
+    /// address `anchor` is running at in the 2 bytes at `zp`, read back from
+    /// the stack. A and X are clobbered.
+    pub fn get_pc(&mut self, anchor: &str, rts: Address, zp: u8) {
+        let done = format!("_{}_done", anchor);
+        self.inst(Jsr(Absolute), Addr(rts));
+        self.label(anchor);
+        // The return address is still just below the stack pointer. It's
+        // read through $0100,X, with X moved rather than the base, so that
+        // it wraps within the stack page when S is $00.
+        self.inst(Tsx, ());
+        self.inst(Dex, ());
+        self.inst(Lda(AbsoluteXIndexed), Addr(0x0100));
+        self.inst(Sta(ZeroPage), zp);
+        self.inst(Inx, ());
+        self.inst(Lda(AbsoluteXIndexed), Addr(0x0100));
+        self.inst(Sta(ZeroPage), zp.wrapping_add(1));
+        // `JSR` pushes the address of its own last byte.
+        self.inst(Inc(ZeroPage), zp);
+        self.inst(Bne, LabelRelativeOffsetOwned(done.clone()));
+        self.inst(Inc(ZeroPage), zp.wrapping_add(1));
+        self.label(done);
+    }
+    /// The address of `label`, recorded for the relocation stub to fix up.
+    pub fn relocatable_le<S: AsRef<str>>(&mut self, label: S) {
+        let index = self.relocations.len();
+        self.relocations.push(self.program.len());
+        let name = relocation_label(index);
+        self.label(name);
+        self.label_offset_le(label);
+    }
+    /// Emits a routine that adds the difference between where it is running
+    /// and where it was assembled to every `Reloc` reference. It is position
+    /// independent itself, and uses `get_pc` with `rts`. Uses 6 bytes of zero
+    /// page, and A, X and Y are clobbered.
+    pub fn relocation_stub(&mut self, rts: Address, zp: u8) {
+        let (delta, table, word) = (zp, zp.wrapping_add(2), zp.wrapping_add(4));
+        self.get_pc("_relocate_pc", rts, delta);
+        self.inst(Sec, ());
+        self.inst(Lda(ZeroPage), delta);
+        self.inst(Sbc(Immediate), LabelOffsetLo("_relocate_pc"));
+        self.inst(Sta(ZeroPage), delta);
+        self.inst(Lda(ZeroPage), delta.wrapping_add(1));
+        self.inst(Sbc(Immediate), LabelOffsetHi("_relocate_pc"));
+        self.inst(Sta(ZeroPage), delta.wrapping_add(1));
+        self.inst(Clc, ());
+        self.inst(Lda(Immediate), LabelOffsetLo("_relocations"));
+        self.inst(Adc(ZeroPage), delta);
+        self.inst(Sta(ZeroPage), table);
+        self.inst(Lda(Immediate), LabelOffsetHi("_relocations"));
+        self.inst(Adc(ZeroPage), delta.wrapping_add(1));
+        self.inst(Sta(ZeroPage), table.wrapping_add(1));
+        self.inst(Ldx(Immediate), LabelOffsetLo("_relocation_count"));
+        self.inst(Beq, LabelRelativeOffset("_relocate_done"));
+        self.label("_relocate_loop");
+        // Find where the word is running, then move what it points to.
+        self.inst(Ldy(Immediate), 0);
+        self.inst(Clc, ());
+        self.inst(Lda(IndirectYIndexed), table);
+        self.inst(Adc(ZeroPage), delta);
+        self.inst(Sta(ZeroPage), word);
+        self.inst(Iny, ());
+        self.inst(Lda(IndirectYIndexed), table);
+        self.inst(Adc(ZeroPage), delta.wrapping_add(1));
+        self.inst(Sta(ZeroPage), word.wrapping_add(1));
+        self.inst(Dey, ());
+        self.inst(Clc, ());
+        self.inst(Lda(IndirectYIndexed), word);
+        self.inst(Adc(ZeroPage), delta);
+        self.inst(Sta(IndirectYIndexed), word);
+        self.inst(Iny, ());
+        self.inst(Lda(IndirectYIndexed), word);
+        self.inst(Adc(ZeroPage), delta.wrapping_add(1));
+        self.inst(Sta(IndirectYIndexed), word);
+        self.inst(Clc, ());
+        self.inst(Lda(ZeroPage), table);
+        self.inst(Adc(Immediate), 2);
+        self.inst(Sta(ZeroPage), table);
+        self.inst(Bcc, LabelRelativeOffset("_relocate_next"));
+        self.inst(Inc(ZeroPage), table.wrapping_add(1));
+        self.label("_relocate_next");
+        self.inst(Dex, ());
+        self.inst(Bne, LabelRelativeOffset("_relocate_loop"));
+        self.label("_relocate_done");
+        self.inst(Rts, ());
+    }
+    /// Emits the table read by `relocation_stub`, which must come after every
+    /// `Reloc` reference. At most 255 references are supported, and ones in
+    /// blocks that are included aren't recorded.
+    pub fn relocation_table(&mut self) {
+        let count = self.relocations.len();
+        if count > 0xFF {
+            self.build_error(Error::TooManyRelocations(count));
+        }
+        self.constant("_relocation_count", count as Address);
+        self.label("_relocations");
+        for index in 0..count {
+            self.label_offset_le(relocation_label(index));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use portal_solutions_mos6502_model::machine::{Cpu, Memory, Ram};
+
+    const BASE: Address = 0x1000;
+    const RTS: Address = 0x0400;
+    // Where the stub returns to.
+    const DONE: Address = 0x0300;
+
+    fn relocatable() -> Block {
+        let mut block = Block::new();
+        block.label("load");
+        block.inst(Lda(Absolute), Reloc("data"));
+        block.inst(Jmp(Absolute), Reloc("load"));
+        block.label("relocate");
+        block.relocation_stub(RTS, 0x10);
+        block.label("pointer");
+        block.relocatable_le("data");
+        block.relocation_table();
+        block.label("data");
+        block.literal_byte(0x42);
+        block
+    }
+
+    // Loads the block at `address` and runs its relocation stub with the
+    // stack pointer at `sp`.
+    fn run_stub(address: Address, sp: u8) -> Ram {
+        let block = relocatable();
+        let mut image = Vec::new();
+        let assembled = block.assemble(BASE, 0x200, &mut image).unwrap();
+        image.truncate(assembled.end());
+        let mut ram = Ram::new();
+        ram.load(address, &image);
+        ram.write_u8(RTS, 0x60);
+        let mut cpu = Cpu::new();
+        cpu.sp = sp;
+        // The stub's own return address, as a `JSR` just before `DONE`
+        // would have pushed it.
+        let [lo, hi] = (DONE - 1).to_le_bytes();
+        ram.write_u8(0x0100 | sp.wrapping_add(1) as Address, lo);
+        ram.write_u8(0x0100 | sp.wrapping_add(2) as Address, hi);
+        cpu.pc = address + assembled.offset_of_label("relocate").unwrap();
+        for _ in 0..10_000 {
+            if cpu.pc == DONE {
+                return ram;
+            }
+            cpu.step(&mut ram).unwrap();
+        }
+        panic!("relocation stub didn't return");
+    }
+
+    #[test]
+    fn relocation_stub_fixes_up_references() {
+        let block = relocatable();
+        let mut image = Vec::new();
+        let assembled = block.assemble(BASE, 0x200, &mut image).unwrap();
+        let offset = |label| assembled.offset_of_label(label).unwrap();
+        // Including with S at $00, where the address `get_pc` reads wraps
+        // around the stack page.
+        for (address, sp) in [(0x2000, 0xFD), (0x3456, 0x00), (BASE, 0x80)] {
+            let mut ram = run_stub(address, sp);
+            let mut word = |at: Address| {
+                let at = address + at;
+                u16::from_le_bytes([ram.read_u8(at), ram.read_u8(at + 1)])
+            };
+            assert_eq!(word(offset("load") + 1), address + offset("data"));
+            assert_eq!(word(offset("load") + 4), address + offset("load"));
+            assert_eq!(word(offset("pointer")), address + offset("data"));
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use crate::{AssembledBlock, Block, Error};
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::{fmt::Write, ops::Range};
+use portal_solutions_mos6502_model::Address;
+
+/// The state passed through each stage of a `Pipeline`.
+pub struct Rom {
+    pub base: Address,
+    pub image: Vec<u8>,
+    pub assembled: AssembledBlock,
+    pub banks: Vec<Vec<u8>>,
+    pub header: Vec<u8>,
+    pub symbol_file: String,
+}
+
+impl Rom {
+    /// The header followed by every bank, in order.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header.clone();
+        for bank in self.banks.iter() {
+            bytes.extend_from_slice(bank);
+        }
+        bytes
+    }
+}
+
+/// Patches the assembled image in place, e.g. to fill in a checksum.
+pub trait Fixup {
+    fn apply(&self, rom: &mut Rom) -> Result<(), Error>;
+}
+
+/// Splits the (fixed up) image into the banks that make up the output.
+pub trait BankPacker {
+    fn pack(&self, rom: &Rom) -> Result<Vec<Vec<u8>>, Error>;
+}
+
+/// Produces bytes placed before the first bank.
+pub trait HeaderGenerator {
+    fn header(&self, rom: &Rom) -> Result<Vec<u8>, Error>;
+}
+
+/// Renders the symbol table in some external tool's format.
+pub trait SymbolExporter {
+    fn export(&self, rom: &Rom, out: &mut String);
+}
+
+/// Whole image as a single bank.
+pub struct SingleBank;
+impl BankPacker for SingleBank {
+    fn pack(&self, rom: &Rom) -> Result<Vec<Vec<u8>>, Error> {
+        Ok(alloc::vec![rom.image.clone()])
+    }
+}
+
+/// Fixed size banks, with the last one padded with `fill`.
+pub struct FixedSizeBanks {
+    pub bank_size: usize,
+    pub fill: u8,
+}
+impl BankPacker for FixedSizeBanks {
+    fn pack(&self, rom: &Rom) -> Result<Vec<Vec<u8>>, Error> {
+        Ok(rom
+            .image
+            .chunks(self.bank_size.max(1))
+            .map(|chunk| {
+                let mut bank = chunk.to_vec();
+                bank.resize(self.bank_size, self.fill);
+                bank
+            })
+            .collect())
+    }
+}
+
+pub struct NoHeader;
+impl HeaderGenerator for NoHeader {
+    fn header(&self, _rom: &Rom) -> Result<Vec<u8>, Error> {
+        Ok(Vec::new())
+    }
+}
+
+pub struct NoSymbols;
+impl SymbolExporter for NoSymbols {
+    fn export(&self, _rom: &Rom, _out: &mut String) {}
+}
+
+/// VICE monitor label file (`al C:8000 .start`).
+pub struct ViceLabels;
+impl SymbolExporter for ViceLabels {
+    fn export(&self, rom: &Rom, out: &mut String) {
+        for (label, address) in rom.assembled.labels.iter() {
+            let _ = writeln!(out, "al C:{:04X} .{}", address, label);
+        }
+    }
+}
+
+/// Stores the 8-bit sum of the bytes in `range` at offset `at`.
+pub struct ByteSum {
+    pub range: Range<usize>,
+    pub at: usize,
+}
+impl Fixup for ByteSum {
+    fn apply(&self, rom: &mut Rom) -> Result<(), Error> {
+        let out_of_bounds = |offset: usize| Error::OffsetOutOfBounds {
+            offset: offset as Address,
+            label: None,
+        };
+        let sum = rom
+            .image
+            .get(self.range.clone())
+            .ok_or_else(|| out_of_bounds(self.range.end))?
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        *rom.image
+            .get_mut(self.at)
+            .ok_or_else(|| out_of_bounds(self.at))? = sum;
+        Ok(())
+    }
+}
+
+/// Chains assembly, fixups, bank packing, header generation and symbol
+/// export. Each stage defaults to a no-op (or a single bank) and can be
+/// replaced independently.
+pub struct Pipeline<'a> {
+    fixups: Vec<Box<dyn Fixup + 'a>>,
+    packer: Box<dyn BankPacker + 'a>,
+    header: Box<dyn HeaderGenerator + 'a>,
+    symbols: Box<dyn SymbolExporter + 'a>,
+}
+
+impl Default for Pipeline<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new() -> Self {
+        Self {
+            fixups: Vec::new(),
+            packer: Box::new(SingleBank),
+            header: Box::new(NoHeader),
+            symbols: Box::new(NoSymbols),
+        }
+    }
+    /// Fixups run in the order they are added.
+    pub fn fixup<F: Fixup + 'a>(mut self, fixup: F) -> Self {
+        self.fixups.push(Box::new(fixup));
+        self
+    }
+    pub fn packer<P: BankPacker + 'a>(mut self, packer: P) -> Self {
+        self.packer = Box::new(packer);
+        self
+    }
+    pub fn header<H: HeaderGenerator + 'a>(mut self, header: H) -> Self {
+        self.header = Box::new(header);
+        self
+    }
+    pub fn symbols<S: SymbolExporter + 'a>(mut self, symbols: S) -> Self {
+        self.symbols = Box::new(symbols);
+        self
+    }
+    pub fn build(&self, block: &Block, base: Address, size: usize) -> Result<Rom, Error> {
+        let mut image = Vec::new();
+        let assembled = block.assemble(base, size, &mut image)?;
+        let mut rom = Rom {
+            base,
+            image,
+            assembled,
+            banks: Vec::new(),
+            header: Vec::new(),
+            symbol_file: String::new(),
+        };
+        for fixup in self.fixups.iter() {
+            fixup.apply(&mut rom)?;
+        }
+        rom.banks = self.packer.pack(&rom)?;
+        rom.header = self.header.header(&rom)?;
+        let mut symbol_file = String::new();
+        self.symbols.export(&rom, &mut symbol_file);
+        rom.symbol_file = symbol_file;
+        Ok(rom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Five bytes 1 to 5 at $8000, labelled at the start and the third.
+    fn block() -> Block {
+        let mut block = Block::new();
+        block.label("start");
+        block.literal_byte(1);
+        block.literal_byte(2);
+        block.label("middle");
+        for byte in 3..=5 {
+            block.literal_byte(byte);
+        }
+        block
+    }
+
+    // Stores `value` at offset `at`.
+    struct Poke {
+        at: usize,
+        value: u8,
+    }
+    impl Fixup for Poke {
+        fn apply(&self, rom: &mut Rom) -> Result<(), Error> {
+            rom.image[self.at] = self.value;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn single_bank_holds_the_image() {
+        let rom = Pipeline::new().build(&block(), 0x8000, 5).unwrap();
+        assert_eq!(rom.banks, [[1, 2, 3, 4, 5]]);
+        assert!(rom.header.is_empty());
+        assert!(rom.symbol_file.is_empty());
+        assert_eq!(rom.to_bytes(), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn fixed_size_banks_pad_the_last() {
+        let rom = Pipeline::new()
+            .packer(FixedSizeBanks {
+                bank_size: 2,
+                fill: 0xFF,
+            })
+            .build(&block(), 0x8000, 5)
+            .unwrap();
+        assert_eq!(rom.banks, [[1, 2], [3, 4], [5, 0xFF]]);
+        assert_eq!(rom.to_bytes(), [1, 2, 3, 4, 5, 0xFF]);
+    }
+
+    #[test]
+    fn fixups_run_in_order_before_packing() {
+        // The sum sees the poke before it, but not the one after it.
+        let rom = Pipeline::new()
+            .fixup(Poke { at: 1, value: 10 })
+            .fixup(ByteSum { range: 0..4, at: 4 })
+            .fixup(Poke { at: 0, value: 20 })
+            .build(&block(), 0x8000, 5)
+            .unwrap();
+        assert_eq!(rom.banks, [[20, 10, 3, 4, 18]]);
+    }
+
+    #[test]
+    fn byte_sum_out_of_bounds() {
+        let pipeline = Pipeline::new().fixup(ByteSum { range: 0..8, at: 0 });
+        match pipeline.build(&block(), 0x8000, 5) {
+            Err(Error::OffsetOutOfBounds { offset: 8, .. }) => (),
+            other => panic!("expected OffsetOutOfBounds, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn vice_labels() {
+        let rom = Pipeline::new()
+            .symbols(ViceLabels)
+            .build(&block(), 0x8000, 5)
+            .unwrap();
+        assert_eq!(rom.symbol_file, "al C:8002 .middle\nal C:8000 .start\n");
+    }
+}
@@ -0,0 +1,351 @@
+//! Common routines as blocks ready to `append` or `include_at` into a
+//! program. Each one is called with `JSR` to its entry label and returns
+//! with `RTS`. Routines take their arguments in the zero page, at the
+//! address passed to the function that builds them, and define a constant
+//! for every argument so callers can refer to them by name. Arguments
+//! which run past $FF wrap round to $00, as zero page addressing does.
+//! Labels that start with `_` are internal.
+use crate::{Block, LabelRelativeOffset};
+use portal_solutions_mos6502_model::{addressing_mode::*, assembler_instruction::*, Address};
+
+fn routine(entry: &str) -> Block {
+    let mut b = Block::new();
+    b.label(entry);
+    b
+}
+
+/// `add16_a += add16_b`, both little-endian. Uses 4 bytes of zero page.
+pub fn add16(zp: u8) -> Block {
+    let mut b = routine("add16");
+    b.constant("add16_a", zp as Address);
+    b.constant("add16_b", zp.wrapping_add(2) as Address);
+    b.inst(Clc, ());
+    for i in 0..2 {
+        b.inst(Lda(ZeroPage), zp.wrapping_add(i));
+        b.inst(Adc(ZeroPage), zp.wrapping_add(2 + i));
+        b.inst(Sta(ZeroPage), zp.wrapping_add(i));
+    }
+    b.inst(Rts, ());
+    b
+}
+
+/// `sub16_a -= sub16_b`, both little-endian. Carry is clear afterwards if
+/// the subtraction borrowed. Uses 4 bytes of zero page.
+pub fn sub16(zp: u8) -> Block {
+    let mut b = routine("sub16");
+    b.constant("sub16_a", zp as Address);
+    b.constant("sub16_b", zp.wrapping_add(2) as Address);
+    b.inst(Sec, ());
+    for i in 0..2 {
+        b.inst(Lda(ZeroPage), zp.wrapping_add(i));
+        b.inst(Sbc(ZeroPage), zp.wrapping_add(2 + i));
+        b.inst(Sta(ZeroPage), zp.wrapping_add(i));
+    }
+    b.inst(Rts, ());
+    b
+}
+
+/// Compares `cmp16_a` with `cmp16_b` (both unsigned little-endian),
+/// leaving the flags as `CMP` would: Z set if equal, C set if a >= b.
+/// Uses 4 bytes of zero page.
+pub fn cmp16(zp: u8) -> Block {
+    let mut b = routine("cmp16");
+    b.constant("cmp16_a", zp as Address);
+    b.constant("cmp16_b", zp.wrapping_add(2) as Address);
+    b.inst(Lda(ZeroPage), zp.wrapping_add(1));
+    b.inst(Cmp(ZeroPage), zp.wrapping_add(3));
+    b.inst(Bne, LabelRelativeOffset("_cmp16_done"));
+    b.inst(Lda(ZeroPage), zp);
+    b.inst(Cmp(ZeroPage), zp.wrapping_add(2));
+    b.label("_cmp16_done");
+    b.inst(Rts, ());
+    b
+}
+
+/// `mul8_product = mul8_a * mul8_b`, with a 16-bit little-endian product.
+/// `mul8_a` is destroyed. Uses 4 bytes of zero page.
+pub fn mul8(zp: u8) -> Block {
+    let mut b = routine("mul8");
+    b.constant("mul8_a", zp as Address);
+    b.constant("mul8_b", zp.wrapping_add(1) as Address);
+    b.constant("mul8_product", zp.wrapping_add(2) as Address);
+    b.inst(Lda(Immediate), 0);
+    b.inst(Ldx(Immediate), 8);
+    b.inst(Lsr(ZeroPage), zp);
+    b.label("_mul8_loop");
+    b.inst(Bcc, LabelRelativeOffset("_mul8_skip"));
+    b.inst(Clc, ());
+    b.inst(Adc(ZeroPage), zp.wrapping_add(1));
+    b.label("_mul8_skip");
+    // The high byte of the product builds up in A, and the low byte is
+    // rotated into `mul8_a` as its bits are used up.
+    b.inst(Ror(Accumulator), ());
+    b.inst(Ror(ZeroPage), zp);
+    b.inst(Dex, ());
+    b.inst(Bne, LabelRelativeOffset("_mul8_loop"));
+    b.inst(Sta(ZeroPage), zp.wrapping_add(3));
+    b.inst(Lda(ZeroPage), zp);
+    b.inst(Sta(ZeroPage), zp.wrapping_add(2));
+    b.inst(Rts, ());
+    b
+}
+
+/// Divides `div8_dividend` by `div8_divisor`, leaving the quotient in
+/// `div8_dividend` and the remainder in `div8_remainder` and A. Dividing
+/// by zero gives a quotient of $FF, with the dividend as the remainder.
+/// Uses 3 bytes of zero page.
+pub fn div8(zp: u8) -> Block {
+    let mut b = routine("div8");
+    b.constant("div8_dividend", zp as Address);
+    b.constant("div8_divisor", zp.wrapping_add(1) as Address);
+    b.constant("div8_remainder", zp.wrapping_add(2) as Address);
+    b.inst(Lda(Immediate), 0);
+    b.inst(Ldx(Immediate), 8);
+    b.label("_div8_loop");
+    b.inst(Asl(ZeroPage), zp);
+    b.inst(Rol(Accumulator), ());
+    b.inst(Cmp(ZeroPage), zp.wrapping_add(1));
+    b.inst(Bcc, LabelRelativeOffset("_div8_skip"));
+    b.inst(Sbc(ZeroPage), zp.wrapping_add(1));
+    b.inst(Inc(ZeroPage), zp);
+    b.label("_div8_skip");
+    b.inst(Dex, ());
+    b.inst(Bne, LabelRelativeOffset("_div8_loop"));
+    b.inst(Sta(ZeroPage), zp.wrapping_add(2));
+    b.inst(Rts, ());
+    b
+}
+
+/// Copies `memcpy_length` bytes (16-bit) from `memcpy_source` to
+/// `memcpy_destination`, lowest address first, so overlapping copies only
+/// work towards lower addresses. The high bytes of both pointers are
+/// advanced. Uses 6 bytes of zero page.
+pub fn memcpy(zp: u8) -> Block {
+    let (source, destination, length) = (zp, zp.wrapping_add(2), zp.wrapping_add(4));
+    let mut b = routine("memcpy");
+    b.constant("memcpy_source", source as Address);
+    b.constant("memcpy_destination", destination as Address);
+    b.constant("memcpy_length", length as Address);
+    b.inst(Ldy(Immediate), 0);
+    b.inst(Ldx(ZeroPage), length.wrapping_add(1));
+    b.inst(Beq, LabelRelativeOffset("_memcpy_partial"));
+    b.label("_memcpy_page");
+    b.inst(Lda(IndirectYIndexed), source);
+    b.inst(Sta(IndirectYIndexed), destination);
+    b.inst(Iny, ());
+    b.inst(Bne, LabelRelativeOffset("_memcpy_page"));
+    b.inst(Inc(ZeroPage), source.wrapping_add(1));
+    b.inst(Inc(ZeroPage), destination.wrapping_add(1));
+    b.inst(Dex, ());
+    b.inst(Bne, LabelRelativeOffset("_memcpy_page"));
+    b.label("_memcpy_partial");
+    b.inst(Ldx(ZeroPage), length);
+    b.inst(Beq, LabelRelativeOffset("_memcpy_done"));
+    b.label("_memcpy_tail");
+    b.inst(Lda(IndirectYIndexed), source);
+    b.inst(Sta(IndirectYIndexed), destination);
+    b.inst(Iny, ());
+    b.inst(Dex, ());
+    b.inst(Bne, LabelRelativeOffset("_memcpy_tail"));
+    b.label("_memcpy_done");
+    b.inst(Rts, ());
+    b
+}
+
+/// Fills `memset_length` bytes (16-bit) from `memset_destination` with A.
+/// The high byte of the pointer is advanced. Uses 4 bytes of zero page.
+pub fn memset(zp: u8) -> Block {
+    let (destination, length) = (zp, zp.wrapping_add(2));
+    let mut b = routine("memset");
+    b.constant("memset_destination", destination as Address);
+    b.constant("memset_length", length as Address);
+    b.inst(Ldy(Immediate), 0);
+    b.inst(Ldx(ZeroPage), length.wrapping_add(1));
+    b.inst(Beq, LabelRelativeOffset("_memset_partial"));
+    b.label("_memset_page");
+    b.inst(Sta(IndirectYIndexed), destination);
+    b.inst(Iny, ());
+    b.inst(Bne, LabelRelativeOffset("_memset_page"));
+    b.inst(Inc(ZeroPage), destination.wrapping_add(1));
+    b.inst(Dex, ());
+    b.inst(Bne, LabelRelativeOffset("_memset_page"));
+    b.label("_memset_partial");
+    b.inst(Ldx(ZeroPage), length);
+    b.inst(Beq, LabelRelativeOffset("_memset_done"));
+    b.label("_memset_tail");
+    b.inst(Sta(IndirectYIndexed), destination);
+    b.inst(Iny, ());
+    b.inst(Dex, ());
+    b.inst(Bne, LabelRelativeOffset("_memset_tail"));
+    b.label("_memset_done");
+    b.inst(Rts, ());
+    b
+}
+
+/// Converts the packed BCD byte in A to two ASCII digits, tens first, at
+/// `bcd_to_ascii_out`. Uses 2 bytes of zero page.
+pub fn bcd_to_ascii(zp: u8) -> Block {
+    let mut b = routine("bcd_to_ascii");
+    b.constant("bcd_to_ascii_out", zp as Address);
+    b.inst(Pha, ());
+    for _ in 0..4 {
+        b.inst(Lsr(Accumulator), ());
+    }
+    b.inst(Ora(Immediate), 0x30);
+    b.inst(Sta(ZeroPage), zp);
+    b.inst(Pla, ());
+    b.inst(And(Immediate), 0x0F);
+    b.inst(Ora(Immediate), 0x30);
+    b.inst(Sta(ZeroPage), zp.wrapping_add(1));
+    b.inst(Rts, ());
+    b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Outcome, Test};
+    use alloc::vec::Vec;
+    use portal_solutions_mos6502_model::status::flag;
+
+    const BASE: Address = 0x0200;
+    const ZP: u8 = 0x10;
+
+    // Calls `routine` at `entry` with the zero page arguments `args`, and
+    // the accumulator set to `a`, then stops at a `BRK`.
+    fn call(routine: Block, entry: &'static str, a: u8, args: &[u8]) -> Outcome {
+        call_with_memory(routine, entry, a, args, &[])
+    }
+
+    fn call_with_memory(
+        routine: Block,
+        entry: &'static str,
+        a: u8,
+        args: &[u8],
+        memory: &[(Address, &[u8])],
+    ) -> Outcome {
+        let mut block = Block::new();
+        block.inst(Jsr(Absolute), entry);
+        block.inst(Brk, ());
+        block.append(&routine);
+        let mut test = Test::new(&block, BASE).a(a).memory(ZP as Address, args);
+        for &(address, bytes) in memory {
+            test = test.memory(address, bytes);
+        }
+        test.run().unwrap()
+    }
+
+    fn word(value: u16) -> [u8; 2] {
+        value.to_le_bytes()
+    }
+
+    #[test]
+    fn mul8_multiplies() {
+        for (a, b) in [(12, 13), (0, 200), (200, 0), (0x80, 2), (0xFF, 0xFF)] {
+            call(mul8(ZP), "mul8", 0, &[a, b])
+                .assert_word(ZP as Address + 2, a as u16 * b as u16)
+                .assert_memory(ZP as Address + 1, &[b]);
+        }
+    }
+
+    #[test]
+    fn div8_divides() {
+        for (dividend, divisor) in [(200, 7), (7, 200), (0xFF, 1), (0, 3), (0x80, 0x80)] {
+            let remainder = dividend % divisor;
+            call(div8(ZP), "div8", 0, &[dividend, divisor])
+                .assert_memory(ZP as Address, &[dividend / divisor, divisor, remainder])
+                .assert_a(remainder);
+        }
+    }
+
+    #[test]
+    fn div8_by_zero_gives_ff_and_the_dividend() {
+        call(div8(ZP), "div8", 0, &[42, 0])
+            .assert_memory(ZP as Address, &[0xFF, 0, 42])
+            .assert_a(42);
+    }
+
+    #[test]
+    fn add16_carries_between_bytes() {
+        let args = [word(0x12FF), word(0x0001)].concat();
+        call(add16(ZP), "add16", 0, &args)
+            .assert_word(ZP as Address, 0x1300)
+            .assert_flag(flag::CARRY, false);
+        let args = [word(0xFFFF), word(0x0002)].concat();
+        call(add16(ZP), "add16", 0, &args)
+            .assert_word(ZP as Address, 0x0001)
+            .assert_flag(flag::CARRY, true);
+    }
+
+    #[test]
+    fn sub16_borrows_between_bytes() {
+        let args = [word(0x1300), word(0x0001)].concat();
+        call(sub16(ZP), "sub16", 0, &args)
+            .assert_word(ZP as Address, 0x12FF)
+            .assert_flag(flag::CARRY, true);
+        let args = [word(0x0000), word(0x0001)].concat();
+        call(sub16(ZP), "sub16", 0, &args)
+            .assert_word(ZP as Address, 0xFFFF)
+            .assert_flag(flag::CARRY, false);
+    }
+
+    #[test]
+    fn cmp16_sets_flags_as_cmp() {
+        for (a, b) in [
+            (0x1234, 0x1234),
+            (0x1234, 0x1235),
+            (0x1235, 0x1234),
+            (0x0100, 0x00FF),
+            (0x00FF, 0x0100),
+            (0, 0),
+        ] {
+            let args = [word(a), word(b)].concat();
+            call(cmp16(ZP), "cmp16", 0, &args)
+                .assert_flag(flag::ZERO, a == b)
+                .assert_flag(flag::CARRY, a >= b);
+        }
+    }
+
+    #[test]
+    fn memcpy_copies_exactly_the_length() {
+        const SOURCE: Address = 0x3000;
+        const DESTINATION: Address = 0x4000;
+        let source: Vec<u8> = (0..0x200).map(|i| (i as u8) ^ 0x5A | 1).collect();
+        for length in [0, 1, 0xFF, 0x100, 0x101, 0x1FF] {
+            let args = [word(SOURCE), word(DESTINATION), word(length)].concat();
+            let outcome = call_with_memory(memcpy(ZP), "memcpy", 0, &args, &[(SOURCE, &source)]);
+            outcome
+                .assert_memory(DESTINATION, &source[..length as usize])
+                .assert_memory(DESTINATION + length, &[0]);
+            // Both pointers' high bytes move on a page per whole page.
+            let pages = (length >> 8) as u8;
+            outcome.assert_memory(ZP as Address, &[0x00, 0x30 + pages, 0x00, 0x40 + pages]);
+        }
+    }
+
+    #[test]
+    fn memset_fills_exactly_the_length() {
+        const DESTINATION: Address = 0x4000;
+        for length in [0, 1, 0x100, 0x101, 0x1FF] {
+            let args = [word(DESTINATION), word(length)].concat();
+            call(memset(ZP), "memset", 0xAA, &args)
+                .assert_memory(DESTINATION, &alloc::vec![0xAA; length as usize])
+                .assert_memory(DESTINATION + length, &[0]);
+        }
+    }
+
+    #[test]
+    fn bcd_to_ascii_writes_both_digits() {
+        for (bcd, ascii) in [
+            (0x42, b"42"),
+            (0x09, b"09"),
+            (0x90, b"90"),
+            (0x00, b"00"),
+            (0x99, b"99"),
+        ] {
+            call(bcd_to_ascii(ZP), "bcd_to_ascii", bcd, &[])
+                .assert_memory(ZP as Address, ascii)
+                .assert_a(bcd & 0x0F | 0x30);
+        }
+    }
+}
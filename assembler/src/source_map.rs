@@ -0,0 +1,114 @@
+use alloc::{format, string::String, vec::Vec};
+use portal_solutions_mos6502_model::{annotation::Annotations, Address};
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+struct Entry {
+    address: Address,
+    size: Address,
+    index: usize,
+    source_hint: Option<usize>,
+    instruction: bool,
+}
+
+/// Where one program item ended up once assembled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Origin<'a> {
+    pub address: Address,
+    pub size: Address,
+    /// Index of the item, counting every emitted item in order, as in
+    /// `Location`.
+    pub index: usize,
+    /// The `Block::source_hint` in effect when the item was emitted.
+    pub source_hint: Option<&'a str>,
+    /// Whether the item is the start of an instruction emitted with `inst`.
+    pub instruction: bool,
+}
+
+/// The program item each assembled byte came from, for working back from
+/// an address in a trace to the builder call which emitted it.
+///
+/// ```ignore
+/// let assembled = block.assemble_into_machine(0xC000, &mut machine, Some("start"))?;
+/// let annotations = assembled.source_map().annotations();
+/// machine.enable_history(64);
+/// machine.run(Fuel::cycles(100_000))?;
+/// let mut trace = String::new();
+/// machine.history().unwrap().write_annotated(&mut trace, &annotations)?;
+/// ```
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    // In address order.
+    entries: Vec<Entry>,
+    source_hints: Vec<String>,
+}
+
+impl SourceMap {
+    pub(crate) fn new(source_hints: Vec<String>) -> Self {
+        Self {
+            entries: Vec::new(),
+            source_hints,
+        }
+    }
+    pub(crate) fn push(
+        &mut self,
+        address: Address,
+        size: Address,
+        index: usize,
+        source_hint: Option<usize>,
+        instruction: bool,
+    ) {
+        self.entries.push(Entry {
+            address,
+            size,
+            index,
+            source_hint,
+            instruction,
+        });
+    }
+    pub(crate) fn sort(&mut self) {
+        self.entries
+            .sort_by_key(|entry| (entry.address, entry.index));
+    }
+    fn origin(&self, entry: &Entry) -> Origin<'_> {
+        Origin {
+            address: entry.address,
+            size: entry.size,
+            index: entry.index,
+            source_hint: entry
+                .source_hint
+                .map(|hint| self.source_hints[hint].as_str()),
+            instruction: entry.instruction,
+        }
+    }
+    /// Every item, in address order.
+    pub fn iter(&self) -> impl Iterator<Item = Origin<'_>> {
+        self.entries.iter().map(move |entry| self.origin(entry))
+    }
+    /// The item which assembled to the byte at `address`. Items in an
+    /// assembled block don't overlap, so there is at most one.
+    pub fn get(&self, address: Address) -> Option<Origin<'_>> {
+        let after = self
+            .entries
+            .partition_point(|entry| entry.address <= address);
+        let entry = self.entries[..after].last()?;
+        (address - entry.address < entry.size).then(|| self.origin(entry))
+    }
+    /// A comment at the start of every instruction, naming the item it
+    /// came from and its source hint, as `item 12: main loop`, for
+    /// `TraceFormat::write_annotated_line` and `History::write_annotated`.
+    pub fn annotations(&self) -> Annotations {
+        let mut annotations = Annotations::new();
+        for origin in self.iter().filter(|origin| origin.instruction) {
+            match origin.source_hint {
+                Some(hint) => annotations
+                    .set_comment(origin.address, format!("item {}: {}", origin.index, hint)),
+                None => annotations.set_comment(origin.address, format!("item {}", origin.index)),
+            }
+        }
+        annotations
+    }
+}
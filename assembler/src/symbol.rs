@@ -0,0 +1,98 @@
+//! Label names are interned per block, so emitting a reference to a label
+//! that has been seen before doesn't allocate. `Symbol` exposes the handles
+//! for code that emits many references to the same labels.
+use crate::{ArgOperand, Block, Data};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use portal_solutions_mos6502_model::operand;
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+/// An interned label name. Symbols are only meaningful to the block that
+/// created them, and `.` local labels are resolved against the scope that
+/// was current when the symbol was created.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol(u32);
+
+pub struct SymbolLo(pub Symbol);
+pub struct SymbolHi(pub Symbol);
+pub struct SymbolRelative(pub Symbol);
+
+impl Symbol {
+    pub(crate) fn index(self) -> usize {
+        self.0 as usize
+    }
+    pub fn lo(self) -> SymbolLo {
+        SymbolLo(self)
+    }
+    pub fn hi(self) -> SymbolHi {
+        SymbolHi(self)
+    }
+    pub fn relative(self) -> SymbolRelative {
+        SymbolRelative(self)
+    }
+}
+
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Default)]
+pub(crate) struct Interner {
+    names: Vec<String>,
+    symbols: BTreeMap<String, Symbol>,
+}
+
+impl Interner {
+    pub(crate) fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(name) {
+            return symbol;
+        }
+        let symbol = Symbol(self.names.len() as u32);
+        self.names.push(name.into());
+        self.symbols.insert(name.into(), symbol);
+        symbol
+    }
+    pub(crate) fn resolve(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.index()]
+    }
+    pub(crate) fn len(&self) -> usize {
+        self.names.len()
+    }
+}
+
+impl Block {
+    /// Interns `label`, applying the current local scope, so it can be
+    /// referenced repeatedly without looking the name up each time.
+    pub fn symbol<S: AsRef<str>>(&mut self, label: S) -> Symbol {
+        self.intern_label(label.as_ref())
+    }
+    pub fn symbol_name(&self, symbol: Symbol) -> &str {
+        self.symbols.resolve(symbol)
+    }
+}
+
+impl ArgOperand for Symbol {
+    type Operand = operand::Address;
+    fn program(self, block: &mut Block) {
+        block.emit(Data::LabelOffsetLe(self, 0));
+    }
+}
+
+impl ArgOperand for SymbolLo {
+    type Operand = operand::Byte;
+    fn program(self, block: &mut Block) {
+        block.emit(Data::LabelOffsetLo(self.0, 0));
+    }
+}
+
+impl ArgOperand for SymbolHi {
+    type Operand = operand::Byte;
+    fn program(self, block: &mut Block) {
+        block.emit(Data::LabelOffsetHi(self.0, 0));
+    }
+}
+
+impl ArgOperand for SymbolRelative {
+    type Operand = operand::Byte;
+    fn program(self, block: &mut Block) {
+        block.emit(Data::LabelRelativeOffset(self.0));
+    }
+}
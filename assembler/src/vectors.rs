@@ -0,0 +1,22 @@
+use crate::Block;
+use portal_solutions_mos6502_model::{interrupt_vector, Address};
+
+/// Labels of the three interrupt handlers.
+#[derive(Debug, Clone, Copy)]
+pub struct Vectors<'a> {
+    pub nmi: &'a str,
+    pub reset: &'a str,
+    pub irq: &'a str,
+}
+
+impl Block {
+    /// Moves the cursor to $FFFA (for a block assembled at `base`) and
+    /// emits the NMI, reset and IRQ vectors. Labels that are never defined
+    /// are reported as `UndeclaredLabel` by `assemble`.
+    pub fn vectors(&mut self, base: Address, vectors: Vectors) {
+        self.set_offset(interrupt_vector::NMI_LO.wrapping_sub(base));
+        self.label_offset_le(vectors.nmi);
+        self.label_offset_le(vectors.reset);
+        self.label_offset_le(vectors.irq);
+    }
+}
@@ -0,0 +1,60 @@
+use crate::{Block, Error};
+use core::ops::Range;
+use portal_solutions_mos6502_model::Address;
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+/// Hands out zero-page addresses from a pool, in increasing order.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default)]
+pub struct ZeroPageAllocator {
+    next: Address,
+    end: Address,
+}
+
+impl ZeroPageAllocator {
+    /// `pool` is clamped to the zero page.
+    pub fn new(pool: Range<Address>) -> Self {
+        let end = pool.end.min(0x100);
+        Self {
+            next: pool.start.min(end),
+            end,
+        }
+    }
+    /// The address of `size` consecutive free bytes, or `None` if the
+    /// pool doesn't have that many left.
+    pub fn allocate(&mut self, size: Address) -> Option<Address> {
+        if self.remaining() < size {
+            return None;
+        }
+        let address = self.next;
+        self.next += size;
+        Some(address)
+    }
+    pub fn remaining(&self) -> Address {
+        self.end - self.next
+    }
+}
+
+impl Block {
+    /// Replaces the pool used by `zp_var`, which is empty by default.
+    pub fn set_zero_page_pool(&mut self, pool: Range<Address>) {
+        self.zero_page = ZeroPageAllocator::new(pool);
+    }
+    /// Allocates `size` bytes of the zero-page pool and defines `name` as a
+    /// constant for the first of them. Records `ZeroPageExhausted` if the
+    /// pool is too small.
+    pub fn zp_var<S: AsRef<str>>(&mut self, name: S, size: Address) -> Option<Address> {
+        let name = name.as_ref();
+        match self.zero_page.allocate(size) {
+            Some(address) => {
+                self.constant(name, address);
+                Some(address)
+            }
+            None => {
+                self.build_error(Error::ZeroPageExhausted(name.into()));
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,167 @@
+/* C API for the MOS6502 model, generated from ffi/src/lib.rs by cbindgen. */
+
+#ifndef MOS6502_H
+#define MOS6502_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define MOS6502_ERROR_UNKNOWN_OPCODE -1
+
+#define MOS6502_ERROR_FAULT -2
+
+#define MOS6502_ERROR_TRAP_STOP -3
+
+#define MOS6502_ERROR_HALTED -4
+
+#define MOS6502_TRAP_CONTINUE 0
+
+#define MOS6502_TRAP_RESUME 1
+
+#define MOS6502_TRAP_RETURN 2
+
+#define MOS6502_TRAP_STOP 3
+
+// Opaque to C.
+typedef struct Mos6502Machine Mos6502Machine;
+
+typedef struct {
+  uint16_t pc;
+  uint8_t a;
+  uint8_t x;
+  uint8_t y;
+  uint8_t sp;
+  // As an interrupt would push it.
+  uint8_t p;
+} Mos6502Registers;
+
+// Null to leave reads to RAM.
+typedef uint8_t (*Mos6502ReadHook)(void *user, uint16_t address);
+
+// Null to leave writes to RAM.
+typedef void (*Mos6502WriteHook)(void *user, uint16_t address, uint8_t data);
+
+typedef int (*Mos6502TrapHook)(void *user, Mos6502Machine *machine);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// A machine with 64KB of RAM, all zero, and the CPU at $0000. Free it with
+// `mos6502_machine_free`.
+Mos6502Machine *mos6502_machine_new(void);
+
+// # Safety
+//
+// `machine` must be null or from `mos6502_machine_new`, and not used
+// again.
+void mos6502_machine_free(Mos6502Machine *machine);
+
+// Copies `len` bytes into RAM at `address`, bypassing hooks. Returns
+// false without loading anything if `data` is null and `len` isn't zero.
+//
+// # Safety
+//
+// Unless it's null, `data` must point to `len` readable bytes.
+bool mos6502_machine_load(Mos6502Machine *machine,
+                          uint16_t address,
+                          const uint8_t *data,
+                          size_t len);
+
+// Reads as the CPU would, through any hook.
+//
+// # Safety
+//
+// See the crate documentation.
+uint8_t mos6502_machine_read(Mos6502Machine *machine, uint16_t address);
+
+// Writes as the CPU would, through any hook.
+//
+// # Safety
+//
+// See the crate documentation.
+void mos6502_machine_write(Mos6502Machine *machine, uint16_t address, uint8_t data);
+
+// Runs one instruction, returning the cycles it took or a negative
+// `MOS6502_ERROR_*` code.
+//
+// # Safety
+//
+// See the crate documentation.
+int mos6502_machine_step(Mos6502Machine *machine);
+
+// Runs until at least `cycles` cycles have passed, taking interrupts,
+// and returns the exact count, or a negative `MOS6502_ERROR_*` code. A
+// halt ends the run early without an error.
+//
+// # Safety
+//
+// See the crate documentation.
+int64_t mos6502_machine_run_cycles(Mos6502Machine *machine, uint32_t cycles);
+
+// # Safety
+//
+// See the crate documentation. `registers` must be writable.
+void mos6502_machine_get_registers(const Mos6502Machine *machine, Mos6502Registers *registers);
+
+// # Safety
+//
+// See the crate documentation. `registers` must be readable.
+void mos6502_machine_set_registers(Mos6502Machine *machine, const Mos6502Registers *registers);
+
+// # Safety
+//
+// See the crate documentation.
+uint64_t mos6502_machine_cycles(const Mos6502Machine *machine);
+
+// # Safety
+//
+// See the crate documentation.
+void mos6502_machine_reset(Mos6502Machine *machine);
+
+// # Safety
+//
+// See the crate documentation.
+void mos6502_machine_set_irq(Mos6502Machine *machine, bool asserted);
+
+// # Safety
+//
+// See the crate documentation.
+void mos6502_machine_request_nmi(Mos6502Machine *machine);
+
+// Sends reads and writes from `first` to `last` to `read` and `write`,
+// either of which can be null to leave those accesses to RAM.
+//
+// # Safety
+//
+// See the crate documentation. `user` is passed back to the hooks as is,
+// and must stay valid for as long as the machine.
+void mos6502_machine_add_io_hook(Mos6502Machine *machine,
+                                 uint16_t first,
+                                 uint16_t last,
+                                 Mos6502ReadHook read,
+                                 Mos6502WriteHook write,
+                                 void *user);
+
+// Calls `hook` before the instruction at `address` runs, with the machine,
+// which it may change. It returns a `MOS6502_TRAP_*` action: carry on
+// with the instruction, resume at the program counter without it, return
+// as if by `RTS`, or stop with `MOS6502_ERROR_TRAP_STOP`.
+//
+// # Safety
+//
+// See the crate documentation. `user` is passed back to the hook as is,
+// and must stay valid for as long as the machine.
+void mos6502_machine_add_trap(Mos6502Machine *machine,
+                              uint16_t address,
+                              Mos6502TrapHook hook,
+                              void *user);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* MOS6502_H */
@@ -0,0 +1,418 @@
+//! A C API over `Machine`, declared in `include/mos6502.h`, which cbindgen
+//! generates from this file with `ffi/cbindgen.toml`. A machine owns
+//! 64KB of RAM, and ranges of it can be handed to I/O hooks: C functions
+//! called for each read or write, with a user pointer. Traps call a C
+//! function before the instruction at an address runs.
+//!
+//! Every function taking a machine pointer requires one returned by
+//! `mos6502_machine_new` and not yet freed, and must not be called on the
+//! same machine from more than one thread at once.
+use core::ffi::{c_int, c_void};
+use core::ops::RangeInclusive;
+use portal_solutions_mos6502_model::machine::{
+    Cpu, Machine, Memory, MemoryReadOnly, Ram, StepError, Trap, TrapAction,
+};
+use portal_solutions_mos6502_model::status::Register;
+use portal_solutions_mos6502_model::Address;
+
+/// Null to leave reads to RAM.
+pub type ReadHook = Option<extern "C" fn(user: *mut c_void, address: u16) -> u8>;
+/// Null to leave writes to RAM.
+pub type WriteHook = Option<extern "C" fn(user: *mut c_void, address: u16, data: u8)>;
+pub type TrapHook = extern "C" fn(user: *mut c_void, machine: *mut Mos6502Machine) -> c_int;
+
+pub const MOS6502_ERROR_UNKNOWN_OPCODE: c_int = -1;
+pub const MOS6502_ERROR_FAULT: c_int = -2;
+pub const MOS6502_ERROR_TRAP_STOP: c_int = -3;
+pub const MOS6502_ERROR_HALTED: c_int = -4;
+
+pub const MOS6502_TRAP_CONTINUE: c_int = 0;
+pub const MOS6502_TRAP_RESUME: c_int = 1;
+pub const MOS6502_TRAP_RETURN: c_int = 2;
+pub const MOS6502_TRAP_STOP: c_int = 3;
+
+struct Hook {
+    range: RangeInclusive<Address>,
+    read: ReadHook,
+    write: WriteHook,
+    user: *mut c_void,
+}
+
+/// RAM with I/O hooks over parts of it. The first hook added over an
+/// address takes it; a hook without a read or write function leaves those
+/// accesses to RAM.
+pub struct HookedRam {
+    ram: Ram,
+    hooks: Vec<Hook>,
+}
+
+impl HookedRam {
+    fn hook(&self, address: Address) -> Option<&Hook> {
+        self.hooks.iter().find(|hook| hook.range.contains(&address))
+    }
+}
+
+impl Memory for HookedRam {
+    fn read_u8(&mut self, address: Address) -> u8 {
+        match self.hook(address) {
+            Some(&Hook {
+                read: Some(read),
+                user,
+                ..
+            }) => read(user, address),
+            _ => self.ram.read_u8(address),
+        }
+    }
+    fn write_u8(&mut self, address: Address, data: u8) {
+        match self.hook(address) {
+            Some(&Hook {
+                write: Some(write),
+                user,
+                ..
+            }) => write(user, address, data),
+            _ => self.ram.write_u8(address, data),
+        }
+    }
+    fn load(&mut self, address: Address, data: &[u8]) {
+        self.ram.load(address, data);
+    }
+}
+
+impl MemoryReadOnly for HookedRam {
+    fn read_u8_read_only(&self, address: Address) -> u8 {
+        self.ram.read_u8_read_only(address)
+    }
+}
+
+/// Opaque to C.
+// Transparent so that the `Machine` a trap is given can be cast back to
+// one. cbindgen would emit it as a typedef of `Machine`, so it's left
+// opaque there.
+#[cfg_attr(not(cbindgen), repr(transparent))]
+pub struct Mos6502Machine {
+    machine: Machine<HookedRam>,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Mos6502Registers {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    /// As an interrupt would push it.
+    pub p: u8,
+}
+
+fn error_code(error: StepError) -> c_int {
+    match error {
+        StepError::UnknownOpcode(_) => MOS6502_ERROR_UNKNOWN_OPCODE,
+        StepError::Fault(_) => MOS6502_ERROR_FAULT,
+        StepError::TrapStop => MOS6502_ERROR_TRAP_STOP,
+        StepError::Halted(_) => MOS6502_ERROR_HALTED,
+    }
+}
+
+/// A machine with 64KB of RAM, all zero, and the CPU at $0000. Free it with
+/// `mos6502_machine_free`.
+#[no_mangle]
+pub extern "C" fn mos6502_machine_new() -> *mut Mos6502Machine {
+    let memory = HookedRam {
+        ram: Ram::new(),
+        hooks: Vec::new(),
+    };
+    Box::into_raw(Box::new(Mos6502Machine {
+        machine: Machine::new(Cpu::new(), memory),
+    }))
+}
+
+/// # Safety
+///
+/// `machine` must be null or from `mos6502_machine_new`, and not used
+/// again.
+#[no_mangle]
+pub unsafe extern "C" fn mos6502_machine_free(machine: *mut Mos6502Machine) {
+    if !machine.is_null() {
+        drop(Box::from_raw(machine));
+    }
+}
+
+/// Copies `len` bytes into RAM at `address`, bypassing hooks. Returns
+/// false without loading anything if `data` is null and `len` isn't zero.
+///
+/// # Safety
+///
+/// Unless it's null, `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn mos6502_machine_load(
+    machine: *mut Mos6502Machine,
+    address: u16,
+    data: *const u8,
+    len: usize,
+) -> bool {
+    if len == 0 {
+        return true;
+    }
+    if data.is_null() {
+        return false;
+    }
+    let data = core::slice::from_raw_parts(data, len);
+    (*machine).machine.memory.load(address, data);
+    true
+}
+
+/// Reads as the CPU would, through any hook.
+///
+/// # Safety
+///
+/// See the crate documentation.
+#[no_mangle]
+pub unsafe extern "C" fn mos6502_machine_read(machine: *mut Mos6502Machine, address: u16) -> u8 {
+    (*machine).machine.memory.read_u8(address)
+}
+
+/// Writes as the CPU would, through any hook.
+///
+/// # Safety
+///
+/// See the crate documentation.
+#[no_mangle]
+pub unsafe extern "C" fn mos6502_machine_write(
+    machine: *mut Mos6502Machine,
+    address: u16,
+    data: u8,
+) {
+    (*machine).machine.memory.write_u8(address, data);
+}
+
+/// Runs one instruction, returning the cycles it took or a negative
+/// `MOS6502_ERROR_*` code.
+///
+/// # Safety
+///
+/// See the crate documentation.
+#[no_mangle]
+pub unsafe extern "C" fn mos6502_machine_step(machine: *mut Mos6502Machine) -> c_int {
+    match (*machine).machine.step() {
+        Ok(cycles) => cycles as c_int,
+        Err(error) => error_code(error),
+    }
+}
+
+/// Runs until at least `cycles` cycles have passed, taking interrupts,
+/// and returns the exact count, or a negative `MOS6502_ERROR_*` code. A
+/// halt ends the run early without an error.
+///
+/// # Safety
+///
+/// See the crate documentation.
+#[no_mangle]
+pub unsafe extern "C" fn mos6502_machine_run_cycles(
+    machine: *mut Mos6502Machine,
+    cycles: u32,
+) -> i64 {
+    match (*machine).machine.run_cycles(cycles as usize) {
+        Ok(report) => report.cycles as i64,
+        Err(error) => error_code(error) as i64,
+    }
+}
+
+/// # Safety
+///
+/// See the crate documentation. `registers` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn mos6502_machine_get_registers(
+    machine: *const Mos6502Machine,
+    registers: *mut Mos6502Registers,
+) {
+    let cpu = &(*machine).machine.cpu;
+    *registers = Mos6502Registers {
+        pc: cpu.pc,
+        a: cpu.acc,
+        x: cpu.x,
+        y: cpu.y,
+        sp: cpu.sp,
+        p: u8::from(cpu.status),
+    };
+}
+
+/// # Safety
+///
+/// See the crate documentation. `registers` must be readable.
+#[no_mangle]
+pub unsafe extern "C" fn mos6502_machine_set_registers(
+    machine: *mut Mos6502Machine,
+    registers: *const Mos6502Registers,
+) {
+    let registers = *registers;
+    let cpu = &mut (*machine).machine.cpu;
+    cpu.pc = registers.pc;
+    cpu.acc = registers.a;
+    cpu.x = registers.x;
+    cpu.y = registers.y;
+    cpu.sp = registers.sp;
+    cpu.status = Register::from(registers.p);
+}
+
+/// # Safety
+///
+/// See the crate documentation.
+#[no_mangle]
+pub unsafe extern "C" fn mos6502_machine_cycles(machine: *const Mos6502Machine) -> u64 {
+    (*machine).machine.cycles()
+}
+
+/// # Safety
+///
+/// See the crate documentation.
+#[no_mangle]
+pub unsafe extern "C" fn mos6502_machine_reset(machine: *mut Mos6502Machine) {
+    (*machine).machine.reset();
+}
+
+/// # Safety
+///
+/// See the crate documentation.
+#[no_mangle]
+pub unsafe extern "C" fn mos6502_machine_set_irq(machine: *mut Mos6502Machine, asserted: bool) {
+    (*machine).machine.set_irq(asserted);
+}
+
+/// # Safety
+///
+/// See the crate documentation.
+#[no_mangle]
+pub unsafe extern "C" fn mos6502_machine_request_nmi(machine: *mut Mos6502Machine) {
+    (*machine).machine.request_nmi();
+}
+
+/// Sends reads and writes from `first` to `last` to `read` and `write`,
+/// either of which can be null to leave those accesses to RAM.
+///
+/// # Safety
+///
+/// See the crate documentation. `user` is passed back to the hooks as is,
+/// and must stay valid for as long as the machine.
+#[no_mangle]
+pub unsafe extern "C" fn mos6502_machine_add_io_hook(
+    machine: *mut Mos6502Machine,
+    first: u16,
+    last: u16,
+    read: ReadHook,
+    write: WriteHook,
+    user: *mut c_void,
+) {
+    (*machine).machine.memory.hooks.push(Hook {
+        range: first..=last,
+        read,
+        write,
+        user,
+    });
+}
+
+/// Calls `hook` before the instruction at `address` runs, with the machine,
+/// which it may change. It returns a `MOS6502_TRAP_*` action: carry on
+/// with the instruction, resume at the program counter without it, return
+/// as if by `RTS`, or stop with `MOS6502_ERROR_TRAP_STOP`.
+///
+/// # Safety
+///
+/// See the crate documentation. `user` is passed back to the hook as is,
+/// and must stay valid for as long as the machine.
+#[no_mangle]
+pub unsafe extern "C" fn mos6502_machine_add_trap(
+    machine: *mut Mos6502Machine,
+    address: u16,
+    hook: TrapHook,
+    user: *mut c_void,
+) {
+    (*machine)
+        .machine
+        .add_trap(Trap::Address(address), move |machine| {
+            let machine = (machine as *mut Machine<HookedRam>).cast();
+            match hook(user, machine) {
+                MOS6502_TRAP_RESUME => TrapAction::Resume,
+                MOS6502_TRAP_RETURN => TrapAction::Return,
+                MOS6502_TRAP_STOP => TrapAction::Stop,
+                _ => TrapAction::Continue,
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ptr;
+
+    extern "C" fn read_hook(_: *mut c_void, address: u16) -> u8 {
+        address as u8 ^ 0x40
+    }
+
+    extern "C" fn write_hook(user: *mut c_void, address: u16, data: u8) {
+        let writes = unsafe { &mut *user.cast::<Vec<(u16, u8)>>() };
+        writes.push((address, data));
+    }
+
+    extern "C" fn stop(user: *mut c_void, machine: *mut Mos6502Machine) -> c_int {
+        let mut registers = Mos6502Registers::default();
+        unsafe { mos6502_machine_get_registers(machine, &mut registers) };
+        unsafe { *user.cast::<u16>() = registers.pc };
+        MOS6502_TRAP_STOP
+    }
+
+    #[test]
+    fn runs_a_program_through_the_c_api() {
+        let mut writes: Vec<(u16, u8)> = Vec::new();
+        let mut trapped_at = 0u16;
+        unsafe {
+            let machine = mos6502_machine_new();
+            // LDA $D002; STA $D003; LDX #$05; NOP
+            let program = [0xAD, 0x02, 0xD0, 0x8D, 0x03, 0xD0, 0xA2, 0x05, 0xEA];
+            assert!(mos6502_machine_load(
+                machine,
+                0x0200,
+                program.as_ptr(),
+                program.len()
+            ));
+            assert!(!mos6502_machine_load(machine, 0x0200, ptr::null(), 1));
+            mos6502_machine_add_io_hook(
+                machine,
+                0xD000,
+                0xD0FF,
+                Some(read_hook),
+                Some(write_hook),
+                (&mut writes as *mut Vec<(u16, u8)>).cast(),
+            );
+            mos6502_machine_add_trap(machine, 0x0208, stop, (&mut trapped_at as *mut u16).cast());
+            let mut registers = Mos6502Registers {
+                pc: 0x0200,
+                sp: 0xFF,
+                ..Default::default()
+            };
+            mos6502_machine_set_registers(machine, &registers);
+
+            assert_eq!(mos6502_machine_step(machine), 4);
+            assert_eq!(mos6502_machine_step(machine), 4);
+            assert_eq!(mos6502_machine_step(machine), 2);
+            mos6502_machine_get_registers(machine, &mut registers);
+            assert_eq!(
+                (registers.pc, registers.a, registers.x),
+                (0x0208, 0x42, 0x05)
+            );
+            assert_eq!(writes, [(0xD003, 0x42)]);
+            assert_eq!(mos6502_machine_cycles(machine), 10);
+
+            assert_eq!(mos6502_machine_step(machine), MOS6502_ERROR_TRAP_STOP);
+            assert_eq!(trapped_at, 0x0208);
+            // Reads and writes from C go through the hooks as well.
+            assert_eq!(mos6502_machine_read(machine, 0xD001), 0x41);
+            mos6502_machine_write(machine, 0xD004, 0x99);
+            assert_eq!(writes, [(0xD003, 0x42), (0xD004, 0x99)]);
+            assert_eq!(mos6502_machine_read(machine, 0x0206), 0xA2);
+
+            mos6502_machine_free(machine);
+            mos6502_machine_free(ptr::null_mut());
+        }
+    }
+}
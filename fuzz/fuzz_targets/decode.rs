@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use portal_solutions_mos6502_model::debug;
+
+fuzz_target!(|data: &[u8]| {
+    debug::decode_all(data);
+});
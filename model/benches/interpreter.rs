@@ -0,0 +1,95 @@
+//! Compares ways of interpreting NMOS 6502 code on a hot loop of loads,
+//! stores, arithmetic and branches. Run with
+//! `cargo bench -p portal-solutions-mos6502-model --features threaded` to
+//! include the threaded backend.
+use portal_solutions_mos6502_model::decode_cache::DecodeCache;
+use portal_solutions_mos6502_model::machine::{Cpu, Memory, Ram};
+use portal_solutions_mos6502_model::memory_map::MemoryMap;
+#[cfg(feature = "threaded")]
+use portal_solutions_mos6502_model::threaded::Threaded;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const START: u16 = 0x0200;
+const CYCLES: usize = 20_000_000;
+
+#[rustfmt::skip]
+const PROGRAM: &[u8] = &[
+    0xA2, 0x00,       // LDX #0
+    0xBD, 0x00, 0x10, // LDA $1000,X
+    0x69, 0x01,       // ADC #1
+    0x9D, 0x00, 0x10, // STA $1000,X
+    0x85, 0x10,       // STA $10
+    0xE8,             // INX
+    0xD0, 0xF3,       // BNE $0202
+    0xE6, 0x00,       // INC $00
+    0x4C, 0x00, 0x02, // JMP $0200
+];
+
+fn ram() -> Ram {
+    let mut ram = Ram::new();
+    ram.write_block(START, PROGRAM);
+    ram
+}
+
+fn memory_map() -> MemoryMap {
+    let mut memory = MemoryMap::new().ram(0x0000..=0x1FFF);
+    memory.write_block(START, PROGRAM);
+    memory
+}
+
+// Times `step` until it has run `CYCLES` cycles, and prints the rate and
+// how many times faster than `baseline` it was.
+fn bench<M, F>(name: &str, mut memory: M, baseline: Option<Duration>, mut step: F) -> Duration
+where
+    F: FnMut(&mut Cpu, &mut M) -> usize,
+{
+    let mut cpu = Cpu::new();
+    cpu.pc = START;
+    let memory = &mut memory;
+    let start = Instant::now();
+    let mut elapsed = 0;
+    while elapsed < CYCLES {
+        elapsed += step(&mut cpu, memory);
+    }
+    let time = start.elapsed();
+    black_box(&cpu);
+    let mhz = elapsed as f64 / time.as_secs_f64() / 1e6;
+    match baseline {
+        Some(baseline) => {
+            let speedup = baseline.as_secs_f64() / time.as_secs_f64();
+            println!("  {:<16} {:>7.1} MHz  {:>5.2}x", name, mhz, speedup)
+        }
+        None => println!("  {:<16} {:>7.1} MHz", name, mhz),
+    }
+    time
+}
+
+// Runs each interpreter over memory made by `memory`, comparing them with
+// `Cpu::step`.
+fn run<M: Memory>(label: &str, memory: fn() -> M) {
+    println!("{}:", label);
+    let step = bench("Cpu::step", memory(), None, |cpu, memory| {
+        cpu.step(memory).unwrap() as usize
+    });
+    bench("Cpu::execute", memory(), Some(step), |cpu, memory| {
+        let opcode = memory.read_u8(cpu.pc);
+        cpu.execute(opcode, memory).unwrap() as usize
+    });
+    let mut cache = DecodeCache::new();
+    bench("DecodeCache", memory(), Some(step), |cpu, memory| {
+        cache.step(cpu, memory).unwrap() as usize
+    });
+    #[cfg(feature = "threaded")]
+    {
+        let mut threaded = Threaded::new();
+        bench("Threaded", memory(), Some(step), |cpu, memory| {
+            threaded.run_block(cpu, memory).unwrap().cycles
+        });
+    }
+}
+
+fn main() {
+    run("Ram", ram);
+    run("MemoryMap", memory_map);
+}
@@ -0,0 +1,129 @@
+//! A 6551 ACIA. Characters are sent and received instantly through a
+//! `SerialPort` rather than at the programmed baud rate, and parity, echo
+//! and break aren't modelled. Bytes are only taken from the port while the
+//! receive register is empty, so overruns never happen.
+use crate::peripheral::{InvalidState, Peripheral};
+use crate::Address;
+use alloc::{boxed::Box, vec, vec::Vec};
+
+pub const DATA: Address = 0;
+/// Reads give the status, and writes do a programmed reset.
+pub const STATUS: Address = 1;
+pub const COMMAND: Address = 2;
+pub const CONTROL: Address = 3;
+
+pub const STATUS_RECEIVE_FULL: u8 = 0x08;
+pub const STATUS_TRANSMIT_EMPTY: u8 = 0x10;
+pub const STATUS_IRQ: u8 = 0x80;
+
+/// Data terminal ready, which enables the receiver.
+pub const COMMAND_DTR: u8 = 0x01;
+/// Disables the receive interrupt when set.
+pub const COMMAND_RECEIVE_IRQ_DISABLE: u8 = 0x02;
+pub const COMMAND_TRANSMIT_CONTROL: u8 = 0x0C;
+/// The transmitter control value which enables the transmit interrupt.
+pub const TRANSMIT_IRQ_ENABLED: u8 = 0x04;
+
+/// The other end of the serial line.
+pub trait SerialPort {
+    /// The next byte arriving, if there is one.
+    fn receive(&mut self) -> Option<u8>;
+    fn transmit(&mut self, data: u8);
+}
+
+pub struct Acia {
+    port: Box<dyn SerialPort>,
+    receive: u8,
+    receive_full: bool,
+    irq: bool,
+    command: u8,
+    control: u8,
+}
+
+impl Acia {
+    pub fn new<P: SerialPort + 'static>(port: P) -> Self {
+        Self {
+            port: Box::new(port),
+            receive: 0,
+            receive_full: false,
+            irq: false,
+            command: COMMAND_RECEIVE_IRQ_DISABLE,
+            control: 0,
+        }
+    }
+    fn status(&self) -> u8 {
+        let mut status = STATUS_TRANSMIT_EMPTY;
+        if self.receive_full {
+            status |= STATUS_RECEIVE_FULL;
+        }
+        if self.irq {
+            status |= STATUS_IRQ;
+        }
+        status
+    }
+    fn receiver_enabled(&self) -> bool {
+        self.command & COMMAND_DTR != 0
+    }
+}
+
+impl Peripheral for Acia {
+    fn read(&mut self, offset: Address) -> u8 {
+        match offset & 3 {
+            DATA => {
+                self.receive_full = false;
+                self.receive
+            }
+            STATUS => {
+                let status = self.status();
+                self.irq = false;
+                status
+            }
+            COMMAND => self.command,
+            _ => self.control,
+        }
+    }
+    fn write(&mut self, offset: Address, data: u8) {
+        match offset & 3 {
+            DATA => {
+                self.port.transmit(data);
+                // The transmit register empties again straight away.
+                if self.command & COMMAND_TRANSMIT_CONTROL == TRANSMIT_IRQ_ENABLED {
+                    self.irq = true;
+                }
+            }
+            STATUS => self.command &= 0xE0,
+            COMMAND => self.command = data,
+            _ => self.control = data,
+        }
+    }
+    fn tick(&mut self, _cycles: u8) {
+        if !self.receiver_enabled() || self.receive_full {
+            return;
+        }
+        if let Some(data) = self.port.receive() {
+            self.receive = data;
+            self.receive_full = true;
+            if self.command & COMMAND_RECEIVE_IRQ_DISABLE == 0 {
+                self.irq = true;
+            }
+        }
+    }
+    fn irq(&self) -> bool {
+        self.irq
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let flags = self.receive_full as u8 | (self.irq as u8) << 1;
+        vec![self.receive, flags, self.command, self.control]
+    }
+    fn load_state(&mut self, state: &[u8]) -> Result<(), InvalidState> {
+        let &[receive, flags, command, control] = state else {
+            return Err(InvalidState);
+        };
+        self.receive = receive;
+        self.receive_full = flags & 1 != 0;
+        self.irq = flags & 2 != 0;
+        self.command = command;
+        self.control = control;
+        Ok(())
+    }
+}
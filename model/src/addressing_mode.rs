@@ -19,7 +19,116 @@ pub trait ReadData: Trait {
 }
 
 pub trait WriteData: Trait {
-    fn write_data<M: Memory>(cpu: &Cpu, memory: &mut M, data: u8);
+    /// Finds the address to write to, making the accesses the 6502 makes
+    /// on the way. Indexed modes always read from the address before the
+    /// carry into the high byte is fixed, as a write can't be undone.
+    fn write_address<M: Memory>(cpu: &Cpu, memory: &mut M) -> Address;
+    fn write_data<M: Memory>(cpu: &Cpu, memory: &mut M, data: u8) {
+        let address = Self::write_address(cpu, memory);
+        memory.write_u8(address, data)
+    }
+}
+
+pub trait ReadModifyWriteData: ReadData + WriteData {
+    /// Reads data to be modified and written back to the address returned,
+    /// first writing it back unchanged if `Quirks::rmw_dummy_write` is set,
+    /// as the NMOS 6502 does, or reading it again if not. The address is
+    /// found as for a write.
+    fn read_data_for_modify<M: Memory>(cpu: &Cpu, memory: &mut M) -> (Address, u8) {
+        let address = Self::write_address(cpu, memory);
+        let data = memory.read_u8(address);
+        if cpu.quirks.rmw_dummy_write {
+            memory.write_u8(address, data);
+        } else {
+            memory.read_u8_dummy(address);
+        }
+        (address, data)
+    }
+}
+impl<A: ReadData + WriteData> ReadModifyWriteData for A {}
+
+// The address of byte `offset` of the zero page, wherever the variant puts
+// it.
+pub(crate) fn zero_page_address<M: Memory>(memory: &M, offset: Address) -> Address {
+    memory.zero_page_base().wrapping_add(offset)
+}
+
+// Indexes into the zero page, carrying into the next page if
+// `Quirks::zero_page_wrap` is unset.
+pub(crate) fn zero_page_indexed(cpu: &Cpu, base: u8, index: u8) -> Address {
+    if cpu.quirks.zero_page_wrap {
+        base.wrapping_add(index) as Address
+    } else {
+        base as Address + index as Address
+    }
+}
+
+// Reads the base from the operand, then reads from it while the index is
+// added, and returns the indexed offset into the zero page.
+fn zero_page_indexed_offset<M: Memory>(cpu: &Cpu, memory: &mut M, index: u8) -> Address {
+    let base = memory.read_u8(cpu.pc.wrapping_add(1));
+    memory.read_u8_dummy(zero_page_address(memory, base as Address));
+    zero_page_indexed(cpu, base, index)
+}
+
+fn zero_page_indexed_address<M: Memory>(cpu: &Cpu, memory: &mut M, index: u8) -> Address {
+    let offset = zero_page_indexed_offset(cpu, memory, index);
+    zero_page_address(memory, offset)
+}
+
+fn read_zero_page_indexed<M: Memory>(cpu: &Cpu, memory: &mut M, index: u8) -> u8 {
+    match zero_page_indexed_offset(cpu, memory, index) {
+        offset if cpu.quirks.zero_page_wrap => memory.read_u8_zero_page(offset as u8),
+        offset => memory.read_u8(zero_page_address(memory, offset)),
+    }
+}
+
+fn write_zero_page_indexed<M: Memory>(cpu: &Cpu, memory: &mut M, index: u8, data: u8) {
+    match zero_page_indexed_offset(cpu, memory, index) {
+        offset if cpu.quirks.zero_page_wrap => memory.write_u8_zero_page(offset as u8, data),
+        offset => memory.write_u8(zero_page_address(memory, offset), data),
+    }
+}
+
+// Reads a pointer from the zero page, which may straddle into the next page
+// if `Quirks::zero_page_wrap` is unset.
+pub(crate) fn read_zero_page_pointer<M: Memory>(
+    cpu: &Cpu,
+    memory: &mut M,
+    base: u8,
+    index: u8,
+) -> Address {
+    if cpu.quirks.zero_page_wrap {
+        memory.read_u16_le_zero_page(base.wrapping_add(index))
+    } else {
+        memory.read_u16_le(zero_page_address(
+            memory,
+            base as Address + index as Address,
+        ))
+    }
+}
+
+// Adds `index` to `base` as the 6502 does, a byte at a time: the first
+// read is from the low byte's sum with the high byte not yet carried
+// into. That read is the one wanted unless a page is crossed, and then
+// it's thrown away, so for a read it's only made if `always` is unset
+// and a page is crossed. Returns the indexed address, and whether a page
+// was crossed.
+pub(crate) fn index_address<M: Memory>(
+    memory: &mut M,
+    base: Address,
+    index: u8,
+    always: bool,
+) -> (Address, bool) {
+    let indexed = base.wrapping_add(index as Address);
+    let crossed = address::on_different_pages(base, indexed);
+    if always || crossed {
+        memory.read_u8_dummy(address::from_u8_lo_hi(
+            address::lo(indexed),
+            address::hi(base),
+        ));
+    }
+    (indexed, crossed)
 }
 
 pub struct Absolute;
@@ -43,9 +152,8 @@ impl ReadData for Absolute {
     }
 }
 impl WriteData for Absolute {
-    fn write_data<M: Memory>(cpu: &Cpu, memory: &mut M, data: u8) {
-        let address = Self::address(cpu, memory);
-        memory.write_u8(address, data)
+    fn write_address<M: Memory>(cpu: &Cpu, memory: &mut M) -> Address {
+        Self::address(cpu, memory)
     }
 }
 
@@ -54,17 +162,12 @@ impl Trait for AbsoluteXIndexed {
     type Operand = operand::Address;
 }
 impl AbsoluteXIndexed {
-    pub fn address<M: Memory>(cpu: &Cpu, memory: &mut M) -> Address {
-        let base_address = memory.read_u16_le(cpu.pc.wrapping_add(1));
-        base_address.wrapping_add(cpu.x as Address)
-    }
-    fn address_check_cross_page_boundary<M: Memory>(cpu: &Cpu, memory: &mut M) -> (Address, bool) {
+    pub fn address_check_cross_page_boundary<M: Memory>(
+        cpu: &Cpu,
+        memory: &mut M,
+    ) -> (Address, bool) {
         let base_address = memory.read_u16_le(cpu.pc.wrapping_add(1));
-        let indexed_address = base_address.wrapping_add(cpu.x as Address);
-        (
-            indexed_address,
-            address::on_different_pages(base_address, indexed_address),
-        )
+        index_address(memory, base_address, cpu.x, false)
     }
     pub fn read_data_check_cross_page_boundary<M: Memory>(cpu: &Cpu, memory: &mut M) -> (u8, bool) {
         let (address, cross_page_boundary) = Self::address_check_cross_page_boundary(cpu, memory);
@@ -73,14 +176,13 @@ impl AbsoluteXIndexed {
 }
 impl ReadData for AbsoluteXIndexed {
     fn read_data<M: Memory>(cpu: &Cpu, memory: &mut M) -> u8 {
-        let address = Self::address(cpu, memory);
-        memory.read_u8(address)
+        Self::read_data_check_cross_page_boundary(cpu, memory).0
     }
 }
 impl WriteData for AbsoluteXIndexed {
-    fn write_data<M: Memory>(cpu: &Cpu, memory: &mut M, data: u8) {
-        let address = Self::address(cpu, memory);
-        memory.write_u8(address, data)
+    fn write_address<M: Memory>(cpu: &Cpu, memory: &mut M) -> Address {
+        let base_address = memory.read_u16_le(cpu.pc.wrapping_add(1));
+        index_address(memory, base_address, cpu.x, true).0
     }
 }
 
@@ -89,20 +191,12 @@ impl Trait for AbsoluteYIndexed {
     type Operand = operand::Address;
 }
 impl AbsoluteYIndexed {
-    pub fn address<M: Memory>(cpu: &Cpu, memory: &mut M) -> Address {
-        let base_address = memory.read_u16_le(cpu.pc.wrapping_add(1));
-        base_address.wrapping_add(cpu.y as Address)
-    }
     pub fn address_check_cross_page_boundary<M: Memory>(
         cpu: &Cpu,
         memory: &mut M,
     ) -> (Address, bool) {
         let base_address = memory.read_u16_le(cpu.pc.wrapping_add(1));
-        let indexed_address = base_address.wrapping_add(cpu.y as Address);
-        (
-            indexed_address,
-            address::on_different_pages(base_address, indexed_address),
-        )
+        index_address(memory, base_address, cpu.y, false)
     }
     pub fn read_data_check_cross_page_boundary<M: Memory>(cpu: &Cpu, memory: &mut M) -> (u8, bool) {
         let (address, cross_page_boundary) = Self::address_check_cross_page_boundary(cpu, memory);
@@ -111,14 +205,13 @@ impl AbsoluteYIndexed {
 }
 impl ReadData for AbsoluteYIndexed {
     fn read_data<M: Memory>(cpu: &Cpu, memory: &mut M) -> u8 {
-        let address = Self::address(cpu, memory);
-        memory.read_u8(address)
+        Self::read_data_check_cross_page_boundary(cpu, memory).0
     }
 }
 impl WriteData for AbsoluteYIndexed {
-    fn write_data<M: Memory>(cpu: &Cpu, memory: &mut M, data: u8) {
-        let address = Self::address(cpu, memory);
-        memory.write_u8(address, data)
+    fn write_address<M: Memory>(cpu: &Cpu, memory: &mut M) -> Address {
+        let base_address = memory.read_u16_le(cpu.pc.wrapping_add(1));
+        index_address(memory, base_address, cpu.y, true).0
     }
 }
 
@@ -126,6 +219,13 @@ pub struct Accumulator;
 impl Trait for Accumulator {
     type Operand = operand::None;
 }
+impl Accumulator {
+    /// Makes the read of the byte after the opcode which the 6502 throws
+    /// away.
+    pub fn read_dummy<M: Memory>(cpu: &Cpu, memory: &mut M) {
+        memory.read_u8_dummy(cpu.pc.wrapping_add(1));
+    }
+}
 
 pub struct Immediate;
 impl Trait for Immediate {
@@ -141,6 +241,13 @@ pub struct Implied;
 impl Trait for Implied {
     type Operand = operand::None;
 }
+impl Implied {
+    /// Makes the read of the byte after the opcode which the 6502 throws
+    /// away.
+    pub fn read_dummy<M: Memory>(cpu: &Cpu, memory: &mut M) {
+        memory.read_u8_dummy(cpu.pc.wrapping_add(1));
+    }
+}
 
 pub struct Indirect;
 impl Trait for Indirect {
@@ -149,7 +256,7 @@ impl Trait for Indirect {
 impl ReadJumpTarget for Indirect {
     fn read_jump_target<M: Memory>(cpu: &Cpu, memory: &mut M) -> Address {
         let address = memory.read_u16_le(cpu.pc.wrapping_add(1));
-        if address::lo(address) != 0xFF {
+        if address::lo(address) != 0xFF || !cpu.quirks.jmp_indirect_page_wrap {
             memory.read_u16_le(address)
         } else {
             let lo = memory.read_u8(address);
@@ -164,23 +271,16 @@ impl Trait for IndirectYIndexed {
     type Operand = operand::Byte;
 }
 impl IndirectYIndexed {
-    fn address<M: Memory>(cpu: &Cpu, memory: &mut M) -> Address {
-        let base_address = memory.read_u8(cpu.pc.wrapping_add(1));
-        memory
-            .read_u16_le_zero_page(base_address)
-            .wrapping_add(cpu.y as Address)
+    fn base_address<M: Memory>(cpu: &Cpu, memory: &mut M) -> Address {
+        let indirect_address = memory.read_u8(cpu.pc.wrapping_add(1));
+        read_zero_page_pointer(cpu, memory, indirect_address, 0)
     }
     pub fn address_check_cross_page_boundary<M: Memory>(
         cpu: &Cpu,
         memory: &mut M,
     ) -> (Address, bool) {
-        let indirect_address = memory.read_u8(cpu.pc.wrapping_add(1));
-        let base_address = memory.read_u16_le_zero_page(indirect_address);
-        let indexed_address = base_address.wrapping_add(cpu.y as Address);
-        (
-            indexed_address,
-            address::on_different_pages(base_address, indexed_address),
-        )
+        let base_address = Self::base_address(cpu, memory);
+        index_address(memory, base_address, cpu.y, false)
     }
     pub fn read_data_check_cross_page_boundary<M: Memory>(cpu: &Cpu, memory: &mut M) -> (u8, bool) {
         let (address, cross_page_boundary) = Self::address_check_cross_page_boundary(cpu, memory);
@@ -189,14 +289,13 @@ impl IndirectYIndexed {
 }
 impl ReadData for IndirectYIndexed {
     fn read_data<M: Memory>(cpu: &Cpu, memory: &mut M) -> u8 {
-        let address = Self::address(cpu, memory);
-        memory.read_u8(address)
+        Self::read_data_check_cross_page_boundary(cpu, memory).0
     }
 }
 impl WriteData for IndirectYIndexed {
-    fn write_data<M: Memory>(cpu: &Cpu, memory: &mut M, data: u8) {
-        let address = Self::address(cpu, memory);
-        memory.write_u8(address, data)
+    fn write_address<M: Memory>(cpu: &Cpu, memory: &mut M) -> Address {
+        let base_address = Self::base_address(cpu, memory);
+        index_address(memory, base_address, cpu.y, true).0
     }
 }
 
@@ -219,7 +318,8 @@ impl Trait for XIndexedIndirect {
 impl XIndexedIndirect {
     fn address<M: Memory>(cpu: &Cpu, memory: &mut M) -> Address {
         let offset = memory.read_u8(cpu.pc.wrapping_add(1));
-        memory.read_u16_le_zero_page(offset.wrapping_add(cpu.x))
+        memory.read_u8_dummy(zero_page_address(memory, offset as Address));
+        read_zero_page_pointer(cpu, memory, offset, cpu.x)
     }
 }
 impl ReadData for XIndexedIndirect {
@@ -229,9 +329,8 @@ impl ReadData for XIndexedIndirect {
     }
 }
 impl WriteData for XIndexedIndirect {
-    fn write_data<M: Memory>(cpu: &Cpu, memory: &mut M, data: u8) {
-        let address = Self::address(cpu, memory);
-        memory.write_u8(address, data)
+    fn write_address<M: Memory>(cpu: &Cpu, memory: &mut M) -> Address {
+        Self::address(cpu, memory)
     }
 }
 
@@ -246,6 +345,10 @@ impl ReadData for ZeroPage {
     }
 }
 impl WriteData for ZeroPage {
+    fn write_address<M: Memory>(cpu: &Cpu, memory: &mut M) -> Address {
+        let offset = memory.read_u8(cpu.pc.wrapping_add(1));
+        zero_page_address(memory, offset as Address)
+    }
     fn write_data<M: Memory>(cpu: &Cpu, memory: &mut M, data: u8) {
         let address = memory.read_u8(cpu.pc.wrapping_add(1));
         memory.write_u8_zero_page(address, data)
@@ -258,16 +361,15 @@ impl Trait for ZeroPageXIndexed {
 }
 impl ReadData for ZeroPageXIndexed {
     fn read_data<M: Memory>(cpu: &Cpu, memory: &mut M) -> u8 {
-        let base_address_lo = memory.read_u8(cpu.pc.wrapping_add(1));
-        let address_lo = base_address_lo.wrapping_add(cpu.x);
-        memory.read_u8_zero_page(address_lo)
+        read_zero_page_indexed(cpu, memory, cpu.x)
     }
 }
 impl WriteData for ZeroPageXIndexed {
+    fn write_address<M: Memory>(cpu: &Cpu, memory: &mut M) -> Address {
+        zero_page_indexed_address(cpu, memory, cpu.x)
+    }
     fn write_data<M: Memory>(cpu: &Cpu, memory: &mut M, data: u8) {
-        let base_address_lo = memory.read_u8(cpu.pc.wrapping_add(1));
-        let address_lo = base_address_lo.wrapping_add(cpu.x);
-        memory.write_u8_zero_page(address_lo, data)
+        write_zero_page_indexed(cpu, memory, cpu.x, data)
     }
 }
 
@@ -277,15 +379,14 @@ impl Trait for ZeroPageYIndexed {
 }
 impl ReadData for ZeroPageYIndexed {
     fn read_data<M: Memory>(cpu: &Cpu, memory: &mut M) -> u8 {
-        let base_address_lo = memory.read_u8(cpu.pc.wrapping_add(1));
-        let address_lo = base_address_lo.wrapping_add(cpu.y);
-        memory.read_u8_zero_page(address_lo)
+        read_zero_page_indexed(cpu, memory, cpu.y)
     }
 }
 impl WriteData for ZeroPageYIndexed {
+    fn write_address<M: Memory>(cpu: &Cpu, memory: &mut M) -> Address {
+        zero_page_indexed_address(cpu, memory, cpu.y)
+    }
     fn write_data<M: Memory>(cpu: &Cpu, memory: &mut M, data: u8) {
-        let base_address_lo = memory.read_u8(cpu.pc.wrapping_add(1));
-        let address_lo = base_address_lo.wrapping_add(cpu.y);
-        memory.write_u8_zero_page(address_lo, data)
+        write_zero_page_indexed(cpu, memory, cpu.y, data)
     }
 }
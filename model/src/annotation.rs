@@ -0,0 +1,117 @@
+use crate::Address;
+use alloc::{collections::BTreeMap, string::String};
+use core::ops::RangeBounds;
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+/// How the bytes at an address should be interpreted by tools.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    Code,
+    Byte,
+    Word,
+    /// A little-endian address.
+    Pointer,
+    Text,
+}
+
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default)]
+pub struct Annotation {
+    pub comment: Option<String>,
+    pub bookmark: Option<String>,
+    pub data_type: Option<DataType>,
+}
+
+impl Annotation {
+    fn is_empty(&self) -> bool {
+        self.comment.is_none() && self.bookmark.is_none() && self.data_type.is_none()
+    }
+}
+
+/// Notes attached to addresses, accumulated while exploring a program.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default)]
+pub struct Annotations {
+    entries: BTreeMap<Address, Annotation>,
+}
+
+impl Annotations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn get(&self, address: Address) -> Option<&Annotation> {
+        self.entries.get(&address)
+    }
+    fn update<F: FnOnce(&mut Annotation)>(&mut self, address: Address, f: F) {
+        let annotation = self.entries.entry(address).or_default();
+        f(annotation);
+        if annotation.is_empty() {
+            self.entries.remove(&address);
+        }
+    }
+    pub fn set_comment<S: AsRef<str>>(&mut self, address: Address, comment: S) {
+        self.update(address, |a| a.comment = Some(comment.as_ref().into()));
+    }
+    pub fn clear_comment(&mut self, address: Address) {
+        self.update(address, |a| a.comment = None);
+    }
+    pub fn set_bookmark<S: AsRef<str>>(&mut self, address: Address, name: S) {
+        self.update(address, |a| a.bookmark = Some(name.as_ref().into()));
+    }
+    pub fn clear_bookmark(&mut self, address: Address) {
+        self.update(address, |a| a.bookmark = None);
+    }
+    pub fn set_data_type(&mut self, address: Address, data_type: DataType) {
+        self.update(address, |a| a.data_type = Some(data_type));
+    }
+    pub fn clear_data_type(&mut self, address: Address) {
+        self.update(address, |a| a.data_type = None);
+    }
+    pub fn remove(&mut self, address: Address) -> Option<Annotation> {
+        self.entries.remove(&address)
+    }
+    pub fn comment(&self, address: Address) -> Option<&str> {
+        self.get(address)?.comment.as_deref()
+    }
+    pub fn data_type(&self, address: Address) -> Option<DataType> {
+        self.get(address)?.data_type
+    }
+    /// Address of the bookmark with the given name.
+    pub fn find_bookmark(&self, name: &str) -> Option<Address> {
+        self.bookmarks()
+            .find(|&(_, bookmark)| bookmark == name)
+            .map(|(address, _)| address)
+    }
+    pub fn bookmarks(&self) -> impl Iterator<Item = (Address, &str)> {
+        self.entries
+            .iter()
+            .filter_map(|(&address, a)| Some((address, a.bookmark.as_deref()?)))
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (Address, &Annotation)> {
+        self.entries.iter().map(|(&address, a)| (address, a))
+    }
+    pub fn range<R: RangeBounds<Address>>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = (Address, &Annotation)> {
+        self.entries.range(range).map(|(&address, a)| (address, a))
+    }
+    /// Adds every annotation from `other`, which wins where both have a
+    /// value for the same field.
+    pub fn merge(&mut self, other: &Annotations) {
+        for (&address, theirs) in other.entries.iter() {
+            let ours = self.entries.entry(address).or_default();
+            if theirs.comment.is_some() {
+                ours.comment = theirs.comment.clone();
+            }
+            if theirs.bookmark.is_some() {
+                ours.bookmark = theirs.bookmark.clone();
+            }
+            if theirs.data_type.is_some() {
+                ours.data_type = theirs.data_type;
+            }
+        }
+    }
+}
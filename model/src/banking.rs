@@ -0,0 +1,163 @@
+use crate::Address;
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
+
+/// Equally sized banks of memory, any of which can be shown in each of a
+/// set of windows of the address space. Windows are switched by writes to
+/// the registers configured with `register`, or with `switch`. Added to a
+/// `MemoryMap` with `MemoryMap::banked`.
+pub struct Banked {
+    bytes: Vec<u8>,
+    bank_size: usize,
+    writable: bool,
+    // Start address and selected bank of each window.
+    windows: Vec<(Address, usize)>,
+    registers: Vec<(RangeInclusive<Address>, usize)>,
+}
+
+impl Banked {
+    /// Read-only banks cut from `image`, which is padded with zeroes to a
+    /// whole number of banks. `bank_size` must be between 1 and $10000.
+    pub fn rom(image: &[u8], bank_size: usize) -> Self {
+        assert!((1..=0x10000).contains(&bank_size), "invalid bank size");
+        let mut bytes = image.to_vec();
+        bytes.resize(image.len().div_ceil(bank_size).max(1) * bank_size, 0);
+        Self {
+            bytes,
+            bank_size,
+            writable: false,
+            windows: Vec::new(),
+            registers: Vec::new(),
+        }
+    }
+    /// `count` zeroed banks of RAM.
+    pub fn ram(count: usize, bank_size: usize) -> Self {
+        Self {
+            writable: true,
+            ..Self::rom(&alloc::vec![0; count.max(1) * bank_size], bank_size)
+        }
+    }
+    /// Adds a window of one bank's size at `start`, showing `bank`.
+    pub fn window(mut self, start: Address, bank: usize) -> Self {
+        self.windows.push((start, bank % self.num_banks()));
+        self
+    }
+    /// Makes writes to `range` select the bank shown in `window`, modulo
+    /// the number of banks. Register writes don't reach the memory under
+    /// them.
+    pub fn register(mut self, range: RangeInclusive<Address>, window: usize) -> Self {
+        self.registers.push((range, window));
+        self
+    }
+    pub fn num_banks(&self) -> usize {
+        self.bytes.len() / self.bank_size
+    }
+    pub fn bank_size(&self) -> usize {
+        self.bank_size
+    }
+    pub fn num_windows(&self) -> usize {
+        self.windows.len()
+    }
+    /// The bank shown in `window`.
+    pub fn selected(&self, window: usize) -> usize {
+        self.windows[window].1
+    }
+    pub fn switch(&mut self, window: usize, bank: usize) {
+        self.windows[window].1 = bank % self.num_banks();
+    }
+    /// The contents of `bank`, e.g. to load it. Panics if there's no such
+    /// bank.
+    pub fn bank_mut(&mut self, bank: usize) -> &mut [u8] {
+        assert!(bank < self.num_banks(), "no such bank");
+        let start = bank * self.bank_size;
+        &mut self.bytes[start..start + self.bank_size]
+    }
+    pub(crate) fn window_range(&self, window: usize) -> RangeInclusive<Address> {
+        let start = self.windows[window].0;
+        let end = (start as usize + self.bank_size - 1).min(Address::MAX as usize);
+        start..=end as Address
+    }
+    // Handles a write to a bank register, if `address` is one.
+    pub(crate) fn write_register(&mut self, address: Address, data: u8) -> bool {
+        let Some(&(_, window)) = self
+            .registers
+            .iter()
+            .find(|(range, _)| range.contains(&address))
+        else {
+            return false;
+        };
+        self.switch(window, data as usize);
+        true
+    }
+    fn index(&self, window: usize, offset: Address) -> usize {
+        self.windows[window].1 * self.bank_size + offset as usize
+    }
+    pub(crate) fn read(&self, window: usize, offset: Address) -> u8 {
+        self.bytes[self.index(window, offset)]
+    }
+    pub(crate) fn write(&mut self, window: usize, offset: Address, data: u8) {
+        if self.writable {
+            let index = self.index(window, offset);
+            self.bytes[index] = data;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::Memory;
+    use crate::memory_map::MemoryMap;
+
+    // Four 8KB banks of ROM, each filled with its number, shown at $8000
+    // and switched by writes to $8000-$9FFF, over RAM at $0000-$9FFF.
+    fn mapper() -> MemoryMap {
+        let mut banked = Banked::rom(&[0; 0x8000], 0x2000)
+            .window(0x8000, 0)
+            .register(0x8000..=0x9FFF, 0);
+        for bank in 0..4 {
+            banked.bank_mut(bank).fill(bank as u8);
+        }
+        MemoryMap::new().banked(banked).ram(0x0000..=0x9FFF)
+    }
+
+    #[test]
+    fn register_write_switches_bank() {
+        let mut memory = mapper();
+        assert_eq!(memory.read_u8(0x8123), 0);
+        memory.write_u8(0x8000, 2);
+        assert_eq!(memory.read_u8(0x8123), 2);
+        assert_eq!(memory.read_u8(0x9FFF), 2);
+        // Modulo the number of banks.
+        memory.write_u8(0x9FFF, 7);
+        assert_eq!(memory.read_u8(0x8123), 3);
+        assert_eq!(memory.banks_mut(0).unwrap().selected(0), 3);
+    }
+
+    #[test]
+    fn registers_take_priority_over_regions() {
+        let mut memory = MemoryMap::new().ram(0x0000..=0xFFFF).banked(
+            Banked::ram(2, 0x100)
+                .window(0x4000, 0)
+                .register(0x0010..=0x0010, 0),
+        );
+        memory.write_u8(0x0010, 1);
+        // The write switched the bank and didn't reach the RAM.
+        assert_eq!(memory.banks_mut(0).unwrap().selected(0), 1);
+        assert_eq!(memory.read_u8(0x0010), 0);
+    }
+
+    #[test]
+    fn banks_mut_switches_banks() {
+        let mut memory = mapper();
+        memory.banks_mut(0).unwrap().switch(0, 1);
+        assert_eq!(memory.read_u8(0x8000), 1);
+        assert!(memory.banks_mut(1).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "no such bank")]
+    fn bank_mut_out_of_range() {
+        Banked::ram(2, 0x100).bank_mut(2);
+    }
+}
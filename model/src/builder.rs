@@ -0,0 +1,116 @@
+use crate::banking::Banked;
+use crate::machine::{Cpu, Machine, Memory, Quirks, Variant};
+use crate::memory_map::{MemoryMap, RomWrite};
+use crate::{address, interrupt_vector, Address};
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
+
+/// Builds a `Machine` over a `MemoryMap`, with images loaded and the CPU
+/// ready to run from the reset vector.
+///
+/// ```ignore
+/// let machine = MachineBuilder::new()
+///     .ram(0x0000..=0x07FF)
+///     .rom(0xC000, &[0; 0x4000])
+///     .image(0xC000, &assembled)
+///     .reset_vector(0xC000)
+///     .build();
+/// ```
+pub struct MachineBuilder {
+    memory: MemoryMap,
+    images: Vec<(Address, Vec<u8>)>,
+    reset_vector: Option<Address>,
+    variant: Variant,
+    quirks: Quirks,
+}
+
+impl Default for MachineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MachineBuilder {
+    pub fn new() -> Self {
+        Self {
+            memory: MemoryMap::new(),
+            images: Vec::new(),
+            reset_vector: None,
+            variant: Variant::default(),
+            quirks: Quirks::default(),
+        }
+    }
+    /// Replaces the memory map built so far.
+    pub fn memory(mut self, memory: MemoryMap) -> Self {
+        self.memory = memory;
+        self
+    }
+    pub fn ram(mut self, range: RangeInclusive<Address>) -> Self {
+        self.memory = self.memory.ram(range);
+        self
+    }
+    pub fn rom(mut self, start: Address, image: &[u8]) -> Self {
+        self.memory = self.memory.rom(start, image);
+        self
+    }
+    pub fn rom_with(mut self, start: Address, image: &[u8], on_write: RomWrite) -> Self {
+        self.memory = self.memory.rom_with(start, image, on_write);
+        self
+    }
+    pub fn mirror(mut self, range: RangeInclusive<Address>, of: RangeInclusive<Address>) -> Self {
+        self.memory = self.memory.mirror(range, of);
+        self
+    }
+    pub fn io<R, W>(mut self, range: RangeInclusive<Address>, read: R, write: W) -> Self
+    where
+        R: FnMut(Address) -> u8 + 'static,
+        W: FnMut(Address, u8) + 'static,
+    {
+        self.memory = self.memory.io(range, read, write);
+        self
+    }
+    pub fn banked(mut self, banked: Banked) -> Self {
+        self.memory = self.memory.banked(banked);
+        self
+    }
+    /// Loads `image` at `base` when the machine is built, after the regions
+    /// are set up. Images go straight into RAM and ROM, so they can fill in
+    /// ROM regions, and later images overwrite earlier ones.
+    pub fn image(mut self, base: Address, image: &[u8]) -> Self {
+        self.images.push((base, image.into()));
+        self
+    }
+    /// Stores `address` in the reset vector, overriding any image.
+    pub fn reset_vector(mut self, address: Address) -> Self {
+        self.reset_vector = Some(address);
+        self
+    }
+    pub fn variant(mut self, variant: Variant) -> Self {
+        self.variant = variant;
+        self
+    }
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+    /// Loads everything and sets the program counter from the reset vector.
+    pub fn build(mut self) -> Machine<MemoryMap> {
+        for (base, image) in self.images.iter() {
+            self.memory.load(*base, image);
+        }
+        if let Some(reset) = self.reset_vector {
+            let vector = [address::lo(reset), address::hi(reset)];
+            self.memory.load(interrupt_vector::START_LO, &vector);
+        }
+        let mut cpu = Cpu::new();
+        cpu.variant = self.variant;
+        cpu.quirks = self.quirks;
+        cpu.start(&mut self.memory);
+        // The vector may not be mapped, but the requested address still
+        // has to be honoured.
+        if let Some(reset) = self.reset_vector {
+            cpu.pc = reset;
+        }
+        Machine::new(cpu, self.memory)
+    }
+}
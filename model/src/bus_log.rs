@@ -0,0 +1,395 @@
+//! A bounded log of every access the CPU makes to the bus, for debugging
+//! memory-mapped I/O and comparing against logic analyser captures.
+//! Enabled with `Machine::enable_bus_log`.
+//!
+//! As in `pins`, the NMOS core makes one access per cycle in the order
+//! the chip does, so each access is logged with the cycle it's made on,
+//! dummy reads and writes included. Accesses made by the host through
+//! `Machine::memory` aren't seen.
+//!
+//! ```ignore
+//! machine.enable_bus_log(BusLog::new(4096).only(0x4016..=0x4017));
+//! machine.run(Fuel::cycles(30_000))?;
+//! let mut csv = String::new();
+//! machine.bus_log().unwrap().write_csv(&mut csv)?;
+//! ```
+use crate::Address;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub cycle: u64,
+    pub address: Address,
+    /// The byte read or written.
+    pub data: u8,
+    pub write: bool,
+    /// Whether this is an opcode fetch, when the 6502 raises SYNC.
+    pub fetch: bool,
+}
+
+/// As `   1234  $4016 R $41 SYNC`.
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>8}  ${:04X} {} ${:02X}",
+            self.cycle,
+            self.address,
+            if self.write { 'W' } else { 'R' },
+            self.data
+        )?;
+        if self.fetch {
+            write!(f, " SYNC")?;
+        }
+        Ok(())
+    }
+}
+
+pub struct BusLog {
+    accesses: VecDeque<Access>,
+    capacity: usize,
+    ranges: Vec<RangeInclusive<Address>>,
+    dropped: u64,
+}
+
+impl BusLog {
+    /// Keeps the last `capacity` accesses.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            accesses: VecDeque::with_capacity(capacity),
+            capacity,
+            ranges: Vec::new(),
+            dropped: 0,
+        }
+    }
+    /// Logs only accesses within `range`, and within any other ranges
+    /// given, rather than all of them.
+    pub fn only(mut self, range: RangeInclusive<Address>) -> Self {
+        self.ranges.push(range);
+        self
+    }
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    pub fn len(&self) -> usize {
+        self.accesses.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.accesses.is_empty()
+    }
+    /// How many accesses have been dropped to make room for newer ones.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+    pub(crate) fn record(&mut self, access: Access) {
+        if !self.ranges.is_empty()
+            && !self
+                .ranges
+                .iter()
+                .any(|range| range.contains(&access.address))
+        {
+            return;
+        }
+        if self.capacity == 0 {
+            self.dropped += 1;
+            return;
+        }
+        if self.accesses.len() == self.capacity {
+            self.accesses.pop_front();
+            self.dropped += 1;
+        }
+        self.accesses.push_back(access);
+    }
+    /// The accesses, oldest first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Access> {
+        self.accesses.iter()
+    }
+    /// Removes and returns the accesses so far, oldest first, so a long
+    /// run can be exported a buffer at a time.
+    pub fn drain(&mut self) -> impl DoubleEndedIterator<Item = Access> + '_ {
+        self.accesses.drain(..)
+    }
+    pub fn clear(&mut self) {
+        self.accesses.clear();
+        self.dropped = 0;
+    }
+    /// Writes the accesses as CSV, with a header line: `cycle`, `address`
+    /// and `data` in decimal, hex and hex, then `rw` as `R` or `W` and
+    /// `sync` as 0 or 1.
+    pub fn write_csv<W: fmt::Write>(&self, out: &mut W) -> fmt::Result {
+        writeln!(out, "cycle,address,data,rw,sync")?;
+        for access in &self.accesses {
+            writeln!(
+                out,
+                "{},{:04X},{:02X},{},{}",
+                access.cycle,
+                access.address,
+                access.data,
+                if access.write { 'W' } else { 'R' },
+                access.fetch as u8
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// One access per line.
+impl fmt::Display for BusLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for access in &self.accesses {
+            writeln!(f, "{}", access)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address;
+    use crate::debug::{AddressingMode, InstructionType};
+    use crate::machine::{Cpu, Machine, Quirks, Ram};
+    use crate::opcode;
+    use alloc::vec;
+
+    const PC: Address = 0x0200;
+    const SP: u8 = 0xFB;
+
+    fn stack(sp: u8) -> Address {
+        address::from_u8_lo_hi(sp, 0x01)
+    }
+
+    // Whether the branch `instruction_type` is taken with `status`, or
+    // `None` if it isn't a branch.
+    fn taken(instruction_type: InstructionType, status: u8) -> Option<bool> {
+        use InstructionType::*;
+        let (flag, set) = match instruction_type {
+            Bcc => (0x01, false),
+            Bcs => (0x01, true),
+            Bne => (0x02, false),
+            Beq => (0x02, true),
+            Bvc => (0x40, false),
+            Bvs => (0x40, true),
+            Bpl => (0x80, false),
+            Bmi => (0x80, true),
+            _ => return None,
+        };
+        Some((status & flag != 0) == set)
+    }
+
+    // The accesses the NMOS 6502 makes for `opcode`, worked out from its
+    // instruction type and addressing mode alone, as `(address, write)`.
+    fn expected(
+        opcode: u8,
+        [lo, hi]: [u8; 2],
+        index: u8,
+        status: u8,
+        quirks: Quirks,
+        bytes: &[u8],
+    ) -> Vec<(Address, bool)> {
+        use AddressingMode::*;
+        use InstructionType::*;
+        let info = opcode::info(opcode).unwrap();
+        let peek16 = |lo: Address, hi: Address| {
+            address::from_u8_lo_hi(bytes[lo as usize], bytes[hi as usize])
+        };
+        let read = |address| (address, false);
+        let write = |address| (address, true);
+        let mut accesses = vec![read(PC), read(PC + 1)];
+        let rest = match info.instruction_type {
+            Brk => vec![
+                write(stack(SP)),
+                write(stack(SP - 1)),
+                write(stack(SP - 2)),
+                read(0xFFFE),
+                read(0xFFFF),
+            ],
+            Jsr => vec![
+                read(stack(SP)),
+                write(stack(SP)),
+                write(stack(SP - 1)),
+                read(PC + 2),
+            ],
+            Rts => vec![
+                read(stack(SP)),
+                read(stack(SP + 1)),
+                read(stack(SP + 2)),
+                read(peek16(stack(SP + 1), stack(SP + 2))),
+            ],
+            Rti => vec![
+                read(stack(SP)),
+                read(stack(SP + 1)),
+                read(stack(SP + 2)),
+                read(stack(SP + 3)),
+            ],
+            Pha | Php => vec![write(stack(SP))],
+            Pla | Plp => vec![read(stack(SP)), read(stack(SP + 1))],
+            Jmp if info.addressing_mode == Indirect => {
+                let pointer = address::from_u8_lo_hi(lo, hi);
+                vec![read(PC + 2), read(pointer), read(pointer + 1)]
+            }
+            Jmp => vec![read(PC + 2)],
+            _ => match taken(info.instruction_type, status) {
+                Some(false) => vec![],
+                Some(true) => {
+                    let next = PC + 2;
+                    let target = next.wrapping_add(lo as i8 as Address);
+                    let mut rest = vec![read(next)];
+                    if address::hi(target) != address::hi(next) {
+                        rest.push(read(address::from_u8_lo_hi(
+                            address::lo(target),
+                            address::hi(next),
+                        )));
+                    }
+                    rest
+                }
+                None => return data_accesses(accesses, info, [lo, hi], index, quirks, bytes),
+            },
+        };
+        accesses.extend(rest);
+        accesses
+    }
+
+    // Adds the accesses an instruction working on data makes after reading
+    // its first operand byte.
+    fn data_accesses(
+        mut accesses: Vec<(Address, bool)>,
+        info: opcode::Info,
+        [lo, hi]: [u8; 2],
+        index: u8,
+        quirks: Quirks,
+        bytes: &[u8],
+    ) -> Vec<(Address, bool)> {
+        use AddressingMode::*;
+        use InstructionType::*;
+        let read = |address| (address, false);
+        let read_modify_write = matches!(
+            info.instruction_type,
+            Asl | Lsr | Rol | Ror | Inc | Dec | Slo | Rla | Sre | Rra | Dcp | Isc
+        );
+        let writes = matches!(
+            info.instruction_type,
+            Sta | Stx | Sty | Sax | Ahx | Sxa | Sya
+        );
+        // Indexed reads only read before fixing the high byte when a page
+        // is crossed.
+        let indexed = |accesses: &mut Vec<_>, base: Address| {
+            let indexed = base.wrapping_add(index as Address);
+            if read_modify_write || writes || address::hi(indexed) != address::hi(base) {
+                accesses.push(read(address::from_u8_lo_hi(
+                    address::lo(indexed),
+                    address::hi(base),
+                )));
+            }
+            indexed
+        };
+        let zero_page = lo as Address;
+        let target = match info.addressing_mode {
+            Implied | Accumulator | Immediate => return accesses,
+            ZeroPage => zero_page,
+            ZeroPageXIndexed | ZeroPageYIndexed => {
+                accesses.push(read(zero_page));
+                lo.wrapping_add(index) as Address
+            }
+            Absolute => {
+                accesses.push(read(PC + 2));
+                address::from_u8_lo_hi(lo, hi)
+            }
+            AbsoluteXIndexed | AbsoluteYIndexed => {
+                accesses.push(read(PC + 2));
+                indexed(&mut accesses, address::from_u8_lo_hi(lo, hi))
+            }
+            XIndexedIndirect => {
+                let pointer = lo.wrapping_add(index) as Address;
+                let pointer_hi = lo.wrapping_add(index).wrapping_add(1) as Address;
+                accesses.extend([read(zero_page), read(pointer), read(pointer_hi)]);
+                address::from_u8_lo_hi(bytes[pointer as usize], bytes[pointer_hi as usize])
+            }
+            IndirectYIndexed => {
+                let pointer_hi = lo.wrapping_add(1) as Address;
+                accesses.extend([read(zero_page), read(pointer_hi)]);
+                let base = address::from_u8_lo_hi(bytes[lo as usize], bytes[pointer_hi as usize]);
+                indexed(&mut accesses, base)
+            }
+            Indirect | Relative => unreachable!(),
+        };
+        if read_modify_write {
+            accesses.extend([
+                read(target),
+                (target, quirks.rmw_dummy_write),
+                (target, true),
+            ]);
+        } else {
+            accesses.push((target, writes));
+        }
+        accesses
+    }
+
+    #[test]
+    fn accesses_match_hardware() {
+        let mut compared = 0;
+        for opcode in 0..=0xFF {
+            let Some(info) = opcode::info(opcode) else {
+                continue;
+            };
+            if info.instruction_type == InstructionType::Kil {
+                continue;
+            }
+            for operand in [[0x10, 0x12], [0x80, 0x12]] {
+                for index in [0x01, 0xFF] {
+                    for status in [0x00, 0xFF] {
+                        for quirks in [Quirks::nmos(), Quirks::fixed()] {
+                            let mut ram = Ram::new();
+                            let bytes = ram.as_mut_slice();
+                            bytes[PC as usize..PC as usize + 3]
+                                .copy_from_slice(&[opcode, operand[0], operand[1]]);
+                            bytes[0x10..0x12].copy_from_slice(&[0x40, 0x13]);
+                            bytes[0x80..0x82].copy_from_slice(&[0xF0, 0x12]);
+                            bytes[stack(SP + 1) as usize..stack(SP + 3) as usize]
+                                .copy_from_slice(&[0x34, 0x56]);
+                            let expected =
+                                expected(opcode, operand, index, status, quirks, ram.as_slice());
+                            let mut cpu = Cpu::new();
+                            cpu.pc = PC;
+                            cpu.sp = SP;
+                            cpu.x = index;
+                            cpu.y = index;
+                            cpu.status.set(status);
+                            cpu.quirks = quirks;
+                            let mut machine = Machine::new(cpu, ram);
+                            machine.enable_bus_log(BusLog::new(16));
+                            machine.step().unwrap();
+                            let log = machine.bus_log().unwrap();
+                            let mut accesses: Vec<_> = log
+                                .iter()
+                                .map(|access| (access.address, access.write))
+                                .collect();
+                            // These write to an address depending on the data.
+                            if matches!(
+                                info.instruction_type,
+                                InstructionType::Ahx | InstructionType::Sxa | InstructionType::Sya
+                            ) {
+                                accesses.last_mut().unwrap().0 = expected.last().unwrap().0;
+                            }
+                            assert_eq!(
+                                accesses, expected,
+                                "opcode {opcode:02X} operand {operand:02X?} index {index:02X} status {status:02X}"
+                            );
+                            assert!(log
+                                .iter()
+                                .map(|access| access.cycle)
+                                .eq(0..expected.len() as u64));
+                            assert!(log.iter().take(1).all(|access| access.fetch));
+                            assert!(log.iter().skip(1).all(|access| !access.fetch));
+                            compared += 1;
+                        }
+                    }
+                }
+            }
+        }
+        assert!(compared > 0);
+    }
+}
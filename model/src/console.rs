@@ -0,0 +1,85 @@
+use crate::peripheral::{InvalidState, Peripheral};
+use crate::Address;
+use alloc::{boxed::Box, vec, vec::Vec};
+
+pub const DATA: Address = 0;
+/// Bit 0 is set while input is waiting, and bit 1 is always set since
+/// output never blocks.
+pub const STATUS: Address = 1;
+
+pub const STATUS_INPUT_READY: u8 = 0x01;
+pub const STATUS_OUTPUT_READY: u8 = 0x02;
+
+/// A character device for programs to talk to the host with. Writes to
+/// `DATA` go to the sink, and reads from it take the next byte from the
+/// source, or 0 if there isn't one. Map it over one byte to leave out the
+/// `STATUS` register.
+pub struct Console {
+    sink: Box<dyn FnMut(u8)>,
+    source: Box<dyn FnMut() -> Option<u8>>,
+    // A byte taken from the source to answer a status read.
+    pending: Option<u8>,
+}
+
+impl Console {
+    pub fn new<W, R>(sink: W, source: R) -> Self
+    where
+        W: FnMut(u8) + 'static,
+        R: FnMut() -> Option<u8> + 'static,
+    {
+        Self {
+            sink: Box::new(sink),
+            source: Box::new(source),
+            pending: None,
+        }
+    }
+    /// A console with no input.
+    pub fn output_only<W: FnMut(u8) + 'static>(sink: W) -> Self {
+        Self::new(sink, || None)
+    }
+    fn peek(&mut self) -> Option<u8> {
+        if self.pending.is_none() {
+            self.pending = (self.source)();
+        }
+        self.pending
+    }
+}
+
+impl Peripheral for Console {
+    fn read(&mut self, offset: Address) -> u8 {
+        match offset {
+            DATA => {
+                self.peek();
+                self.pending.take().unwrap_or(0)
+            }
+            STATUS => {
+                let input = if self.peek().is_some() {
+                    STATUS_INPUT_READY
+                } else {
+                    0
+                };
+                input | STATUS_OUTPUT_READY
+            }
+            _ => 0,
+        }
+    }
+    fn write(&mut self, offset: Address, data: u8) {
+        if offset == DATA {
+            (self.sink)(data);
+        }
+    }
+    fn save_state(&self) -> Vec<u8> {
+        match self.pending {
+            Some(byte) => vec![1, byte],
+            None => vec![0, 0],
+        }
+    }
+    fn load_state(&mut self, state: &[u8]) -> Result<(), InvalidState> {
+        self.pending = match *state {
+            [0, _] => None,
+            [1, byte] => Some(byte),
+            _ => return Err(InvalidState),
+        };
+        Ok(())
+    }
+}
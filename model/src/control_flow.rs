@@ -0,0 +1,399 @@
+//! Static control-flow analysis of an image. Starting from the entry
+//! points, branches, jumps and calls are followed to find every
+//! instruction which can be reached without running anything, which
+//! bytes are code and which are data, and the basic blocks the code splits
+//! into. Indirect jumps, returns and jump tables can't be followed, so
+//! code only reached through them has to be given as an entry point.
+//!
+//! ```ignore
+//! let analysis = Analysis::new(0xC000, &rom, &[reset, nmi, irq]);
+//! for block in analysis.blocks() {
+//!     println!("{:04X}: {:?}", block.start, block.exit);
+//! }
+//! for range in analysis.unreachable() {
+//!     println!("data at {:04X}", 0xC000 + range.start);
+//! }
+//! ```
+use crate::debug::{AddressingMode, InstructionType, InstructionWithOperand};
+use crate::machine::MemoryReadOnly;
+use crate::Address;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use core::ops::Range;
+
+struct Image<'a> {
+    origin: Address,
+    bytes: &'a [u8],
+}
+
+impl Image<'_> {
+    fn contains(&self, address: Address) -> bool {
+        (address.wrapping_sub(self.origin) as usize) < self.bytes.len()
+    }
+    fn decode(&self, address: Address) -> Option<InstructionWithOperand> {
+        let instruction = InstructionWithOperand::decode(address, self).ok()?;
+        let last = address.wrapping_add(instruction.instruction().size() as Address - 1);
+        self.contains(last).then_some(instruction)
+    }
+}
+
+impl MemoryReadOnly for Image<'_> {
+    fn read_u8_read_only(&self, address: Address) -> u8 {
+        let offset = address.wrapping_sub(self.origin) as usize;
+        self.bytes.get(offset).copied().unwrap_or(0)
+    }
+}
+
+// Where control can go after `instruction`, as far as can be known
+// statically, and whether it can carry on to the next instruction.
+fn successors(instruction: &InstructionWithOperand) -> (Option<Address>, bool) {
+    use InstructionType::*;
+    let address = instruction.address();
+    let next = address.wrapping_add(instruction.instruction().size() as Address);
+    match instruction.instruction().instruction_type() {
+        Bcc | Bcs | Beq | Bmi | Bne | Bpl | Bvc | Bvs => {
+            let offset = instruction.operand()[0] as i8;
+            (Some(next.wrapping_add(offset as Address)), true)
+        }
+        Jmp if instruction.instruction().addressing_mode() == AddressingMode::Absolute => {
+            (instruction.operand_u16_le(), false)
+        }
+        // Falling through to the return address of a call.
+        Jsr => (instruction.operand_u16_le(), true),
+        Jmp | Rts | Rti | Brk | Kil => (None, false),
+        _ => (None, true),
+    }
+}
+
+/// What a byte of the image was found to be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    /// The first byte of a reachable instruction.
+    Opcode,
+    /// Part of a reachable instruction's operand, and not an opcode too.
+    Operand,
+    /// Not reached, so data or code only reached dynamically.
+    Data,
+}
+
+/// How control leaves a basic block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// Runs on into the block at this address, which is also jumped to.
+    Next(Address),
+    Branch {
+        taken: Address,
+        not_taken: Address,
+    },
+    /// A `JMP` to an absolute address.
+    Jump(Address),
+    /// A `JSR`, which is assumed to return to `next`.
+    Call {
+        target: Address,
+        next: Address,
+    },
+    /// `RTS` or `RTI`.
+    Return,
+    /// `JMP` through a pointer.
+    Indirect,
+    /// `BRK`, or an opcode which jams the CPU.
+    Stop,
+    /// Runs into a byte which isn't an opcode, or off the end of the image.
+    Invalid,
+}
+
+impl Exit {
+    /// The addresses control can go to which are known statically.
+    pub fn successors(self) -> impl Iterator<Item = Address> {
+        let (first, second) = match self {
+            Exit::Next(next) | Exit::Jump(next) => (Some(next), None),
+            Exit::Branch { taken, not_taken } => (Some(taken), Some(not_taken)),
+            Exit::Call { target, next } => (Some(target), Some(next)),
+            Exit::Return | Exit::Indirect | Exit::Stop | Exit::Invalid => (None, None),
+        };
+        first.into_iter().chain(second)
+    }
+}
+
+/// A run of instructions which is only entered at the start and only left
+/// at the end.
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    pub start: Address,
+    pub instructions: Vec<InstructionWithOperand>,
+    pub exit: Exit,
+}
+
+impl BasicBlock {
+    /// How many bytes the block's instructions take up.
+    pub fn size(&self) -> usize {
+        self.instructions
+            .iter()
+            .map(|instruction| instruction.instruction().size())
+            .sum()
+    }
+}
+
+pub struct Analysis {
+    origin: Address,
+    classes: Vec<Class>,
+    instructions: BTreeMap<Address, InstructionWithOperand>,
+    blocks: BTreeMap<Address, BasicBlock>,
+    external: BTreeSet<Address>,
+}
+
+impl Analysis {
+    /// Analyses `image`, which is mapped at `origin`, from each of
+    /// `entry_points`.
+    pub fn new(origin: Address, image: &[u8], entry_points: &[Address]) -> Self {
+        let image = Image {
+            origin,
+            bytes: &image[..image.len().min(0x10000)],
+        };
+        let mut instructions = BTreeMap::new();
+        let mut starts = BTreeSet::new();
+        let mut external = BTreeSet::new();
+        let mut pending = entry_points.to_vec();
+        starts.extend(entry_points.iter().copied());
+        while let Some(address) = pending.pop() {
+            if instructions.contains_key(&address) {
+                continue;
+            }
+            let Some(instruction) = image.decode(address) else {
+                if !image.contains(address) {
+                    external.insert(address);
+                }
+                continue;
+            };
+            let (target, falls_through) = successors(&instruction);
+            let next = address.wrapping_add(instruction.instruction().size() as Address);
+            instructions.insert(address, instruction);
+            if let Some(target) = target {
+                starts.insert(target);
+                pending.push(target);
+            }
+            if falls_through {
+                if target.is_some() {
+                    starts.insert(next);
+                }
+                pending.push(next);
+            }
+        }
+        starts.retain(|start| instructions.contains_key(start));
+        let mut classes = alloc::vec![Class::Data; image.bytes.len()];
+        for (&address, instruction) in instructions.iter() {
+            for i in 1..instruction.instruction().size() {
+                let offset = address.wrapping_add(i as Address).wrapping_sub(origin) as usize;
+                // An instruction can wrap around $FFFF past the end of an
+                // image which nearly fills memory.
+                if let Some(class @ Class::Data) = classes.get_mut(offset) {
+                    *class = Class::Operand;
+                }
+            }
+        }
+        for &address in instructions.keys() {
+            classes[address.wrapping_sub(origin) as usize] = Class::Opcode;
+        }
+        let blocks = starts
+            .iter()
+            .map(|&start| (start, Self::trace_block(&instructions, &starts, start)))
+            .collect();
+        Self {
+            origin,
+            classes,
+            instructions,
+            blocks,
+            external,
+        }
+    }
+    fn trace_block(
+        instructions: &BTreeMap<Address, InstructionWithOperand>,
+        starts: &BTreeSet<Address>,
+        start: Address,
+    ) -> BasicBlock {
+        let mut block = Vec::new();
+        let mut address = start;
+        let exit = loop {
+            let instruction = &instructions[&address];
+            block.push(instruction.clone());
+            let (target, falls_through) = successors(instruction);
+            address = address.wrapping_add(instruction.instruction().size() as Address);
+            let call = instruction.instruction().instruction_type() == InstructionType::Jsr;
+            match (target, falls_through) {
+                (Some(target), true) if call => {
+                    break Exit::Call {
+                        target,
+                        next: address,
+                    }
+                }
+                (Some(taken), true) => {
+                    break Exit::Branch {
+                        taken,
+                        not_taken: address,
+                    }
+                }
+                (Some(target), false) => break Exit::Jump(target),
+                (None, false) => {
+                    break match instruction.instruction().instruction_type() {
+                        InstructionType::Rts | InstructionType::Rti => Exit::Return,
+                        InstructionType::Jmp => Exit::Indirect,
+                        _ => Exit::Stop,
+                    }
+                }
+                (None, true) if starts.contains(&address) => break Exit::Next(address),
+                (None, true) if !instructions.contains_key(&address) => break Exit::Invalid,
+                (None, true) => (),
+            }
+        };
+        BasicBlock {
+            start,
+            instructions: block,
+            exit,
+        }
+    }
+    pub fn origin(&self) -> Address {
+        self.origin
+    }
+    /// What the byte at `address` is, or `None` if it isn't in the image.
+    pub fn class(&self, address: Address) -> Option<Class> {
+        self.classes
+            .get(address.wrapping_sub(self.origin) as usize)
+            .copied()
+    }
+    pub fn is_code(&self, address: Address) -> bool {
+        matches!(self.class(address), Some(Class::Opcode | Class::Operand))
+    }
+    /// The reachable instruction at `address`, if there is one.
+    pub fn instruction(&self, address: Address) -> Option<&InstructionWithOperand> {
+        self.instructions.get(&address)
+    }
+    /// Every reachable instruction, in address order.
+    pub fn instructions(&self) -> impl Iterator<Item = &InstructionWithOperand> {
+        self.instructions.values()
+    }
+    /// Every basic block, in order of start address.
+    pub fn blocks(&self) -> impl Iterator<Item = &BasicBlock> {
+        self.blocks.values()
+    }
+    pub fn block(&self, start: Address) -> Option<&BasicBlock> {
+        self.blocks.get(&start)
+    }
+    /// The starts of the blocks which can pass control to the block at
+    /// `start`.
+    pub fn predecessors(&self, start: Address) -> impl Iterator<Item = Address> + '_ {
+        self.blocks
+            .values()
+            .filter(move |block| block.exit.successors().any(|successor| successor == start))
+            .map(|block| block.start)
+    }
+    /// Addresses outside the image which are jumped or fallen through to,
+    /// such as calls into another ROM.
+    pub fn external(&self) -> impl Iterator<Item = Address> + '_ {
+        self.external.iter().copied()
+    }
+    /// The runs of bytes which weren't reached, as offsets from the origin.
+    pub fn unreachable(&self) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for (offset, &class) in self.classes.iter().enumerate() {
+            if class != Class::Data {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some(range) if range.end == offset => range.end += 1,
+                _ => ranges.push(offset..offset + 1),
+            }
+        }
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn starts(analysis: &Analysis, start: Address) -> Vec<Address> {
+        analysis
+            .block(start)
+            .unwrap()
+            .instructions
+            .iter()
+            .map(|instruction| instruction.address())
+            .collect()
+    }
+
+    #[test]
+    fn branches_split_blocks() {
+        // LDX #$00; loop: DEX; BNE loop; JMP ($0300); then data.
+        let image = [0xA2, 0x00, 0xCA, 0xD0, 0xFD, 0x6C, 0x00, 0x03, 0x12, 0x34];
+        let analysis = Analysis::new(0xC000, &image, &[0xC000]);
+        let blocks: Vec<_> = analysis
+            .blocks()
+            .map(|block| (block.start, block.exit))
+            .collect();
+        assert_eq!(
+            blocks,
+            vec![
+                (0xC000, Exit::Next(0xC002)),
+                (
+                    0xC002,
+                    Exit::Branch {
+                        taken: 0xC002,
+                        not_taken: 0xC005
+                    }
+                ),
+                (0xC005, Exit::Indirect),
+            ]
+        );
+        assert_eq!(starts(&analysis, 0xC002), vec![0xC002, 0xC003]);
+        assert_eq!(analysis.block(0xC005).unwrap().size(), 3);
+        assert_eq!(
+            analysis.predecessors(0xC002).collect::<Vec<_>>(),
+            vec![0xC000, 0xC002]
+        );
+        assert_eq!(analysis.class(0xC003), Some(Class::Opcode));
+        assert_eq!(analysis.class(0xC004), Some(Class::Operand));
+        assert_eq!(analysis.unreachable(), vec![8..10]);
+        // The pointer's target isn't known, so nothing past it is followed.
+        assert_eq!(analysis.external().count(), 0);
+    }
+
+    #[test]
+    fn calls_and_jumps_are_followed() {
+        // JSR $C007; JMP $C008; RTS; BRK
+        let image = [0x20, 0x07, 0xC0, 0x4C, 0x08, 0xC0, 0xFF, 0x60, 0x00];
+        let analysis = Analysis::new(0xC000, &image, &[0xC000]);
+        let exits: Vec<_> = analysis.blocks().map(|block| block.exit).collect();
+        assert_eq!(
+            exits,
+            vec![
+                Exit::Call {
+                    target: 0xC007,
+                    next: 0xC003
+                },
+                Exit::Jump(0xC008),
+                Exit::Return,
+                Exit::Stop,
+            ]
+        );
+        assert_eq!(analysis.unreachable(), vec![6..7]);
+    }
+
+    #[test]
+    fn code_can_fall_off_ffff() {
+        // Two NOPs at $FFFE run on to $0000, which isn't in the image.
+        let analysis = Analysis::new(0xFFF0, &[0xEA; 0x10], &[0xFFFE]);
+        assert_eq!(analysis.block(0xFFFE).unwrap().exit, Exit::Invalid);
+        assert_eq!(starts(&analysis, 0xFFFE), vec![0xFFFE, 0xFFFF]);
+        assert_eq!(analysis.external().collect::<Vec<_>>(), vec![0x0000]);
+        assert_eq!(analysis.unreachable(), vec![0..0xE]);
+        // An instruction whose operand would wrap past $FFFF isn't decoded.
+        let mut image = [0xEA; 0x10];
+        image[0xF] = 0xAD;
+        let analysis = Analysis::new(0xFFF0, &image, &[0xFFFE]);
+        assert_eq!(analysis.block(0xFFFE).unwrap().exit, Exit::Invalid);
+        assert_eq!(starts(&analysis, 0xFFFE), vec![0xFFFE]);
+        assert_eq!(analysis.class(0xFFFF), Some(Class::Data));
+        assert_eq!(analysis.external().count(), 0);
+    }
+}
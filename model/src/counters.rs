@@ -0,0 +1,62 @@
+//! Counts a `Machine` keeps as it runs, for tracking a program's
+//! performance. They're always on, and cost a few additions per
+//! instruction. Branches and page crossings are worked out from how many
+//! cycles each instruction took beyond the fewest it can, so they're only
+//! counted for the NMOS variants, whose timings `Instruction::cycles`
+//! gives.
+//!
+//! ```ignore
+//! machine.reset_counters();
+//! machine.run_until_pc(done)?;
+//! let counters = machine.counters();
+//! println!("{} cycles, {} branches taken", counters.cycles, counters.branches_taken);
+//! ```
+use crate::debug::{AddressingMode, Instruction};
+use crate::machine::Variant;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    /// Instructions run to completion.
+    pub instructions: u64,
+    /// Including interrupts and stalls.
+    pub cycles: u64,
+    pub branches_taken: u64,
+    pub branches_not_taken: u64,
+    /// Extra cycles taken by indexing across a page, or by a taken branch
+    /// landing on another page.
+    pub page_crossings: u64,
+    /// NMIs and IRQs taken, not counting `BRK`.
+    pub interrupts: u64,
+}
+
+impl Counters {
+    pub(crate) fn record_instruction(&mut self, variant: Variant, opcode: Option<u8>, cycles: u8) {
+        self.instructions += 1;
+        self.cycles += cycles as u64;
+        if !matches!(variant, Variant::Nmos6502 | Variant::Ricoh2A03) {
+            return;
+        }
+        let Some(instruction) = opcode.and_then(|opcode| Instruction::from_opcode(opcode).ok())
+        else {
+            return;
+        };
+        let extra = cycles.saturating_sub(instruction.cycles().0);
+        if instruction.addressing_mode() == AddressingMode::Relative {
+            if extra == 0 {
+                self.branches_not_taken += 1;
+            } else {
+                self.branches_taken += 1;
+                self.page_crossings += (extra - 1) as u64;
+            }
+        } else {
+            self.page_crossings += extra as u64;
+        }
+    }
+    pub(crate) fn record_interrupt(&mut self, cycles: u8) {
+        self.interrupts += 1;
+        self.cycles += cycles as u64;
+    }
+    pub(crate) fn record_stall(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+    }
+}
@@ -0,0 +1,166 @@
+//! Records which addresses a `Machine` executed, read and wrote, once
+//! enabled with `Machine::enable_coverage`. Executed addresses include the
+//! operand bytes of each instruction; the fetches themselves aren't counted
+//! as reads. Accesses made by interrupts are counted, but those made by
+//! `Machine` itself, such as for traps, aren't.
+//!
+//! ```ignore
+//! machine.enable_coverage();
+//! machine.run(Fuel::instructions(1_000_000))?;
+//! let mut coverage = machine.take_coverage().unwrap();
+//! coverage.merge(&previous_run);
+//! for range in coverage.executed.ranges() {
+//!     println!("{:04X}-{:04X}", range.start(), range.end());
+//! }
+//! ```
+use crate::debug::Instruction;
+use crate::Address;
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
+
+const WORDS: usize = 0x10000 / 64;
+
+/// A set of addresses, stored as a bitmap of the whole address space.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AddressSet {
+    words: Vec<u64>,
+}
+
+impl Default for AddressSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl core::fmt::Debug for AddressSet {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_list()
+            .entries(
+                self.ranges()
+                    .iter()
+                    .map(|range| (range.start(), range.end())),
+            )
+            .finish()
+    }
+}
+
+impl AddressSet {
+    pub fn new() -> Self {
+        Self {
+            words: alloc::vec![0; WORDS],
+        }
+    }
+    pub fn insert(&mut self, address: Address) {
+        self.words[address as usize / 64] |= 1 << (address % 64);
+    }
+    pub fn contains(&self, address: Address) -> bool {
+        self.words[address as usize / 64] & (1 << (address % 64)) != 0
+    }
+    pub fn len(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+    pub fn union_with(&mut self, other: &AddressSet) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+    }
+    pub fn clear(&mut self) {
+        self.words.fill(0);
+    }
+    /// The addresses as the fewest inclusive ranges, in order.
+    pub fn ranges(&self) -> Vec<RangeInclusive<Address>> {
+        let mut ranges = Vec::new();
+        let mut start = None;
+        for address in 0..=Address::MAX {
+            match (self.contains(address), start) {
+                (true, None) => start = Some(address),
+                (false, Some(first)) => {
+                    ranges.push(first..=address - 1);
+                    start = None;
+                }
+                _ => (),
+            }
+        }
+        if let Some(first) = start {
+            ranges.push(first..=Address::MAX);
+        }
+        ranges
+    }
+    /// One bit per address, 8KB in all, with address 0 in the lowest bit
+    /// of the first byte.
+    pub fn to_bitmap(&self) -> Vec<u8> {
+        self.words
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect()
+    }
+    /// Reads a bitmap made by `to_bitmap`. Missing bytes are taken as zero
+    /// and extra ones are ignored.
+    pub fn from_bitmap(bitmap: &[u8]) -> Self {
+        let mut set = Self::new();
+        for (word, chunk) in set.words.iter_mut().zip(bitmap.chunks(8)) {
+            let mut bytes = [0; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            *word = u64::from_le_bytes(bytes);
+        }
+        set
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    pub executed: AddressSet,
+    pub read: AddressSet,
+    pub written: AddressSet,
+    // The address of the opcode about to be fetched.
+    fetch: Option<Address>,
+    // The instruction being run, whose operand fetches aren't counted as
+    // reads.
+    instruction: Option<(Address, usize)>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Adds everything recorded in `other`, e.g. from another run.
+    pub fn merge(&mut self, other: &Coverage) {
+        self.executed.union_with(&other.executed);
+        self.read.union_with(&other.read);
+        self.written.union_with(&other.written);
+    }
+    pub fn clear(&mut self) {
+        self.executed.clear();
+        self.read.clear();
+        self.written.clear();
+    }
+    pub(crate) fn fetch_at(&mut self, pc: Address) {
+        self.fetch = Some(pc);
+        self.instruction = None;
+    }
+    pub(crate) fn record_read(&mut self, address: Address, data: u8) {
+        if self.fetch == Some(address) {
+            self.fetch = None;
+            let size = Instruction::from_opcode(data).map_or(1, |instruction| instruction.size());
+            for offset in 0..size {
+                self.executed
+                    .insert(address.wrapping_add(offset as Address));
+            }
+            self.instruction = Some((address, size));
+        } else if !self.is_operand(address) {
+            self.read.insert(address);
+        }
+    }
+    fn is_operand(&self, address: Address) -> bool {
+        self.instruction.is_some_and(|(pc, size)| {
+            let offset = address.wrapping_sub(pc) as usize;
+            offset > 0 && offset < size
+        })
+    }
+}
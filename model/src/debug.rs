@@ -1,10 +1,13 @@
-use alloc::vec::Vec;
+use alloc::{collections::VecDeque, format, string::String, vec::Vec};
 
-use crate::machine::{Cpu, MemoryReadOnly};
-use crate::{Address, UnknownOpcode};
-use core::fmt;
+use crate::annotation::Annotations;
+use crate::machine::{Cpu, Machine, Memory, MemoryReadOnly, Snapshot, StepError};
+use crate::status::flag;
+use crate::{address, opcode, Address, UnknownOpcode};
+use core::fmt::{self, Write as _};
+use core::ops::RangeInclusive;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InstructionType {
     Adc,
     Ahx,
@@ -43,6 +46,7 @@ pub enum InstructionType {
     Isc,
     Jmp,
     Jsr,
+    Kil,
     Lax,
     Lda,
     Ldx,
@@ -80,7 +84,65 @@ pub enum InstructionType {
     Txs,
     Tya,
 }
-#[derive(Debug, Clone, Copy)]
+impl InstructionType {
+    /// Whether this is one of the instructions MOS documented. Some opcodes
+    /// of documented instructions, such as the extra `SBC #` and the other
+    /// one-byte `NOP`s, are still undocumented; see `opcode::info`.
+    pub fn is_documented(self) -> bool {
+        use InstructionType::*;
+        !matches!(
+            self,
+            Ahx | Alr
+                | Anc
+                | Arr
+                | Axs
+                | Dcp
+                | Ign
+                | Isc
+                | Kil
+                | Lax
+                | Rla
+                | Rra
+                | Sax
+                | Skb
+                | Slo
+                | Sre
+                | Sxa
+                | Sya
+        )
+    }
+    /// Whether this can move the program counter anywhere but the next
+    /// instruction, or stop the CPU.
+    pub fn is_control_flow(self) -> bool {
+        use InstructionType::*;
+        matches!(
+            self,
+            Bcc | Bcs | Beq | Bmi | Bne | Bpl | Bvc | Bvs | Brk | Jmp | Jsr | Kil | Rti | Rts
+        )
+    }
+    /// The status flags this instruction can change, as a mask of
+    /// `status::flag` bits.
+    pub fn flags_modified(self) -> u8 {
+        use crate::status::flag::*;
+        use InstructionType::*;
+        match self {
+            Adc | Sbc | Arr | Isc | Rra => NEGATIVE | OVERFLOW | ZERO | CARRY,
+            Asl | Lsr | Rol | Ror | Slo | Sre | Rla | Cmp | Cpx | Cpy | Dcp | Anc | Alr | Axs => {
+                NEGATIVE | ZERO | CARRY
+            }
+            And | Eor | Ora | Lda | Ldx | Ldy | Lax | Tax | Tay | Tsx | Txa | Tya | Inx | Iny
+            | Dex | Dey | Inc | Dec | Pla => NEGATIVE | ZERO,
+            Bit => NEGATIVE | OVERFLOW | ZERO,
+            Clc | Sec => CARRY,
+            Cld | Sed => DECIMAL,
+            Cli | Sei | Brk => INTERRUPT_DISABLE,
+            Clv => OVERFLOW,
+            Plp | Rti => NEGATIVE | OVERFLOW | DECIMAL | INTERRUPT_DISABLE | ZERO | CARRY,
+            _ => 0,
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AddressingMode {
     Absolute,
     AbsoluteXIndexed,
@@ -247,6 +309,18 @@ impl Instruction {
             opcode::jmp::ABSOLUTE => (Jmp, Absolute),
             opcode::jmp::INDIRECT => (Jmp, Indirect),
             opcode::jsr::ABSOLUTE => (Jsr, Absolute),
+            opcode::kil::unofficial0::IMPLIED => (Kil, Implied),
+            opcode::kil::unofficial1::IMPLIED => (Kil, Implied),
+            opcode::kil::unofficial2::IMPLIED => (Kil, Implied),
+            opcode::kil::unofficial3::IMPLIED => (Kil, Implied),
+            opcode::kil::unofficial4::IMPLIED => (Kil, Implied),
+            opcode::kil::unofficial5::IMPLIED => (Kil, Implied),
+            opcode::kil::unofficial6::IMPLIED => (Kil, Implied),
+            opcode::kil::unofficial7::IMPLIED => (Kil, Implied),
+            opcode::kil::unofficial8::IMPLIED => (Kil, Implied),
+            opcode::kil::unofficial9::IMPLIED => (Kil, Implied),
+            opcode::kil::unofficial10::IMPLIED => (Kil, Implied),
+            opcode::kil::unofficial11::IMPLIED => (Kil, Implied),
             opcode::lax::unofficial0::ABSOLUTE => (Lax, Absolute),
             opcode::lax::unofficial0::ABSOLUTE_Y_INDEXED => (Lax, AbsoluteYIndexed),
             opcode::lax::unofficial0::IMMEDIATE => (Lax, Immediate),
@@ -388,10 +462,55 @@ impl Instruction {
     pub fn addressing_mode(&self) -> AddressingMode {
         self.addressing_mode
     }
+    /// Fewest and most cycles the instruction can take. The extra cycles
+    /// come from crossing a page when indexing, or from taking a branch
+    /// (and landing on another page).
+    pub fn cycles(&self) -> (u8, u8) {
+        use AddressingMode::*;
+        use InstructionType::*;
+        match self.instruction_type {
+            Bcc | Bcs | Beq | Bmi | Bne | Bpl | Bvc | Bvs => return (2, 4),
+            Brk => return (7, 7),
+            Jsr | Rts | Rti => return (6, 6),
+            Jmp if self.addressing_mode == Indirect => return (5, 5),
+            Jmp => return (3, 3),
+            Pha | Php => return (3, 3),
+            Pla | Plp => return (4, 4),
+            _ => (),
+        }
+        let read_modify_write = matches!(
+            self.instruction_type,
+            Asl | Lsr | Rol | Ror | Inc | Dec | Slo | Rla | Sre | Rra | Dcp | Isc
+        );
+        let write = matches!(
+            self.instruction_type,
+            Sta | Stx | Sty | Sax | Ahx | Sxa | Sya
+        );
+        let cycles = match self.addressing_mode {
+            Implied | Accumulator | Immediate | Relative => 2,
+            ZeroPage if read_modify_write => 5,
+            ZeroPage => 3,
+            ZeroPageXIndexed | ZeroPageYIndexed if read_modify_write => 6,
+            ZeroPageXIndexed | ZeroPageYIndexed => 4,
+            Absolute if read_modify_write => 6,
+            Absolute => 4,
+            AbsoluteXIndexed | AbsoluteYIndexed if read_modify_write => 7,
+            AbsoluteXIndexed | AbsoluteYIndexed if write => 5,
+            AbsoluteXIndexed | AbsoluteYIndexed => return (4, 5),
+            XIndexedIndirect if read_modify_write => 8,
+            XIndexedIndirect => 6,
+            IndirectYIndexed if read_modify_write => 8,
+            IndirectYIndexed if write => 6,
+            IndirectYIndexed => return (5, 6),
+            Indirect => 5,
+        };
+        (cycles, cycles)
+    }
 }
 #[derive(Debug, Clone)]
 pub struct InstructionWithOperand {
     address: Address,
+    opcode: u8,
     instruction: Instruction,
     operand: Vec<u8>,
 }
@@ -407,6 +526,7 @@ impl InstructionWithOperand {
         }
         Ok(Self {
             address,
+            opcode,
             instruction,
             operand,
         })
@@ -418,28 +538,908 @@ impl InstructionWithOperand {
         self.instruction
     }
     pub fn operand_u16_le(&self) -> Option<u16> {
-        match self.operand.as_slice() {
-            &[_x] => None,
-            &[x0, x1] => Some((x1 as u16) << 8 | x0 as u16),
+        match *self.operand.as_slice() {
+            [_x] => None,
+            [x0, x1] => Some((x1 as u16) << 8 | x0 as u16),
             _ => None,
         }
     }
     pub fn address(&self) -> Address {
         self.address
     }
+    pub fn opcode(&self) -> u8 {
+        self.opcode
+    }
+    /// The opcode followed by the operand, as in memory.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.instruction.size());
+        bytes.push(self.opcode);
+        bytes.extend_from_slice(&self.operand);
+        bytes
+    }
+}
+impl InstructionWithOperand {
+    pub fn operand(&self) -> &[u8] {
+        &self.operand
+    }
+    fn write_instruction<W: fmt::Write>(&self, f: &mut W) -> fmt::Result {
+        write!(
+            f,
+            "{:?}({:?}) ",
+            self.instruction.instruction_type, self.instruction.addressing_mode
+        )?;
+        match *self.operand.as_slice() {
+            [x] => write!(f, "{:02X}", x)?,
+            [x0, x1] => write!(f, "{:04X}", (x1 as u16) << 8 | x0 as u16)?,
+            _ => (),
+        }
+        Ok(())
+    }
+    // Writes the instruction as nestest.log does, about to run on `cpu`:
+    // the mnemonic and operand in the usual syntax, then if `values` is
+    // set, the effective address and the value there as they stand before
+    // it runs.
+    fn write_traced<W: fmt::Write, M: MemoryReadOnly>(
+        &self,
+        f: &mut W,
+        cpu: &Cpu,
+        memory: &M,
+        values: bool,
+    ) -> fmt::Result {
+        use AddressingMode::*;
+        use InstructionType::*;
+        let read = |address: Address| memory.read_u8_read_only(address);
+        let zero_page_indexed = |base: u8, index: u8| {
+            if cpu.quirks.zero_page_wrap {
+                base.wrapping_add(index) as Address
+            } else {
+                base as Address + index as Address
+            }
+        };
+        let zero_page_pointer = |base: u8, index: u8| {
+            let address = zero_page_indexed(base, index);
+            let next = if cpu.quirks.zero_page_wrap {
+                (address as u8).wrapping_add(1) as Address
+            } else {
+                address.wrapping_add(1)
+            };
+            address::from_u8_lo_hi(read(address), read(next))
+        };
+        let instruction_type = self.instruction.instruction_type;
+        match instruction_type {
+            // nestest.log's names for them.
+            Ign | Skb => write!(f, "NOP")?,
+            Isc => write!(f, "ISB")?,
+            _ => write!(
+                f,
+                "{}",
+                format!("{:?}", instruction_type).to_ascii_uppercase()
+            )?,
+        }
+        let byte = self.operand.first().copied().unwrap_or(0);
+        let word = self.operand_u16_le().unwrap_or(0);
+        let (index, register) = match self.instruction.addressing_mode {
+            ZeroPageYIndexed | AbsoluteYIndexed => (cpu.y, 'Y'),
+            _ => (cpu.x, 'X'),
+        };
+        match self.instruction.addressing_mode {
+            Implied => Ok(()),
+            Accumulator => write!(f, " A"),
+            Immediate => write!(f, " #${:02X}", byte),
+            Relative => {
+                let target = self
+                    .address
+                    .wrapping_add(2)
+                    .wrapping_add(byte as i8 as Address);
+                write!(f, " ${:04X}", target)
+            }
+            ZeroPage => {
+                write!(f, " ${:02X}", byte)?;
+                if values {
+                    write!(f, " = {:02X}", read(byte as Address))?;
+                }
+                Ok(())
+            }
+            ZeroPageXIndexed | ZeroPageYIndexed => {
+                write!(f, " ${:02X},{}", byte, register)?;
+                if values {
+                    let address = zero_page_indexed(byte, index);
+                    write!(f, " @ {:02X} = {:02X}", address, read(address))?;
+                }
+                Ok(())
+            }
+            Absolute if matches!(instruction_type, Jmp | Jsr) => write!(f, " ${:04X}", word),
+            Absolute => {
+                write!(f, " ${:04X}", word)?;
+                if values {
+                    write!(f, " = {:02X}", read(word))?;
+                }
+                Ok(())
+            }
+            AbsoluteXIndexed | AbsoluteYIndexed => {
+                write!(f, " ${:04X},{}", word, register)?;
+                if values {
+                    let address = word.wrapping_add(index as Address);
+                    write!(f, " @ {:04X} = {:02X}", address, read(address))?;
+                }
+                Ok(())
+            }
+            Indirect => {
+                write!(f, " (${:04X})", word)?;
+                if values {
+                    let hi = if address::lo(word) == 0xFF && cpu.quirks.jmp_indirect_page_wrap {
+                        word & 0xFF00
+                    } else {
+                        word.wrapping_add(1)
+                    };
+                    let target = address::from_u8_lo_hi(read(word), read(hi));
+                    write!(f, " = {:04X}", target)?;
+                }
+                Ok(())
+            }
+            XIndexedIndirect => {
+                write!(f, " (${:02X},X)", byte)?;
+                if values {
+                    let pointer = zero_page_indexed(byte, cpu.x);
+                    let address = zero_page_pointer(byte, cpu.x);
+                    let value = read(address);
+                    write!(f, " @ {:02X} = {:04X} = {:02X}", pointer, address, value)?;
+                }
+                Ok(())
+            }
+            IndirectYIndexed => {
+                write!(f, " (${:02X}),Y", byte)?;
+                if values {
+                    let base = zero_page_pointer(byte, 0);
+                    let address = base.wrapping_add(cpu.y as Address);
+                    let value = read(address);
+                    write!(f, " = {:04X} @ {:04X} = {:02X}", base, address, value)?;
+                }
+                Ok(())
+            }
+        }
+    }
 }
 impl fmt::Display for InstructionWithOperand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X}  ", self.address)?;
+        self.write_instruction(f)
+    }
+}
+
+/// Which columns go in a trace line, which always starts with the program
+/// counter. The defaults give the nestest.log layout, without the PPU
+/// column. Undocumented opcodes are marked with `*`, and operands in memory
+/// are followed by their effective address and value:
+///
+/// ```text
+/// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7
+/// C72E  B1 89     LDA ($89),Y = 0300 @ 0300 = 89  A:00 X:00 Y:00 P:27 SP:FB CYC:278
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceFormat {
+    /// The instruction's bytes, padded to the width of three.
+    pub bytes: bool,
+    /// The decoded instruction, padded to a fixed width.
+    pub disassembly: bool,
+    /// `A`, `X`, `Y`, `P` and `SP` in hex.
+    pub registers: bool,
+    /// The flags as `NV-BDIZC` letters.
+    pub flags: bool,
+    /// The cycle count as `CYC:n`.
+    pub cycles: bool,
+}
+
+impl Default for TraceFormat {
+    fn default() -> Self {
+        Self::nestest()
+    }
+}
+
+const DISASSEMBLY_WIDTH: usize = 31;
+
+impl TraceFormat {
+    pub fn nestest() -> Self {
+        Self {
+            bytes: true,
+            disassembly: true,
+            registers: true,
+            flags: false,
+            cycles: true,
+        }
+    }
+    /// Only the program counter and registers, for comparing state with
+    /// emulators that disassemble differently.
+    pub fn registers_only() -> Self {
+        Self {
+            bytes: false,
+            disassembly: false,
+            registers: true,
+            flags: false,
+            cycles: false,
+        }
+    }
+    /// Writes the line for the instruction `cpu` is about to run, without a
+    /// newline.
+    pub fn write_line<W: fmt::Write, M: MemoryReadOnly>(
+        &self,
+        out: &mut W,
+        cpu: &Cpu,
+        memory: &M,
+        cycles: u64,
+    ) -> fmt::Result {
+        self.write_line_with(out, cpu, memory, Some(cycles), true)
+    }
+    // As `write_line`, leaving out the cycle count if it isn't known, and
+    // the effective address and value of the operand if `values` is unset,
+    // as when `memory` only has the instruction's own bytes.
+    pub(crate) fn write_line_with<W: fmt::Write, M: MemoryReadOnly>(
+        &self,
+        out: &mut W,
+        cpu: &Cpu,
+        memory: &M,
+        cycles: Option<u64>,
+        values: bool,
+    ) -> fmt::Result {
+        write!(out, "{:04X}", cpu.pc)?;
+        let decoded = InstructionWithOperand::next(cpu, memory);
+        if self.bytes {
+            let size = decoded.as_ref().map_or(1, |i| i.instruction.size());
+            write!(out, "  ")?;
+            for i in 0..3 {
+                if i < size {
+                    let byte = memory.read_u8_read_only(cpu.pc.wrapping_add(i as Address));
+                    write!(out, "{:02X} ", byte)?;
+                } else {
+                    write!(out, "   ")?;
+                }
+            }
+        }
+        if self.disassembly {
+            let mut text = String::new();
+            let documented = match &decoded {
+                Ok(instruction) => {
+                    instruction.write_traced(&mut text, cpu, memory, values)?;
+                    opcode::info(instruction.opcode).is_some_and(|info| info.documented)
+                }
+                Err(UnknownOpcode(value)) => {
+                    write!(text, ".byte {:02X}", value)?;
+                    true
+                }
+            };
+            let marker = if documented { ' ' } else { '*' };
+            write!(out, "{}{:width$}", marker, text, width = DISASSEMBLY_WIDTH)?;
+        }
+        if self.registers {
+            write!(
+                out,
+                " A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+                cpu.acc,
+                cpu.x,
+                cpu.y,
+                u8::from(cpu.status),
+                cpu.sp
+            )?;
+        }
+        if self.flags {
+            write!(out, " {}", cpu.status)?;
+        }
+        if let Some(cycles) = cycles.filter(|_| self.cycles) {
+            write!(out, " CYC:{}", cycles)?;
+        }
+        Ok(())
+    }
+    /// Like `write_line`, with the comment `annotations` has at the program
+    /// counter, if any, appended after `;`.
+    pub fn write_annotated_line<W: fmt::Write, M: MemoryReadOnly>(
+        &self,
+        out: &mut W,
+        cpu: &Cpu,
+        memory: &M,
+        cycles: u64,
+        annotations: &Annotations,
+    ) -> fmt::Result {
+        self.write_line(out, cpu, memory, cycles)?;
+        if let Some(comment) = annotations.comment(cpu.pc) {
+            write!(out, "  ; {}", comment)?;
+        }
+        Ok(())
+    }
+    pub fn line<M: MemoryReadOnly>(&self, cpu: &Cpu, memory: &M, cycles: u64) -> String {
+        let mut line = String::new();
+        // Writing to a `String` can't fail.
+        let _ = self.write_line(&mut line, cpu, memory, cycles);
+        line
+    }
+}
+
+/// One line of a disassembly: either a decoded instruction or a byte which
+/// isn't a valid opcode.
+#[derive(Debug, Clone)]
+pub enum Disassembled {
+    Instruction(InstructionWithOperand),
+    Byte { address: Address, value: u8 },
+}
+
+impl Disassembled {
+    fn decode<M: MemoryReadOnly>(address: Address, memory: &M) -> Self {
+        match InstructionWithOperand::decode(address, memory) {
+            Ok(instruction) => Disassembled::Instruction(instruction),
+            Err(UnknownOpcode(value)) => Disassembled::Byte { address, value },
+        }
+    }
+    pub fn address(&self) -> Address {
+        match self {
+            Disassembled::Instruction(instruction) => instruction.address(),
+            Disassembled::Byte { address, .. } => *address,
+        }
+    }
+    pub fn size(&self) -> usize {
+        match self {
+            Disassembled::Instruction(instruction) => instruction.instruction().size(),
+            Disassembled::Byte { .. } => 1,
+        }
+    }
+}
+
+impl fmt::Display for Disassembled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Disassembled::Instruction(instruction) => fmt::Display::fmt(instruction, f),
+            Disassembled::Byte { address, value } => {
+                write!(f, "{:04X}  .byte {:02X}", address, value)
+            }
+        }
+    }
+}
+
+// Bytes from `base`, for decoding a plain buffer.
+pub(crate) struct Bytes<'a> {
+    pub(crate) base: Address,
+    pub(crate) bytes: &'a [u8],
+}
+
+impl MemoryReadOnly for Bytes<'_> {
+    fn read_u8_read_only(&self, address: Address) -> u8 {
+        let offset = address.wrapping_sub(self.base) as usize;
+        self.bytes.get(offset).copied().unwrap_or(0)
+    }
+}
+
+/// Disassembles all of `bytes` in order from offset 0, as a fuzz target
+/// or for a dump of a file. Any byte which isn't a valid opcode, and the
+/// bytes of an instruction cut short by the end, are given as `Byte`s.
+/// Only the first 64KiB are decoded.
+pub fn decode_all(bytes: &[u8]) -> Vec<Disassembled> {
+    decode_all_at(0, bytes)
+}
+
+/// Like `decode_all`, for `bytes` loaded at `base`. Decoding stops at
+/// $FFFF rather than wrapping around.
+pub fn decode_all_at(base: Address, bytes: &[u8]) -> Vec<Disassembled> {
+    let bytes = &bytes[..bytes.len().min(0x10000 - base as usize)];
+    let memory = Bytes { base, bytes };
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let address = base + offset as Address;
+        let line = match Disassembled::decode(address, &memory) {
+            line if offset + line.size() <= bytes.len() => line,
+            _ => Disassembled::Byte {
+                address,
+                value: bytes[offset],
+            },
+        };
+        offset += line.size();
+        lines.push(line);
+    }
+    lines
+}
+
+/// Disassembles `before` lines leading up to `address`, the line at
+/// `address`, then `after` more lines. Since the instruction boundaries
+/// before `address` aren't known, every start point up to three bytes per
+/// line back is tried, keeping the one which lands exactly on `address`
+/// with the fewest invalid opcodes on the way.
+pub fn disassemble_window<M: MemoryReadOnly>(
+    memory: &M,
+    address: Address,
+    before: usize,
+    after: usize,
+) -> Vec<Disassembled> {
+    let mut best: Option<(usize, Vec<Disassembled>)> = None;
+    for distance in 1..=(before * 3) {
+        let mut lines = Vec::new();
+        let mut invalid = 0;
+        let mut cursor = address.wrapping_sub(distance as Address);
+        let mut remaining = distance;
+        while remaining > 0 {
+            let line = Disassembled::decode(cursor, memory);
+            if line.size() > remaining {
+                break;
+            }
+            if let Disassembled::Byte { .. } = line {
+                invalid += 1;
+            }
+            remaining -= line.size();
+            cursor = cursor.wrapping_add(line.size() as Address);
+            lines.push(line);
+        }
+        if remaining != 0 {
+            continue;
+        }
+        let better = match &best {
+            None => true,
+            Some((best_invalid, best_lines)) => {
+                invalid < *best_invalid
+                    || (invalid == *best_invalid
+                        && best_lines.len() < before
+                        && lines.len() > best_lines.len())
+            }
+        };
+        if better {
+            best = Some((invalid, lines));
+        }
+    }
+    let mut window = best.map(|(_, lines)| lines).unwrap_or_default();
+    if window.len() > before {
+        window.drain(..window.len() - before);
+    }
+    let mut cursor = address;
+    for _ in 0..=after {
+        let line = Disassembled::decode(cursor, memory);
+        cursor = cursor.wrapping_add(line.size() as Address);
+        window.push(line);
+    }
+    window
+}
+
+/// Writes one line per entry, with bookmarks from `annotations` as label
+/// lines and comments appended after `;`.
+pub fn write_disassembly<W: fmt::Write>(
+    out: &mut W,
+    lines: &[Disassembled],
+    annotations: Option<&Annotations>,
+) -> fmt::Result {
+    for line in lines {
+        let annotation = annotations.and_then(|a| a.get(line.address()));
+        if let Some(bookmark) = annotation.and_then(|a| a.bookmark.as_ref()) {
+            writeln!(out, "{}:", bookmark)?;
+        }
+        write!(out, "{}", line)?;
+        if let Some(comment) = annotation.and_then(|a| a.comment.as_ref()) {
+            write!(out, "  ; {}", comment)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Writes `range` 16 bytes a line, as
+/// `C000  A9 01 8D 00 02 ...  ..........`: the address, the bytes in hex,
+/// and the bytes as ASCII with anything unprintable as `.`. A short last
+/// line is padded so the text lines up.
+pub fn hexdump<W: fmt::Write, M: MemoryReadOnly>(
+    out: &mut W,
+    memory: &M,
+    range: RangeInclusive<Address>,
+) -> fmt::Result {
+    let (start, end) = (*range.start() as usize, *range.end() as usize);
+    for at in (start..=end).step_by(16) {
+        let mut bytes = [0; 16];
+        let len = 16.min(end + 1 - at);
+        for (i, byte) in bytes[..len].iter_mut().enumerate() {
+            *byte = memory.read_u8_read_only((at + i) as Address);
+        }
+        write!(out, "{:04X} ", at)?;
+        for byte in &bytes[..len] {
+            write!(out, " {:02X}", byte)?;
+        }
+        write!(out, "{:1$}  ", "", 3 * (16 - len))?;
+        for &byte in &bytes[..len] {
+            let printable = (0x20..0x7F).contains(&byte);
+            out.write_char(if printable { byte as char } else { '.' })?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Every address `pattern` starts at, in order, without wrapping past
+/// $FFFF. Matches can overlap.
+pub fn find_bytes<M: MemoryReadOnly>(memory: &M, pattern: &[u8]) -> Vec<Address> {
+    if pattern.is_empty() || pattern.len() > 0x10000 {
+        return Vec::new();
+    }
+    (0..=0x10000 - pattern.len())
+        .filter(|&start| {
+            pattern
+                .iter()
+                .enumerate()
+                .all(|(i, &byte)| memory.read_u8_read_only((start + i) as Address) == byte)
+        })
+        .map(|start| start as Address)
+        .collect()
+}
+
+/// The registers and cycle count from one line of a reference log, or of
+/// the machine being checked against it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogState {
+    pub pc: Address,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub cycles: Option<u64>,
+}
+
+impl LogState {
+    pub fn of(cpu: &Cpu, cycles: u64) -> Self {
+        Self {
+            pc: cpu.pc,
+            a: cpu.acc,
+            x: cpu.x,
+            y: cpu.y,
+            p: u8::from(cpu.status),
+            sp: cpu.sp,
+            cycles: Some(cycles),
+        }
+    }
+    /// Reads a line in nestest.log format: the program counter in the first
+    /// column, then `A:`, `X:`, `Y:`, `P:` and `SP:` in hex after the
+    /// disassembly, and optionally `CYC:` in decimal. Other columns, such as
+    /// `PPU:`, are skipped.
+    pub fn parse(line: &str) -> Option<Self> {
+        let pc = Address::from_str_radix(line.get(..4)?, 16).ok()?;
+        let registers = &line[line.rfind(" A:")?..];
+        let (mut a, mut x, mut y, mut p, mut sp, mut cycles) = (None, None, None, None, None, None);
+        for field in registers.split_whitespace() {
+            let Some((name, value)) = field.split_once(':') else {
+                continue;
+            };
+            let hex = || u8::from_str_radix(value, 16).ok();
+            match name {
+                "A" => a = hex(),
+                "X" => x = hex(),
+                "Y" => y = hex(),
+                "P" => p = hex(),
+                "SP" => sp = hex(),
+                "CYC" => cycles = value.parse().ok(),
+                _ => (),
+            }
+        }
+        Some(Self {
+            pc,
+            a: a?,
+            x: x?,
+            y: y?,
+            p: p?,
+            sp: sp?,
+            cycles,
+        })
+    }
+    // Whether `actual` agrees with this state, as read from a reference
+    // log. Cycles are only compared if the log has them.
+    fn matches(&self, actual: &Self) -> bool {
+        let cycles = self.cycles.is_none() || self.cycles == actual.cycles;
+        cycles
+            && Self {
+                cycles: None,
+                ..*self
+            } == Self {
+                cycles: None,
+                ..*actual
+            }
+    }
+}
+
+impl fmt::Display for LogState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "{:04X}  {:?}({:?}) ",
-            self.address, self.instruction.instruction_type, self.instruction.addressing_mode
+            "{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+            self.pc, self.a, self.x, self.y, self.p, self.sp
         )?;
-        match self.operand.as_slice() {
-            &[x] => write!(f, "{:02X}", x)?,
-            &[x0, x1] => write!(f, "{:04X}", (x1 as u16) << 8 | x0 as u16)?,
-            _ => (),
+        if let Some(cycles) = self.cycles {
+            write!(f, " CYC:{}", cycles)?;
         }
         Ok(())
     }
 }
+
+#[derive(Debug, Clone)]
+pub enum Mismatch {
+    /// The registers or cycles differ.
+    State {
+        expected: LogState,
+        actual: LogState,
+    },
+    /// The step ran before this line failed.
+    Step(StepError),
+    /// The line couldn't be read.
+    Unreadable(String),
+}
+
+/// Where a run first disagreed with a reference log.
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    /// 1-based, counting every line of the log.
+    pub line: usize,
+    pub mismatch: Mismatch,
+    /// The log's lines leading up to and including the bad one, each with
+    /// the trace line the machine gave in its place.
+    pub context: Vec<(String, String)>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.mismatch {
+            Mismatch::State { expected, actual } => writeln!(
+                f,
+                "line {}: expected {}, got {}",
+                self.line, expected, actual
+            )?,
+            Mismatch::Step(error) => writeln!(f, "line {}: {:?}", self.line, error)?,
+            Mismatch::Unreadable(text) => writeln!(f, "line {}: can't read {:?}", self.line, text)?,
+        }
+        for (expected, actual) in &self.context {
+            writeln!(f, "- {}", expected)?;
+            writeln!(f, "+ {}", actual)?;
+        }
+        Ok(())
+    }
+}
+
+/// Steps `machine` once per line of `log`, a reference log in nestest.log
+/// format, checking the state before each step against the line. Blank
+/// lines are skipped. Cycles are compared relative to the first line, so
+/// the machine's count needn't start at the log's. Returns the number of
+/// lines matched, or the first divergence with up to `context` lines
+/// before it.
+///
+/// ```ignore
+/// machine.cpu.pc = 0xC000;
+/// if let Err(divergence) = compare_with_log(&mut machine, NESTEST_LOG, 8) {
+///     panic!("{}", divergence);
+/// }
+/// ```
+pub fn compare_with_log<M: Memory + MemoryReadOnly>(
+    machine: &mut Machine<M>,
+    log: &str,
+    context: usize,
+) -> Result<usize, Divergence> {
+    let format = TraceFormat::default();
+    let mut window = VecDeque::with_capacity(context + 1);
+    let mut offset = None;
+    let mut matched = 0;
+    for (index, expected_line) in log.lines().enumerate() {
+        if expected_line.trim().is_empty() {
+            continue;
+        }
+        let expected = LogState::parse(expected_line);
+        let offset = *offset.get_or_insert_with(|| {
+            expected
+                .and_then(|expected| expected.cycles)
+                .map_or(0, |cycles| cycles as i64 - machine.cycles() as i64)
+        });
+        let cycles = (machine.cycles() as i64 + offset) as u64;
+        if window.len() > context {
+            window.pop_front();
+        }
+        window.push_back((
+            String::from(expected_line),
+            format.line(&machine.cpu, &machine.memory, cycles),
+        ));
+        let actual = LogState::of(&machine.cpu, cycles);
+        let mismatch = match expected {
+            None => Some(Mismatch::Unreadable(String::from(expected_line))),
+            Some(expected) if !expected.matches(&actual) => {
+                Some(Mismatch::State { expected, actual })
+            }
+            Some(_) => machine.step().err().map(Mismatch::Step),
+        };
+        if let Some(mismatch) = mismatch {
+            return Err(Divergence {
+                line: index + 1,
+                mismatch,
+                context: window.into_iter().collect(),
+            });
+        }
+        matched += 1;
+    }
+    Ok(matched)
+}
+
+/// One way two machine states differ, as found by `diff`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    Register {
+        name: &'static str,
+        a: u16,
+        b: u16,
+    },
+    /// One of `NV-DIZC`.
+    Flag {
+        name: char,
+        a: bool,
+        b: bool,
+    },
+    Cycles {
+        a: u64,
+        b: u64,
+    },
+    /// A range of memory with at least one byte different, and its bytes
+    /// in each state.
+    Memory {
+        range: RangeInclusive<Address>,
+        a: Vec<u8>,
+        b: Vec<u8>,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difference::Register { name: "PC", a, b } => write!(f, "PC: {:04X} -> {:04X}", a, b),
+            Difference::Register { name, a, b } => write!(f, "{}: {:02X} -> {:02X}", name, a, b),
+            Difference::Flag { name, a, b } => write!(f, "{}: {} -> {}", name, *a as u8, *b as u8),
+            Difference::Cycles { a, b } => write!(f, "cycles: {} -> {}", a, b),
+            Difference::Memory { range, a, b } => {
+                write!(f, "{:04X}-{:04X}:", range.start(), range.end())?;
+                for byte in a {
+                    write!(f, " {:02X}", byte)?;
+                }
+                write!(f, " ->")?;
+                for byte in b {
+                    write!(f, " {:02X}", byte)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The registers, flags, cycle count and memory which differ between two
+/// states, in that order, with each run of differing bytes as one range.
+pub fn diff<A: MemoryReadOnly, B: MemoryReadOnly>(
+    a: &Snapshot<A>,
+    b: &Snapshot<B>,
+) -> Vec<Difference> {
+    diff_with_granularity(a, b, 1)
+}
+
+/// As `diff`, but memory is compared in aligned blocks of `granularity`
+/// bytes, e.g. 16 or a page, each differing block given whole and
+/// neighbouring ones merged.
+pub fn diff_with_granularity<A: MemoryReadOnly, B: MemoryReadOnly>(
+    a: &Snapshot<A>,
+    b: &Snapshot<B>,
+    granularity: usize,
+) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    let registers = [
+        ("PC", a.cpu.pc, b.cpu.pc),
+        ("SP", a.cpu.sp as u16, b.cpu.sp as u16),
+        ("A", a.cpu.acc as u16, b.cpu.acc as u16),
+        ("X", a.cpu.x as u16, b.cpu.x as u16),
+        ("Y", a.cpu.y as u16, b.cpu.y as u16),
+    ];
+    for (name, a, b) in registers {
+        if a != b {
+            differences.push(Difference::Register { name, a, b });
+        }
+    }
+    let (status_a, status_b) = (u8::from(a.cpu.status), u8::from(b.cpu.status));
+    for (name, mask) in [
+        ('N', flag::NEGATIVE),
+        ('V', flag::OVERFLOW),
+        ('D', flag::DECIMAL),
+        ('I', flag::INTERRUPT_DISABLE),
+        ('Z', flag::ZERO),
+        ('C', flag::CARRY),
+    ] {
+        let (a, b) = (status_a & mask != 0, status_b & mask != 0);
+        if a != b {
+            differences.push(Difference::Flag { name, a, b });
+        }
+    }
+    if a.cycles != b.cycles {
+        differences.push(Difference::Cycles {
+            a: a.cycles,
+            b: b.cycles,
+        });
+    }
+    let granularity = granularity.clamp(1, 0x10000);
+    let mut pending: Option<RangeInclusive<Address>> = None;
+    let mut flush = |pending: &mut Option<RangeInclusive<Address>>| {
+        if let Some(range) = pending.take() {
+            differences.push(Difference::Memory {
+                a: range
+                    .clone()
+                    .map(|address| a.memory.read_u8_read_only(address))
+                    .collect(),
+                b: range
+                    .clone()
+                    .map(|address| b.memory.read_u8_read_only(address))
+                    .collect(),
+                range,
+            });
+        }
+    };
+    for start in (0..0x10000).step_by(granularity) {
+        let end = (start + granularity - 1).min(0xFFFF);
+        let block = start as Address..=end as Address;
+        let differs = block.clone().any(|address| {
+            a.memory.read_u8_read_only(address) != b.memory.read_u8_read_only(address)
+        });
+        match (&mut pending, differs) {
+            (Some(range), true) => *range = *range.start()..=*block.end(),
+            (None, true) => pending = Some(block),
+            (_, false) => flush(&mut pending),
+        }
+    }
+    flush(&mut pending);
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::Ram;
+    use crate::status::Register;
+
+    fn trace(bytes: &[u8], setup: impl FnOnce(&mut Cpu, &mut [u8])) -> String {
+        let mut ram = Ram::new();
+        ram.as_mut_slice()[0xC000..0xC000 + bytes.len()].copy_from_slice(bytes);
+        let mut cpu = Cpu::new();
+        cpu.pc = 0xC000;
+        cpu.sp = 0xFD;
+        cpu.status = Register::from(0x24);
+        setup(&mut cpu, ram.as_mut_slice());
+        TraceFormat::nestest().line(&cpu, &ram, 7)
+    }
+
+    #[test]
+    fn trace_line_matches_nestest() {
+        assert_eq!(
+            trace(&[0x4C, 0xF5, 0xC5], |_, _| ()),
+            "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7"
+        );
+    }
+
+    #[test]
+    fn trace_shows_effective_address() {
+        let line = trace(&[0xB1, 0x89], |cpu, ram| {
+            cpu.y = 0x34;
+            ram[0x89] = 0x00;
+            ram[0x8A] = 0x03;
+            ram[0x0334] = 0x5A;
+        });
+        assert_eq!(
+            &line[..48],
+            "C000  B1 89     LDA ($89),Y = 0300 @ 0334 = 5A  "
+        );
+        let line = trace(&[0xA1, 0x80], |cpu, ram| {
+            cpu.x = 0x02;
+            ram[0x82] = 0x00;
+            ram[0x83] = 0x02;
+            ram[0x0200] = 0x5A;
+        });
+        assert!(line.starts_with("C000  A1 80     LDA ($80,X) @ 82 = 0200 = 5A "));
+        let line = trace(&[0x6C, 0xFF, 0x02], |_, ram| {
+            ram[0x02FF] = 0x7E;
+            ram[0x0200] = 0xDB;
+        });
+        assert!(line.starts_with("C000  6C FF 02  JMP ($02FF) = DB7E "));
+    }
+
+    #[test]
+    fn trace_marks_undocumented_opcodes() {
+        let line = trace(&[0x04, 0xA9], |_, ram| ram[0xA9] = 0x12);
+        assert!(line.starts_with("C000  04 A9    *NOP $A9 = 12 "));
+        let line = trace(&[0xD0, 0xFE], |_, _| ());
+        assert!(line.starts_with("C000  D0 FE     BNE $C000 "));
+    }
+}
@@ -0,0 +1,166 @@
+//! An optional cache of the instruction bytes fetched at each address, for
+//! memory which is slow to read. While an instruction runs out of the cache,
+//! its opcode and operand come from the entry and only its data accesses go
+//! to memory. Writes the CPU makes invalidate any entry whose bytes they
+//! overlap, so self-modifying code still runs correctly; anything else that
+//! changes code, such as DMA, a bank switch or a loader, must call
+//! `DecodeCache::invalidate` or `DecodeCache::clear`.
+//!
+//! This is only worth it for slow memory. The loop in
+//! `benches/interpreter.rs` runs about 1.9x as fast as with `Cpu::step` out
+//! of a `MemoryMap`, but slower out of `Ram`, at about 0.7x, as checking
+//! the cache costs more than reading the bytes again.
+//!
+//! ```ignore
+//! let mut cache = DecodeCache::new();
+//! loop {
+//!     cache.step(&mut cpu, &mut memory)?;
+//! }
+//! ```
+use crate::dispatch::Table;
+use crate::machine::{Cpu, Fault, Memory, Variant};
+use crate::{address, debug, Address, UnknownOpcode};
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy)]
+struct Fetch {
+    bytes: [u8; 3],
+    size: u8,
+}
+
+pub struct DecodeCache {
+    entries: Vec<Option<Fetch>>,
+    hits: u64,
+    misses: u64,
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Instructions run out of the cache see memory through this, which serves
+// the fetch from the entry and invalidates entries the instruction writes
+// over.
+struct Cached<'a, M> {
+    memory: &'a mut M,
+    entries: &'a mut [Option<Fetch>],
+    pc: Address,
+    fetch: Fetch,
+}
+
+impl<M> Cached<'_, M> {
+    fn invalidate(&mut self, address: Address) {
+        invalidate(self.entries, address);
+    }
+}
+
+// Clears the entries for instructions which can include `address`, those
+// starting up to two bytes before it.
+fn invalidate(entries: &mut [Option<Fetch>], address: Address) {
+    for offset in 0..3 {
+        entries[address.wrapping_sub(offset) as usize] = None;
+    }
+}
+
+impl<M: Memory> Memory for Cached<'_, M> {
+    fn read_u8(&mut self, address: Address) -> u8 {
+        let offset = address.wrapping_sub(self.pc);
+        if offset < self.fetch.size as Address {
+            self.fetch.bytes[offset as usize]
+        } else {
+            self.memory.read_u8(address)
+        }
+    }
+    fn write_u8(&mut self, address: Address, data: u8) {
+        self.invalidate(address);
+        self.memory.write_u8(address, data)
+    }
+    fn read_u8_zero_page(&mut self, address: u8) -> u8 {
+        self.memory.read_u8_zero_page(address)
+    }
+    fn write_u8_zero_page(&mut self, address: u8, data: u8) {
+        self.invalidate(address as Address);
+        self.memory.write_u8_zero_page(address, data)
+    }
+    fn read_u8_stack(&mut self, stack_pointer: u8) -> u8 {
+        self.memory.read_u8_stack(stack_pointer)
+    }
+    fn write_u8_stack(&mut self, stack_pointer: u8, data: u8) {
+        self.invalidate(address::from_u8_lo_hi(stack_pointer, 0x01));
+        self.memory.write_u8_stack(stack_pointer, data)
+    }
+    fn take_fault(&mut self) -> Option<Fault> {
+        self.memory.take_fault()
+    }
+    fn set_mapping_register(&mut self, index: u8, bank: u8) {
+        self.memory.set_mapping_register(index, bank)
+    }
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        Self {
+            entries: alloc::vec![None; 0x10000],
+            hits: 0,
+            misses: 0,
+        }
+    }
+    /// Runs one instruction as `Cpu::step` would. Only the NMOS 6502 and
+    /// 2A03 are cached; other variants step as normal.
+    pub fn step<M: Memory>(&mut self, cpu: &mut Cpu, memory: &mut M) -> Result<u8, UnknownOpcode> {
+        if !matches!(cpu.variant, Variant::Nmos6502 | Variant::Ricoh2A03) {
+            return cpu.step(memory);
+        }
+        let pc = cpu.pc;
+        let fetch = match self.entries[pc as usize] {
+            Some(fetch) => {
+                self.hits += 1;
+                fetch
+            }
+            None => {
+                self.misses += 1;
+                let opcode = memory.read_u8(pc);
+                let size = debug::Instruction::from_opcode(opcode)?.size();
+                let mut bytes = [opcode, 0, 0];
+                for (i, byte) in bytes.iter_mut().enumerate().take(size).skip(1) {
+                    *byte = memory.read_u8(pc.wrapping_add(i as Address));
+                }
+                let fetch = Fetch {
+                    bytes,
+                    size: size as u8,
+                };
+                self.entries[pc as usize] = Some(fetch);
+                fetch
+            }
+        };
+        let mut cached = Cached {
+            memory,
+            entries: &mut self.entries,
+            pc,
+            fetch,
+        };
+        Table::<Cached<M>>::HANDLERS[fetch.bytes[0] as usize](cpu, &mut cached)
+    }
+    /// Forgets any instruction which includes the byte at `address`.
+    pub fn invalidate(&mut self, address: Address) {
+        invalidate(&mut self.entries, address);
+    }
+    pub fn invalidate_range(&mut self, start: Address, len: usize) {
+        for i in 0..len.min(0x10000) {
+            self.invalidate(start.wrapping_add(i as Address));
+        }
+    }
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+    /// Steps run from the cache and steps which had to fetch, since the
+    /// cache was made.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
@@ -0,0 +1,100 @@
+//! Runs Klaus Dormann's 6502 functional test
+//! (<https://github.com/Klaus2m5/6502_65C02_functional_tests>). The test
+//! signals both failure and success by jumping to itself, so it's run until
+//! the program counter stops moving and the address it stopped at decides
+//! the outcome.
+//!
+//! ```ignore
+//! let image = std::fs::read("6502_functional_test.bin")?;
+//! let mut machine = Machine::new(Cpu::new(), Ram::new());
+//! match run(&mut machine, &image, &Config::default())? {
+//!     Outcome::Passed { .. } => (),
+//!     outcome => panic!("{:?}", outcome),
+//! }
+//! ```
+use crate::machine::{Fuel, Machine, Memory, MemoryReadOnly, StepError, Stopped};
+use crate::Address;
+
+/// Where the test is loaded and its traps are. The defaults suit the
+/// prebuilt `6502_functional_test.bin`; a test assembled with other
+/// settings needs the addresses from its listing.
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    pub load_address: Address,
+    pub start: Address,
+    /// The loop the test jumps to once every test has passed.
+    pub success: Address,
+    /// Where the test keeps the number of the test being run.
+    pub test_case: Address,
+    pub fuel: Fuel,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            load_address: 0x0000,
+            start: 0x0400,
+            success: 0x3469,
+            test_case: 0x0200,
+            fuel: Fuel::instructions(100_000_000),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Passed {
+        instructions: usize,
+        cycles: usize,
+    },
+    /// The test got stuck at `pc` during test number `test_case`.
+    Failed {
+        pc: Address,
+        test_case: u8,
+        instructions: usize,
+        cycles: usize,
+    },
+    /// The fuel ran out, or the run stopped for another reason, before the
+    /// test got stuck anywhere.
+    Unfinished {
+        pc: Address,
+        test_case: u8,
+        stopped: Stopped,
+    },
+}
+
+/// Loads `image` into `machine`, starts it at `config.start` and runs until
+/// it traps.
+pub fn run<M: Memory + MemoryReadOnly>(
+    machine: &mut Machine<M>,
+    image: &[u8],
+    config: &Config,
+) -> Result<Outcome, StepError> {
+    machine.memory.load(config.load_address, image);
+    machine.cpu.pc = config.start;
+    let mut previous = config.start;
+    let report = machine.run_until_with_fuel(config.fuel, |machine| {
+        let stuck = machine.cpu.pc == previous;
+        previous = machine.cpu.pc;
+        stuck
+    })?;
+    let pc = machine.cpu.pc;
+    let test_case = machine.memory.read_u8_read_only(config.test_case);
+    Ok(match report.stopped {
+        Stopped::Condition if pc == config.success => Outcome::Passed {
+            instructions: report.instructions,
+            cycles: report.cycles,
+        },
+        Stopped::Condition => Outcome::Failed {
+            pc,
+            test_case,
+            instructions: report.instructions,
+            cycles: report.cycles,
+        },
+        stopped => Outcome::Unfinished {
+            pc,
+            test_case,
+            stopped,
+        },
+    })
+}
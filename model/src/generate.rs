@@ -0,0 +1,267 @@
+//! Random instructions, programs and machine states for property tests
+//! and fuzzing, built from a plain byte source so they can be driven by
+//! any framework: `arbitrary`'s raw input, a proptest `Vec<u8>`, or
+//! a fuzzer's buffer. The same bytes always give the same result, so a
+//! failing case can be kept as its bytes. With the `arbitrary` and
+//! `proptest` features, instructions and CPUs also implement each crate's
+//! `Arbitrary`, and `strategy` has proptest strategies for them.
+//!
+//! ```ignore
+//! proptest! {
+//!     #[test]
+//!     fn steps(data: Vec<u8>) {
+//!         let mut source = Source::new(&data);
+//!         let program = generate::program(&mut source, 16, true);
+//!         let mut machine = generate::machine(&mut source, &program);
+//!         // Branches can leave the program for random RAM, which may jam.
+//!         let _ = machine.run(Fuel::instructions(16));
+//!     }
+//! }
+//! ```
+#[cfg(any(feature = "arbitrary", feature = "proptest"))]
+use crate::debug::Instruction;
+use crate::debug::{Bytes, InstructionWithOperand};
+use crate::machine::{Cpu, Machine, Ram};
+use crate::power_on::Pattern;
+use crate::status::Register;
+use crate::{opcode, Address};
+use alloc::vec::Vec;
+
+/// Bytes to build values from. Once they run out, every value is zero,
+/// which keeps generated values small as a fuzzer shrinks its input.
+pub struct Source<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Source<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    pub fn u8(&mut self) -> u8 {
+        match self.data.split_first() {
+            Some((&byte, rest)) => {
+                self.data = rest;
+                byte
+            }
+            None => 0,
+        }
+    }
+    pub fn u16(&mut self) -> u16 {
+        u16::from_le_bytes([self.u8(), self.u8()])
+    }
+    pub fn u64(&mut self) -> u64 {
+        (self.u16() as u64)
+            | (self.u16() as u64) << 16
+            | (self.u16() as u64) << 32
+            | (self.u16() as u64) << 48
+    }
+    pub fn bool(&mut self) -> bool {
+        self.u8() & 1 != 0
+    }
+    /// One of `choices`, which must not be empty.
+    pub fn choose<T: Copy>(&mut self, choices: &[T]) -> T {
+        choices[self.u16() as usize % choices.len()]
+    }
+}
+
+/// Every opcode the NMOS core decodes, or only the documented ones.
+pub fn opcodes(documented_only: bool) -> Vec<u8> {
+    (0..=u8::MAX)
+        .filter(|&byte| opcode::info(byte).is_some_and(|info| info.documented || !documented_only))
+        .collect()
+}
+
+/// One instruction with random operand bytes, as it would be in memory.
+pub fn instruction(source: &mut Source, documented_only: bool) -> Vec<u8> {
+    let opcode = source.choose(&opcodes(documented_only));
+    let size = opcode::info(opcode).map_or(1, |info| info.size);
+    let mut bytes = Vec::with_capacity(size);
+    bytes.push(opcode);
+    bytes.extend((1..size).map(|_| source.u8()));
+    bytes
+}
+
+/// One instruction as `instruction` makes, decoded at a random address.
+pub fn instruction_with_operand(
+    source: &mut Source,
+    documented_only: bool,
+) -> InstructionWithOperand {
+    let address = source.u16();
+    decode(address, &instruction(source, documented_only))
+}
+
+// Decodes `bytes`, which start with an opcode the NMOS core decodes.
+fn decode(address: Address, bytes: &[u8]) -> InstructionWithOperand {
+    let memory = Bytes {
+        base: address,
+        bytes,
+    };
+    InstructionWithOperand::decode(address, &memory).expect("opcode from `opcodes`")
+}
+
+/// `instructions` instructions one after another.
+pub fn program(source: &mut Source, instructions: usize, documented_only: bool) -> Vec<u8> {
+    (0..instructions)
+        .flat_map(|_| instruction(source, documented_only))
+        .collect()
+}
+
+/// An NMOS CPU with random registers and status.
+pub fn cpu(source: &mut Source) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.pc = source.u16();
+    cpu.acc = source.u8();
+    cpu.x = source.u8();
+    cpu.y = source.u8();
+    cpu.sp = source.u8();
+    cpu.status = Register::from(source.u8());
+    cpu
+}
+
+/// A machine with a random CPU and RAM filled from a random seed, with
+/// `program` loaded at the program counter.
+pub fn machine(source: &mut Source, program: &[u8]) -> Machine<Ram> {
+    let cpu = cpu(source);
+    let mut ram = Ram::with_pattern(Pattern::Random(source.u64()));
+    let pc: Address = cpu.pc;
+    for (i, &byte) in program.iter().enumerate() {
+        ram.as_mut_slice()[pc.wrapping_add(i as Address) as usize] = byte;
+    }
+    Machine::new(cpu, ram)
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Instruction {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let opcode = *u.choose(&opcodes(false))?;
+        Ok(Instruction::from_opcode(opcode).expect("opcode from `opcodes`"))
+    }
+}
+
+/// Any instruction the NMOS core decodes, documented or not, at any
+/// address.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for InstructionWithOperand {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let address = u.arbitrary()?;
+        let opcode = *u.choose(&opcodes(false))?;
+        let [lo, hi]: [u8; 2] = u.arbitrary()?;
+        Ok(decode(address, &[opcode, lo, hi]))
+    }
+}
+
+/// An NMOS CPU with arbitrary registers and status, as `cpu` makes.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Cpu {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let bytes: [u8; 7] = u.arbitrary()?;
+        Ok(cpu(&mut Source::new(&bytes)))
+    }
+}
+
+/// Proptest strategies for instructions and CPU states. Each type also
+/// implements proptest's `Arbitrary` with the widest of them, so it can be
+/// used with `any`.
+#[cfg(feature = "proptest")]
+pub mod strategy {
+    use super::*;
+    use proptest::prelude::*;
+    use proptest::sample::select;
+
+    /// Instructions for every opcode the NMOS core decodes, or only the
+    /// documented ones.
+    pub fn instruction(documented_only: bool) -> impl Strategy<Value = Instruction> {
+        select(opcodes(documented_only))
+            .prop_map(|opcode| Instruction::from_opcode(opcode).expect("opcode from `opcodes`"))
+    }
+
+    /// As `instruction`, with any operand at any address.
+    pub fn instruction_with_operand(
+        documented_only: bool,
+    ) -> impl Strategy<Value = InstructionWithOperand> {
+        (
+            any::<Address>(),
+            select(opcodes(documented_only)),
+            any::<[u8; 2]>(),
+        )
+            .prop_map(|(address, opcode, [lo, hi])| decode(address, &[opcode, lo, hi]))
+    }
+
+    /// NMOS CPUs with any registers and status.
+    pub fn cpu() -> impl Strategy<Value = Cpu> {
+        any::<[u8; 7]>().prop_map(|bytes| super::cpu(&mut Source::new(&bytes)))
+    }
+
+    impl Arbitrary for Instruction {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+        fn arbitrary_with((): ()) -> Self::Strategy {
+            instruction(false).boxed()
+        }
+    }
+
+    impl Arbitrary for InstructionWithOperand {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+        fn arbitrary_with((): ()) -> Self::Strategy {
+            instruction_with_operand(false).boxed()
+        }
+    }
+
+    impl Arbitrary for Cpu {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+        fn arbitrary_with((): ()) -> Self::Strategy {
+            cpu().boxed()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Encodes `instruction`, decodes the bytes at the same address, and
+    // checks nothing changed.
+    fn assert_round_trips(instruction: &InstructionWithOperand) {
+        let bytes = instruction.encode();
+        assert_eq!(bytes.len(), instruction.instruction().size());
+        let decoded = decode(instruction.address(), &bytes);
+        assert_eq!(decoded.address(), instruction.address());
+        assert_eq!(decoded.opcode(), instruction.opcode());
+        assert_eq!(decoded.operand(), instruction.operand());
+        assert_eq!(decoded.encode(), bytes);
+    }
+
+    #[test]
+    fn every_opcode_round_trips() {
+        for opcode in opcodes(false) {
+            // At the top of the address space, so the operand wraps.
+            assert_round_trips(&decode(0xFFFF, &[opcode, 0x12, 0x34]));
+        }
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn arbitrary_instructions_round_trip() {
+        use arbitrary::{Arbitrary, Unstructured};
+        for seed in 0..=u8::MAX {
+            let data: Vec<u8> = (0..16)
+                .map(|i| seed.wrapping_mul(31).wrapping_add(i))
+                .collect();
+            let instruction = InstructionWithOperand::arbitrary(&mut Unstructured::new(&data));
+            assert_round_trips(&instruction.unwrap());
+        }
+    }
+
+    #[cfg(feature = "proptest")]
+    proptest::proptest! {
+        #[test]
+        fn instructions_round_trip(instruction in strategy::instruction_with_operand(false)) {
+            assert_round_trips(&instruction);
+        }
+    }
+}
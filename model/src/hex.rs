@@ -0,0 +1,319 @@
+//! Intel HEX and Motorola S-record images, as firmware for real boards is
+//! often distributed. An image is parsed completely before anything is
+//! loaded, so a bad record leaves memory as it was. Both formats can hold
+//! 32-bit addresses, and any data or entry point past $FFFF is an error.
+//!
+//! ```ignore
+//! let text = std::fs::read_to_string("firmware.hex")?;
+//! if let Some(entry) = machine.load_ihex(&text)? {
+//!     machine.cpu.pc = entry;
+//! }
+//! ```
+use crate::machine::Memory;
+use crate::Address;
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The line doesn't start with `:`, or `S` for an S-record.
+    NoStartCode,
+    /// A character which isn't a hex digit, or an odd number of them.
+    BadHex,
+    /// The record's length doesn't match its byte count or its type.
+    BadLength,
+    BadChecksum,
+    UnknownRecordType(u8),
+    /// Data or an entry point at this address, the first past $FFFF.
+    AddressOutOfRange(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error {
+    /// Counting from 1.
+    pub line: usize,
+    pub kind: ErrorKind,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match self.kind {
+            ErrorKind::NoStartCode => write!(f, "missing start code"),
+            ErrorKind::BadHex => write!(f, "bad hex digits"),
+            ErrorKind::BadLength => write!(f, "bad record length"),
+            ErrorKind::BadChecksum => write!(f, "bad checksum"),
+            ErrorKind::UnknownRecordType(kind) => write!(f, "unknown record type {}", kind),
+            ErrorKind::AddressOutOfRange(address) => {
+                write!(f, "address {:X} is out of range", address)
+            }
+        }
+    }
+}
+
+/// The data of a parsed image, with contiguous records joined together.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Image {
+    /// Each run of data and the address it starts at, in the order given.
+    pub chunks: Vec<(Address, Vec<u8>)>,
+    /// The start address, if the image gives one.
+    pub entry: Option<Address>,
+}
+
+impl Image {
+    /// Writes every chunk with `Memory::load`, so ROM is written too.
+    pub fn load<M: Memory>(&self, memory: &mut M) {
+        for (address, data) in &self.chunks {
+            memory.load(*address, data);
+        }
+    }
+    fn place(&mut self, address: u32, data: &[u8]) -> Result<(), ErrorKind> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if address as u64 + data.len() as u64 > 0x10000 {
+            return Err(ErrorKind::AddressOutOfRange(address.max(0x10000)));
+        }
+        let address = address as Address;
+        match self.chunks.last_mut() {
+            Some((start, chunk)) if *start as usize + chunk.len() == address as usize => {
+                chunk.extend_from_slice(data)
+            }
+            _ => self.chunks.push((address, data.into())),
+        }
+        Ok(())
+    }
+    fn set_entry(&mut self, entry: u32) -> Result<(), ErrorKind> {
+        if entry > 0xFFFF {
+            return Err(ErrorKind::AddressOutOfRange(entry));
+        }
+        self.entry = Some(entry as Address);
+        Ok(())
+    }
+}
+
+fn decode(digits: &str) -> Result<Vec<u8>, ErrorKind> {
+    if !digits.len().is_multiple_of(2) || !digits.is_ascii() {
+        return Err(ErrorKind::BadHex);
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| ErrorKind::BadHex))
+        .collect()
+}
+
+fn be(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0, |value, &byte| value << 8 | byte as u32)
+}
+
+// Calls `record` with each non-blank line, trimmed, stopping early if it
+// returns `Ok(false)`. Errors are given the line's number.
+fn each_line<F: FnMut(&str) -> Result<bool, ErrorKind>>(
+    text: &str,
+    mut record: F,
+) -> Result<(), Error> {
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match record(line) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(kind) => {
+                return Err(Error {
+                    line: number + 1,
+                    kind,
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses Intel HEX, with 16-bit data records, segment and linear
+/// extended addresses, and either kind of start address. Anything after
+/// the end of file record is ignored.
+pub fn parse_ihex(text: &str) -> Result<Image, Error> {
+    let mut image = Image::default();
+    let mut upper = 0u32;
+    each_line(text, |line| {
+        let record = line.strip_prefix(':').ok_or(ErrorKind::NoStartCode)?;
+        let bytes = decode(record)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(ErrorKind::BadLength);
+        }
+        if bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+            return Err(ErrorKind::BadChecksum);
+        }
+        let address = be(&bytes[1..3]);
+        let data = &bytes[4..bytes.len() - 1];
+        let expect = |len: usize| {
+            if data.len() == len {
+                Ok(())
+            } else {
+                Err(ErrorKind::BadLength)
+            }
+        };
+        match bytes[3] {
+            0x00 => image.place(upper + address, data)?,
+            0x01 => return Ok(false),
+            0x02 => {
+                expect(2)?;
+                upper = be(data) << 4;
+            }
+            0x03 => {
+                expect(4)?;
+                image.set_entry((be(&data[..2]) << 4) + be(&data[2..]))?;
+            }
+            0x04 => {
+                expect(2)?;
+                upper = be(data) << 16;
+            }
+            0x05 => {
+                expect(4)?;
+                image.set_entry(be(data))?;
+            }
+            kind => return Err(ErrorKind::UnknownRecordType(kind)),
+        }
+        Ok(true)
+    })?;
+    Ok(image)
+}
+
+/// Parses Motorola S-records: data in S1, S2 or S3 records and the start
+/// address in S7, S8 or S9, with headers and record counts skipped.
+pub fn parse_srec(text: &str) -> Result<Image, Error> {
+    let mut image = Image::default();
+    each_line(text, |line| {
+        let record = line.strip_prefix('S').ok_or(ErrorKind::NoStartCode)?;
+        let kind = record
+            .chars()
+            .next()
+            .and_then(|kind| kind.to_digit(10))
+            .ok_or(ErrorKind::BadHex)? as u8;
+        let bytes = decode(&record[1..])?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(ErrorKind::BadLength);
+        }
+        if bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0xFF {
+            return Err(ErrorKind::BadChecksum);
+        }
+        let address_size = match kind {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            kind => return Err(ErrorKind::UnknownRecordType(kind)),
+        };
+        if bytes.len() < address_size + 2 {
+            return Err(ErrorKind::BadLength);
+        }
+        let address = be(&bytes[1..1 + address_size]);
+        let data = &bytes[1 + address_size..bytes.len() - 1];
+        match kind {
+            1..=3 => image.place(address, data)?,
+            7..=9 => image.set_entry(address)?,
+            _ => (),
+        }
+        Ok(true)
+    })?;
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn parses_ihex() {
+        let text = ":03C00000A9018D06\n:02C00300000239\n\n:01020000EA13\n\
+                    :040000050000C00037\n:00000001FF\nignored";
+        let image = parse_ihex(text).unwrap();
+        assert_eq!(
+            image.chunks,
+            vec![
+                (0xC000, vec![0xA9, 0x01, 0x8D, 0x00, 0x02]),
+                (0x0200, vec![0xEA])
+            ]
+        );
+        assert_eq!(image.entry, Some(0xC000));
+    }
+
+    #[test]
+    fn parses_srec() {
+        let text = "S00600004844521B\nS105C000A90190\nS20500C0028DAB\nS5030001FB\nS903C0003C";
+        let image = parse_srec(text).unwrap();
+        assert_eq!(image.chunks, vec![(0xC000, vec![0xA9, 0x01, 0x8D])]);
+        assert_eq!(image.entry, Some(0xC000));
+    }
+
+    fn error(line: usize, kind: ErrorKind) -> Error {
+        Error { line, kind }
+    }
+
+    #[test]
+    fn rejects_bad_checksums() {
+        assert_eq!(
+            parse_ihex(":01020000EA13\n:03C00000A9018D07"),
+            Err(error(2, ErrorKind::BadChecksum))
+        );
+        assert_eq!(
+            parse_srec("S105C000A90191"),
+            Err(error(1, ErrorKind::BadChecksum))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_record_types() {
+        assert_eq!(
+            parse_ihex(":00000006FA"),
+            Err(error(1, ErrorKind::UnknownRecordType(6)))
+        );
+        assert_eq!(
+            parse_srec("S4030000FC"),
+            Err(error(1, ErrorKind::UnknownRecordType(4)))
+        );
+    }
+
+    #[test]
+    fn rejects_addresses_past_ffff() {
+        // Four bytes at $FFFE run two past the end.
+        assert_eq!(
+            parse_ihex(":04FFFE0001020304F5"),
+            Err(error(1, ErrorKind::AddressOutOfRange(0x10000)))
+        );
+        assert_eq!(
+            parse_ihex(":020000040001F9\n:01000000EA15"),
+            Err(error(2, ErrorKind::AddressOutOfRange(0x10000)))
+        );
+        assert_eq!(
+            parse_ihex(":04000003C000001029"),
+            Err(error(1, ErrorKind::AddressOutOfRange(0xC0010)))
+        );
+        assert_eq!(
+            parse_srec("S105FFFF0102F9"),
+            Err(error(1, ErrorKind::AddressOutOfRange(0x10000)))
+        );
+        assert_eq!(
+            parse_srec("S804010000FA"),
+            Err(error(1, ErrorKind::AddressOutOfRange(0x10000)))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert_eq!(
+            parse_ihex("01020000EA13"),
+            Err(error(1, ErrorKind::NoStartCode))
+        );
+        assert_eq!(parse_ihex(":01020000EA1"), Err(error(1, ErrorKind::BadHex)));
+        assert_eq!(
+            parse_ihex(":02020000EA14"),
+            Err(error(1, ErrorKind::BadLength))
+        );
+        assert_eq!(parse_srec("SX"), Err(error(1, ErrorKind::BadHex)));
+    }
+}
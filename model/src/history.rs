@@ -0,0 +1,219 @@
+//! A ring buffer of the last instructions and interrupts a `Machine` ran,
+//! with the registers before each, for working out how it got somewhere
+//! after a breakpoint, `BRK` or jam. Enabled with `Machine::enable_history`,
+//! with the buffer allocated once up front, so it's cheap enough to leave on.
+//! Each instruction's bytes are read an extra time before it runs, which
+//! only matters for code run out of a peripheral.
+//!
+//! ```ignore
+//! machine.enable_history(64);
+//! if let Err(error) = machine.run(Fuel::cycles(10_000_000)) {
+//!     eprintln!("{:?}, after:\n{}", error, machine.history().unwrap());
+//! }
+//! ```
+use crate::annotation::Annotations;
+use crate::debug::{LogState, TraceFormat};
+use crate::machine::{Cpu, MemoryReadOnly, StatusRegister};
+use crate::Address;
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The instruction's bytes. Those past its size are zero.
+    Instruction([u8; 3]),
+    Nmi,
+    Irq,
+}
+
+/// One instruction or interrupt, with the registers and cycle count from
+/// just before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    pub before: LogState,
+    pub event: Event,
+}
+
+// The bytes of an `Event::Instruction`, mapped at its address.
+struct Fetched {
+    pc: Address,
+    bytes: [u8; 3],
+}
+
+impl MemoryReadOnly for Fetched {
+    fn read_u8_read_only(&self, address: Address) -> u8 {
+        let offset = address.wrapping_sub(self.pc) as usize;
+        self.bytes.get(offset).copied().unwrap_or(0)
+    }
+}
+
+/// As a nestest.log line without the values at the operand's effective
+/// address, which aren't kept, like
+/// `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7`,
+/// with `NMI` or `IRQ` in place of the instruction for an interrupt.
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let LogState {
+            pc,
+            a,
+            x,
+            y,
+            p,
+            sp,
+            cycles,
+        } = self.before;
+        let interrupt = match self.event {
+            Event::Instruction(bytes) => {
+                let mut cpu = Cpu::new();
+                cpu.pc = pc;
+                cpu.acc = a;
+                cpu.x = x;
+                cpu.y = y;
+                cpu.status = StatusRegister::from(p);
+                cpu.sp = sp;
+                let memory = Fetched { pc, bytes };
+                return TraceFormat::nestest().write_line_with(f, &cpu, &memory, cycles, false);
+            }
+            Event::Nmi => "NMI",
+            Event::Irq => "IRQ",
+        };
+        // Lined up with the registers of an instruction's line.
+        write!(
+            f,
+            "{:04X}  {:<41} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+            pc, interrupt, a, x, y, p, sp
+        )?;
+        if let Some(cycles) = cycles {
+            write!(f, " CYC:{}", cycles)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct History {
+    entries: Vec<Entry>,
+    capacity: usize,
+    // Where the next entry goes once the buffer is full.
+    next: usize,
+}
+
+impl History {
+    /// Keeps the last `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+            capacity,
+            next: 0,
+        }
+    }
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub(crate) fn record(&mut self, entry: Entry) {
+        if self.entries.len() < self.capacity {
+            self.entries.push(entry);
+        } else if !self.entries.is_empty() {
+            self.entries[self.next] = entry;
+            self.next = (self.next + 1) % self.entries.len();
+        }
+    }
+    /// The entries, oldest first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Entry> {
+        let (newer, older) = self.entries.split_at(self.next);
+        older.iter().chain(newer)
+    }
+    /// The most recent entry.
+    pub fn last(&self) -> Option<&Entry> {
+        self.iter().next_back()
+    }
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.next = 0;
+    }
+    /// Like `Display`, with the comment `annotations` has at each
+    /// instruction's address, if any, appended after `;`.
+    pub fn write_annotated<W: fmt::Write>(
+        &self,
+        out: &mut W,
+        annotations: &Annotations,
+    ) -> fmt::Result {
+        for entry in self.iter() {
+            write!(out, "{}", entry)?;
+            let comment = match entry.event {
+                Event::Instruction(_) => annotations.comment(entry.before.pc),
+                Event::Nmi | Event::Irq => None,
+            };
+            if let Some(comment) = comment {
+                write!(out, "  ; {}", comment)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// One entry per line, oldest first.
+impl fmt::Display for History {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in self.iter() {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(pc: Address, event: Event, cycles: Option<u64>) -> Entry {
+        Entry {
+            before: LogState {
+                pc,
+                a: 0x01,
+                x: 0x02,
+                y: 0x03,
+                p: 0x24,
+                sp: 0xFD,
+                cycles,
+            },
+            event,
+        }
+    }
+
+    #[test]
+    fn entries_are_written_in_nestest_syntax() {
+        let mut history = History::new(8);
+        for entry in [
+            entry(0xC000, Event::Instruction([0x4C, 0xF5, 0xC5]), Some(7)),
+            entry(0xC5F5, Event::Instruction([0xB1, 0x89, 0x00]), Some(10)),
+            entry(0xC5F7, Event::Instruction([0xB5, 0x33, 0x00]), None),
+            entry(0xC5F9, Event::Instruction([0x04, 0xA9, 0x00]), None),
+            entry(0xC5FB, Event::Instruction([0x02, 0x00, 0x00]), None),
+            entry(0xC5FC, Event::Nmi, Some(20)),
+        ] {
+            history.record(entry);
+        }
+        let lines = [
+            "C000  4C F5 C5  JMP $C5F5                       A:01 X:02 Y:03 P:24 SP:FD CYC:7",
+            "C5F5  B1 89     LDA ($89),Y                     A:01 X:02 Y:03 P:24 SP:FD CYC:10",
+            "C5F7  B5 33     LDA $33,X                       A:01 X:02 Y:03 P:24 SP:FD",
+            "C5F9  04 A9    *NOP $A9                         A:01 X:02 Y:03 P:24 SP:FD",
+            "C5FB  02       *KIL                             A:01 X:02 Y:03 P:24 SP:FD",
+            "C5FC  NMI                                       A:01 X:02 Y:03 P:24 SP:FD CYC:20",
+        ];
+        let mut expected = alloc::string::String::new();
+        for line in lines {
+            expected.push_str(line);
+            expected.push('\n');
+        }
+        assert_eq!(alloc::format!("{}", history), expected);
+    }
+}
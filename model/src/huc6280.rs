@@ -0,0 +1,226 @@
+//! Partial support for Hudson's HuC6280, used as `Variant::HuC6280`. The
+//! zero page and stack are moved to $2000 and $2100, and the block
+//! transfer, `TAM`/`TMA`, register swap and clear, `ST0`-`ST2` and speed
+//! instructions are added. Every other opcode runs as on the NMOS 6502, so
+//! the 65C02 instructions the HuC6280 also has aren't there, and the `T`
+//! flag isn't modelled.
+//!
+//! Addresses are logical throughout. `TAM` only stores the mapping
+//! registers in `Cpu::mpr` and passes them on through
+//! `Memory::set_mapping_register`, so memory has to do any mapping to
+//! physical addresses itself. Block transfers run in one step; the cycles
+//! past 255 are left in `Cpu::extra_cycles`.
+use crate::machine::{Cpu, Memory};
+use crate::{address, Address, UnknownOpcode};
+
+pub const ZERO_PAGE: Address = 0x2000;
+pub const STACK: Address = 0x2100;
+
+pub const SXY: u8 = 0x02;
+pub const ST0: u8 = 0x03;
+pub const ST1: u8 = 0x13;
+pub const SAX: u8 = 0x22;
+pub const ST2: u8 = 0x23;
+pub const SAY: u8 = 0x42;
+pub const TMA: u8 = 0x43;
+pub const TAM: u8 = 0x53;
+pub const CSL: u8 = 0x54;
+pub const CLA: u8 = 0x62;
+pub const TII: u8 = 0x73;
+pub const CLX: u8 = 0x82;
+pub const CLY: u8 = 0xC2;
+pub const TDD: u8 = 0xC3;
+pub const TIN: u8 = 0xD3;
+pub const CSH: u8 = 0xD4;
+pub const TIA: u8 = 0xE3;
+pub const TAI: u8 = 0xF3;
+
+// The bank holding the VDC, which `ST0`-`ST2` write to at offsets 0, 2
+// and 3.
+const IO_BANK: u8 = 0xFF;
+const BANK_SIZE: Address = 0x2000;
+
+// Moves zero page accesses up to the given base, which the 65C816 also
+// uses for its direct page.
+pub(crate) struct Relocated<'a, M>(pub(crate) &'a mut M, pub(crate) Address);
+
+impl<M: Memory> Memory for Relocated<'_, M> {
+    fn read_u8(&mut self, address: Address) -> u8 {
+        self.0.read_u8(address)
+    }
+    fn write_u8(&mut self, address: Address, data: u8) {
+        self.0.write_u8(address, data)
+    }
+    fn read_u8_zero_page(&mut self, address: u8) -> u8 {
+        self.0.read_u8(self.1.wrapping_add(address as Address))
+    }
+    fn write_u8_zero_page(&mut self, address: u8, data: u8) {
+        self.0
+            .write_u8(self.1.wrapping_add(address as Address), data)
+    }
+    fn read_block(&mut self, address: Address, buffer: &mut [u8]) {
+        self.0.read_block(address, buffer)
+    }
+    fn write_block(&mut self, address: Address, data: &[u8]) {
+        self.0.write_block(address, data)
+    }
+    fn load(&mut self, address: Address, data: &[u8]) {
+        self.0.load(address, data)
+    }
+    fn take_fault(&mut self) -> Option<crate::machine::Fault> {
+        self.0.take_fault()
+    }
+    fn set_mapping_register(&mut self, index: u8, bank: u8) {
+        self.0.set_mapping_register(index, bank)
+    }
+    fn zero_page_base(&self) -> Address {
+        self.1
+    }
+}
+
+// How a block transfer moves its source and destination after each byte.
+#[derive(Clone, Copy)]
+enum Step {
+    Increment,
+    Decrement,
+    Fixed,
+    Alternate,
+}
+
+impl Step {
+    fn apply(self, base: Address, index: u16) -> Address {
+        match self {
+            Step::Increment => base.wrapping_add(index),
+            Step::Decrement => base.wrapping_sub(index),
+            Step::Fixed => base,
+            Step::Alternate => base.wrapping_add(index & 1),
+        }
+    }
+}
+
+impl Cpu {
+    pub(crate) fn step_huc6280<M: Memory>(
+        &mut self,
+        opcode: u8,
+        memory: &mut M,
+    ) -> Result<u8, UnknownOpcode> {
+        let immediate = |memory: &mut M, pc: Address| memory.read_u8(pc.wrapping_add(1));
+        let (bytes, cycles) = match opcode {
+            SXY => {
+                core::mem::swap(&mut self.x, &mut self.y);
+                (1, 3)
+            }
+            SAX => {
+                core::mem::swap(&mut self.acc, &mut self.x);
+                (1, 3)
+            }
+            SAY => {
+                core::mem::swap(&mut self.acc, &mut self.y);
+                (1, 3)
+            }
+            CLA => {
+                self.acc = 0;
+                (1, 2)
+            }
+            CLX => {
+                self.x = 0;
+                (1, 2)
+            }
+            CLY => {
+                self.y = 0;
+                (1, 2)
+            }
+            // The clock speed isn't modelled.
+            CSL | CSH => (1, 3),
+            ST0 | ST1 | ST2 => {
+                let data = immediate(memory, self.pc);
+                let offset = match opcode {
+                    ST0 => 0,
+                    ST1 => 2,
+                    _ => 3,
+                };
+                if let Some(index) = self.mpr.iter().position(|&bank| bank == IO_BANK) {
+                    memory.write_u8(index as Address * BANK_SIZE + offset, data);
+                }
+                (2, 4)
+            }
+            TAM => {
+                let mask = immediate(memory, self.pc);
+                for index in (0..8).filter(|index| mask & (1 << index) != 0) {
+                    self.mpr[index as usize] = self.acc;
+                    memory.set_mapping_register(index, self.acc);
+                }
+                (2, 5)
+            }
+            TMA => {
+                let mask = immediate(memory, self.pc);
+                if mask != 0 {
+                    self.acc = self.mpr[mask.trailing_zeros() as usize];
+                }
+                (2, 4)
+            }
+            TII | TDD | TIN | TIA | TAI => {
+                let (source_step, destination_step) = match opcode {
+                    TII => (Step::Increment, Step::Increment),
+                    TDD => (Step::Decrement, Step::Decrement),
+                    TIN => (Step::Increment, Step::Fixed),
+                    TIA => (Step::Increment, Step::Alternate),
+                    _ => (Step::Alternate, Step::Increment),
+                };
+                let operand = |memory: &mut M, offset: Address| {
+                    let lo = memory.read_u8(self.pc.wrapping_add(offset));
+                    let hi = memory.read_u8(self.pc.wrapping_add(offset + 1));
+                    address::from_u8_lo_hi(lo, hi)
+                };
+                let source = operand(memory, 1);
+                let destination = operand(memory, 3);
+                // A length of 0 moves 64K.
+                let length = match operand(memory, 5) {
+                    0 => 0x10000,
+                    length => length as u32,
+                };
+                for index in 0..length {
+                    let index = index as u16;
+                    let data = memory.read_u8(source_step.apply(source, index));
+                    memory.write_u8(destination_step.apply(destination, index), data);
+                }
+                let cycles = 17 + 6 * length;
+                self.extra_cycles = cycles.saturating_sub(u8::MAX as u32);
+                (7, cycles.min(u8::MAX as u32) as u8)
+            }
+            _ => return self.execute(opcode, &mut Relocated(memory, ZERO_PAGE)),
+        };
+        self.pc = self.pc.wrapping_add(bytes);
+        Ok(cycles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::{Ram, Variant};
+    use crate::opcode;
+
+    #[test]
+    fn read_modify_write_uses_the_relocated_zero_page() {
+        let mut ram = Ram::new();
+        #[rustfmt::skip]
+        ram.write_block(0x8000, &[
+            opcode::inc::ZERO_PAGE, 0x10,
+            opcode::rol::ZERO_PAGE_X_INDEXED, 0x10,
+            opcode::dcp::unofficial0::ZERO_PAGE, 0x12,
+            opcode::lda::ZERO_PAGE, 0x10,
+        ]);
+        ram.write_block(ZERO_PAGE + 0x10, &[0x41, 0x42, 0x43]);
+        let mut cpu = Cpu::new();
+        cpu.variant = Variant::HuC6280;
+        cpu.pc = 0x8000;
+        cpu.x = 1;
+        for _ in 0..4 {
+            cpu.step(&mut ram).unwrap();
+        }
+        assert_eq!(cpu.acc, 0x42);
+        assert_eq!(&ram.as_slice()[0x2010..0x2013], &[0x42, 0x84, 0x42]);
+        assert_eq!(&ram.as_slice()[0x0010..0x0013], &[0, 0, 0]);
+    }
+}
@@ -0,0 +1,240 @@
+//! NES cartridge images in the iNES format, and NES 2.0's extensions to
+//! its header. Any mapper can be parsed, but only NROM (mapper 0), with
+//! 16KiB of PRG ROM mirrored or 32KiB filling $8000-$FFFF, can be mapped
+//! for the CPU. The PPU isn't emulated, so CHR data is left to the caller.
+//!
+//! ```ignore
+//! let mut machine = Machine::new(cpu, Ram::new());
+//! machine.cpu.variant = Variant::Ricoh2A03;
+//! let rom = machine.load_ines(&std::fs::read("nestest.nes")?)?;
+//! machine.reset();
+//! ```
+use crate::Address;
+use alloc::vec::Vec;
+use core::fmt;
+
+const MAGIC: &[u8; 4] = b"NES\x1A";
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_BANK: usize = 0x4000;
+const CHR_BANK: usize = 0x2000;
+/// Where PRG ROM starts in the CPU's address space.
+pub const PRG_START: Address = 0x8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The file doesn't start with `NES` and $1A.
+    NotInes,
+    /// The file is shorter than its header says, by this many bytes.
+    Truncated(usize),
+    /// A mapper other than NROM, which can't be mapped yet.
+    UnsupportedMapper(u16),
+    /// NROM with a PRG ROM size other than 16KiB or 32KiB.
+    BadPrgSize(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotInes => write!(f, "not an iNES image"),
+            Error::Truncated(missing) => write!(f, "truncated by {} bytes", missing),
+            Error::UnsupportedMapper(mapper) => write!(f, "mapper {} isn't supported", mapper),
+            Error::BadPrgSize(size) => write!(f, "{} bytes of PRG ROM can't be mapped", size),
+        }
+    }
+}
+
+/// How the cartridge wires the PPU's nametables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rom {
+    pub mapper: u16,
+    pub mirroring: Mirroring,
+    /// Whether the cartridge has battery-backed RAM at $6000-$7FFF.
+    pub battery: bool,
+    /// The 512 bytes some dumps have for $7000-$71FF, if present.
+    pub trainer: Option<Vec<u8>>,
+    pub prg: Vec<u8>,
+    /// Empty if the board has CHR RAM instead.
+    pub chr: Vec<u8>,
+    /// Whether the header is NES 2.0.
+    pub nes2: bool,
+}
+
+// A NES 2.0 ROM size from its LSB and the MSB nibble, which if $F makes
+// the LSB an exponent and multiplier instead.
+fn nes2_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0xF {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 3) as usize * 2 + 1;
+        1usize
+            .checked_shl(exponent)
+            .unwrap_or(0)
+            .saturating_mul(multiplier)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * unit
+    }
+}
+
+impl Rom {
+    /// Parses the header and splits out the trainer and ROMs. Anything
+    /// after the CHR ROM is ignored.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC {
+            return Err(Error::NotInes);
+        }
+        let header = &bytes[..HEADER_SIZE];
+        let nes2 = header[7] & 0x0C == 0x08;
+        let mut mapper = (header[6] >> 4) as u16;
+        // Old tools wrote text such as "DiskDude!" over bytes 7-15, so the
+        // high nibble of the mapper is only trusted if they're clear.
+        if nes2 || header[12..].iter().all(|&byte| byte == 0) {
+            mapper |= (header[7] & 0xF0) as u16;
+        }
+        let (prg_size, chr_size) = if nes2 {
+            mapper |= ((header[8] & 0x0F) as u16) << 8;
+            (
+                nes2_size(header[4], header[9] & 0x0F, PRG_BANK),
+                nes2_size(header[5], header[9] >> 4, CHR_BANK),
+            )
+        } else {
+            (header[4] as usize * PRG_BANK, header[5] as usize * CHR_BANK)
+        };
+        let mirroring = if header[6] & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if header[6] & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let trainer_size = if header[6] & 0x04 != 0 {
+            TRAINER_SIZE
+        } else {
+            0
+        };
+        let needed = HEADER_SIZE
+            .saturating_add(trainer_size)
+            .saturating_add(prg_size)
+            .saturating_add(chr_size);
+        if bytes.len() < needed {
+            return Err(Error::Truncated(needed - bytes.len()));
+        }
+        let (trainer, rest) = bytes[HEADER_SIZE..].split_at(trainer_size);
+        let (prg, rest) = rest.split_at(prg_size);
+        Ok(Self {
+            mapper,
+            mirroring,
+            battery: header[6] & 0x02 != 0,
+            trainer: (trainer_size != 0).then(|| trainer.into()),
+            prg: prg.into(),
+            chr: rest[..chr_size].into(),
+            nes2,
+        })
+    }
+    /// What NROM puts at $8000-$FFFF: the PRG ROM, twice over if it's
+    /// 16KiB.
+    pub fn prg_window(&self) -> Result<Vec<u8>, Error> {
+        if self.mapper != 0 {
+            return Err(Error::UnsupportedMapper(self.mapper));
+        }
+        match self.prg.len() {
+            PRG_BANK => Ok(self.prg.repeat(2)),
+            0x8000 => Ok(self.prg.clone()),
+            size => Err(Error::BadPrgSize(size)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    // A header with the given bytes 4-15, followed by `len` bytes counting
+    // up from 0 so each part of the image can be told apart.
+    fn image(header: [u8; 12], len: usize) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(header);
+        bytes.extend((0..len).map(|i| i as u8));
+        bytes
+    }
+
+    #[test]
+    fn splits_out_prg_and_chr() {
+        let rom = Rom::parse(&image([1, 1, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0], 0x6000)).unwrap();
+        assert_eq!(rom.mapper, 0);
+        assert_eq!(rom.mirroring, Mirroring::Vertical);
+        assert!(!rom.nes2);
+        assert_eq!(rom.trainer, None);
+        assert_eq!(rom.prg.len(), PRG_BANK);
+        assert_eq!(rom.chr.len(), CHR_BANK);
+        assert_eq!(rom.prg_window().unwrap(), rom.prg.repeat(2));
+    }
+
+    #[test]
+    fn skips_the_trainer() {
+        let bytes = image(
+            [1, 0, 0x06, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            TRAINER_SIZE + PRG_BANK,
+        );
+        let rom = Rom::parse(&bytes).unwrap();
+        assert!(rom.battery);
+        assert_eq!(
+            rom.trainer.as_deref(),
+            Some(&bytes[HEADER_SIZE..][..TRAINER_SIZE])
+        );
+        assert_eq!(rom.prg, &bytes[HEADER_SIZE + TRAINER_SIZE..]);
+        assert!(rom.chr.is_empty());
+    }
+
+    #[test]
+    fn detects_nes2() {
+        // Mapper $123, with 2 PRG banks and a CHR size of 2^13 * 3.
+        let bytes = image([2, 0x35, 0x30, 0x28, 0x01, 0xF0, 0, 0, 0, 0, 0, 0], 0xE000);
+        let rom = Rom::parse(&bytes).unwrap();
+        assert!(rom.nes2);
+        assert_eq!(rom.mapper, 0x123);
+        assert_eq!(rom.prg.len(), 2 * PRG_BANK);
+        assert_eq!(rom.chr.len(), 3 << 13);
+        assert_eq!(rom.prg_window(), Err(Error::UnsupportedMapper(0x123)));
+        // Without the NES 2.0 bits, byte 8 is ignored.
+        let rom = Rom::parse(&image(
+            [2, 1, 0x30, 0x20, 0x01, 0, 0, 0, 0, 0, 0, 0],
+            0xA000,
+        ))
+        .unwrap();
+        assert!(!rom.nes2);
+        assert_eq!(rom.mapper, 0x23);
+    }
+
+    #[test]
+    fn splits_the_mapper_across_bytes_6_and_7() {
+        let mut bytes = image([1, 0, 0x40, 0x10, 0, 0, 0, 0, 0, 0, 0, 0], PRG_BANK);
+        assert_eq!(Rom::parse(&bytes).unwrap().mapper, 0x14);
+        // The high nibble is dropped when bytes 12-15 hold junk.
+        bytes[12..HEADER_SIZE].copy_from_slice(b"Dude");
+        assert_eq!(Rom::parse(&bytes).unwrap().mapper, 0x04);
+    }
+
+    #[test]
+    fn rejects_truncated_images() {
+        let header = [2, 1, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let full = TRAINER_SIZE + 2 * PRG_BANK + CHR_BANK;
+        assert!(Rom::parse(&image(header, full)).is_ok());
+        assert_eq!(
+            Rom::parse(&image(header, full - 1)),
+            Err(Error::Truncated(1))
+        );
+        assert_eq!(
+            Rom::parse(&image(header, TRAINER_SIZE + PRG_BANK)),
+            Err(Error::Truncated(PRG_BANK + CHR_BANK))
+        );
+        assert_eq!(Rom::parse(&image(header, 0)), Err(Error::Truncated(full)));
+        assert_eq!(Rom::parse(&MAGIC[..]), Err(Error::NotInes));
+        assert_eq!(Rom::parse(&[0; HEADER_SIZE]), Err(Error::NotInes));
+    }
+}
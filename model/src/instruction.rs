@@ -23,6 +23,61 @@ fn adc_common(cpu: &mut Cpu, value: u8) {
     cpu.status.set_zero_from_value(cpu.acc);
     cpu.status.set_negative_from_value(cpu.acc);
 }
+
+fn decimal_mode(cpu: &Cpu) -> bool {
+    cpu.status.decimal() && cpu.variant.has_decimal_mode()
+}
+
+pub(crate) fn adc_with_mode(cpu: &mut Cpu, value: u8) {
+    if decimal_mode(cpu) {
+        adc_decimal(cpu, value);
+    } else {
+        adc_common(cpu, value);
+    }
+}
+
+pub(crate) fn sbc_with_mode(cpu: &mut Cpu, value: u8) {
+    if decimal_mode(cpu) {
+        sbc_decimal(cpu, value);
+    } else {
+        adc_common(cpu, !value);
+    }
+}
+
+// As the NMOS 6502 does it: Z comes from the binary sum, and N and V from the
+// sum before the high digit is adjusted.
+fn adc_decimal(cpu: &mut Cpu, value: u8) {
+    let (acc, carry) = (cpu.acc, cpu.status.carry_value());
+    adc_common(cpu, value);
+    let mut lo = (acc & 0x0F) + (value & 0x0F) + carry;
+    if lo >= 0x0A {
+        lo = ((lo + 0x06) & 0x0F) + 0x10;
+    }
+    let mut sum = (acc & 0xF0) as u16 + (value & 0xF0) as u16 + lo as u16;
+    let signed = (acc & 0xF0) as i8 as i16 + (value & 0xF0) as i8 as i16 + lo as i16;
+    cpu.status.set_negative_from_value(sum as u8);
+    cpu.status.set_overflow_to(!(-128..=127).contains(&signed));
+    if sum >= 0xA0 {
+        sum += 0x60;
+    }
+    cpu.status.set_carry_to(sum >= 0x100);
+    cpu.acc = sum as u8;
+}
+
+// As the NMOS 6502 does it: the flags all come from the binary difference.
+fn sbc_decimal(cpu: &mut Cpu, value: u8) {
+    let (acc, carry) = (cpu.acc, cpu.status.carry_value());
+    adc_common(cpu, !value);
+    let mut lo = (acc & 0x0F) as i16 - (value & 0x0F) as i16 + carry as i16 - 1;
+    if lo < 0 {
+        lo = ((lo - 0x06) & 0x0F) - 0x10;
+    }
+    let mut difference = (acc & 0xF0) as i16 - (value & 0xF0) as i16 + lo;
+    if difference < 0 {
+        difference -= 0x60;
+    }
+    cpu.acc = difference as u8;
+}
 pub mod adc {
     use super::*;
     use opcode::adc::*;
@@ -150,10 +205,7 @@ pub mod adc {
     }
     pub fn interpret<A: AddressingMode, M: Memory>(_: A, cpu: &mut Cpu, memory: &mut M) -> u8 {
         let DataWithCycles { data, cycles } = A::read_data_with_cycles(cpu, memory);
-        if cpu.status.is_decimal() {
-            log::warn!("decimal addition attempted");
-        }
-        adc_common(cpu, data);
+        adc_with_mode(cpu, data);
         cpu.pc = cpu.pc.wrapping_add(A::instruction_bytes());
         cycles
     }
@@ -161,21 +213,17 @@ pub mod adc {
 pub mod ahx {
     use super::*;
     use opcode::ahx::*;
-    pub trait AddressingMode: addressing_mode::Trait {
-        fn address_and_num_cycles<M: Memory>(cpu: &Cpu, memory: &mut M) -> (Address, u8);
+    pub trait AddressingMode: WriteData {
+        fn num_cycles() -> u8;
     }
     impl AddressingMode for IndirectYIndexed {
-        fn address_and_num_cycles<M: Memory>(cpu: &Cpu, memory: &mut M) -> (Address, u8) {
-            let (address, cross_page_boundary) =
-                Self::address_check_cross_page_boundary(cpu, memory);
-            (address, 5u8.wrapping_add(cross_page_boundary as u8))
+        fn num_cycles() -> u8 {
+            6
         }
     }
     impl AddressingMode for AbsoluteYIndexed {
-        fn address_and_num_cycles<M: Memory>(cpu: &Cpu, memory: &mut M) -> (Address, u8) {
-            let (address, cross_page_boundary) =
-                Self::address_check_cross_page_boundary(cpu, memory);
-            (address, 4u8.wrapping_add(cross_page_boundary as u8))
+        fn num_cycles() -> u8 {
+            5
         }
     }
     pub struct Inst<A: AddressingMode>(pub A);
@@ -192,12 +240,12 @@ pub mod ahx {
         }
     }
     pub fn interpret<A: AddressingMode, M: Memory>(_: A, cpu: &mut Cpu, memory: &mut M) -> u8 {
-        let (target_address, num_cycles) = A::address_and_num_cycles(cpu, memory);
+        let target_address = A::write_address(cpu, memory);
         let value = cpu.x & cpu.acc & address::hi(target_address).wrapping_add(1);
         let target_address = address::from_u8_lo_hi(address::lo(target_address), value);
         memory.write_u8(target_address, value);
         cpu.pc = cpu.pc.wrapping_add(A::instruction_bytes());
-        num_cycles
+        A::num_cycles()
     }
 }
 pub mod alr {
@@ -267,7 +315,7 @@ pub mod anc {
         cpu.acc &= data;
         cpu.status.set_zero_from_value(cpu.acc);
         cpu.status.set_negative_from_value(cpu.acc);
-        cpu.status.set_carry_to(cpu.status.is_negative());
+        cpu.status.set_carry_to(cpu.status.negative());
         cpu.pc = cpu.pc.wrapping_add(Immediate::instruction_bytes());
         2
     }
@@ -316,9 +364,11 @@ pub mod and {
     }
     impl AddressingMode for IndirectYIndexed {
         fn read_data_with_cycles<M: Memory>(cpu: &Cpu, memory: &mut M) -> DataWithCycles {
+            let (data, page_boundary_cross) =
+                Self::read_data_check_cross_page_boundary(cpu, memory);
             DataWithCycles {
-                data: Self::read_data(cpu, memory),
-                cycles: 5,
+                data,
+                cycles: 5u8.wrapping_add(page_boundary_cross as u8),
             }
         }
     }
@@ -476,17 +526,18 @@ pub mod asl {
         cpu: &mut Cpu,
         memory: &mut M,
     ) -> u8 {
-        let data = A::read_data(cpu, memory);
+        let (address, data) = A::read_data_for_modify(cpu, memory);
         let carry = data & (1 << 7) != 0;
         let data = data.wrapping_shl(1);
-        A::write_data(cpu, memory, data);
+        memory.write_u8(address, data);
         cpu.status.set_carry_to(carry);
         cpu.status.set_zero_from_value(data);
         cpu.status.set_negative_from_value(data);
         cpu.pc = cpu.pc.wrapping_add(A::instruction_bytes());
         A::num_cycles()
     }
-    pub fn interpret_acc(cpu: &mut Cpu) -> u8 {
+    pub fn interpret_acc<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Accumulator::read_dummy(cpu, memory);
         let carry = cpu.acc & (1 << 7) != 0;
         cpu.acc = cpu.acc.wrapping_shl(1);
         cpu.status.set_carry_to(carry);
@@ -519,10 +570,24 @@ pub mod axs {
         2
     }
 }
-fn branch_next_pc_with_cycles(pc: Address, offset: i8) -> (Address, u8) {
+// Taking a branch, the 6502 reads the opcode after it while adding the
+// offset, then if that carries into the high byte, reads from the address
+// with the high byte not yet fixed.
+pub(crate) fn branch_next_pc_with_cycles<M: Memory>(
+    memory: &mut M,
+    pc: Address,
+    offset: i8,
+) -> (Address, u8) {
+    memory.read_u8_dummy(pc);
     let next_pc = ((pc as i16).wrapping_add(offset as i16)) as Address;
-    let cycles = 3 + address::on_different_pages(pc, next_pc) as u8;
-    (next_pc, cycles)
+    let cross_page_boundary = address::on_different_pages(pc, next_pc);
+    if cross_page_boundary {
+        memory.read_u8_dummy(address::from_u8_lo_hi(
+            address::lo(next_pc),
+            address::hi(pc),
+        ));
+    }
+    (next_pc, 3 + cross_page_boundary as u8)
 }
 pub mod bcc {
     use super::*;
@@ -536,9 +601,9 @@ pub mod bcc {
     }
     pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
         cpu.pc = cpu.pc.wrapping_add(Relative::instruction_bytes());
-        if !cpu.status.is_carry() {
-            let offset = Relative::read_offset(cpu, memory);
-            let (pc, cycles) = branch_next_pc_with_cycles(cpu.pc, offset);
+        let offset = Relative::read_offset(cpu, memory);
+        if !cpu.status.carry() {
+            let (pc, cycles) = branch_next_pc_with_cycles(memory, cpu.pc, offset);
             cpu.pc = pc;
             cycles
         } else {
@@ -558,9 +623,9 @@ pub mod bcs {
     }
     pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
         cpu.pc = cpu.pc.wrapping_add(Relative::instruction_bytes());
-        if cpu.status.is_carry() {
-            let offset = Relative::read_offset(cpu, memory);
-            let (pc, cycles) = branch_next_pc_with_cycles(cpu.pc, offset);
+        let offset = Relative::read_offset(cpu, memory);
+        if cpu.status.carry() {
+            let (pc, cycles) = branch_next_pc_with_cycles(memory, cpu.pc, offset);
             cpu.pc = pc;
             cycles
         } else {
@@ -580,9 +645,9 @@ pub mod beq {
     }
     pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
         cpu.pc = cpu.pc.wrapping_add(Relative::instruction_bytes());
-        if cpu.status.is_zero() {
-            let offset = Relative::read_offset(cpu, memory);
-            let (pc, cycles) = branch_next_pc_with_cycles(cpu.pc, offset);
+        let offset = Relative::read_offset(cpu, memory);
+        if cpu.status.zero() {
+            let (pc, cycles) = branch_next_pc_with_cycles(memory, cpu.pc, offset);
             cpu.pc = pc;
             cycles
         } else {
@@ -602,9 +667,9 @@ pub mod bmi {
     }
     pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
         cpu.pc = cpu.pc.wrapping_add(Relative::instruction_bytes());
-        if cpu.status.is_negative() {
-            let offset = Relative::read_offset(cpu, memory);
-            let (pc, cycles) = branch_next_pc_with_cycles(cpu.pc, offset);
+        let offset = Relative::read_offset(cpu, memory);
+        if cpu.status.negative() {
+            let (pc, cycles) = branch_next_pc_with_cycles(memory, cpu.pc, offset);
             cpu.pc = pc;
             cycles
         } else {
@@ -624,9 +689,9 @@ pub mod bne {
     }
     pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
         cpu.pc = cpu.pc.wrapping_add(Relative::instruction_bytes());
-        if !cpu.status.is_zero() {
-            let offset = Relative::read_offset(cpu, memory);
-            let (pc, cycles) = branch_next_pc_with_cycles(cpu.pc, offset);
+        let offset = Relative::read_offset(cpu, memory);
+        if !cpu.status.zero() {
+            let (pc, cycles) = branch_next_pc_with_cycles(memory, cpu.pc, offset);
             cpu.pc = pc;
             cycles
         } else {
@@ -646,9 +711,9 @@ pub mod bpl {
     }
     pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
         cpu.pc = cpu.pc.wrapping_add(Relative::instruction_bytes());
-        if !cpu.status.is_negative() {
-            let offset = Relative::read_offset(cpu, memory);
-            let (pc, cycles) = branch_next_pc_with_cycles(cpu.pc, offset);
+        let offset = Relative::read_offset(cpu, memory);
+        if !cpu.status.negative() {
+            let (pc, cycles) = branch_next_pc_with_cycles(memory, cpu.pc, offset);
             cpu.pc = pc;
             cycles
         } else {
@@ -667,6 +732,7 @@ pub mod brk {
         }
     }
     pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         let pc_to_save = cpu.pc.wrapping_add(2);
         cpu.push_stack_u8(memory, address::hi(pc_to_save));
         cpu.push_stack_u8(memory, address::lo(pc_to_save));
@@ -688,9 +754,9 @@ pub mod bvc {
     }
     pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
         cpu.pc = cpu.pc.wrapping_add(Relative::instruction_bytes());
-        if !cpu.status.is_overflow() {
-            let offset = Relative::read_offset(cpu, memory);
-            let (pc, cycles) = branch_next_pc_with_cycles(cpu.pc, offset);
+        let offset = Relative::read_offset(cpu, memory);
+        if !cpu.status.overflow() {
+            let (pc, cycles) = branch_next_pc_with_cycles(memory, cpu.pc, offset);
             cpu.pc = pc;
             cycles
         } else {
@@ -710,9 +776,9 @@ pub mod bvs {
     }
     pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
         cpu.pc = cpu.pc.wrapping_add(Relative::instruction_bytes());
-        if cpu.status.is_overflow() {
-            let offset = Relative::read_offset(cpu, memory);
-            let (pc, cycles) = branch_next_pc_with_cycles(cpu.pc, offset);
+        let offset = Relative::read_offset(cpu, memory);
+        if cpu.status.overflow() {
+            let (pc, cycles) = branch_next_pc_with_cycles(memory, cpu.pc, offset);
             cpu.pc = pc;
             cycles
         } else {
@@ -769,7 +835,8 @@ pub mod clc {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.status.clear_carry();
         cpu.pc = cpu.pc.wrapping_add(Implied::instruction_bytes());
         2
@@ -785,7 +852,8 @@ pub mod cld {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.status.clear_decimal();
         cpu.pc = cpu.pc.wrapping_add(Implied::instruction_bytes());
         2
@@ -801,7 +869,8 @@ pub mod cli {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.status.clear_interrupt_disable();
         cpu.pc = cpu.pc.wrapping_add(Implied::instruction_bytes());
         2
@@ -817,7 +886,8 @@ pub mod clv {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.status.clear_overflow();
         cpu.pc = cpu.pc.wrapping_add(Implied::instruction_bytes());
         2
@@ -1137,8 +1207,9 @@ pub mod dcp {
         }
     }
     pub fn interpret<A: AddressingMode, M: Memory>(_: A, cpu: &mut Cpu, memory: &mut M) -> u8 {
-        let data = A::read_data(cpu, memory).wrapping_sub(1);
-        A::write_data(cpu, memory, data);
+        let (address, data) = A::read_data_for_modify(cpu, memory);
+        let data = data.wrapping_sub(1);
+        memory.write_u8(address, data);
         let (diff, borrow) = cpu.acc.overflowing_sub(data);
         cpu.status.set_zero_from_value(diff);
         cpu.status.set_negative_from_value(diff);
@@ -1199,8 +1270,9 @@ pub mod dec {
         }
     }
     pub fn interpret<A: AddressingMode, M: Memory>(_: A, cpu: &mut Cpu, memory: &mut M) -> u8 {
-        let data = A::read_data(cpu, memory).wrapping_sub(1);
-        A::write_data(cpu, memory, data);
+        let (address, data) = A::read_data_for_modify(cpu, memory);
+        let data = data.wrapping_sub(1);
+        memory.write_u8(address, data);
         cpu.status.set_negative_from_value(data);
         cpu.status.set_zero_from_value(data);
         cpu.pc = cpu.pc.wrapping_add(A::instruction_bytes());
@@ -1217,7 +1289,8 @@ pub mod dex {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.x = cpu.x.wrapping_sub(1);
         cpu.status.set_negative_from_value(cpu.x);
         cpu.status.set_zero_from_value(cpu.x);
@@ -1235,7 +1308,8 @@ pub mod dey {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.y = cpu.y.wrapping_sub(1);
         cpu.status.set_negative_from_value(cpu.y);
         cpu.status.set_zero_from_value(cpu.y);
@@ -1500,8 +1574,9 @@ pub mod inc {
         }
     }
     pub fn interpret<A: AddressingMode, M: Memory>(_: A, cpu: &mut Cpu, memory: &mut M) -> u8 {
-        let data = A::read_data(cpu, memory).wrapping_add(1);
-        A::write_data(cpu, memory, data);
+        let (address, data) = A::read_data_for_modify(cpu, memory);
+        let data = data.wrapping_add(1);
+        memory.write_u8(address, data);
         cpu.status.set_negative_from_value(data);
         cpu.status.set_zero_from_value(data);
         cpu.pc = cpu.pc.wrapping_add(A::instruction_bytes());
@@ -1518,7 +1593,8 @@ pub mod inx {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.x = cpu.x.wrapping_add(1);
         cpu.status.set_negative_from_value(cpu.x);
         cpu.status.set_zero_from_value(cpu.x);
@@ -1536,7 +1612,8 @@ pub mod iny {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.y = cpu.y.wrapping_add(1);
         cpu.status.set_negative_from_value(cpu.y);
         cpu.status.set_zero_from_value(cpu.y);
@@ -1623,9 +1700,10 @@ pub mod isc {
         }
     }
     pub fn interpret<A: AddressingMode, M: Memory>(_: A, cpu: &mut Cpu, memory: &mut M) -> u8 {
-        let data = A::read_data(cpu, memory).wrapping_add(1);
-        A::write_data(cpu, memory, data);
-        adc_common(cpu, !data);
+        let (address, data) = A::read_data_for_modify(cpu, memory);
+        let data = data.wrapping_add(1);
+        memory.write_u8(address, data);
+        sbc_with_mode(cpu, data);
         cpu.pc = cpu.pc.wrapping_add(A::instruction_bytes());
         A::num_cycles()
     }
@@ -1667,8 +1745,30 @@ pub mod jmp {
 pub mod jsr {
     use super::*;
     use opcode::jsr::*;
-    pub trait AddressingMode: ReadJumpTarget {}
-    impl AddressingMode for Absolute {}
+    pub trait AddressingMode: ReadJumpTarget {
+        /// Reads the jump target while pushing `return_address`, in the
+        /// order the 6502 does: the stack is read after the low byte of the
+        /// target, and the high byte is read last.
+        fn push_and_read_jump_target<M: Memory>(
+            cpu: &mut Cpu,
+            memory: &mut M,
+            return_address: Address,
+        ) -> Address;
+    }
+    impl AddressingMode for Absolute {
+        fn push_and_read_jump_target<M: Memory>(
+            cpu: &mut Cpu,
+            memory: &mut M,
+            return_address: Address,
+        ) -> Address {
+            let lo = memory.read_u8(cpu.pc.wrapping_add(1));
+            cpu.read_stack_dummy(memory);
+            cpu.push_stack_u8(memory, address::hi(return_address));
+            cpu.push_stack_u8(memory, address::lo(return_address));
+            let hi = memory.read_u8(cpu.pc.wrapping_add(2));
+            address::from_u8_lo_hi(lo, hi)
+        }
+    }
     pub struct Inst<A: AddressingMode>(pub A);
     impl AssemblerInstruction for Inst<Absolute> {
         type AddressingMode = Absolute;
@@ -1678,9 +1778,7 @@ pub mod jsr {
     }
     pub fn interpret<A: AddressingMode, M: Memory>(_: A, cpu: &mut Cpu, memory: &mut M) -> u8 {
         let return_address = cpu.pc.wrapping_add(2);
-        cpu.push_stack_u8(memory, address::hi(return_address));
-        cpu.push_stack_u8(memory, address::lo(return_address));
-        cpu.pc = A::read_jump_target(cpu, memory);
+        cpu.pc = A::push_and_read_jump_target(cpu, memory, return_address);
         6
     }
 }
@@ -2186,17 +2284,18 @@ pub mod lsr {
         cpu: &mut Cpu,
         memory: &mut M,
     ) -> u8 {
-        let data = A::read_data(cpu, memory);
+        let (address, data) = A::read_data_for_modify(cpu, memory);
         let carry = data & 1 != 0;
         let data = data.wrapping_shr(1);
-        A::write_data(cpu, memory, data);
+        memory.write_u8(address, data);
         cpu.status.set_carry_to(carry);
         cpu.status.set_zero_from_value(data);
         cpu.status.clear_negative();
         cpu.pc = cpu.pc.wrapping_add(A::instruction_bytes());
         A::num_cycles()
     }
-    pub fn interpret_acc(cpu: &mut Cpu) -> u8 {
+    pub fn interpret_acc<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Accumulator::read_dummy(cpu, memory);
         let carry = cpu.acc & 1 != 0;
         cpu.acc = cpu.acc.wrapping_shr(1);
         cpu.status.set_carry_to(carry);
@@ -2216,7 +2315,8 @@ pub mod nop {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.pc = cpu.pc.wrapping_add(Implied::instruction_bytes());
         2
     }
@@ -2265,9 +2365,11 @@ pub mod ora {
     }
     impl AddressingMode for IndirectYIndexed {
         fn read_data_with_cycles<M: Memory>(cpu: &Cpu, memory: &mut M) -> DataWithCycles {
+            let (data, page_boundary_cross) =
+                Self::read_data_check_cross_page_boundary(cpu, memory);
             DataWithCycles {
-                data: Self::read_data(cpu, memory),
-                cycles: 5,
+                data,
+                cycles: 5u8.wrapping_add(page_boundary_cross as u8),
             }
         }
     }
@@ -2364,6 +2466,7 @@ pub mod pha {
         }
     }
     pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.push_stack_u8(memory, cpu.acc);
         cpu.pc = cpu.pc.wrapping_add(Implied::instruction_bytes());
         3
@@ -2380,6 +2483,7 @@ pub mod php {
         }
     }
     pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.push_stack_u8(memory, cpu.status.masked_with_brk_and_expansion());
         cpu.pc = cpu.pc.wrapping_add(Implied::instruction_bytes());
         3
@@ -2396,6 +2500,8 @@ pub mod pla {
         }
     }
     pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
+        cpu.read_stack_dummy(memory);
         cpu.acc = cpu.pop_stack_u8(memory);
         cpu.status.set_zero_from_value(cpu.acc);
         cpu.status.set_negative_from_value(cpu.acc);
@@ -2414,6 +2520,8 @@ pub mod plp {
         }
     }
     pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
+        cpu.read_stack_dummy(memory);
         let status = cpu.pop_stack_u8(memory);
         cpu.status.set(status);
         cpu.pc = cpu.pc.wrapping_add(Implied::instruction_bytes());
@@ -2499,10 +2607,10 @@ pub mod rla {
         }
     }
     pub fn interpret<A: AddressingMode, M: Memory>(_: A, cpu: &mut Cpu, memory: &mut M) -> u8 {
-        let data = A::read_data(cpu, memory);
+        let (address, data) = A::read_data_for_modify(cpu, memory);
         let carry = data & (1 << 7) != 0;
         let data = data.wrapping_shl(1) | cpu.status.carry_value();
-        A::write_data(cpu, memory, data);
+        memory.write_u8(address, data);
         cpu.status.set_carry_to(carry);
         cpu.acc &= data;
         cpu.status.set_zero_from_value(cpu.acc);
@@ -2583,17 +2691,18 @@ pub mod rol {
         cpu: &mut Cpu,
         memory: &mut M,
     ) -> u8 {
-        let data = A::read_data(cpu, memory);
+        let (address, data) = A::read_data_for_modify(cpu, memory);
         let carry = data & (1 << 7) != 0;
         let data = data.wrapping_shl(1) | cpu.status.carry_value();
-        A::write_data(cpu, memory, data);
+        memory.write_u8(address, data);
         cpu.status.set_carry_to(carry);
         cpu.status.set_zero_from_value(data);
         cpu.status.set_negative_from_value(data);
         cpu.pc = cpu.pc.wrapping_add(A::instruction_bytes());
         A::num_cycles()
     }
-    pub fn interpret_acc(cpu: &mut Cpu) -> u8 {
+    pub fn interpret_acc<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Accumulator::read_dummy(cpu, memory);
         let carry = cpu.acc & (1 << 7) != 0;
         cpu.acc = cpu.acc.wrapping_shl(1) | cpu.status.carry_value();
         cpu.status.set_carry_to(carry);
@@ -2675,17 +2784,18 @@ pub mod ror {
         cpu: &mut Cpu,
         memory: &mut M,
     ) -> u8 {
-        let data = A::read_data(cpu, memory);
+        let (address, data) = A::read_data_for_modify(cpu, memory);
         let carry = data & 1 != 0;
         let data = data.wrapping_shr(1) | cpu.status.carry_value().wrapping_shl(7);
-        A::write_data(cpu, memory, data);
+        memory.write_u8(address, data);
         cpu.status.set_carry_to(carry);
         cpu.status.set_zero_from_value(data);
         cpu.status.set_negative_from_value(data);
         cpu.pc = cpu.pc.wrapping_add(A::instruction_bytes());
         A::num_cycles()
     }
-    pub fn interpret_acc(cpu: &mut Cpu) -> u8 {
+    pub fn interpret_acc<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Accumulator::read_dummy(cpu, memory);
         let carry = cpu.acc & 1 != 0;
         cpu.acc = cpu.acc.wrapping_shr(1) | cpu.status.carry_value().wrapping_shl(7);
         cpu.status.set_carry_to(carry);
@@ -2774,12 +2884,12 @@ pub mod rra {
         }
     }
     pub fn interpret<A: AddressingMode, M: Memory>(_: A, cpu: &mut Cpu, memory: &mut M) -> u8 {
-        let data = A::read_data(cpu, memory);
+        let (address, data) = A::read_data_for_modify(cpu, memory);
         let carry = data & 1 != 0;
         let data = data.wrapping_shr(1) | cpu.status.carry_value().wrapping_shl(7);
-        A::write_data(cpu, memory, data);
+        memory.write_u8(address, data);
         cpu.status.set_carry_to(carry);
-        adc_common(cpu, data);
+        adc_with_mode(cpu, data);
         cpu.pc = cpu.pc.wrapping_add(A::instruction_bytes());
         A::num_cycles()
     }
@@ -2795,6 +2905,8 @@ pub mod rti {
         }
     }
     pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
+        cpu.read_stack_dummy(memory);
         let status = cpu.pop_stack_u8(memory);
         let return_address_lo = cpu.pop_stack_u8(memory);
         let return_address_hi = cpu.pop_stack_u8(memory);
@@ -2814,9 +2926,13 @@ pub mod rts {
         }
     }
     pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
+        cpu.read_stack_dummy(memory);
         let return_address_lo = cpu.pop_stack_u8(memory);
         let return_address_hi = cpu.pop_stack_u8(memory);
-        cpu.pc = address::from_u8_lo_hi(return_address_lo, return_address_hi).wrapping_add(1);
+        let return_address = address::from_u8_lo_hi(return_address_lo, return_address_hi);
+        memory.read_u8_dummy(return_address);
+        cpu.pc = return_address.wrapping_add(1);
         6
     }
 }
@@ -3004,10 +3120,7 @@ pub mod sbc {
     }
     pub fn interpret<A: AddressingMode, M: Memory>(_: A, cpu: &mut Cpu, memory: &mut M) -> u8 {
         let DataWithCycles { data, cycles } = A::read_data_with_cycles(cpu, memory);
-        if cpu.status.is_decimal() {
-            log::warn!("decimal subtraction attempted");
-        }
-        adc_common(cpu, !data);
+        sbc_with_mode(cpu, data);
         cpu.pc = cpu.pc.wrapping_add(A::instruction_bytes());
         cycles
     }
@@ -3022,7 +3135,8 @@ pub mod sec {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.status.set_carry();
         cpu.pc = cpu.pc.wrapping_add(Implied::instruction_bytes());
         2
@@ -3038,7 +3152,8 @@ pub mod sed {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.status.set_decimal();
         cpu.pc = cpu.pc.wrapping_add(Implied::instruction_bytes());
         2
@@ -3054,7 +3169,8 @@ pub mod sei {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.status.set_interrupt_disable();
         cpu.pc = cpu.pc.wrapping_add(Implied::instruction_bytes());
         2
@@ -3155,10 +3271,10 @@ pub mod slo {
         }
     }
     pub fn interpret<A: AddressingMode, M: Memory>(_: A, cpu: &mut Cpu, memory: &mut M) -> u8 {
-        let data = A::read_data(cpu, memory);
+        let (address, data) = A::read_data_for_modify(cpu, memory);
         let carry = data & (1 << 7) != 0;
         let data = data.wrapping_shl(1);
-        A::write_data(cpu, memory, data);
+        memory.write_u8(address, data);
         cpu.status.set_carry_to(carry);
         cpu.acc |= data;
         cpu.status.set_zero_from_value(cpu.acc);
@@ -3246,10 +3362,10 @@ pub mod sre {
         }
     }
     pub fn interpret<A: AddressingMode, M: Memory>(_: A, cpu: &mut Cpu, memory: &mut M) -> u8 {
-        let data = A::read_data(cpu, memory);
+        let (address, data) = A::read_data_for_modify(cpu, memory);
         let carry = data & 1 != 0;
         let data = data.wrapping_shr(1);
-        A::write_data(cpu, memory, data);
+        memory.write_u8(address, data);
         cpu.status.set_carry_to(carry);
         cpu.acc ^= data;
         cpu.status.set_zero_from_value(cpu.acc);
@@ -3450,7 +3566,8 @@ pub mod tax {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.x = cpu.acc;
         cpu.status.set_zero_from_value(cpu.x);
         cpu.status.set_negative_from_value(cpu.x);
@@ -3469,7 +3586,7 @@ pub mod sxa {
         }
     }
     pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
-        let target_address = AbsoluteYIndexed::address(cpu, memory);
+        let target_address = AbsoluteYIndexed::write_address(cpu, memory);
         let value = cpu.x & address::hi(target_address).wrapping_add(1);
         let target_address = address::from_u8_lo_hi(address::lo(target_address), value);
         memory.write_u8(target_address, value);
@@ -3488,7 +3605,7 @@ pub mod sya {
         }
     }
     pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
-        let target_address = AbsoluteXIndexed::address(cpu, memory);
+        let target_address = AbsoluteXIndexed::write_address(cpu, memory);
         let value = cpu.y & address::hi(target_address).wrapping_add(1);
         let target_address = address::from_u8_lo_hi(address::lo(target_address), value);
         memory.write_u8(target_address, value);
@@ -3506,7 +3623,8 @@ pub mod tay {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.y = cpu.acc;
         cpu.status.set_zero_from_value(cpu.y);
         cpu.status.set_negative_from_value(cpu.y);
@@ -3524,7 +3642,8 @@ pub mod tsx {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.x = cpu.sp;
         cpu.status.set_zero_from_value(cpu.x);
         cpu.status.set_negative_from_value(cpu.x);
@@ -3542,7 +3661,8 @@ pub mod txa {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.acc = cpu.x;
         cpu.status.set_zero_from_value(cpu.acc);
         cpu.status.set_negative_from_value(cpu.acc);
@@ -3560,7 +3680,8 @@ pub mod txs {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.sp = cpu.x;
         cpu.pc = cpu.pc.wrapping_add(Implied::instruction_bytes());
         2
@@ -3576,7 +3697,8 @@ pub mod tya {
             IMPLIED
         }
     }
-    pub fn interpret(cpu: &mut Cpu) -> u8 {
+    pub fn interpret<M: Memory>(cpu: &mut Cpu, memory: &mut M) -> u8 {
+        Implied::read_dummy(cpu, memory);
         cpu.acc = cpu.y;
         cpu.status.set_zero_from_value(cpu.acc);
         cpu.status.set_negative_from_value(cpu.acc);
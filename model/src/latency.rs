@@ -0,0 +1,71 @@
+use alloc::collections::BTreeMap;
+use core::fmt;
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+/// Distribution of the cycles between an interrupt being asserted and the
+/// first instruction of its handler starting.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default)]
+pub struct LatencyStats {
+    count: usize,
+    total: u64,
+    min: Option<u64>,
+    max: Option<u64>,
+    histogram: BTreeMap<u64, usize>,
+}
+
+impl LatencyStats {
+    pub fn record(&mut self, cycles: u64) {
+        self.count += 1;
+        self.total += cycles;
+        self.min = Some(self.min.map_or(cycles, |min| min.min(cycles)));
+        self.max = Some(self.max.map_or(cycles, |max| max.max(cycles)));
+        *self.histogram.entry(cycles).or_default() += 1;
+    }
+    pub fn count(&self) -> usize {
+        self.count
+    }
+    pub fn min(&self) -> Option<u64> {
+        self.min
+    }
+    pub fn max(&self) -> Option<u64> {
+        self.max
+    }
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.total as f64 / self.count as f64)
+    }
+    /// Number of interrupts taken with each latency, in cycles.
+    pub fn histogram(&self) -> impl Iterator<Item = (u64, usize)> + '_ {
+        self.histogram
+            .iter()
+            .map(|(&cycles, &count)| (cycles, count))
+    }
+}
+
+impl fmt::Display for LatencyStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (Some(min), Some(max)) = (self.min, self.max) else {
+            return writeln!(f, "no interrupts taken");
+        };
+        writeln!(f, "{} taken, min {} max {} cycles", self.count, min, max)?;
+        for (cycles, count) in self.histogram() {
+            writeln!(f, "  {:>5}: {}", cycles, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default)]
+pub struct InterruptLatency {
+    pub nmi: LatencyStats,
+    pub irq: LatencyStats,
+}
+
+impl fmt::Display for InterruptLatency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NMI: {}", self.nmi)?;
+        write!(f, "IRQ: {}", self.irq)
+    }
+}
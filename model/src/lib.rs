@@ -1,13 +1,81 @@
 #![no_std]
+#[cfg(feature = "alloc")]
 extern crate alloc;
+#[cfg(feature = "alloc")]
+pub mod acia;
 pub mod addressing_mode;
+#[cfg(feature = "alloc")]
+pub mod annotation;
 pub mod assembler_instruction;
+#[cfg(feature = "alloc")]
+pub mod banking;
+#[cfg(feature = "alloc")]
+pub mod builder;
+#[cfg(feature = "alloc")]
+pub mod bus_log;
+#[cfg(feature = "alloc")]
+pub mod console;
+#[cfg(feature = "alloc")]
+pub mod control_flow;
+#[cfg(feature = "alloc")]
+pub mod counters;
+#[cfg(feature = "alloc")]
+pub mod coverage;
+#[cfg(feature = "alloc")]
 pub mod debug;
+#[cfg(feature = "alloc")]
+pub mod decode_cache;
+pub mod dispatch;
+#[cfg(feature = "alloc")]
+pub mod functional_test;
+#[cfg(feature = "generate")]
+pub mod generate;
+#[cfg(feature = "alloc")]
+pub mod hex;
+#[cfg(feature = "alloc")]
+pub mod history;
+pub mod huc6280;
+#[cfg(feature = "alloc")]
+pub mod ines;
 pub mod instruction;
+#[cfg(feature = "alloc")]
+pub mod latency;
 pub mod machine;
+#[cfg(feature = "alloc")]
+pub mod memory_map;
+#[cfg(feature = "alloc")]
+pub mod monitor;
 pub mod opcode;
 pub mod operand;
+#[cfg(feature = "alloc")]
+pub mod peripheral;
+pub mod pins;
+pub mod power_on;
+#[cfg(all(feature = "alloc", feature = "serialize"))]
+pub mod processor_tests;
+#[cfg(feature = "alloc")]
+pub mod profile;
+#[cfg(feature = "alloc")]
+pub mod recompile;
+#[cfg(feature = "alloc")]
+pub mod replay;
+#[cfg(feature = "alloc")]
+pub mod rewind;
+#[cfg(feature = "alloc")]
+pub mod riot;
+#[cfg(feature = "alloc")]
+pub mod shared_bus;
+#[cfg(feature = "alloc")]
+pub mod smc;
+pub mod specialised;
+#[cfg(feature = "alloc")]
+pub mod stack;
 pub mod status;
+#[cfg(feature = "alloc")]
+pub mod stepper;
+#[cfg(feature = "threaded")]
+pub mod threaded;
+pub mod w65c816;
 
 pub use addressing_mode::Trait as AddressingMode;
 pub use assembler_instruction::Trait as AssemblerInstruction;
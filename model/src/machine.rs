@@ -1,10 +1,117 @@
 use crate::addressing_mode::*;
+#[cfg(feature = "alloc")]
+use crate::bus_log::{self, BusLog};
+#[cfg(feature = "alloc")]
+use crate::counters::Counters;
+#[cfg(feature = "alloc")]
+use crate::coverage::Coverage;
+#[cfg(feature = "alloc")]
+use crate::debug::{Instruction, LogState};
+use crate::dispatch::Table;
+#[cfg(feature = "alloc")]
+use crate::hex;
+#[cfg(feature = "alloc")]
+use crate::history::{self, History};
+#[cfg(feature = "alloc")]
+use crate::ines;
 use crate::instruction::*;
+#[cfg(feature = "alloc")]
+use crate::latency::InterruptLatency;
+#[cfg(feature = "alloc")]
+pub use crate::memory_map::MemoryMap;
+#[cfg(feature = "alloc")]
+use crate::peripheral::{Bus, InvalidState, Mapped, Peripheral};
+#[cfg(feature = "alloc")]
+use crate::power_on::Pattern;
+#[cfg(feature = "alloc")]
+use crate::profile::Profile;
+#[cfg(feature = "alloc")]
+use crate::replay::{self, Inputs, Recording};
+#[cfg(feature = "alloc")]
+use crate::smc::{CodeWatch, CodeWrite};
+#[cfg(feature = "alloc")]
+use crate::stepper;
 pub use crate::{address, status, Address};
+use crate::{huc6280, w65c816};
 use crate::{opcode, UnknownOpcode};
+#[cfg(feature = "alloc")]
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+#[cfg(feature = "alloc")]
+use core::ops::RangeInclusive;
 #[cfg(feature = "serialize")]
 use serde::{Deserialize, Serialize};
 
+/// Which chip a `Cpu` behaves as.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Variant {
+    /// The NMOS 6502. Its decimal mode is emulated, so `ADC` and `SBC`
+    /// work in BCD while D is set, leaving N, V and Z as the NMOS chip
+    /// does; before, they were always binary.
+    #[default]
+    Nmos6502,
+    /// The NES's 2A03, or the PAL 2A07: an NMOS 6502 whose decimal mode is
+    /// disconnected, so `SED` still sets D but `ADC` and `SBC` stay binary.
+    Ricoh2A03,
+    /// The PC Engine's HuC6280, partially: see the `huc6280` module.
+    HuC6280,
+    /// The 65C816 in emulation mode, partially: see the `w65c816` module.
+    W65C816,
+}
+
+impl Variant {
+    pub fn has_decimal_mode(self) -> bool {
+        self != Variant::Ricoh2A03
+    }
+}
+
+/// Hardware bugs and oddities which can each be switched off, e.g. to run
+/// code written against an emulator which lacked them.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// `JMP ($xxFF)` reads the high byte of the target from $xx00 rather
+    /// than from the next page.
+    pub jmp_indirect_page_wrap: bool,
+    /// Read-modify-write instructions write the unmodified value back
+    /// before writing the result, which peripherals can see.
+    pub rmw_dummy_write: bool,
+    /// Zero page indexed addressing, and pointers read from $FF for
+    /// indirect addressing, wrap around within the zero page rather than
+    /// carrying into page 1.
+    pub zero_page_wrap: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::nmos()
+    }
+}
+
+impl Quirks {
+    /// Everything as the NMOS 6502 does it.
+    pub fn nmos() -> Self {
+        Self {
+            jmp_indirect_page_wrap: true,
+            rmw_dummy_write: true,
+            zero_page_wrap: true,
+        }
+    }
+    /// The page-wrap bug fixed and no dummy writes. Zero page addressing
+    /// still wraps, since every 6502 family chip does that.
+    pub fn fixed() -> Self {
+        Self {
+            jmp_indirect_page_wrap: false,
+            rmw_dummy_write: false,
+            zero_page_wrap: true,
+        }
+    }
+}
+
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone)]
 pub struct Cpu {
@@ -14,6 +121,39 @@ pub struct Cpu {
     pub x: u8,
     pub y: u8,
     pub status: StatusRegister,
+    pub variant: Variant,
+    pub quirks: Quirks,
+    /// The HuC6280's mapping registers, set by `TAM`.
+    pub mpr: [u8; 8],
+    /// Cycles the last instruction took beyond the 255 `step` can return,
+    /// which only HuC6280 block transfers need. `Machine` runs them as a
+    /// stall, and anything else driving the `Cpu` should take them.
+    pub extra_cycles: u32,
+    pub w65c816: w65c816::Registers,
+}
+
+/// The registers on one line, as `C000  A:00 X:00 Y:00 P:24 SP:FD nv-bdIzc`.
+/// `P` is the status as an interrupt would push it.
+impl core::fmt::Display for Cpu {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04X}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} {}",
+            self.pc,
+            self.acc,
+            self.x,
+            self.y,
+            u8::from(self.status),
+            self.sp,
+            self.status
+        )
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cpu {
@@ -25,6 +165,11 @@ impl Cpu {
             x: 0,
             y: 0,
             status: StatusRegister::new(),
+            variant: Variant::Nmos6502,
+            quirks: Quirks::nmos(),
+            mpr: [0; 8],
+            extra_cycles: 0,
+            w65c816: w65c816::Registers::default(),
         }
     }
     pub fn retrieve_nmi_return_address_during_nmi<MRO: MemoryReadOnly>(
@@ -39,19 +184,50 @@ impl Cpu {
             None
         }
     }
+    /// Takes an NMI. Like an IRQ, it pushes the status with B clear and
+    /// disables interrupts.
     pub fn nmi<M: Memory>(&mut self, memory: &mut M) {
+        memory.read_u8_dummy(self.pc);
+        memory.read_u8_dummy(self.pc);
         self.push_stack_u8(memory, address::hi(self.pc));
         self.push_stack_u8(memory, address::lo(self.pc));
-        self.push_stack_u8(memory, self.status.masked_with_brk_and_expansion());
+        self.push_stack_u8(memory, self.status.masked_with_expansion());
+        self.status.set_interrupt_disable();
         self.pc = memory.read_u16_le(crate::interrupt_vector::NMI_LO);
     }
+    pub fn irq<M: Memory>(&mut self, memory: &mut M) {
+        memory.read_u8_dummy(self.pc);
+        memory.read_u8_dummy(self.pc);
+        self.push_stack_u8(memory, address::hi(self.pc));
+        self.push_stack_u8(memory, address::lo(self.pc));
+        self.push_stack_u8(memory, self.status.masked_with_expansion());
+        self.status.set_interrupt_disable();
+        self.pc = memory.read_u16_le(crate::interrupt_vector::IRQ_LO);
+    }
     pub fn push_stack_u8<M: Memory>(&mut self, memory: &mut M, value: u8) {
-        memory.write_u8_stack(self.sp, value);
+        if self.variant == Variant::HuC6280 {
+            memory.write_u8(huc6280::STACK | self.sp as Address, value);
+        } else {
+            memory.write_u8_stack(self.sp, value);
+        }
         self.sp = self.sp.wrapping_sub(1);
     }
+    /// Reads from the top of the stack, as the 6502 does while it adjusts
+    /// the stack pointer, throwing the value away.
+    pub fn read_stack_dummy<M: Memory>(&self, memory: &mut M) {
+        if self.variant == Variant::HuC6280 {
+            memory.read_u8_dummy(huc6280::STACK | self.sp as Address);
+        } else {
+            memory.read_u8_dummy(address::from_u8_lo_hi(self.sp, STACK_ADDRESS_HI));
+        }
+    }
     pub fn pop_stack_u8<M: Memory>(&mut self, memory: &mut M) -> u8 {
         self.sp = self.sp.wrapping_add(1);
-        memory.read_u8_stack(self.sp)
+        if self.variant == Variant::HuC6280 {
+            memory.read_u8(huc6280::STACK | self.sp as Address)
+        } else {
+            memory.read_u8_stack(self.sp)
+        }
     }
     pub fn start<M: Memory>(&mut self, memory: &mut M) {
         self.pc = memory.read_u16_le(crate::interrupt_vector::START_LO);
@@ -69,6 +245,34 @@ impl Cpu {
     }
     pub fn step<M: Memory>(&mut self, memory: &mut M) -> Result<u8, UnknownOpcode> {
         let opcode = memory.read_u8(self.pc);
+        self.step_with_opcode(opcode, memory)
+    }
+    /// Runs the instruction at the program counter as `step` does, with
+    /// its opcode already read.
+    pub fn step_with_opcode<M: Memory>(
+        &mut self,
+        opcode: u8,
+        memory: &mut M,
+    ) -> Result<u8, UnknownOpcode> {
+        match self.variant {
+            Variant::HuC6280 => self.step_huc6280(opcode, memory),
+            Variant::W65C816 => self.step_w65c816(opcode, memory),
+            _ => Table::<M>::HANDLERS[opcode as usize](self, memory),
+        }
+    }
+    /// Runs `opcode`, already fetched from the program counter, decoding it
+    /// with a match each time rather than through the dispatch table.
+    pub fn execute<M: Memory>(&mut self, opcode: u8, memory: &mut M) -> Result<u8, UnknownOpcode> {
+        self.execute_inline(opcode, memory)
+    }
+    // Inlined into each entry of the dispatch table, where `opcode` is a
+    // constant.
+    #[inline(always)]
+    pub(crate) fn execute_inline<M: Memory>(
+        &mut self,
+        opcode: u8,
+        memory: &mut M,
+    ) -> Result<u8, UnknownOpcode> {
         let cycles = match opcode {
             opcode::adc::ABSOLUTE => adc::interpret(Absolute, self, memory),
             opcode::adc::ABSOLUTE_X_INDEXED => adc::interpret(AbsoluteXIndexed, self, memory),
@@ -98,7 +302,7 @@ impl Cpu {
             opcode::and::ZERO_PAGE_X_INDEXED => and::interpret(ZeroPageXIndexed, self, memory),
             opcode::asl::ABSOLUTE => asl::interpret(Absolute, self, memory),
             opcode::asl::ABSOLUTE_X_INDEXED => asl::interpret(AbsoluteXIndexed, self, memory),
-            opcode::asl::ACCUMULATOR => asl::interpret_acc(self),
+            opcode::asl::ACCUMULATOR => asl::interpret_acc(self, memory),
             opcode::asl::ZERO_PAGE => asl::interpret(ZeroPage, self, memory),
             opcode::asl::ZERO_PAGE_X_INDEXED => asl::interpret(ZeroPageXIndexed, self, memory),
             opcode::axs::unofficial0::IMMEDIATE => axs::interpret(self, memory),
@@ -113,10 +317,10 @@ impl Cpu {
             opcode::bit::ABSOLUTE => bit::interpret(Absolute, self, memory),
             opcode::bit::ZERO_PAGE => bit::interpret(ZeroPage, self, memory),
             opcode::brk::IMPLIED => brk::interpret(self, memory),
-            opcode::clc::IMPLIED => clc::interpret(self),
-            opcode::cld::IMPLIED => cld::interpret(self),
-            opcode::cli::IMPLIED => cli::interpret(self),
-            opcode::clv::IMPLIED => clv::interpret(self),
+            opcode::clc::IMPLIED => clc::interpret(self, memory),
+            opcode::cld::IMPLIED => cld::interpret(self, memory),
+            opcode::cli::IMPLIED => cli::interpret(self, memory),
+            opcode::clv::IMPLIED => clv::interpret(self, memory),
             opcode::cmp::ABSOLUTE => cmp::interpret(Absolute, self, memory),
             opcode::cmp::ABSOLUTE_X_INDEXED => cmp::interpret(AbsoluteXIndexed, self, memory),
             opcode::cmp::ABSOLUTE_Y_INDEXED => cmp::interpret(AbsoluteYIndexed, self, memory),
@@ -152,8 +356,8 @@ impl Cpu {
             opcode::dec::ABSOLUTE_X_INDEXED => dec::interpret(AbsoluteXIndexed, self, memory),
             opcode::dec::ZERO_PAGE => dec::interpret(ZeroPage, self, memory),
             opcode::dec::ZERO_PAGE_X_INDEXED => dec::interpret(ZeroPageXIndexed, self, memory),
-            opcode::dex::IMPLIED => dex::interpret(self),
-            opcode::dey::IMPLIED => dey::interpret(self),
+            opcode::dex::IMPLIED => dex::interpret(self, memory),
+            opcode::dey::IMPLIED => dey::interpret(self, memory),
             opcode::eor::ABSOLUTE => eor::interpret(Absolute, self, memory),
             opcode::eor::ABSOLUTE_X_INDEXED => eor::interpret(AbsoluteXIndexed, self, memory),
             opcode::eor::ABSOLUTE_Y_INDEXED => eor::interpret(AbsoluteYIndexed, self, memory),
@@ -206,8 +410,8 @@ impl Cpu {
             opcode::inc::ABSOLUTE_X_INDEXED => inc::interpret(AbsoluteXIndexed, self, memory),
             opcode::inc::ZERO_PAGE => inc::interpret(ZeroPage, self, memory),
             opcode::inc::ZERO_PAGE_X_INDEXED => inc::interpret(ZeroPageXIndexed, self, memory),
-            opcode::inx::IMPLIED => inx::interpret(self),
-            opcode::iny::IMPLIED => iny::interpret(self),
+            opcode::inx::IMPLIED => inx::interpret(self, memory),
+            opcode::iny::IMPLIED => iny::interpret(self, memory),
             opcode::isc::unofficial0::X_INDEXED_INDIRECT => {
                 isc::interpret(XIndexedIndirect, self, memory)
             }
@@ -263,16 +467,16 @@ impl Cpu {
             opcode::ldy::ZERO_PAGE_X_INDEXED => ldy::interpret(ZeroPageXIndexed, self, memory),
             opcode::lsr::ABSOLUTE => lsr::interpret(Absolute, self, memory),
             opcode::lsr::ABSOLUTE_X_INDEXED => lsr::interpret(AbsoluteXIndexed, self, memory),
-            opcode::lsr::ACCUMULATOR => lsr::interpret_acc(self),
+            opcode::lsr::ACCUMULATOR => lsr::interpret_acc(self, memory),
             opcode::lsr::ZERO_PAGE => lsr::interpret(ZeroPage, self, memory),
             opcode::lsr::ZERO_PAGE_X_INDEXED => lsr::interpret(ZeroPageXIndexed, self, memory),
-            opcode::nop::IMPLIED => nop::interpret(self),
-            opcode::nop::unofficial0::IMPLIED => nop::interpret(self),
-            opcode::nop::unofficial1::IMPLIED => nop::interpret(self),
-            opcode::nop::unofficial2::IMPLIED => nop::interpret(self),
-            opcode::nop::unofficial3::IMPLIED => nop::interpret(self),
-            opcode::nop::unofficial4::IMPLIED => nop::interpret(self),
-            opcode::nop::unofficial5::IMPLIED => nop::interpret(self),
+            opcode::nop::IMPLIED => nop::interpret(self, memory),
+            opcode::nop::unofficial0::IMPLIED => nop::interpret(self, memory),
+            opcode::nop::unofficial1::IMPLIED => nop::interpret(self, memory),
+            opcode::nop::unofficial2::IMPLIED => nop::interpret(self, memory),
+            opcode::nop::unofficial3::IMPLIED => nop::interpret(self, memory),
+            opcode::nop::unofficial4::IMPLIED => nop::interpret(self, memory),
+            opcode::nop::unofficial5::IMPLIED => nop::interpret(self, memory),
             opcode::ora::ABSOLUTE => ora::interpret(Absolute, self, memory),
             opcode::ora::ABSOLUTE_X_INDEXED => ora::interpret(AbsoluteXIndexed, self, memory),
             opcode::ora::ABSOLUTE_Y_INDEXED => ora::interpret(AbsoluteYIndexed, self, memory),
@@ -304,12 +508,12 @@ impl Cpu {
             }
             opcode::rol::ABSOLUTE => rol::interpret(Absolute, self, memory),
             opcode::rol::ABSOLUTE_X_INDEXED => rol::interpret(AbsoluteXIndexed, self, memory),
-            opcode::rol::ACCUMULATOR => rol::interpret_acc(self),
+            opcode::rol::ACCUMULATOR => rol::interpret_acc(self, memory),
             opcode::rol::ZERO_PAGE => rol::interpret(ZeroPage, self, memory),
             opcode::rol::ZERO_PAGE_X_INDEXED => rol::interpret(ZeroPageXIndexed, self, memory),
             opcode::ror::ABSOLUTE => ror::interpret(Absolute, self, memory),
             opcode::ror::ABSOLUTE_X_INDEXED => ror::interpret(AbsoluteXIndexed, self, memory),
-            opcode::ror::ACCUMULATOR => ror::interpret_acc(self),
+            opcode::ror::ACCUMULATOR => ror::interpret_acc(self, memory),
             opcode::ror::ZERO_PAGE => ror::interpret(ZeroPage, self, memory),
             opcode::ror::ZERO_PAGE_X_INDEXED => ror::interpret(ZeroPageXIndexed, self, memory),
             opcode::rra::unofficial0::X_INDEXED_INDIRECT => {
@@ -348,9 +552,9 @@ impl Cpu {
             opcode::sbc::ZERO_PAGE => sbc::interpret(ZeroPage, self, memory),
             opcode::sbc::ZERO_PAGE_X_INDEXED => sbc::interpret(ZeroPageXIndexed, self, memory),
             opcode::sbc::unofficial0::IMMEDIATE => sbc::interpret(Immediate, self, memory),
-            opcode::sec::IMPLIED => sec::interpret(self),
-            opcode::sed::IMPLIED => sed::interpret(self),
-            opcode::sei::IMPLIED => sei::interpret(self),
+            opcode::sec::IMPLIED => sec::interpret(self, memory),
+            opcode::sed::IMPLIED => sed::interpret(self, memory),
+            opcode::sei::IMPLIED => sei::interpret(self, memory),
             opcode::skb::unofficial0::IMMEDIATE => skb::interpret(self, memory),
             opcode::skb::unofficial1::IMMEDIATE => skb::interpret(self, memory),
             opcode::skb::unofficial2::IMMEDIATE => skb::interpret(self, memory),
@@ -405,12 +609,12 @@ impl Cpu {
             opcode::sty::ZERO_PAGE_X_INDEXED => sty::interpret(ZeroPageXIndexed, self, memory),
             opcode::sxa::unofficial0::ABSOLUTE_Y_INDEXED => sxa::interpret(self, memory),
             opcode::sya::unofficial0::ABSOLUTE_X_INDEXED => sya::interpret(self, memory),
-            opcode::tax::IMPLIED => tax::interpret(self),
-            opcode::tay::IMPLIED => tay::interpret(self),
-            opcode::tsx::IMPLIED => tsx::interpret(self),
-            opcode::txa::IMPLIED => txa::interpret(self),
-            opcode::txs::IMPLIED => txs::interpret(self),
-            opcode::tya::IMPLIED => tya::interpret(self),
+            opcode::tax::IMPLIED => tax::interpret(self, memory),
+            opcode::tay::IMPLIED => tay::interpret(self, memory),
+            opcode::tsx::IMPLIED => tsx::interpret(self, memory),
+            opcode::txa::IMPLIED => txa::interpret(self, memory),
+            opcode::txs::IMPLIED => txs::interpret(self, memory),
+            opcode::tya::IMPLIED => tya::interpret(self, memory),
             _ => return Err(UnknownOpcode(opcode)),
         };
         Ok(cycles)
@@ -418,6 +622,54 @@ impl Cpu {
 }
 
 const STACK_ADDRESS_HI: u8 = 0x01;
+
+/// A problem with a memory access which doesn't stop the instruction that
+/// made it, but is reported by `Machine::step` afterwards.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy)]
+pub enum Fault {
+    RomWrite { address: Address, data: u8 },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum StepError {
+    UnknownOpcode(UnknownOpcode),
+    Fault(Fault),
+    /// A trap handler returned `TrapAction::Stop`.
+    TrapStop,
+    /// The CPU is jammed by a KIL or `STP` opcode at this address, or by
+    /// `Machine::halt`, and won't run until reset.
+    Halted(Address),
+}
+
+// An NMI asserted within this many cycles of the start of a `BRK` or IRQ
+// hijacks it.
+#[cfg(feature = "alloc")]
+const HIJACK_CYCLES: u64 = 4;
+
+// Taken by a trap which skips its instruction.
+#[cfg(feature = "alloc")]
+const TRAP_CYCLES: u8 = 1;
+
+// Whether `opcode` halts the CPU. The NMOS KIL opcodes are those ending in
+// 2 other than $82, $A2, $C2 and $E2.
+#[cfg(feature = "alloc")]
+fn jams(variant: Variant, opcode: u8) -> bool {
+    match variant {
+        Variant::Nmos6502 | Variant::Ricoh2A03 => {
+            opcode & 0x0F == 0x02 && !matches!(opcode, 0x82 | 0xA2 | 0xC2 | 0xE2)
+        }
+        Variant::HuC6280 => false,
+        Variant::W65C816 => opcode == w65c816::STP,
+    }
+}
+
+impl From<UnknownOpcode> for StepError {
+    fn from(error: UnknownOpcode) -> Self {
+        StepError::UnknownOpcode(error)
+    }
+}
+
 pub trait Memory {
     fn read_u8(&mut self, address: Address) -> u8;
     fn read_u16_le(&mut self, address: Address) -> u16 {
@@ -436,6 +688,11 @@ pub trait Memory {
     fn read_u8_stack(&mut self, stack_pointer: u8) -> u8 {
         self.read_u8(address::from_u8_lo_hi(stack_pointer, STACK_ADDRESS_HI))
     }
+    /// Reads a byte the CPU throws away, as the 6502 reads on every cycle
+    /// it has nothing else to do with the bus.
+    fn read_u8_dummy(&mut self, address: Address) {
+        self.read_u8(address);
+    }
     fn write_u8(&mut self, address: Address, data: u8);
     fn write_u8_zero_page(&mut self, address: u8, data: u8) {
         self.write_u8(address as Address, data);
@@ -446,6 +703,38 @@ pub trait Memory {
             data,
         );
     }
+    /// Reads `buffer.len()` consecutive bytes, wrapping at the top of the
+    /// address space. Implementations backed by plain arrays should
+    /// override this with a copy.
+    fn read_block(&mut self, address: Address, buffer: &mut [u8]) {
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.read_u8(address.wrapping_add(i as Address));
+        }
+    }
+    /// Writes consecutive bytes, wrapping at the top of the address space.
+    fn write_block(&mut self, address: Address, data: &[u8]) {
+        for (i, &byte) in data.iter().enumerate() {
+            self.write_u8(address.wrapping_add(i as Address), byte);
+        }
+    }
+    /// Writes `data` as a loader would, e.g. into ROM, which `write_block`
+    /// might not be able to change.
+    fn load(&mut self, address: Address, data: &[u8]) {
+        self.write_block(address, data);
+    }
+    /// Returns and clears the first fault raised since the last call.
+    fn take_fault(&mut self) -> Option<Fault> {
+        None
+    }
+    /// Called when a HuC6280 `TAM` sets mapping register `index` to `bank`,
+    /// for memory which maps logical addresses through them.
+    fn set_mapping_register(&mut self, _index: u8, _bank: u8) {}
+    /// Where the zero page starts. The HuC6280 and 65C816 move it, and
+    /// `Cpu` wraps memory to say so while it runs their instructions, so
+    /// memory itself should leave this as is.
+    fn zero_page_base(&self) -> Address {
+        0
+    }
 }
 
 /// View of memory which never changed by reading, for use in debugging and testing
@@ -453,7 +742,7 @@ pub trait MemoryReadOnly {
     fn read_u8_read_only(&self, address: Address) -> u8;
     fn read_u16_le_read_only(&self, address: Address) -> u16 {
         let lo = self.read_u8_read_only(address);
-        let hi = self.read_u8_read_only(address + 1);
+        let hi = self.read_u8_read_only(address.wrapping_add(1));
         ((hi as u16) << 8) | lo as u16
     }
     fn read_u8_stack_read_only(&self, stack_pointer: u8) -> u8 {
@@ -462,3 +751,1664 @@ pub trait MemoryReadOnly {
 }
 
 pub use status::Register as StatusRegister;
+
+#[cfg(feature = "alloc")]
+const ADDRESS_SPACE_SIZE: usize = 0x10000;
+
+// Memory as seen by the CPU during a step, and during an interrupt while
+// coverage, bus logging or a code watch is on. The first byte read is kept,
+// as during a step it's the opcode.
+#[cfg(feature = "alloc")]
+struct Observed<'a, M> {
+    memory: &'a mut M,
+    coverage: Option<&'a mut Coverage>,
+    opcode: Option<u8>,
+    // With the cycle the instruction started at.
+    inputs: Option<(&'a mut Inputs, u64)>,
+    // With the cycle to log the next access at.
+    log: Option<(&'a mut BusLog, u64)>,
+    // Whether the next read is an opcode fetch.
+    sync: bool,
+    // With the address of the instruction running.
+    code: Option<(&'a mut CodeWatch, Address)>,
+    // With the address of the instruction, the first value read from each
+    // of its bytes.
+    bytes: Option<(Address, [Option<u8>; 3])>,
+}
+
+#[cfg(feature = "alloc")]
+impl<M> Observed<'_, M> {
+    fn log(&mut self, address: Address, data: u8, write: bool) {
+        let fetch = !write && core::mem::take(&mut self.sync);
+        if let Some((log, cycle)) = &mut self.log {
+            log.record(bus_log::Access {
+                cycle: *cycle,
+                address,
+                data,
+                write,
+                fetch,
+            });
+            *cycle += 1;
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<M: Memory> Memory for Observed<'_, M> {
+    fn read_u8(&mut self, address: Address) -> u8 {
+        let mut data = self.memory.read_u8(address);
+        if let Some((inputs, cycle)) = &mut self.inputs {
+            data = inputs.read(*cycle, address, data);
+        }
+        self.opcode.get_or_insert(data);
+        if let Some((pc, bytes)) = &mut self.bytes {
+            if let Some(byte) = bytes.get_mut(address.wrapping_sub(*pc) as usize) {
+                byte.get_or_insert(data);
+            }
+        }
+        if let Some(coverage) = self.coverage.as_deref_mut() {
+            coverage.record_read(address, data);
+        }
+        self.log(address, data, false);
+        data
+    }
+    // Reaches devices and the bus log, but as its value isn't used it
+    // isn't coverage, part of the instruction or an input.
+    fn read_u8_dummy(&mut self, address: Address) {
+        let data = self.memory.read_u8(address);
+        self.log(address, data, false);
+    }
+    fn write_u8(&mut self, address: Address, data: u8) {
+        if let Some(coverage) = self.coverage.as_deref_mut() {
+            coverage.written.insert(address);
+        }
+        self.log(address, data, true);
+        if let Some((watch, pc)) = &mut self.code {
+            watch.record_write(*pc, address, data);
+        }
+        self.memory.write_u8(address, data)
+    }
+    fn take_fault(&mut self) -> Option<Fault> {
+        self.memory.take_fault()
+    }
+    fn set_mapping_register(&mut self, index: u8, bank: u8) {
+        self.memory.set_mapping_register(index, bank)
+    }
+}
+
+/// A flat 64KB of RAM covering the whole address space.
+#[cfg(feature = "alloc")]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Clone)]
+pub struct Ram {
+    bytes: Vec<u8>,
+}
+
+#[cfg(feature = "alloc")]
+impl Default for Ram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Ram {
+    pub fn new() -> Self {
+        Self {
+            bytes: alloc::vec![0; ADDRESS_SPACE_SIZE],
+        }
+    }
+    /// RAM holding `pattern` rather than zeros.
+    pub fn with_pattern(pattern: Pattern) -> Self {
+        let mut ram = Self::new();
+        pattern.fill(0, &mut ram.bytes);
+        ram
+    }
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes
+    }
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+}
+
+// Splits a transfer of `len` bytes starting at `address` at the point where
+// it wraps past $FFFF, returning the lengths before and after the wrap.
+#[cfg(feature = "alloc")]
+fn split_at_wrap(address: Address, len: usize) -> (usize, usize) {
+    let before_wrap = (ADDRESS_SPACE_SIZE - address as usize).min(len);
+    (before_wrap, len - before_wrap)
+}
+
+#[cfg(feature = "alloc")]
+impl Memory for Ram {
+    fn read_u8(&mut self, address: Address) -> u8 {
+        self.bytes[address as usize]
+    }
+    fn write_u8(&mut self, address: Address, data: u8) {
+        self.bytes[address as usize] = data;
+    }
+    fn read_block(&mut self, address: Address, buffer: &mut [u8]) {
+        for chunk in buffer.chunks_mut(ADDRESS_SPACE_SIZE) {
+            let (before_wrap, after_wrap) = split_at_wrap(address, chunk.len());
+            let start = address as usize;
+            chunk[..before_wrap].copy_from_slice(&self.bytes[start..start + before_wrap]);
+            chunk[before_wrap..].copy_from_slice(&self.bytes[..after_wrap]);
+        }
+    }
+    fn write_block(&mut self, address: Address, data: &[u8]) {
+        // Every chunk starts back at `address`, so later chunks overwrite
+        // earlier ones just as individual writes would.
+        for chunk in data.chunks(ADDRESS_SPACE_SIZE) {
+            let (before_wrap, after_wrap) = split_at_wrap(address, chunk.len());
+            let start = address as usize;
+            self.bytes[start..start + before_wrap].copy_from_slice(&chunk[..before_wrap]);
+            self.bytes[..after_wrap].copy_from_slice(&chunk[before_wrap..]);
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl MemoryReadOnly for Ram {
+    fn read_u8_read_only(&self, address: Address) -> u8 {
+        self.bytes[address as usize]
+    }
+}
+
+// Cycles taken by the CPU to push state and load an interrupt vector.
+#[cfg(feature = "alloc")]
+const INTERRUPT_CYCLES: u8 = 7;
+
+/// Summary of a single call to `Machine::run_frame`.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, Default)]
+pub struct FrameReport {
+    pub cycles: usize,
+    pub instructions: usize,
+    pub interrupts: usize,
+    /// Breakpoints reached during the call. Running stops at the first
+    /// one, so this holds at most one address.
+    pub breakpoints: Vec<Address>,
+    /// Set if a trap stopped the machine or the CPU halted, which ends the
+    /// frame early.
+    pub stopped: Option<Stopped>,
+}
+
+/// Why one of the `Machine::run_*` functions returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stopped {
+    ReachedPc,
+    CyclesElapsed,
+    Condition,
+    /// The instruction or cycle limit of the `Fuel` given was reached.
+    OutOfFuel,
+    /// A trap handler returned `TrapAction::Stop`.
+    Trap,
+    /// The CPU is halted; see `StepError::Halted`.
+    Halted,
+}
+
+/// Limits on how long `Machine::run` can go on for. The cycle limit is
+/// checked after each instruction, so it can be passed by a few cycles.
+#[derive(Debug, Clone, Copy)]
+pub struct Fuel {
+    pub max_instructions: usize,
+    pub max_cycles: usize,
+}
+
+impl Fuel {
+    pub fn new(max_instructions: usize, max_cycles: usize) -> Self {
+        Self {
+            max_instructions,
+            max_cycles,
+        }
+    }
+    pub fn instructions(max_instructions: usize) -> Self {
+        Self::new(max_instructions, usize::MAX)
+    }
+    pub fn cycles(max_cycles: usize) -> Self {
+        Self::new(usize::MAX, max_cycles)
+    }
+    #[cfg(feature = "alloc")]
+    fn exhausted(&self, instructions: usize, cycles: usize) -> bool {
+        instructions >= self.max_instructions || cycles >= self.max_cycles
+    }
+}
+
+/// Summary of a call to one of the `Machine::run_*` functions. Interrupts
+/// taken count towards `cycles` but not `instructions`.
+#[derive(Debug, Clone)]
+pub struct RunReport {
+    pub instructions: usize,
+    pub cycles: usize,
+    pub stopped: Stopped,
+}
+
+#[cfg(feature = "alloc")]
+pub type FrameCallback<M> = Box<dyn FnMut(&mut Machine<M>)>;
+
+/// Where a trap handler is called: before any instruction with the given
+/// opcode, or before the instruction at the given address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trap {
+    Opcode(u8),
+    Address(Address),
+}
+
+/// What to do once a trap handler has run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapAction {
+    /// Execute the trapped instruction as normal.
+    Continue,
+    /// Skip the trapped instruction and carry on from the program counter,
+    /// which the handler will usually have moved.
+    Resume,
+    /// Skip the trapped instruction and return as `RTS` would, e.g. when
+    /// standing in for a ROM routine.
+    Return,
+    /// Skip the trapped instruction and stop running.
+    Stop,
+}
+
+#[cfg(feature = "alloc")]
+pub type TrapHandler<M> = Box<dyn FnMut(&mut Machine<M>) -> TrapAction>;
+
+/// Called with the machine and the cycle the event was scheduled for, which
+/// may be a few cycles in the past, so that periodic events can be
+/// rescheduled without drifting.
+#[cfg(feature = "alloc")]
+pub type EventHandler<M> = Box<dyn FnMut(&mut Machine<M>, u64)>;
+
+#[cfg(feature = "alloc")]
+pub type CodeWriteHandler<M> = Box<dyn FnMut(&mut Machine<M>, CodeWrite)>;
+
+/// Identifies a scheduled event, for cancelling it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EventId(u64);
+
+/// A `Cpu` together with the memory it runs against.
+#[cfg(feature = "alloc")]
+pub struct Machine<M> {
+    pub cpu: Cpu,
+    pub memory: M,
+    cycles: u64,
+    nmi_pending: bool,
+    irq_line: bool,
+    // Whether any peripheral was asserting IRQ as of the last tick.
+    peripheral_irq: bool,
+    halted: Option<Address>,
+    // Cycles the CPU is still to be held for by RDY.
+    stall: u32,
+    breakpoints: BTreeSet<Address>,
+    frame_carry: usize,
+    frame_progress: usize,
+    frame_callbacks: Vec<FrameCallback<M>>,
+    traps: Vec<(Trap, TrapHandler<M>)>,
+    // Keyed by cycle and then id, so events due at the same cycle fire in
+    // the order they were scheduled.
+    events: BTreeMap<(u64, EventId), EventHandler<M>>,
+    next_event_id: u64,
+    peripherals: Vec<Mapped>,
+    // Cycle counts at which each interrupt was asserted and not yet taken.
+    nmi_asserted_at: Option<u64>,
+    irq_asserted_at: Option<u64>,
+    latency: InterruptLatency,
+    coverage: Option<Coverage>,
+    profile: Option<Profile>,
+    history: Option<History>,
+    bus_log: Option<BusLog>,
+    code_watch: Option<CodeWatch>,
+    counters: Counters,
+    // The opcode of the instruction just run, if the last step ran one.
+    last_opcode: Option<u8>,
+    code_write_handler: Option<CodeWriteHandler<M>>,
+    inputs: Option<Inputs>,
+}
+
+/// Complete machine state: the cpu, memory, interrupt lines and the saved
+/// state of every registered peripheral, in registration order.
+#[cfg(feature = "alloc")]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+pub struct Snapshot<M> {
+    pub cpu: Cpu,
+    pub memory: M,
+    pub cycles: u64,
+    pub nmi_pending: bool,
+    pub irq_line: bool,
+    pub halted: Option<Address>,
+    pub stall: u32,
+    pub frame_carry: usize,
+    pub frame_progress: usize,
+    pub peripherals: Vec<Vec<u8>>,
+}
+
+#[cfg(feature = "alloc")]
+impl<M: Memory> Machine<M> {
+    pub fn new(cpu: Cpu, memory: M) -> Self {
+        Self {
+            cpu,
+            memory,
+            cycles: 0,
+            nmi_pending: false,
+            irq_line: false,
+            peripheral_irq: false,
+            halted: None,
+            stall: 0,
+            breakpoints: BTreeSet::new(),
+            frame_carry: 0,
+            frame_progress: 0,
+            frame_callbacks: Vec::new(),
+            traps: Vec::new(),
+            events: BTreeMap::new(),
+            next_event_id: 0,
+            peripherals: Vec::new(),
+            nmi_asserted_at: None,
+            irq_asserted_at: None,
+            latency: InterruptLatency::default(),
+            coverage: None,
+            profile: None,
+            history: None,
+            bus_log: None,
+            code_watch: None,
+            counters: Counters::default(),
+            last_opcode: None,
+            code_write_handler: None,
+            inputs: None,
+        }
+    }
+    /// Maps `peripheral` over `range`, taking priority over memory and
+    /// over peripherals registered later.
+    pub fn add_peripheral<P: Peripheral + 'static>(
+        &mut self,
+        range: RangeInclusive<Address>,
+        peripheral: P,
+    ) {
+        self.peripherals.push(Mapped {
+            range,
+            peripheral: Box::new(peripheral),
+        });
+    }
+    pub fn snapshot(&self) -> Snapshot<M>
+    where
+        M: Clone,
+    {
+        Snapshot {
+            cpu: self.cpu.clone(),
+            memory: self.memory.clone(),
+            cycles: self.cycles,
+            nmi_pending: self.nmi_pending,
+            irq_line: self.irq_line,
+            halted: self.halted,
+            stall: self.stall,
+            frame_carry: self.frame_carry,
+            frame_progress: self.frame_progress,
+            peripherals: self
+                .peripherals
+                .iter()
+                .map(|mapped| mapped.peripheral.save_state())
+                .collect(),
+        }
+    }
+    /// Restores a snapshot taken from a machine with the same peripherals
+    /// registered in the same order. If any peripheral rejects its state,
+    /// those already restored are put back and nothing else changes.
+    pub fn restore(&mut self, snapshot: &Snapshot<M>) -> Result<(), InvalidState>
+    where
+        M: Clone,
+    {
+        if snapshot.peripherals.len() != self.peripherals.len() {
+            return Err(InvalidState);
+        }
+        let previous: Vec<Vec<u8>> = self
+            .peripherals
+            .iter()
+            .map(|mapped| mapped.peripheral.save_state())
+            .collect();
+        for (index, state) in snapshot.peripherals.iter().enumerate() {
+            if let Err(error) = self.peripherals[index].peripheral.load_state(state) {
+                // Each device takes back the state it saved itself.
+                for (mapped, state) in self.peripherals[..index].iter_mut().zip(&previous) {
+                    let _ = mapped.peripheral.load_state(state);
+                }
+                return Err(error);
+            }
+        }
+        self.cpu = snapshot.cpu.clone();
+        self.memory = snapshot.memory.clone();
+        self.cycles = snapshot.cycles;
+        self.nmi_pending = snapshot.nmi_pending;
+        self.irq_line = snapshot.irq_line;
+        self.halted = snapshot.halted;
+        self.stall = snapshot.stall;
+        self.frame_carry = snapshot.frame_carry;
+        self.frame_progress = snapshot.frame_progress;
+        self.peripheral_irq = self.peripherals_asserting_irq();
+        self.nmi_asserted_at = snapshot.nmi_pending.then_some(snapshot.cycles);
+        self.irq_asserted_at =
+            (snapshot.irq_line || self.peripheral_irq).then_some(snapshot.cycles);
+        Ok(())
+    }
+    /// A 64-bit FNV-1a hash of the registers, the cycle count and the bytes
+    /// in `ranges`, for golden-value regression tests. It's the same on
+    /// every platform: the program counter, `A`, `X`, `Y`, the stack
+    /// pointer, the status as an interrupt would push it and the cycle
+    /// count are hashed in that order, little-endian, then each range's
+    /// bytes in turn.
+    pub fn state_hash(&self, ranges: &[RangeInclusive<Address>]) -> u64
+    where
+        M: MemoryReadOnly,
+    {
+        let cpu = &self.cpu;
+        let registers = [cpu.acc, cpu.x, cpu.y, cpu.sp, u8::from(cpu.status)];
+        let memory = ranges
+            .iter()
+            .flat_map(|range| range.clone())
+            .map(|address| self.memory.read_u8_read_only(address));
+        cpu.pc
+            .to_le_bytes()
+            .into_iter()
+            .chain(registers)
+            .chain(self.cycles.to_le_bytes())
+            .chain(memory)
+            .fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+            })
+    }
+    /// Loads an Intel HEX image into memory with `Memory::load`, and
+    /// returns its start address if it gives one. Nothing is loaded if the
+    /// image has an error.
+    pub fn load_ihex(&mut self, text: &str) -> Result<Option<Address>, hex::Error> {
+        let image = hex::parse_ihex(text)?;
+        image.load(&mut self.memory);
+        Ok(image.entry)
+    }
+    /// Like `load_ihex`, for Motorola S-records.
+    pub fn load_srec(&mut self, text: &str) -> Result<Option<Address>, hex::Error> {
+        let image = hex::parse_srec(text)?;
+        image.load(&mut self.memory);
+        Ok(image.entry)
+    }
+    /// Parses an iNES image and maps its PRG ROM into $8000-$FFFF with
+    /// `Memory::load`. Only NROM is supported. The CHR ROM is returned with
+    /// the rest of the cartridge for the caller, and the program counter is
+    /// left alone, so call `reset` to start from the reset vector.
+    pub fn load_ines(&mut self, bytes: &[u8]) -> Result<ines::Rom, ines::Error> {
+        let rom = ines::Rom::parse(bytes)?;
+        self.memory.load(ines::PRG_START, &rom.prg_window()?);
+        Ok(rom)
+    }
+    fn peripherals_asserting_irq(&self) -> bool {
+        self.peripherals
+            .iter()
+            .any(|mapped| mapped.peripheral.irq())
+    }
+    fn tick_peripherals(&mut self, cycles: u8) {
+        for mapped in self.peripherals.iter_mut() {
+            mapped.peripheral.tick(cycles);
+            self.stall = self.stall.saturating_add(mapped.peripheral.take_stall());
+        }
+        let asserted = self.peripherals_asserting_irq();
+        if asserted && !self.irq_asserted() {
+            self.irq_asserted_at = Some(self.cycles);
+        }
+        self.peripheral_irq = asserted;
+    }
+    // The IRQ line is wired-or between `set_irq` and the peripherals.
+    fn irq_asserted(&self) -> bool {
+        self.irq_line || self.peripheral_irq
+    }
+    /// Total cycles executed since the machine was created.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+    /// Counts of what has run since the machine was made or the counters
+    /// were last reset.
+    pub fn counters(&self) -> Counters {
+        self.counters
+    }
+    pub fn reset_counters(&mut self) {
+        self.counters = Counters::default();
+    }
+    /// Latches an NMI, which is taken before the next instruction.
+    pub fn request_nmi(&mut self) {
+        self.request_nmi_at(self.cycles);
+    }
+    /// Latches an NMI which was asserted at `cycle`, e.g. the cycle an event
+    /// was due at. An NMI asserted during the first four cycles of a `BRK`
+    /// or IRQ hijacks it, as on the NMOS 6502: the status is pushed as for
+    /// the `BRK` or IRQ, but the NMI vector is taken. A cycle still to come
+    /// is taken as the current one.
+    pub fn request_nmi_at(&mut self, cycle: u64) {
+        if self.host_input(replay::Input::Nmi { asserted_at: cycle }) {
+            self.latch_nmi(cycle);
+        }
+    }
+    fn latch_nmi(&mut self, cycle: u64) {
+        if !self.nmi_pending {
+            self.nmi_asserted_at = Some(cycle.min(self.cycles));
+        }
+        self.nmi_pending = true;
+    }
+    // Redirects a `BRK` or IRQ which began at `start` to the NMI vector if
+    // an NMI arrived in time to hijack it.
+    fn hijack(&mut self, start: u64) {
+        let Some(asserted_at) = self.nmi_asserted_at.filter(|_| self.nmi_pending) else {
+            return;
+        };
+        if asserted_at >= start + HIJACK_CYCLES {
+            return;
+        }
+        self.nmi_pending = false;
+        self.nmi_asserted_at = None;
+        self.cpu.pc = Bus {
+            memory: &mut self.memory,
+            peripherals: &mut self.peripherals,
+        }
+        .read_u16_le(crate::interrupt_vector::NMI_LO);
+        self.latency
+            .nmi
+            .record(self.cycles.saturating_sub(asserted_at));
+    }
+    /// Sets the level of the IRQ line. While held, an IRQ is taken before
+    /// each instruction whenever interrupts are enabled. Peripherals can
+    /// also hold it, through `Peripheral::irq`.
+    pub fn set_irq(&mut self, asserted: bool) {
+        if self.host_input(replay::Input::Irq(asserted)) {
+            self.set_irq_line(asserted);
+        }
+    }
+    fn set_irq_line(&mut self, asserted: bool) {
+        if asserted && !self.irq_asserted() {
+            self.irq_asserted_at = Some(self.cycles);
+        }
+        self.irq_line = asserted;
+    }
+    /// Starts recording coverage, if it isn't already.
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert_with(Coverage::new);
+    }
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+    /// Stops recording coverage and returns what was recorded.
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+    /// Starts profiling, if it isn't already.
+    pub fn enable_profiling(&mut self) {
+        self.profile.get_or_insert_with(Profile::new);
+    }
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+    /// Stops profiling and returns the profile.
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+    /// Starts keeping the last `capacity` instructions and interrupts,
+    /// replacing any history kept so far.
+    pub fn enable_history(&mut self, capacity: usize) {
+        self.history = Some(History::new(capacity));
+    }
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+    /// Stops keeping history and returns what was kept.
+    pub fn take_history(&mut self) -> Option<History> {
+        self.history.take()
+    }
+    /// Starts logging bus accesses to `log`, replacing any log so far.
+    pub fn enable_bus_log(&mut self, log: BusLog) {
+        self.bus_log = Some(log);
+    }
+    pub fn bus_log(&self) -> Option<&BusLog> {
+        self.bus_log.as_ref()
+    }
+    /// For draining the log while it's kept on.
+    pub fn bus_log_mut(&mut self) -> Option<&mut BusLog> {
+        self.bus_log.as_mut()
+    }
+    /// Stops logging bus accesses and returns the log.
+    pub fn take_bus_log(&mut self) -> Option<BusLog> {
+        self.bus_log.take()
+    }
+    /// Calls `handler` for every write to code covered by `watch`, after
+    /// the instruction or interrupt making it, replacing any watch so far.
+    pub fn watch_code<F: FnMut(&mut Machine<M>, CodeWrite) + 'static>(
+        &mut self,
+        watch: CodeWatch,
+        handler: F,
+    ) {
+        self.code_watch = Some(watch);
+        self.code_write_handler = Some(Box::new(handler));
+    }
+    pub fn code_watch(&self) -> Option<&CodeWatch> {
+        self.code_watch.as_ref()
+    }
+    /// Stops watching code and returns the watch.
+    pub fn stop_watching_code(&mut self) -> Option<CodeWatch> {
+        self.code_write_handler = None;
+        self.code_watch.take()
+    }
+    /// Starts recording inputs, as described in `replay`, with reads from
+    /// `io` and from every peripheral added so far counted as input.
+    pub fn record_inputs(&mut self, io: &[RangeInclusive<Address>]) {
+        let mut io = io.to_vec();
+        io.extend(self.peripherals.iter().map(|mapped| mapped.range.clone()));
+        self.inputs = Some(Inputs::record(io));
+    }
+    /// Plays `recording` back, from the machine's state now.
+    pub fn replay(&mut self, recording: Recording) {
+        self.inputs = Some(Inputs::replay(recording));
+    }
+    pub fn replay_status(&self) -> Option<replay::Status> {
+        self.inputs.as_ref().map(Inputs::status)
+    }
+    /// Stops recording or replaying, returning the recording.
+    pub fn take_recording(&mut self) -> Option<Recording> {
+        self.inputs.take().map(Inputs::into_recording)
+    }
+    // Notes a call from the host, returning whether to act on it.
+    fn host_input(&mut self, input: replay::Input) -> bool {
+        let cycles = self.cycles;
+        self.inputs
+            .as_mut()
+            .is_none_or(|inputs| inputs.host(cycles, input))
+    }
+    // Makes any recorded calls which are due.
+    fn replay_inputs(&mut self) {
+        while let Some(input) = self
+            .inputs
+            .as_mut()
+            .and_then(|inputs| inputs.due(self.cycles))
+        {
+            match input {
+                replay::Input::Read { .. } => (),
+                replay::Input::Irq(asserted) => self.set_irq_line(asserted),
+                replay::Input::Nmi { asserted_at } => self.latch_nmi(asserted_at),
+                replay::Input::Stall(cycles) => self.stall = self.stall.saturating_add(cycles),
+                replay::Input::Reset => self.reset_line(),
+            }
+        }
+    }
+    /// Jams the CPU as a KIL opcode would, until `reset`.
+    pub fn halt(&mut self) {
+        self.halted = Some(self.cpu.pc);
+    }
+    /// The address the CPU halted at, if it is halted.
+    pub fn halted(&self) -> Option<Address> {
+        self.halted
+    }
+    /// Pulls RESET: the program counter is loaded from the reset vector,
+    /// interrupts are disabled, the stack pointer moves down by three as if
+    /// for an interrupt without anything being written, and any halt or
+    /// pending interrupt is cleared.
+    pub fn reset(&mut self) {
+        if self.host_input(replay::Input::Reset) {
+            self.reset_line();
+        }
+    }
+    fn reset_line(&mut self) {
+        self.halted = None;
+        self.nmi_pending = false;
+        self.nmi_asserted_at = None;
+        self.cpu.sp = self.cpu.sp.wrapping_sub(3);
+        self.cpu.status.set_interrupt_disable();
+        self.cpu.start(&mut Bus {
+            memory: &mut self.memory,
+            peripherals: &mut self.peripherals,
+        });
+    }
+    /// Cycles from each interrupt being asserted until its handler started.
+    /// An IRQ held across several handler runs is only measured the first
+    /// time, from the edge that raised it.
+    pub fn interrupt_latency(&self) -> &InterruptLatency {
+        &self.latency
+    }
+    pub fn reset_interrupt_latency(&mut self) {
+        self.latency = InterruptLatency::default();
+    }
+    /// Holds the CPU off the bus for `cycles` more cycles before its next
+    /// instruction or interrupt, as a device pulling RDY low would, e.g.
+    /// for OAM DMA or badlines. Time still passes for peripherals and
+    /// events while stalled. Stalls are served by the `run_*` functions,
+    /// not by `step`, and peripherals can request them with
+    /// `Peripheral::take_stall`.
+    pub fn stall(&mut self, cycles: u32) {
+        if self.host_input(replay::Input::Stall(cycles)) {
+            self.stall = self.stall.saturating_add(cycles);
+        }
+    }
+    /// Stall cycles still to be served.
+    pub fn stall_pending(&self) -> u32 {
+        self.stall
+    }
+    // Serves any pending stall, returning the cycles it took.
+    fn take_stall(&mut self) -> Option<u32> {
+        self.replay_inputs();
+        if self.stall == 0 {
+            return None;
+        }
+        let mut taken = 0;
+        while self.stall > 0 {
+            // Stop at the next event so it fires on time.
+            let until_event = self
+                .next_event()
+                .map_or(u64::MAX, |cycle| cycle.saturating_sub(self.cycles).max(1));
+            let cycles = until_event.min(self.stall.min(u8::MAX as u32) as u64) as u32;
+            self.stall -= cycles;
+            taken += cycles;
+            self.cycles += cycles as u64;
+            self.counters.record_stall(cycles);
+            self.tick_peripherals(cycles as u8);
+            self.dispatch_events();
+        }
+        Some(taken)
+    }
+    pub fn add_breakpoint(&mut self, address: Address) {
+        self.breakpoints.insert(address);
+    }
+    pub fn remove_breakpoint(&mut self, address: Address) {
+        self.breakpoints.remove(&address);
+    }
+    /// Registers a callback fired at the end of every frame, e.g. to raise
+    /// a vblank NMI or present video output.
+    pub fn on_frame_edge<F: FnMut(&mut Machine<M>) + 'static>(&mut self, callback: F) {
+        self.frame_callbacks.push(Box::new(callback));
+    }
+    /// Registers `handler` to be called with the machine at `trap`, e.g. to
+    /// provide host services to programs through `BRK`. A trap on the
+    /// address of an instruction is used over one on its opcode, and
+    /// otherwise the first registered is used. A trap which skips the
+    /// instruction takes a cycle, so one which keeps resuming at the same
+    /// address, e.g. to wait for an event, doesn't stop time. An opcode
+    /// trap which continues runs the opcode it was fetched for, unless the
+    /// handler moved the program counter. Traps aren't checked while a
+    /// handler runs.
+    pub fn add_trap<F: FnMut(&mut Machine<M>) -> TrapAction + 'static>(
+        &mut self,
+        trap: Trap,
+        handler: F,
+    ) {
+        self.traps.push((trap, Box::new(handler)));
+    }
+    /// Schedules `handler` to run once the cycle count reaches `cycle`.
+    /// Events fire between instructions, so one due partway through an
+    /// instruction runs as soon as it finishes, or straight after the next
+    /// instruction or interrupt if `cycle` has already passed. Handlers can
+    /// schedule further events. Events aren't part of a `Snapshot`.
+    pub fn schedule_at<F: FnMut(&mut Machine<M>, u64) + 'static>(
+        &mut self,
+        cycle: u64,
+        handler: F,
+    ) -> EventId {
+        let id = EventId(self.next_event_id);
+        self.next_event_id += 1;
+        self.events.insert((cycle, id), Box::new(handler));
+        id
+    }
+    /// Schedules `handler` to run `delay` cycles from now.
+    pub fn schedule_in<F: FnMut(&mut Machine<M>, u64) + 'static>(
+        &mut self,
+        delay: u64,
+        handler: F,
+    ) -> EventId {
+        self.schedule_at(self.cycles + delay, handler)
+    }
+    /// Cancels an event, returning whether it was still pending.
+    pub fn cancel_event(&mut self, id: EventId) -> bool {
+        let key = self.events.keys().find(|&&(_, event)| event == id).copied();
+        key.and_then(|key| self.events.remove(&key)).is_some()
+    }
+    /// The cycle the next event is due at, if any are pending.
+    pub fn next_event(&self) -> Option<u64> {
+        self.events.keys().next().map(|&(cycle, _)| cycle)
+    }
+    fn dispatch_events(&mut self) {
+        while let Some(entry) = self.events.first_entry() {
+            let (cycle, _) = *entry.key();
+            if cycle > self.cycles {
+                break;
+            }
+            let mut handler = entry.remove();
+            handler(self, cycle);
+        }
+        self.replay_inputs();
+    }
+    // Finds the trap to run for the instruction at the program counter, if
+    // any, with address traps taking priority over opcode traps. Returns it
+    // and the opcode, if it had to be fetched to check.
+    fn find_trap(&mut self, start: u64) -> (Option<usize>, Option<u8>) {
+        let pc = self.cpu.pc;
+        let find = |traps: &[(Trap, TrapHandler<M>)], wanted: Trap| {
+            traps.iter().position(|&(trap, _)| trap == wanted)
+        };
+        if let Some(index) = find(&self.traps, Trap::Address(pc)) {
+            return (Some(index), None);
+        }
+        if !self
+            .traps
+            .iter()
+            .any(|(trap, _)| matches!(trap, Trap::Opcode(_)))
+        {
+            return (None, None);
+        }
+        // The fetch is the start of the instruction, observed as usual.
+        let opcode = Observed {
+            memory: &mut Bus {
+                memory: &mut self.memory,
+                peripherals: &mut self.peripherals,
+            },
+            coverage: self.coverage.as_mut(),
+            opcode: None,
+            inputs: self.inputs.as_mut().map(|inputs| (inputs, start)),
+            log: self.bus_log.as_mut().map(|log| (log, start)),
+            sync: true,
+            code: None,
+            bytes: None,
+        }
+        .read_u8(pc);
+        (find(&self.traps, Trap::Opcode(opcode)), Some(opcode))
+    }
+    // Runs the handler for trap `index`. Returns the cycles taken if it was
+    // handled without running the instruction.
+    fn run_trap(&mut self, index: usize) -> Result<Option<u8>, StepError> {
+        let mut traps = core::mem::take(&mut self.traps);
+        let action = (traps[index].1)(self);
+        traps.append(&mut self.traps);
+        self.traps = traps;
+        match action {
+            TrapAction::Continue => Ok(None),
+            TrapAction::Resume => Ok(Some(TRAP_CYCLES)),
+            TrapAction::Return => {
+                let mut bus = Bus {
+                    memory: &mut self.memory,
+                    peripherals: &mut self.peripherals,
+                };
+                let lo = self.cpu.pop_stack_u8(&mut bus);
+                let hi = self.cpu.pop_stack_u8(&mut bus);
+                self.cpu.pc = address::from_u8_lo_hi(lo, hi).wrapping_add(1);
+                self.last_opcode = Some(opcode::rts::IMPLIED);
+                Ok(Some(TRAP_CYCLES))
+            }
+            TrapAction::Stop => Err(StepError::TrapStop),
+        }
+    }
+    // Services a pending interrupt, returning the cycles it took.
+    fn take_interrupt(&mut self) -> Option<u8> {
+        let start = self.cycles;
+        self.last_opcode = None;
+        let irq = !self.nmi_pending;
+        let before = self
+            .history
+            .is_some()
+            .then(|| LogState::of(&self.cpu, start));
+        let (asserted_at, stats) = if self.halted.is_some() {
+            return None;
+        } else if self.nmi_pending {
+            self.nmi_pending = false;
+            let mut bus = Bus {
+                memory: &mut self.memory,
+                peripherals: &mut self.peripherals,
+            };
+            if self.coverage.is_some() || self.bus_log.is_some() || self.code_watch.is_some() {
+                let pc = self.cpu.pc;
+                self.cpu.nmi(&mut Observed {
+                    memory: &mut bus,
+                    coverage: self.coverage.as_mut(),
+                    opcode: None,
+                    inputs: None,
+                    log: self.bus_log.as_mut().map(|log| (log, start)),
+                    sync: false,
+                    code: self.code_watch.as_mut().map(|watch| (watch, pc)),
+                    bytes: None,
+                })
+            } else {
+                self.cpu.nmi(&mut bus)
+            }
+            (self.nmi_asserted_at.take(), &mut self.latency.nmi)
+        } else if self.irq_asserted() && !self.cpu.status.interrupt_disable() {
+            let mut bus = Bus {
+                memory: &mut self.memory,
+                peripherals: &mut self.peripherals,
+            };
+            if self.coverage.is_some() || self.bus_log.is_some() || self.code_watch.is_some() {
+                let pc = self.cpu.pc;
+                self.cpu.irq(&mut Observed {
+                    memory: &mut bus,
+                    coverage: self.coverage.as_mut(),
+                    opcode: None,
+                    inputs: None,
+                    log: self.bus_log.as_mut().map(|log| (log, start)),
+                    sync: false,
+                    code: self.code_watch.as_mut().map(|watch| (watch, pc)),
+                    bytes: None,
+                })
+            } else {
+                self.cpu.irq(&mut bus)
+            }
+            (self.irq_asserted_at.take(), &mut self.latency.irq)
+        } else {
+            return None;
+        };
+        self.cycles += INTERRUPT_CYCLES as u64;
+        self.counters.record_interrupt(INTERRUPT_CYCLES);
+        if let Some(asserted_at) = asserted_at {
+            stats.record(self.cycles.saturating_sub(asserted_at));
+        }
+        self.tick_peripherals(INTERRUPT_CYCLES);
+        self.dispatch_events();
+        if irq {
+            self.hijack(start);
+        }
+        if let Some(profile) = &mut self.profile {
+            profile.record_interrupt(self.cpu.pc, INTERRUPT_CYCLES);
+        }
+        if let (Some(history), Some(before)) = (&mut self.history, before) {
+            let event = if irq {
+                history::Event::Irq
+            } else {
+                history::Event::Nmi
+            };
+            history.record(history::Entry { before, event });
+        }
+        self.report_code_writes();
+        Some(INTERRUPT_CYCLES)
+    }
+    // Calls the code watch handler for each write to code since the last
+    // call. The handler can replace or stop the watch.
+    fn report_code_writes(&mut self) {
+        let Some(watch) = &mut self.code_watch else {
+            return;
+        };
+        let writes = watch.take_pending();
+        let Some(mut handler) = self.code_write_handler.take() else {
+            return;
+        };
+        for write in writes {
+            handler(self, write);
+        }
+        if self.code_watch.is_some() && self.code_write_handler.is_none() {
+            self.code_write_handler = Some(handler);
+        }
+    }
+    fn check_fault(&mut self) -> Result<(), StepError> {
+        match self.memory.take_fault() {
+            Some(fault) => Err(StepError::Fault(fault)),
+            None => Ok(()),
+        }
+    }
+    /// Runs one instruction. A fault is reported after the instruction has
+    /// finished, with its cycles counted. Once halted, every step returns
+    /// `StepError::Halted` without doing anything.
+    pub fn step(&mut self) -> Result<u8, StepError> {
+        self.replay_inputs();
+        self.last_opcode = None;
+        if let Some(address) = self.halted {
+            return Err(StepError::Halted(address));
+        }
+        let start = self.cycles;
+        let trap_pc = self.cpu.pc;
+        let (trap, mut fetched) = if self.traps.is_empty() {
+            (None, None)
+        } else {
+            self.find_trap(start)
+        };
+        if let Some(index) = trap {
+            if let Some(cycles) = self.run_trap(index)? {
+                self.cycles += cycles as u64;
+                self.counters.record_stall(cycles as u32);
+                self.tick_peripherals(cycles);
+                self.dispatch_events();
+                return Ok(cycles);
+            }
+        }
+        // A handler may have sent the CPU elsewhere, and then the opcode
+        // fetched for it isn't the one to run.
+        let pc = self.cpu.pc;
+        if pc != trap_pc {
+            fetched = None;
+        }
+        // Only an event can raise an NMI partway through an instruction.
+        let events = !self.events.is_empty();
+        let before = self
+            .history
+            .is_some()
+            .then(|| LogState::of(&self.cpu, start));
+        if let Some(coverage) = &mut self.coverage {
+            coverage.fetch_at(pc);
+        }
+        let mut bus = Bus {
+            memory: &mut self.memory,
+            peripherals: &mut self.peripherals,
+        };
+        // Always observed, as the counters need the opcode.
+        let mut observed = Observed {
+            memory: &mut bus,
+            coverage: self.coverage.as_mut(),
+            opcode: fetched,
+            inputs: self.inputs.as_mut().map(|inputs| (inputs, start)),
+            log: self
+                .bus_log
+                .as_mut()
+                .map(|log| (log, start + fetched.is_some() as u64)),
+            sync: fetched.is_none(),
+            code: self.code_watch.as_mut().map(|watch| (watch, pc)),
+            bytes: before.is_some().then_some((pc, [fetched, None, None])),
+        };
+        let result = match fetched {
+            Some(opcode) => self.cpu.step_with_opcode(opcode, &mut observed),
+            None => self.cpu.step(&mut observed),
+        };
+        if let (Some(history), Some(before), Some((_, bytes))) =
+            (&mut self.history, before, observed.bytes)
+        {
+            let mut bytes = bytes.map(|byte| byte.unwrap_or(0));
+            let size =
+                Instruction::from_opcode(bytes[0]).map_or(1, |instruction| instruction.size());
+            bytes[size..].fill(0);
+            history.record(history::Entry {
+                before,
+                event: history::Event::Instruction(bytes),
+            });
+        }
+        let opcode = observed.opcode;
+        self.last_opcode = opcode;
+        let cycles = match result {
+            Err(UnknownOpcode(opcode)) if jams(self.cpu.variant, opcode) => {
+                self.halt();
+                return Err(StepError::Halted(self.cpu.pc));
+            }
+            result => result?,
+        };
+        self.stall = self
+            .stall
+            .saturating_add(core::mem::take(&mut self.cpu.extra_cycles));
+        self.cycles += cycles as u64;
+        self.counters
+            .record_instruction(self.cpu.variant, opcode, cycles);
+        self.tick_peripherals(cycles);
+        self.dispatch_events();
+        if events && opcode == Some(opcode::brk::IMPLIED) {
+            self.hijack(start);
+        }
+        if let (Some(profile), Some(opcode)) = (&mut self.profile, opcode) {
+            profile.record_instruction(pc, opcode, cycles, self.cpu.pc);
+        }
+        if let (Some(watch), Some(opcode)) = (&mut self.code_watch, opcode) {
+            let size = Instruction::from_opcode(opcode).map_or(1, |instruction| instruction.size());
+            watch.record_executed(pc, size);
+        }
+        self.report_code_writes();
+        self.check_fault()?;
+        Ok(cycles)
+    }
+    // Runs instructions and interrupts until `stop` returns a reason, which
+    // is checked after each of them.
+    fn run_while<F>(&mut self, mut stop: F) -> Result<RunReport, StepError>
+    where
+        F: FnMut(&Self, usize, usize) -> Option<Stopped>,
+    {
+        let (mut instructions, mut cycles) = (0, 0);
+        loop {
+            cycles += if let Some(cycles) = self.take_stall() {
+                cycles
+            } else if let Some(cycles) = self.take_interrupt() {
+                self.check_fault()?;
+                cycles as u32
+            } else {
+                instructions += 1;
+                match self.step() {
+                    Err(StepError::TrapStop) => {
+                        return Ok(RunReport {
+                            instructions,
+                            cycles,
+                            stopped: Stopped::Trap,
+                        })
+                    }
+                    Err(StepError::Halted(_)) => {
+                        return Ok(RunReport {
+                            instructions: instructions - 1,
+                            cycles,
+                            stopped: Stopped::Halted,
+                        })
+                    }
+                    result => result? as u32,
+                }
+            } as usize;
+            if let Some(stopped) = stop(self, instructions, cycles) {
+                return Ok(RunReport {
+                    instructions,
+                    cycles,
+                    stopped,
+                });
+            }
+        }
+    }
+    /// Runs until the program counter reaches `address`, after at least one
+    /// instruction or interrupt, so it can be used to go round a loop.
+    pub fn run_until_pc(&mut self, address: Address) -> Result<RunReport, StepError> {
+        self.run_while(|machine, _, _| (machine.cpu.pc == address).then_some(Stopped::ReachedPc))
+    }
+    /// Runs the next instruction, or if it's a `JSR`, the whole call, until
+    /// the program counter is back after it with the stack as it was, so
+    /// recursive calls and interrupts taken along the way are run through.
+    /// An interrupt taken before the instruction is run through in the same
+    /// way, and the instruction is run once it returns. Stops early if
+    /// `fuel` runs out, in case the call never returns.
+    pub fn step_over(&mut self, fuel: Fuel) -> Result<RunReport, StepError> {
+        if fuel.exhausted(0, 0) {
+            return Ok(RunReport {
+                instructions: 0,
+                cycles: 0,
+                stopped: Stopped::OutOfFuel,
+            });
+        }
+        let start = (self.cpu.pc, self.cpu.sp);
+        // Where the last instruction began, and where and with what stack a
+        // `JSR` run from the start returns to.
+        let (mut before, mut counted, mut after) = (start, 0, None);
+        self.run_while(|machine, instructions, cycles| {
+            let state = (machine.cpu.pc, machine.cpu.sp);
+            if instructions != counted && before == start && after.is_none() {
+                if machine.last_opcode != Some(opcode::jsr::ABSOLUTE) {
+                    return Some(Stopped::Condition);
+                }
+                after = Some((start.0.wrapping_add(3), start.1));
+            }
+            (before, counted) = (state, instructions);
+            if after == Some(state) {
+                return Some(Stopped::Condition);
+            }
+            fuel.exhausted(instructions, cycles)
+                .then_some(Stopped::OutOfFuel)
+        })
+    }
+    /// Runs until the current subroutine or interrupt handler returns: an
+    /// `RTS` or `RTI` (or a trap returning as one) which leaves the stack
+    /// above where it is now. Returns from deeper calls, and any bytes the
+    /// routine pulls before returning, don't count. Stops early if `fuel`
+    /// runs out.
+    pub fn step_out(&mut self, fuel: Fuel) -> Result<RunReport, StepError> {
+        let sp = self.cpu.sp;
+        self.run_until_with_fuel(fuel, |machine| {
+            matches!(
+                machine.last_opcode,
+                Some(opcode::rts::IMPLIED | opcode::rti::IMPLIED)
+            ) && (machine.cpu.sp.wrapping_sub(sp) as i8) > 0
+        })
+    }
+    /// Runs until at least `cycles` cycles have passed. Instructions can't be
+    /// split, so the last one may take the total past `cycles`; the report
+    /// has the exact count.
+    pub fn run_cycles(&mut self, cycles: usize) -> Result<RunReport, StepError> {
+        if cycles == 0 {
+            return Ok(RunReport {
+                instructions: 0,
+                cycles: 0,
+                stopped: Stopped::CyclesElapsed,
+            });
+        }
+        self.run_while(|_, _, elapsed| (elapsed >= cycles).then_some(Stopped::CyclesElapsed))
+    }
+    /// Runs until `fuel` runs out, or an error.
+    pub fn run(&mut self, fuel: Fuel) -> Result<RunReport, StepError> {
+        self.run_until_with_fuel(fuel, |_| false)
+    }
+    /// A future which runs until `fuel` runs out, yielding to the executor
+    /// every `stepper::DEFAULT_SLICE` cycles.
+    pub fn run_async(&mut self, fuel: Fuel) -> stepper::RunFor<'_, M> {
+        stepper::RunFor::new(self, fuel)
+    }
+    /// Like `run_until`, but stops with `Stopped::OutOfFuel` once `fuel`
+    /// runs out, without running anything if it is already empty.
+    pub fn run_until_with_fuel<F: FnMut(&Self) -> bool>(
+        &mut self,
+        fuel: Fuel,
+        mut condition: F,
+    ) -> Result<RunReport, StepError> {
+        if fuel.exhausted(0, 0) {
+            return Ok(RunReport {
+                instructions: 0,
+                cycles: 0,
+                stopped: Stopped::OutOfFuel,
+            });
+        }
+        self.run_while(|machine, instructions, cycles| {
+            if condition(machine) {
+                Some(Stopped::Condition)
+            } else {
+                fuel.exhausted(instructions, cycles)
+                    .then_some(Stopped::OutOfFuel)
+            }
+        })
+    }
+    /// Runs until `condition` holds, checking it after every instruction and
+    /// interrupt.
+    pub fn run_until<F: FnMut(&Self) -> bool>(
+        &mut self,
+        mut condition: F,
+    ) -> Result<RunReport, StepError> {
+        self.run_while(|machine, _, _| condition(machine).then_some(Stopped::Condition))
+    }
+    /// Runs one frame's worth of cycles. Instructions can't be split, so
+    /// any cycles run past the end of a frame are deducted from the next
+    /// one, keeping the long-run rate exact. If a breakpoint is reached
+    /// (other than at the starting pc) the call returns early, and the next
+    /// call carries on with the same frame. A trap stopping the machine or
+    /// the CPU halting ends the frame there, as `FrameReport::stopped`
+    /// says.
+    pub fn run_frame(&mut self, cycles_per_frame: usize) -> Result<FrameReport, StepError> {
+        let budget = cycles_per_frame.saturating_sub(self.frame_carry);
+        let mut report = FrameReport::default();
+        let mut at_start = true;
+        while self.frame_progress < budget {
+            let cycles = if let Some(cycles) = self.take_stall() {
+                cycles
+            } else if let Some(cycles) = self.take_interrupt() {
+                report.interrupts += 1;
+                self.check_fault()?;
+                cycles as u32
+            } else {
+                if !at_start && self.breakpoints.contains(&self.cpu.pc) {
+                    report.breakpoints.push(self.cpu.pc);
+                    return Ok(report);
+                }
+                report.instructions += 1;
+                match self.step() {
+                    Err(StepError::TrapStop) => {
+                        report.stopped = Some(Stopped::Trap);
+                        break;
+                    }
+                    Err(StepError::Halted(_)) => {
+                        report.instructions -= 1;
+                        report.stopped = Some(Stopped::Halted);
+                        break;
+                    }
+                    result => result? as u32,
+                }
+            };
+            at_start = false;
+            self.frame_progress += cycles as usize;
+            report.cycles += cycles as usize;
+        }
+        self.frame_carry =
+            (self.frame_carry + self.frame_progress).saturating_sub(cycles_per_frame);
+        self.frame_progress = 0;
+        let mut callbacks = core::mem::take(&mut self.frame_callbacks);
+        for callback in callbacks.iter_mut() {
+            callback(self);
+        }
+        callbacks.append(&mut self.frame_callbacks);
+        self.frame_callbacks = callbacks;
+        Ok(report)
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+
+    const IRQ_HANDLER: Address = 0xA000;
+    const NMI_HANDLER: Address = 0x9000;
+    const B: u8 = 0x10;
+
+    // A `BRK` at $8000, with NOPs at both handlers and interrupts enabled.
+    fn machine() -> Machine<Ram> {
+        let mut ram = Ram::new();
+        let bytes = ram.as_mut_slice();
+        bytes[0x8000] = opcode::brk::IMPLIED;
+        bytes[NMI_HANDLER as usize] = opcode::nop::IMPLIED;
+        bytes[IRQ_HANDLER as usize] = opcode::nop::IMPLIED;
+        bytes[0xFFFA..0xFFFC].copy_from_slice(&NMI_HANDLER.to_le_bytes());
+        bytes[0xFFFE..].copy_from_slice(&IRQ_HANDLER.to_le_bytes());
+        let mut machine = Machine::new(Cpu::new(), ram);
+        machine.cpu.pc = 0x8000;
+        machine.cpu.status.clear_interrupt_disable();
+        machine
+    }
+
+    // The return address and status pushed by the first interrupt or `BRK`.
+    fn pushed(machine: &Machine<Ram>) -> (Address, u8) {
+        let stack = &machine.memory.as_slice()[0x01FD..0x0200];
+        (u16::from_le_bytes([stack[1], stack[2]]), stack[0])
+    }
+
+    fn run_one(machine: &mut Machine<Ram>) {
+        machine.run_until(|_| true).unwrap();
+    }
+
+    #[test]
+    fn brk_pushes_b_set() {
+        let mut machine = machine();
+        machine.step().unwrap();
+        assert_eq!(machine.cpu.pc, IRQ_HANDLER);
+        let (address, status) = pushed(&machine);
+        assert_eq!(address, 0x8002);
+        assert_ne!(status & B, 0);
+    }
+
+    #[test]
+    fn irq_pushes_b_clear() {
+        let mut machine = machine();
+        machine.set_irq(true);
+        run_one(&mut machine);
+        assert_eq!(machine.cpu.pc, IRQ_HANDLER);
+        let (address, status) = pushed(&machine);
+        assert_eq!(address, 0x8000);
+        assert_eq!(status & B, 0);
+    }
+
+    #[test]
+    fn nmi_pushes_b_clear() {
+        let mut machine = machine();
+        machine.request_nmi();
+        run_one(&mut machine);
+        assert_eq!(machine.cpu.pc, NMI_HANDLER);
+        let (address, status) = pushed(&machine);
+        assert_eq!(address, 0x8000);
+        assert_eq!(status & B, 0);
+    }
+
+    #[test]
+    fn nmi_during_brk_hijacks_it() {
+        let mut machine = machine();
+        machine.schedule_at(2, |machine, cycle| machine.request_nmi_at(cycle));
+        machine.step().unwrap();
+        assert_eq!(machine.cpu.pc, NMI_HANDLER);
+        let (address, status) = pushed(&machine);
+        assert_eq!(address, 0x8002);
+        assert_ne!(status & B, 0);
+        // The NMI was used up by the hijack.
+        run_one(&mut machine);
+        assert_eq!(machine.cpu.pc, NMI_HANDLER + 1);
+    }
+
+    #[test]
+    fn nmi_requested_for_a_later_cycle_is_taken_now() {
+        let mut machine = machine();
+        machine.request_nmi_at(1000);
+        machine.run_cycles(10).unwrap();
+        assert_eq!(pushed(&machine).0, 0x8000);
+        assert_eq!(
+            machine.interrupt_latency().nmi.max(),
+            Some(INTERRUPT_CYCLES as u64)
+        );
+    }
+
+    #[test]
+    fn nmi_late_in_brk_is_taken_after_it() {
+        let mut machine = machine();
+        machine.schedule_at(HIJACK_CYCLES, |machine, cycle| {
+            machine.request_nmi_at(cycle)
+        });
+        machine.step().unwrap();
+        assert_eq!(machine.cpu.pc, IRQ_HANDLER);
+        run_one(&mut machine);
+        assert_eq!(machine.cpu.pc, NMI_HANDLER);
+        let stack = &machine.memory.as_slice()[0x01FA..0x01FD];
+        assert_eq!(u16::from_le_bytes([stack[1], stack[2]]), IRQ_HANDLER);
+        assert_eq!(stack[0] & B, 0);
+    }
+
+    #[test]
+    fn irq_during_brk_waits_for_interrupts_to_be_enabled() {
+        let mut machine = machine();
+        machine.schedule_at(2, |machine, _| machine.set_irq(true));
+        machine.step().unwrap();
+        assert_eq!(machine.cpu.pc, IRQ_HANDLER);
+        assert_ne!(pushed(&machine).1 & B, 0);
+        // `BRK` set I, so the handler runs rather than the IRQ.
+        run_one(&mut machine);
+        assert_eq!(machine.cpu.pc, IRQ_HANDLER + 1);
+        assert_eq!(machine.cpu.sp, 0xFC);
+    }
+
+    #[test]
+    fn run_frame_ends_the_frame_when_halted() {
+        let mut machine = machine();
+        machine
+            .memory
+            .write_block(0x8000, &[opcode::nop::IMPLIED, opcode::nop::IMPLIED, 0x02]);
+        let report = machine.run_frame(100).unwrap();
+        assert_eq!(report.stopped, Some(Stopped::Halted));
+        assert_eq!((report.instructions, report.cycles), (2, 4));
+        assert_eq!(machine.frame_progress, 0);
+        let report = machine.run_frame(100).unwrap();
+        assert_eq!(report.stopped, Some(Stopped::Halted));
+        assert_eq!(report.cycles, 0);
+    }
+
+    #[test]
+    fn run_frame_ends_the_frame_when_a_trap_stops() {
+        let mut machine = machine();
+        machine.add_trap(Trap::Address(0x8000), |_| TrapAction::Stop);
+        let report = machine.run_frame(100).unwrap();
+        assert_eq!(report.stopped, Some(Stopped::Trap));
+        assert_eq!(machine.cpu.pc, 0x8000);
+        assert_eq!(machine.frame_progress, 0);
+    }
+
+    // A register which only takes back one-byte states.
+    struct Latch(u8);
+
+    impl Peripheral for Latch {
+        fn read(&mut self, _offset: Address) -> u8 {
+            self.0
+        }
+        fn write(&mut self, _offset: Address, data: u8) {
+            self.0 = data;
+        }
+        fn save_state(&self) -> Vec<u8> {
+            alloc::vec![self.0]
+        }
+        fn load_state(&mut self, state: &[u8]) -> Result<(), InvalidState> {
+            let [data] = *state else {
+                return Err(InvalidState);
+            };
+            self.0 = data;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failed_restore_leaves_peripherals_alone() {
+        let mut machine = machine();
+        machine.add_peripheral(0x6000..=0x6000, Latch(1));
+        machine.add_peripheral(0x6001..=0x6001, Latch(2));
+        let mut snapshot = machine.snapshot();
+        snapshot.peripherals[0] = alloc::vec![3];
+        snapshot.peripherals[1].clear();
+        snapshot.cpu.pc = 0x1234;
+        assert!(machine.restore(&snapshot).is_err());
+        assert_eq!(machine.cpu.pc, 0x8000);
+        let mut bus = Bus {
+            memory: &mut machine.memory,
+            peripherals: &mut machine.peripherals,
+        };
+        assert_eq!((bus.read_u8(0x6000), bus.read_u8(0x6001)), (1, 2));
+    }
+
+    #[test]
+    fn trap_resuming_in_place_takes_time() {
+        let mut machine = machine();
+        machine.add_trap(Trap::Address(0x8000), |_| TrapAction::Resume);
+        machine.schedule_at(10, |machine, cycle| machine.request_nmi_at(cycle));
+        let report = machine.run_cycles(100).unwrap();
+        assert_eq!(report.cycles, 100);
+        assert_eq!(machine.cycles(), 100);
+        // Waiting at $8000 until the NMI, which was then taken.
+        assert_eq!(pushed(&machine).0, 0x8000);
+    }
+
+    #[test]
+    fn step_over_runs_through_an_interrupt() {
+        let mut machine = machine();
+        machine.memory.as_mut_slice()[0x8000] = opcode::nop::IMPLIED;
+        machine.memory.as_mut_slice()[IRQ_HANDLER as usize] = opcode::cli::IMPLIED;
+        machine.memory.as_mut_slice()[IRQ_HANDLER as usize + 1] = opcode::rti::IMPLIED;
+        machine.schedule_at(1, |machine, _| machine.set_irq(false));
+        machine.set_irq(true);
+        let report = machine.step_over(Fuel::instructions(10)).unwrap();
+        assert_eq!(report.stopped, Stopped::Condition);
+        assert_eq!(report.instructions, 3);
+        assert_eq!((machine.cpu.pc, machine.cpu.sp), (0x8001, 0xFF));
+    }
+
+    #[test]
+    fn step_over_runs_a_call() {
+        let mut machine = machine();
+        let bytes = machine.memory.as_mut_slice();
+        bytes[0x8000..0x8003].copy_from_slice(&[opcode::jsr::ABSOLUTE, 0x00, 0x81]);
+        bytes[0x8100] = opcode::nop::IMPLIED;
+        bytes[0x8101] = opcode::rts::IMPLIED;
+        let report = machine.step_over(Fuel::instructions(10)).unwrap();
+        assert_eq!(report.instructions, 3);
+        assert_eq!((machine.cpu.pc, machine.cpu.sp), (0x8003, 0xFF));
+    }
+
+    // Counts reads, as a device whose registers change when read would see
+    // them.
+    struct CountingRam {
+        ram: Ram,
+        reads: Vec<u32>,
+    }
+
+    impl Memory for CountingRam {
+        fn read_u8(&mut self, address: Address) -> u8 {
+            self.reads[address as usize] += 1;
+            self.ram.read_u8(address)
+        }
+        fn write_u8(&mut self, address: Address, data: u8) {
+            self.ram.write_u8(address, data)
+        }
+    }
+
+    #[test]
+    fn instructions_are_read_once_with_history_and_traps() {
+        let mut ram = Ram::new();
+        // LDA $1234; BEQ +4, which isn't taken as A is loaded with 1.
+        ram.as_mut_slice()[0x8000..0x8005].copy_from_slice(&[0xAD, 0x34, 0x12, 0xF0, 0x04]);
+        ram.as_mut_slice()[0x1234] = 1;
+        let memory = CountingRam {
+            ram,
+            reads: alloc::vec![0; 0x10000],
+        };
+        let mut machine = Machine::new(Cpu::new(), memory);
+        machine.cpu.pc = 0x8000;
+        machine.enable_history(4);
+        machine.add_trap(Trap::Opcode(opcode::nop::IMPLIED), |_| TrapAction::Continue);
+        machine.step().unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.cpu.pc, 0x8005);
+        assert_eq!(&machine.memory.reads[0x8000..0x8005], &[1; 5]);
+        assert_eq!(machine.memory.reads[0x1234], 1);
+        let events: Vec<_> = machine
+            .history()
+            .unwrap()
+            .iter()
+            .map(|entry| entry.event)
+            .collect();
+        assert_eq!(
+            events,
+            [
+                history::Event::Instruction([0xAD, 0x34, 0x12]),
+                history::Event::Instruction([0xF0, 0x04, 0x00]),
+            ]
+        );
+    }
+
+    #[test]
+    fn nmi_during_irq_hijacks_it() {
+        let mut machine = machine();
+        machine.set_irq(true);
+        machine.schedule_at(2, |machine, cycle| machine.request_nmi_at(cycle));
+        run_one(&mut machine);
+        assert_eq!(machine.cpu.pc, NMI_HANDLER);
+        let (address, status) = pushed(&machine);
+        assert_eq!(address, 0x8000);
+        assert_eq!(status & B, 0);
+    }
+
+    // Runs `ADC`/`SBC #operand` with D set, from the accumulator and carry
+    // given.
+    fn decimal(variant: Variant, opcode: u8, acc: u8, operand: u8, carry: bool) -> Cpu {
+        let mut ram = Ram::new();
+        ram.write_block(0x0200, &[opcode::sed::IMPLIED, opcode, operand]);
+        let mut cpu = Cpu::new();
+        cpu.variant = variant;
+        cpu.pc = 0x0200;
+        cpu.acc = acc;
+        cpu.status.set_carry_to(carry);
+        for _ in 0..2 {
+            cpu.step(&mut ram).unwrap();
+        }
+        cpu
+    }
+
+    // (carry, zero, negative, overflow)
+    fn flags(cpu: &Cpu) -> (bool, bool, bool, bool) {
+        let status = &cpu.status;
+        (
+            status.carry(),
+            status.zero(),
+            status.negative(),
+            status.overflow(),
+        )
+    }
+
+    #[test]
+    fn nmos_adc_is_decimal() {
+        let cpu = decimal(Variant::Nmos6502, opcode::adc::IMMEDIATE, 0x58, 0x46, true);
+        assert_eq!(cpu.acc, 0x05);
+        // N and V come from the sum before the high digit is adjusted.
+        assert_eq!(flags(&cpu), (true, false, true, true));
+        // Z comes from the binary sum, $9A.
+        let cpu = decimal(Variant::Nmos6502, opcode::adc::IMMEDIATE, 0x99, 0x01, false);
+        assert_eq!(cpu.acc, 0x00);
+        assert_eq!(flags(&cpu), (true, false, true, false));
+    }
+
+    #[test]
+    fn nmos_sbc_is_decimal() {
+        let cpu = decimal(Variant::Nmos6502, opcode::sbc::IMMEDIATE, 0x00, 0x01, true);
+        assert_eq!(cpu.acc, 0x99);
+        // The flags all come from the binary difference, $FF.
+        assert_eq!(flags(&cpu), (false, false, true, false));
+        let cpu = decimal(Variant::Nmos6502, opcode::sbc::IMMEDIATE, 0x46, 0x12, true);
+        assert_eq!(cpu.acc, 0x34);
+        assert_eq!(flags(&cpu), (true, false, false, false));
+    }
+
+    #[test]
+    fn ricoh_2a03_adc_and_sbc_stay_binary() {
+        let cpu = decimal(Variant::Ricoh2A03, opcode::adc::IMMEDIATE, 0x58, 0x46, true);
+        assert_eq!(cpu.acc, 0x9F);
+        assert_eq!(flags(&cpu), (false, false, true, true));
+        let cpu = decimal(Variant::Ricoh2A03, opcode::sbc::IMMEDIATE, 0x00, 0x01, true);
+        assert_eq!(cpu.acc, 0xFF);
+        assert_eq!(flags(&cpu), (false, false, true, false));
+    }
+
+    #[test]
+    fn ricoh_2a03_keeps_the_d_flag() {
+        let mut ram = Ram::new();
+        #[rustfmt::skip]
+        ram.write_block(0x0200, &[
+            opcode::sed::IMPLIED,
+            opcode::php::IMPLIED,
+            opcode::cld::IMPLIED,
+            opcode::plp::IMPLIED,
+        ]);
+        let mut cpu = Cpu::new();
+        cpu.variant = Variant::Ricoh2A03;
+        cpu.pc = 0x0200;
+        for _ in 0..4 {
+            cpu.step(&mut ram).unwrap();
+        }
+        let pushed = ram.as_slice()[0x0100 | cpu.sp as usize];
+        assert_ne!(pushed & status::flag::DECIMAL, 0);
+        assert!(cpu.status.decimal());
+    }
+}
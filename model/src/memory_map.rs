@@ -0,0 +1,417 @@
+use crate::banking::Banked;
+use crate::machine::{Fault, Memory, MemoryReadOnly};
+use crate::power_on::Pattern;
+use crate::Address;
+use alloc::{boxed::Box, vec::Vec};
+use core::ops::RangeInclusive;
+
+pub type ReadHandler = Box<dyn FnMut(Address) -> u8>;
+pub type WriteHandler = Box<dyn FnMut(Address, u8)>;
+
+/// What happens when the CPU writes to ROM. The contents never change.
+pub enum RomWrite {
+    Ignore,
+    /// Calls the handler with the address and data written.
+    Hook(WriteHandler),
+    /// Raises `Fault::RomWrite`, so `Machine::step` returns an error.
+    Fault,
+}
+
+/// What reads from addresses outside every region return.
+pub enum UnmappedRead {
+    Zero,
+    Ones,
+    /// The last value read or written through the map, as left floating on
+    /// the data bus. Accesses to peripherals added to a `Machine` don't go
+    /// through the map, so they aren't seen.
+    OpenBus,
+    /// Calls the handler with the address.
+    Handler(ReadHandler),
+}
+
+enum Region {
+    Ram,
+    Rom(RomWrite),
+    Mirror {
+        of: RangeInclusive<Address>,
+    },
+    Io {
+        read: ReadHandler,
+        write: WriteHandler,
+    },
+    Banked {
+        set: usize,
+        window: usize,
+    },
+}
+
+struct Mapped {
+    range: RangeInclusive<Address>,
+    region: Region,
+}
+
+/// An address space assembled from RAM, ROM, banked memory, mirrors of
+/// other ranges and callback-backed I/O. Where ranges overlap, the one
+/// registered first takes priority. Reads from unmapped addresses return 0
+/// unless configured with `unmapped_reads`, and writes to them are ignored.
+/// Mapping a range which starts after it ends panics.
+///
+/// ```ignore
+/// let memory = MemoryMap::new()
+///     .ram(0x0000..=0x07FF)
+///     .mirror(0x0800..=0x1FFF, 0x0000..=0x07FF)
+///     .io(0x2000..=0x2007, |offset| status(offset), |offset, data| control(offset, data))
+///     .rom(0x8000, &image);
+/// ```
+pub struct MemoryMap {
+    // Backing store for RAM and ROM, indexed by address.
+    bytes: Vec<u8>,
+    regions: Vec<Mapped>,
+    banked: Vec<Banked>,
+    fault: Option<Fault>,
+    unmapped_read: UnmappedRead,
+    bus: u8,
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// What an address resolves to after following a mirror.
+enum Target {
+    Ram(Address),
+    Rom(usize, Address),
+    Io(usize, Address),
+    Banked(usize, usize, Address),
+    Unmapped,
+}
+
+impl MemoryMap {
+    pub fn new() -> Self {
+        Self {
+            bytes: alloc::vec![0; 0x10000],
+            regions: Vec::new(),
+            banked: Vec::new(),
+            fault: None,
+            unmapped_read: UnmappedRead::Zero,
+            bus: 0,
+        }
+    }
+    fn map(mut self, range: RangeInclusive<Address>, region: Region) -> Self {
+        assert!(!range.is_empty(), "mapped range starts after it ends");
+        self.regions.push(Mapped { range, region });
+        self
+    }
+    pub fn ram(self, range: RangeInclusive<Address>) -> Self {
+        self.map(range, Region::Ram)
+    }
+    /// Maps RAM over `range` holding `pattern`, rather than zeros, at
+    /// power-on.
+    pub fn ram_with(self, range: RangeInclusive<Address>, pattern: Pattern) -> Self {
+        let (start, end) = (*range.start(), *range.end() as usize);
+        let mut map = self.ram(range);
+        pattern.fill(start, &mut map.bytes[start as usize..=end]);
+        map
+    }
+    /// Maps `image` as ROM starting at `start`, ignoring writes.
+    pub fn rom(self, start: Address, image: &[u8]) -> Self {
+        self.rom_with(start, image, RomWrite::Ignore)
+    }
+    pub fn rom_with(mut self, start: Address, image: &[u8], on_write: RomWrite) -> Self {
+        if image.is_empty() {
+            return self;
+        }
+        let end = start as usize + image.len() - 1;
+        assert!(end <= Address::MAX as usize, "ROM image runs past $FFFF");
+        self.bytes[start as usize..=end].copy_from_slice(image);
+        self.map(start..=end as Address, Region::Rom(on_write))
+    }
+    /// Makes `range` repeat the contents of `of`, which is looked up again
+    /// (ignoring other mirrors), so RAM, ROM and I/O can all be mirrored.
+    pub fn mirror(self, range: RangeInclusive<Address>, of: RangeInclusive<Address>) -> Self {
+        assert!(!of.is_empty(), "mirrored range starts after it ends");
+        self.map(range, Region::Mirror { of })
+    }
+    /// Maps handlers over `range`. Both are given the offset from the start
+    /// of the range.
+    pub fn io<R, W>(self, range: RangeInclusive<Address>, read: R, write: W) -> Self
+    where
+        R: FnMut(Address) -> u8 + 'static,
+        W: FnMut(Address, u8) + 'static,
+    {
+        let region = Region::Io {
+            read: Box::new(read),
+            write: Box::new(write),
+        };
+        self.map(range, region)
+    }
+    /// Maps every window of `banked`. Its registers take priority over all
+    /// regions, for writes.
+    pub fn banked(mut self, banked: Banked) -> Self {
+        let set = self.banked.len();
+        for window in 0..banked.num_windows() {
+            let range = banked.window_range(window);
+            self = self.map(range, Region::Banked { set, window });
+        }
+        self.banked.push(banked);
+        self
+    }
+    /// The banks added by the `index`th call to `banked`, e.g. to switch
+    /// them from Rust.
+    pub fn banks_mut(&mut self, index: usize) -> Option<&mut Banked> {
+        self.banked.get_mut(index)
+    }
+    pub fn unmapped_reads(mut self, behaviour: UnmappedRead) -> Self {
+        self.unmapped_read = behaviour;
+        self
+    }
+    fn find(&self, address: Address, follow_mirrors: bool) -> Target {
+        for (index, mapped) in self.regions.iter().enumerate() {
+            if !mapped.range.contains(&address) {
+                continue;
+            }
+            let offset = address - mapped.range.start();
+            match &mapped.region {
+                Region::Ram => return Target::Ram(address),
+                Region::Rom(_) => return Target::Rom(index, address),
+                Region::Io { .. } => return Target::Io(index, offset),
+                &Region::Banked { set, window } => return Target::Banked(set, window, offset),
+                Region::Mirror { of } if follow_mirrors => {
+                    let len = (*of.end() - *of.start()) as u32 + 1;
+                    let mirrored = *of.start() as u32 + offset as u32 % len;
+                    return self.find(mirrored as Address, false);
+                }
+                Region::Mirror { .. } => (),
+            }
+        }
+        Target::Unmapped
+    }
+}
+
+impl Memory for MemoryMap {
+    fn read_u8(&mut self, address: Address) -> u8 {
+        let data = match self.find(address, true) {
+            Target::Ram(address) | Target::Rom(_, address) => self.bytes[address as usize],
+            Target::Io(index, offset) => match &mut self.regions[index].region {
+                Region::Io { read, .. } => read(offset),
+                _ => unreachable!(),
+            },
+            Target::Banked(set, window, offset) => self.banked[set].read(window, offset),
+            Target::Unmapped => match &mut self.unmapped_read {
+                UnmappedRead::Zero => 0,
+                UnmappedRead::Ones => 0xFF,
+                UnmappedRead::OpenBus => self.bus,
+                UnmappedRead::Handler(read) => read(address),
+            },
+        };
+        self.bus = data;
+        data
+    }
+    fn write_u8(&mut self, address: Address, data: u8) {
+        self.bus = data;
+        for banked in self.banked.iter_mut() {
+            if banked.write_register(address, data) {
+                return;
+            }
+        }
+        match self.find(address, true) {
+            Target::Ram(address) => self.bytes[address as usize] = data,
+            Target::Io(index, offset) => match &mut self.regions[index].region {
+                Region::Io { write, .. } => write(offset, data),
+                _ => unreachable!(),
+            },
+            Target::Rom(index, address) => match &mut self.regions[index].region {
+                Region::Rom(RomWrite::Ignore) => (),
+                Region::Rom(RomWrite::Hook(hook)) => hook(address, data),
+                Region::Rom(RomWrite::Fault) => {
+                    self.fault.get_or_insert(Fault::RomWrite { address, data });
+                }
+                _ => unreachable!(),
+            },
+            Target::Banked(set, window, offset) => self.banked[set].write(window, offset, data),
+            Target::Unmapped => (),
+        }
+    }
+    // Goes straight into the backing store of RAM and ROM, bypassing the
+    // map.
+    fn load(&mut self, address: Address, data: &[u8]) {
+        for (i, &byte) in data.iter().enumerate() {
+            self.bytes[address.wrapping_add(i as Address) as usize] = byte;
+        }
+    }
+    fn take_fault(&mut self) -> Option<Fault> {
+        self.fault.take()
+    }
+}
+
+// I/O ranges and unmapped reads with a handler read as 0, since calling
+// the handlers could have side effects.
+impl MemoryReadOnly for MemoryMap {
+    fn read_u8_read_only(&self, address: Address) -> u8 {
+        match self.find(address, true) {
+            Target::Ram(address) | Target::Rom(_, address) => self.bytes[address as usize],
+            Target::Unmapped => match self.unmapped_read {
+                UnmappedRead::Ones => 0xFF,
+                UnmappedRead::OpenBus => self.bus,
+                UnmappedRead::Zero | UnmappedRead::Handler(_) => 0,
+            },
+            Target::Banked(set, window, offset) => self.banked[set].read(window, offset),
+            Target::Io(..) => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::{Cpu, Machine, StepError};
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    #[test]
+    #[should_panic(expected = "mapped range starts after it ends")]
+    fn ram_with_inverted_range() {
+        #[allow(clippy::reversed_empty_ranges)]
+        let _ = MemoryMap::new().ram_with(0x0800..=0x07FF, Pattern::Ones);
+    }
+
+    #[test]
+    #[should_panic(expected = "mirrored range starts after it ends")]
+    fn mirror_of_inverted_range() {
+        #[allow(clippy::reversed_empty_ranges)]
+        let _ = MemoryMap::new().mirror(0x0800..=0x1FFF, 0x07FF..=0x0000);
+    }
+
+    #[test]
+    fn ram_with_fills_range() {
+        let mut memory = MemoryMap::new().ram_with(0xFF00..=0xFFFF, Pattern::Ones);
+        assert_eq!(memory.read_u8(0xFEFF), 0);
+        assert_eq!(memory.read_u8(0xFF00), 0xFF);
+        assert_eq!(memory.read_u8(0xFFFF), 0xFF);
+    }
+
+    // RAM at $0000-$7FFF, with the code, and a ROM at $8000.
+    fn storing_to_rom(on_write: RomWrite) -> Machine<MemoryMap> {
+        let memory =
+            MemoryMap::new()
+                .ram(0x0000..=0x7FFF)
+                .rom_with(0x8000, &[0x12, 0x34], on_write);
+        let mut machine = Machine::new(Cpu::new(), memory);
+        // LDA #$56; STA $8001
+        machine.memory.load(0x0200, &[0xA9, 0x56, 0x8D, 0x01, 0x80]);
+        machine.cpu.pc = 0x0200;
+        machine.step().unwrap();
+        machine
+    }
+
+    #[test]
+    fn rom_write_fault() {
+        let mut machine = storing_to_rom(RomWrite::Fault);
+        match machine.step() {
+            Err(StepError::Fault(Fault::RomWrite { address, data })) => {
+                assert_eq!((address, data), (0x8001, 0x56))
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(machine.memory.read_u8(0x8001), 0x34);
+    }
+
+    #[test]
+    fn rom_write_hook() {
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        let hook = {
+            let writes = writes.clone();
+            Box::new(move |address, data| writes.borrow_mut().push((address, data)))
+        };
+        let mut machine = storing_to_rom(RomWrite::Hook(hook));
+        assert_eq!(machine.step().unwrap(), 4);
+        assert_eq!(*writes.borrow(), [(0x8001, 0x56)]);
+        assert_eq!(machine.memory.read_u8(0x8001), 0x34);
+    }
+
+    #[test]
+    fn rom_write_ignore() {
+        let mut machine = storing_to_rom(RomWrite::Ignore);
+        assert_eq!(machine.step().unwrap(), 4);
+        assert_eq!(machine.memory.read_u8(0x8001), 0x34);
+    }
+
+    #[test]
+    fn unmapped_reads_zero() {
+        let mut memory = MemoryMap::new().unmapped_reads(UnmappedRead::Zero);
+        memory.write_u8(0x4000, 0x55);
+        assert_eq!(memory.read_u8(0x4000), 0);
+        assert_eq!(memory.read_u8_read_only(0x4000), 0);
+    }
+
+    #[test]
+    fn unmapped_reads_ones() {
+        let mut memory = MemoryMap::new().unmapped_reads(UnmappedRead::Ones);
+        assert_eq!(memory.read_u8(0x4000), 0xFF);
+        assert_eq!(memory.read_u8_read_only(0x4000), 0xFF);
+    }
+
+    #[test]
+    fn unmapped_reads_open_bus() {
+        let mut memory = MemoryMap::new()
+            .ram(0x0000..=0x00FF)
+            .unmapped_reads(UnmappedRead::OpenBus);
+        // The last byte written is left on the bus, even when it goes
+        // nowhere.
+        memory.write_u8(0x4000, 0x5A);
+        assert_eq!(memory.read_u8_read_only(0x4001), 0x5A);
+        assert_eq!(memory.read_u8(0x4001), 0x5A);
+        // As is the last byte read.
+        memory.write_u8(0x0010, 0xA5);
+        memory.write_u8(0x4000, 0x00);
+        assert_eq!(memory.read_u8(0x0010), 0xA5);
+        assert_eq!(memory.read_u8(0x4001), 0xA5);
+        assert_eq!(memory.read_u8_read_only(0x4001), 0xA5);
+    }
+
+    #[test]
+    fn unmapped_reads_handler() {
+        let mut memory =
+            MemoryMap::new().unmapped_reads(UnmappedRead::Handler(Box::new(|address| {
+                (address >> 8) as u8
+            })));
+        assert_eq!(memory.read_u8(0x4000), 0x40);
+        assert_eq!(memory.read_u8(0xC123), 0xC1);
+        // The handler could have side effects, so isn't called.
+        assert_eq!(memory.read_u8_read_only(0x4000), 0);
+    }
+
+    #[test]
+    fn mirrors_io_and_priority() {
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        let write = {
+            let writes = writes.clone();
+            move |offset, data| writes.borrow_mut().push((offset, data))
+        };
+        let mut memory = MemoryMap::new()
+            .ram(0x0000..=0x07FF)
+            .mirror(0x0800..=0x1FFF, 0x0000..=0x07FF)
+            .io(0x2000..=0x2007, |offset| 0x20 | offset as u8, write)
+            // Under the I/O registers, so only seen from $2008.
+            .ram(0x2000..=0x20FF);
+
+        // The 2KB of RAM repeats three more times.
+        memory.write_u8(0x0123, 0x11);
+        for address in [0x0923, 0x1123, 0x1923] {
+            assert_eq!(memory.read_u8(address), 0x11);
+        }
+        memory.write_u8(0x1FFF, 0x22);
+        assert_eq!(memory.read_u8(0x07FF), 0x22);
+
+        // The handlers are given the offset into the range.
+        assert_eq!(memory.read_u8(0x2005), 0x25);
+        memory.write_u8(0x2003, 0x33);
+        assert_eq!(*writes.borrow(), [(3, 0x33)]);
+
+        memory.write_u8(0x2008, 0x44);
+        assert_eq!(memory.read_u8(0x2008), 0x44);
+        assert_eq!(writes.borrow().len(), 1);
+    }
+}
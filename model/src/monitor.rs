@@ -0,0 +1,416 @@
+//! A classic machine-language monitor, driven a line at a time so any
+//! frontend can put its own I/O around it. Numbers are in hex, with or
+//! without a `$`.
+//!
+//! | Command | |
+//! |---|---|
+//! | `m [start [end]]` | Dump memory, carrying on from the last dump without a start |
+//! | `d [start [end]]` | Disassemble, likewise |
+//! | `a address instruction` | Assemble one instruction, e.g. `a 0200 LDA #$01` |
+//! | `g [address]` | Run until `BRK`, a halt or the fuel runs out |
+//! | `t [count]` | Step, tracing each instruction |
+//! | `r [register value]` | Show the registers, or set `pc`, `a`, `x`, `y`, `sp` or `p` |
+//! | `c address [comment]` | Set or clear the comment at an address |
+//! | `b address [name]` | Set or clear the bookmark at an address |
+//!
+//! Disassembly and traces show the monitor's `annotations`: bookmarks as
+//! labels, and comments after `;`.
+//!
+//! ```ignore
+//! let mut monitor = Monitor::new();
+//! for line in stdin.lines() {
+//!     match monitor.execute(&mut machine, &line?) {
+//!         Ok(output) => print!("{}", output),
+//!         Err(error) => println!("? {}", error),
+//!     }
+//! }
+//! ```
+use crate::annotation::Annotations;
+use crate::debug::{self, AddressingMode, InstructionType, TraceFormat};
+use crate::machine::{Fuel, Machine, Memory, MemoryReadOnly, StepError, Stopped};
+use crate::status::Register;
+use crate::{opcode, Address};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write as _};
+
+const DUMP_BYTES: usize = 0x80;
+const DISASSEMBLY_LINES: usize = 16;
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    UnknownCommand(String),
+    /// A bad or missing argument, described.
+    Syntax(String),
+    Step(StepError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownCommand(command) => write!(f, "unknown command {}", command),
+            Error::Syntax(message) => write!(f, "{}", message),
+            Error::Step(error) => write!(f, "{:?}", error),
+        }
+    }
+}
+
+fn syntax<T>(message: &str) -> Result<T, Error> {
+    Err(Error::Syntax(String::from(message)))
+}
+
+fn number(text: &str) -> Option<u32> {
+    let digits = text.strip_prefix('$').unwrap_or(text);
+    if digits.is_empty() || digits.len() > 4 {
+        return None;
+    }
+    u32::from_str_radix(digits, 16).ok()
+}
+
+fn address(text: Option<&str>) -> Result<Option<Address>, Error> {
+    match text {
+        None => Ok(None),
+        Some(text) => match number(text) {
+            Some(value) => Ok(Some(value as Address)),
+            None => syntax("bad address"),
+        },
+    }
+}
+
+// Splits an operand into its addressing mode and its value, with `wide`
+// set if the value was written with more than two digits, so it can't be
+// a zero page address. Modes an instruction may use instead, such as zero
+// page for absolute, are sorted out by `assemble`.
+fn operand(text: &str) -> Option<(AddressingMode, u32, bool)> {
+    use AddressingMode::*;
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let text = text.to_ascii_uppercase();
+    let value = |text: &str| {
+        let wide = text.trim_start_matches('$').len() > 2;
+        number(text).map(|value| (value, wide))
+    };
+    if text.is_empty() {
+        return Some((Implied, 0, false));
+    }
+    if text == "A" {
+        return Some((Accumulator, 0, false));
+    }
+    if let Some(immediate) = text.strip_prefix('#') {
+        let (value, wide) = value(immediate)?;
+        return (!wide).then_some((Immediate, value, false));
+    }
+    if let Some(inner) = text.strip_prefix('(') {
+        if let Some(pointer) = inner.strip_suffix(",X)") {
+            let (value, wide) = value(pointer)?;
+            return (!wide).then_some((XIndexedIndirect, value, false));
+        }
+        if let Some(pointer) = inner.strip_suffix("),Y") {
+            let (value, wide) = value(pointer)?;
+            return (!wide).then_some((IndirectYIndexed, value, false));
+        }
+        let (value, _) = value(inner.strip_suffix(')')?)?;
+        return Some((Indirect, value, true));
+    }
+    if let Some(base) = text.strip_suffix(",X") {
+        let (value, wide) = value(base)?;
+        return Some((AbsoluteXIndexed, value, wide));
+    }
+    if let Some(base) = text.strip_suffix(",Y") {
+        let (value, wide) = value(base)?;
+        return Some((AbsoluteYIndexed, value, wide));
+    }
+    let (value, wide) = value(&text)?;
+    Some((Absolute, value, wide))
+}
+
+// The opcode for `instruction_type` in `mode`, preferring documented ones.
+fn find_opcode(instruction_type: InstructionType, mode: AddressingMode) -> Option<u8> {
+    let candidates = (0..=u8::MAX).filter_map(|opcode| {
+        let info = opcode::info(opcode)?;
+        (info.instruction_type == instruction_type && info.addressing_mode == mode)
+            .then_some((opcode, info.documented))
+    });
+    let mut best = None;
+    for (opcode, documented) in candidates {
+        if documented {
+            return Some(opcode);
+        }
+        best = best.or(Some(opcode));
+    }
+    best
+}
+
+fn instruction_type(mnemonic: &str) -> Option<InstructionType> {
+    (0..=u8::MAX).find_map(|opcode| {
+        let instruction_type = opcode::info(opcode)?.instruction_type;
+        format!("{:?}", instruction_type)
+            .eq_ignore_ascii_case(mnemonic)
+            .then_some(instruction_type)
+    })
+}
+
+/// Assembles one instruction, such as `LDA #$01` or `BNE $0210`, to be
+/// placed at `address`, which branches are relative to.
+pub fn assemble(address: Address, text: &str) -> Result<Vec<u8>, Error> {
+    use AddressingMode::*;
+    let text = text.trim();
+    let (mnemonic, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let Some(instruction_type) = instruction_type(mnemonic) else {
+        return syntax("unknown mnemonic");
+    };
+    let Some((mode, value, wide)) = operand(rest) else {
+        return syntax("bad operand");
+    };
+    // Modes to try, in order, for what was written.
+    let modes: &[AddressingMode] = match mode {
+        Implied => &[Implied, Accumulator],
+        Absolute if wide => &[Relative, Absolute],
+        Absolute => &[Relative, ZeroPage, Absolute],
+        AbsoluteXIndexed if !wide => &[ZeroPageXIndexed, AbsoluteXIndexed],
+        AbsoluteYIndexed if !wide => &[ZeroPageYIndexed, AbsoluteYIndexed],
+        _ => core::slice::from_ref(&mode),
+    };
+    let Some((opcode, mode)) = modes
+        .iter()
+        .find_map(|&mode| find_opcode(instruction_type, mode).map(|opcode| (opcode, mode)))
+    else {
+        return syntax("addressing mode not available");
+    };
+    let mut bytes = alloc::vec![opcode];
+    match mode {
+        Implied | Accumulator => (),
+        Relative => {
+            let offset = value as i32 - address.wrapping_add(2) as i32;
+            let Ok(offset) = i8::try_from(offset) else {
+                return syntax("branch out of range");
+            };
+            bytes.push(offset as u8);
+        }
+        Absolute | AbsoluteXIndexed | AbsoluteYIndexed | Indirect => {
+            bytes.extend_from_slice(&(value as u16).to_le_bytes())
+        }
+        _ => bytes.push(value as u8),
+    }
+    Ok(bytes)
+}
+
+pub struct Monitor {
+    /// How long `g` runs for at most.
+    pub fuel: Fuel,
+    /// Notes shown alongside disassembly and traces, which can be loaded
+    /// from or saved to an earlier session.
+    pub annotations: Annotations,
+    next_dump: Address,
+    next_disassembly: Address,
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Self {
+            fuel: Fuel::instructions(10_000_000),
+            annotations: Annotations::new(),
+            next_dump: 0,
+            next_disassembly: 0,
+        }
+    }
+    /// Runs one command line, returning its output, which is empty or ends
+    /// with a newline.
+    pub fn execute<M: Memory + MemoryReadOnly>(
+        &mut self,
+        machine: &mut Machine<M>,
+        line: &str,
+    ) -> Result<String, Error> {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let mut arguments = rest.split_whitespace();
+        let mut out = String::new();
+        match command.to_ascii_lowercase().as_str() {
+            "" => (),
+            "m" => {
+                let start = address(arguments.next())?.unwrap_or(self.next_dump);
+                let end = address(arguments.next())?
+                    .unwrap_or(start.wrapping_add(DUMP_BYTES as Address - 1));
+                self.next_dump = self.dump(&mut out, &machine.memory, start, end);
+            }
+            "d" => {
+                let start = address(arguments.next())?.unwrap_or(self.next_disassembly);
+                let end = address(arguments.next())?;
+                self.next_disassembly = self.disassemble(&mut out, &machine.memory, start, end);
+            }
+            "a" => {
+                let Some(start) = address(arguments.next())? else {
+                    return syntax("missing address");
+                };
+                let text = rest
+                    .trim_start()
+                    .split_once(char::is_whitespace)
+                    .map_or("", |(_, text)| text);
+                let bytes = assemble(start, text)?;
+                for (i, &byte) in bytes.iter().enumerate() {
+                    machine
+                        .memory
+                        .write_u8(start.wrapping_add(i as Address), byte);
+                }
+                self.disassemble(&mut out, &machine.memory, start, Some(start));
+                self.next_disassembly = start.wrapping_add(bytes.len() as Address);
+            }
+            "g" => {
+                if let Some(start) = address(arguments.next())? {
+                    machine.cpu.pc = start;
+                }
+                let report = machine
+                    .run_until_with_fuel(self.fuel, |machine| {
+                        machine.memory.read_u8_read_only(machine.cpu.pc) == opcode::brk::IMPLIED
+                    })
+                    .map_err(Error::Step)?;
+                let reason = match report.stopped {
+                    Stopped::Condition => "BRK",
+                    Stopped::Halted => "halted",
+                    Stopped::OutOfFuel => "out of fuel",
+                    _ => "stopped",
+                };
+                let _ = writeln!(
+                    out,
+                    "{} after {} instructions, {} cycles",
+                    reason, report.instructions, report.cycles
+                );
+                let _ = writeln!(out, "{}", machine.cpu);
+            }
+            "t" => {
+                let count = arguments.next().map_or(Some(1), number);
+                let Some(count) = count else {
+                    return syntax("bad count");
+                };
+                let format = TraceFormat::default();
+                for _ in 0..count {
+                    let _ = format.write_annotated_line(
+                        &mut out,
+                        &machine.cpu,
+                        &machine.memory,
+                        machine.cycles(),
+                        &self.annotations,
+                    );
+                    out.push('\n');
+                    machine.run(Fuel::instructions(1)).map_err(Error::Step)?;
+                }
+                let _ = writeln!(out, "{}", machine.cpu);
+            }
+            "r" => {
+                if let Some(register) = arguments.next() {
+                    let Some(value) = arguments.next().and_then(number) else {
+                        return syntax("missing value");
+                    };
+                    let cpu = &mut machine.cpu;
+                    match register.to_ascii_lowercase().as_str() {
+                        "pc" => cpu.pc = value as Address,
+                        "a" => cpu.acc = value as u8,
+                        "x" => cpu.x = value as u8,
+                        "y" => cpu.y = value as u8,
+                        "sp" | "s" => cpu.sp = value as u8,
+                        "p" => cpu.status = Register::from(value as u8),
+                        _ => return syntax("unknown register"),
+                    }
+                }
+                let _ = writeln!(out, "{}", machine.cpu);
+            }
+            "c" | "b" => {
+                let Some(at) = address(arguments.next())? else {
+                    return syntax("missing address");
+                };
+                let text = rest
+                    .trim_start()
+                    .split_once(char::is_whitespace)
+                    .map_or("", |(_, text)| text.trim());
+                match (command.eq_ignore_ascii_case("c"), text) {
+                    (true, "") => self.annotations.clear_comment(at),
+                    (true, comment) => self.annotations.set_comment(at, comment),
+                    (false, "") => self.annotations.clear_bookmark(at),
+                    (false, name) => self.annotations.set_bookmark(at, name),
+                }
+            }
+            _ => return Err(Error::UnknownCommand(String::from(command))),
+        }
+        Ok(out)
+    }
+    // Writes 16 bytes a line from `start` to `end`, wrapping past $FFFF,
+    // returning the address after the last.
+    fn dump<M: MemoryReadOnly>(
+        &self,
+        out: &mut String,
+        memory: &M,
+        start: Address,
+        end: Address,
+    ) -> Address {
+        // Writing to a `String` can't fail.
+        if end < start {
+            let _ = debug::hexdump(out, memory, start..=0xFFFF);
+            let _ = debug::hexdump(out, memory, 0..=end);
+        } else {
+            let _ = debug::hexdump(out, memory, start..=end);
+        }
+        end.wrapping_add(1)
+    }
+    // Disassembles from `start` through the instruction at `end`, or a
+    // screenful without one, returning the address after the last.
+    fn disassemble<M: MemoryReadOnly>(
+        &self,
+        out: &mut String,
+        memory: &M,
+        start: Address,
+        end: Option<Address>,
+    ) -> Address {
+        let mut lines = Vec::new();
+        let mut cursor = start;
+        loop {
+            let window = debug::disassemble_window(memory, cursor, 0, 0);
+            let Some(line) = window.into_iter().next() else {
+                break;
+            };
+            let last = match end {
+                Some(end) => end.wrapping_sub(start) <= cursor.wrapping_sub(start),
+                None => lines.len() + 1 == DISASSEMBLY_LINES,
+            };
+            cursor = cursor.wrapping_add(line.size() as Address);
+            lines.push(line);
+            if last {
+                break;
+            }
+        }
+        let _ = debug::write_disassembly(out, &lines, Some(&self.annotations));
+        cursor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::{Cpu, Ram};
+
+    #[test]
+    fn disassembly_shows_annotations() {
+        let mut machine = Machine::new(Cpu::new(), Ram::new());
+        let mut monitor = Monitor::new();
+        monitor.execute(&mut machine, "a 0200 LDA #$01").unwrap();
+        monitor.execute(&mut machine, "b 0200 start").unwrap();
+        monitor.execute(&mut machine, "c 0200 load one").unwrap();
+        let out = monitor.execute(&mut machine, "d 0200 0200").unwrap();
+        let mut lines = out.lines();
+        assert_eq!(lines.next(), Some("start:"));
+        assert!(lines.next().unwrap().ends_with("  ; load one"));
+        monitor.execute(&mut machine, "r pc 0200").unwrap();
+        let out = monitor.execute(&mut machine, "t").unwrap();
+        assert!(out.lines().next().unwrap().ends_with("  ; load one"));
+        monitor.execute(&mut machine, "c 0200").unwrap();
+        monitor.execute(&mut machine, "B 0200").unwrap();
+        let out = monitor.execute(&mut machine, "d 0200 0200").unwrap();
+        assert_eq!(out.lines().count(), 1);
+        assert!(!out.contains(';'));
+    }
+}
@@ -1,3 +1,40 @@
+#[cfg(feature = "alloc")]
+use crate::debug::{AddressingMode, Instruction, InstructionType};
+
+/// What can be known about an opcode without running it.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Info {
+    pub instruction_type: InstructionType,
+    pub addressing_mode: AddressingMode,
+    /// Including the opcode.
+    pub size: usize,
+    /// Fewest and most cycles; see `Instruction::cycles`.
+    pub cycles: (u8, u8),
+    pub documented: bool,
+    /// A mask of the `status::flag` bits the instruction can change.
+    pub flags_modified: u8,
+}
+
+/// Looks up any NMOS 6502 opcode, or returns `None` for one this crate
+/// doesn't decode.
+#[cfg(feature = "alloc")]
+pub fn info(opcode: u8) -> Option<Info> {
+    let instruction = Instruction::from_opcode(opcode).ok()?;
+    let instruction_type = instruction.instruction_type();
+    let documented = instruction_type.is_documented()
+        && opcode != sbc::unofficial0::IMMEDIATE
+        && (instruction_type != InstructionType::Nop || opcode == nop::IMPLIED);
+    Some(Info {
+        instruction_type,
+        addressing_mode: instruction.addressing_mode(),
+        size: instruction.size(),
+        cycles: instruction.cycles(),
+        documented,
+        flags_modified: instruction_type.flags_modified(),
+    })
+}
+
 pub mod adc {
     pub const ABSOLUTE: u8 = 0x6D;
     pub const ABSOLUTE_X_INDEXED: u8 = 0x7D;
@@ -210,6 +247,44 @@ pub mod jmp {
 pub mod jsr {
     pub const ABSOLUTE: u8 = 0x20;
 }
+pub mod kil {
+    pub mod unofficial0 {
+        pub const IMPLIED: u8 = 0x02;
+    }
+    pub mod unofficial1 {
+        pub const IMPLIED: u8 = 0x12;
+    }
+    pub mod unofficial2 {
+        pub const IMPLIED: u8 = 0x22;
+    }
+    pub mod unofficial3 {
+        pub const IMPLIED: u8 = 0x32;
+    }
+    pub mod unofficial4 {
+        pub const IMPLIED: u8 = 0x42;
+    }
+    pub mod unofficial5 {
+        pub const IMPLIED: u8 = 0x52;
+    }
+    pub mod unofficial6 {
+        pub const IMPLIED: u8 = 0x62;
+    }
+    pub mod unofficial7 {
+        pub const IMPLIED: u8 = 0x72;
+    }
+    pub mod unofficial8 {
+        pub const IMPLIED: u8 = 0x92;
+    }
+    pub mod unofficial9 {
+        pub const IMPLIED: u8 = 0xB2;
+    }
+    pub mod unofficial10 {
+        pub const IMPLIED: u8 = 0xD2;
+    }
+    pub mod unofficial11 {
+        pub const IMPLIED: u8 = 0xF2;
+    }
+}
 pub mod lax {
     pub mod unofficial0 {
         pub const ABSOLUTE: u8 = 0xAF;
@@ -0,0 +1,100 @@
+use crate::machine::{Fault, Memory};
+use crate::Address;
+use alloc::{boxed::Box, vec::Vec};
+use core::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy)]
+pub struct InvalidState;
+
+/// A memory-mapped device attached to a `Machine`. Reads and writes are
+/// given the offset from the start of the range the device is mapped at.
+pub trait Peripheral {
+    fn read(&mut self, offset: Address) -> u8;
+    fn write(&mut self, offset: Address, data: u8);
+    /// Called after every instruction with the number of cycles it took.
+    fn tick(&mut self, _cycles: u8) {}
+    /// Whether the device is asserting the IRQ line, checked after every
+    /// tick.
+    fn irq(&self) -> bool {
+        false
+    }
+    /// Cycles the device wants the CPU held off the bus for, e.g. while it
+    /// does DMA. Polled after every tick, and cleared by polling.
+    fn take_stall(&mut self) -> u32 {
+        0
+    }
+    /// Serializes all of the device's internal state, for inclusion in
+    /// machine snapshots.
+    fn save_state(&self) -> Vec<u8>;
+    /// Restores state previously produced by `save_state`.
+    fn load_state(&mut self, state: &[u8]) -> Result<(), InvalidState>;
+}
+
+pub(crate) struct Mapped {
+    pub(crate) range: RangeInclusive<Address>,
+    pub(crate) peripheral: Box<dyn Peripheral>,
+}
+
+/// Memory with peripherals overlaid on top of it.
+pub(crate) struct Bus<'a, M> {
+    pub(crate) memory: &'a mut M,
+    pub(crate) peripherals: &'a mut [Mapped],
+}
+
+impl<M> Bus<'_, M> {
+    fn overlaps_peripheral(&self, address: Address, len: usize) -> bool {
+        let start = address as usize;
+        let end = start + len;
+        // Ranges that wrap past $FFFF are rare enough to take the slow path.
+        end > 0x10000
+            || self.peripherals.iter().any(|mapped| {
+                (*mapped.range.start() as usize) < end && start <= *mapped.range.end() as usize
+            })
+    }
+    fn find(&mut self, address: Address) -> Option<(&mut Box<dyn Peripheral>, Address)> {
+        self.peripherals
+            .iter_mut()
+            .find(|mapped| mapped.range.contains(&address))
+            .map(|mapped| {
+                let offset = address - mapped.range.start();
+                (&mut mapped.peripheral, offset)
+            })
+    }
+}
+
+impl<M: Memory> Memory for Bus<'_, M> {
+    fn read_u8(&mut self, address: Address) -> u8 {
+        match self.find(address) {
+            Some((peripheral, offset)) => peripheral.read(offset),
+            None => self.memory.read_u8(address),
+        }
+    }
+    fn write_u8(&mut self, address: Address, data: u8) {
+        match self.find(address) {
+            Some((peripheral, offset)) => peripheral.write(offset, data),
+            None => self.memory.write_u8(address, data),
+        }
+    }
+    fn read_block(&mut self, address: Address, buffer: &mut [u8]) {
+        if !self.overlaps_peripheral(address, buffer.len()) {
+            return self.memory.read_block(address, buffer);
+        }
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.read_u8(address.wrapping_add(i as Address));
+        }
+    }
+    fn write_block(&mut self, address: Address, data: &[u8]) {
+        if !self.overlaps_peripheral(address, data.len()) {
+            return self.memory.write_block(address, data);
+        }
+        for (i, &byte) in data.iter().enumerate() {
+            self.write_u8(address.wrapping_add(i as Address), byte);
+        }
+    }
+    fn take_fault(&mut self) -> Option<Fault> {
+        self.memory.take_fault()
+    }
+    fn set_mapping_register(&mut self, index: u8, bank: u8) {
+        self.memory.set_mapping_register(index, bank);
+    }
+}
@@ -0,0 +1,147 @@
+//! A pin-level view of the bus, one clock at a time. The NMOS core makes
+//! one access per cycle in the order the chip does, dummy reads included.
+//! The HuC6280 and 65C816 opcodes aren't modelled a cycle at a time, so
+//! clocks they don't touch memory on are filled in at the end with reads
+//! of the next program counter.
+use crate::machine::{Cpu, Memory, Variant};
+use crate::{Address, UnknownOpcode};
+
+/// The state of the bus pins during one clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pins {
+    pub address: Address,
+    /// The byte being written, or 0 on a read.
+    pub data: u8,
+    /// R/W: high for a read, low for a write.
+    pub read: bool,
+    /// Set during opcode fetches.
+    pub sync: bool,
+}
+
+pub trait PinBus {
+    /// Called once per clock. On a read, the byte returned is what is
+    /// driven onto the data pins, and on a write it's ignored.
+    fn clock(&mut self, pins: Pins) -> u8;
+}
+
+struct Clocked<'a, B> {
+    bus: &'a mut B,
+    // Whether the next read is the opcode fetch.
+    sync: bool,
+    clocks: u8,
+}
+
+impl<B: PinBus> Memory for Clocked<'_, B> {
+    fn read_u8(&mut self, address: Address) -> u8 {
+        self.clocks = self.clocks.saturating_add(1);
+        let sync = core::mem::take(&mut self.sync);
+        self.bus.clock(Pins {
+            address,
+            data: 0,
+            read: true,
+            sync,
+        })
+    }
+    fn write_u8(&mut self, address: Address, data: u8) {
+        self.clocks = self.clocks.saturating_add(1);
+        self.bus.clock(Pins {
+            address,
+            data,
+            read: false,
+            sync: false,
+        });
+    }
+}
+
+impl Cpu {
+    /// Runs one instruction against `bus`, clocking it once per cycle the
+    /// instruction takes, and returns the cycle count.
+    pub fn step_pins<B: PinBus>(&mut self, bus: &mut B) -> Result<u8, UnknownOpcode> {
+        let mut clocked = Clocked {
+            bus,
+            sync: true,
+            clocks: 0,
+        };
+        let cycles = self.step(&mut clocked)?;
+        while matches!(self.variant, Variant::HuC6280 | Variant::W65C816) && clocked.clocks < cycles
+        {
+            clocked.read_u8(self.pc);
+        }
+        Ok(cycles)
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::machine::Ram;
+    use alloc::vec::Vec;
+
+    struct Recorder {
+        ram: Ram,
+        clocks: Vec<Pins>,
+    }
+
+    impl PinBus for Recorder {
+        fn clock(&mut self, pins: Pins) -> u8 {
+            self.clocks.push(pins);
+            if pins.read {
+                self.ram.read_u8(pins.address)
+            } else {
+                self.ram.write_u8(pins.address, pins.data);
+                0
+            }
+        }
+    }
+
+    // Runs `opcode` at $0200 with `operand` after it, returning the cycles
+    // taken and the clocks made, or `None` if it doesn't decode.
+    fn run(opcode: u8, operand: [u8; 2], index: u8, status: u8) -> Option<(u8, Vec<Pins>)> {
+        let mut ram = Ram::new();
+        let bytes = ram.as_mut_slice();
+        bytes[0x0200..0x0203].copy_from_slice(&[opcode, operand[0], operand[1]]);
+        // Pointers for both zero page operands, each crossing a page when
+        // indexed by $FF.
+        bytes[0x10..0x12].copy_from_slice(&[0x40, 0x13]);
+        bytes[0x80..0x82].copy_from_slice(&[0xF0, 0x12]);
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x0200;
+        cpu.sp = 0xFD;
+        cpu.x = index;
+        cpu.y = index;
+        cpu.status.set(status);
+        let mut recorder = Recorder {
+            ram,
+            clocks: Vec::new(),
+        };
+        let cycles = cpu.step_pins(&mut recorder).ok()?;
+        Some((cycles, recorder.clocks))
+    }
+
+    #[test]
+    fn one_clock_per_cycle() {
+        let mut decoded = 0;
+        for opcode in 0..=0xFF {
+            for operand in [[0x10, 0x12], [0x80, 0x12]] {
+                for index in [0x01, 0xFF] {
+                    for status in [0x00, 0xFF] {
+                        let Some((cycles, clocks)) = run(opcode, operand, index, status) else {
+                            continue;
+                        };
+                        decoded += 1;
+                        assert_eq!(
+                            clocks.len(),
+                            cycles as usize,
+                            "opcode {opcode:02X} operand {operand:02X?} index {index:02X} status {status:02X}"
+                        );
+                        let (fewest, most) = crate::opcode::info(opcode).unwrap().cycles;
+                        assert!((fewest..=most).contains(&cycles), "opcode {opcode:02X}");
+                        assert!(clocks[0].sync && clocks[0].address == 0x0200);
+                        assert!(clocks[1..].iter().all(|pins| !pins.sync));
+                    }
+                }
+            }
+        }
+        assert!(decoded > 0);
+    }
+}
@@ -0,0 +1,75 @@
+//! What RAM holds at power-on. Real RAM comes up in a pattern which
+//! depends on the chips, and programs which read memory before writing it
+//! can work on one machine and not another; running them from each of
+//! several patterns shows up such reads.
+//!
+//! ```ignore
+//! let memory = MemoryMap::new()
+//!     .ram_with(0x0000..=0x7FFF, Pattern::C64)
+//!     .ram_with(0xC000..=0xCFFF, Pattern::Random(seed));
+//! ```
+use crate::machine::Memory;
+use crate::Address;
+use core::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Pattern {
+    #[default]
+    Zero,
+    Ones,
+    /// `run` bytes of `first` then `run` of `second`, repeating from
+    /// address 0.
+    Alternating {
+        first: u8,
+        second: u8,
+        run: u16,
+    },
+    /// Pseudo-random bytes from a seed. The byte at each address depends
+    /// only on the seed and the address, so any region can be filled alone
+    /// and get the same bytes.
+    Random(u64),
+}
+
+impl Pattern {
+    /// As many C64s power on: 64 bytes of $00, then 64 of $FF.
+    pub const C64: Self = Self::Alternating {
+        first: 0x00,
+        second: 0xFF,
+        run: 64,
+    };
+
+    pub fn byte(&self, address: Address) -> u8 {
+        match *self {
+            Self::Zero => 0x00,
+            Self::Ones => 0xFF,
+            Self::Alternating { first, second, run } => {
+                if (address / run.max(1)).is_multiple_of(2) {
+                    first
+                } else {
+                    second
+                }
+            }
+            // SplitMix64's finaliser over the seed and address.
+            Self::Random(seed) => {
+                let mut z = seed ^ (address as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+                z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                (z ^ (z >> 31)) as u8
+            }
+        }
+    }
+    /// Fills `bytes` as if they were at `start` onwards, wrapping at the
+    /// top of the address space.
+    pub fn fill(&self, start: Address, bytes: &mut [u8]) {
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.byte(start.wrapping_add(i as Address));
+        }
+    }
+    /// Loads the pattern over `range` of any memory, as `Memory::load`
+    /// would, so ROM is written too if it's covered.
+    pub fn apply<M: Memory>(&self, memory: &mut M, range: RangeInclusive<Address>) {
+        for address in range {
+            memory.load(address, &[self.byte(address)]);
+        }
+    }
+}
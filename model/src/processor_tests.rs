@@ -0,0 +1,184 @@
+//! Runs the single-instruction tests from the ProcessorTests project
+//! (<https://github.com/SingleStepTests/ProcessorTests>). The types
+//! deserialize from its JSON with any serde format crate, such as
+//! `serde_json`, so a file of tests is a `Vec<TestCase>`.
+//!
+//! ```ignore
+//! let tests: Vec<TestCase> = serde_json::from_str(&json)?;
+//! for test in &tests {
+//!     let differences = test.run(Variant::Nmos6502);
+//!     assert!(differences.is_empty(), "{}: {:?}", test.name, differences);
+//! }
+//! ```
+use crate::machine::{Cpu, Memory, Ram, Variant};
+use crate::pins::{PinBus, Pins};
+use crate::Address;
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+// B and the unused bit aren't stored in the status register, so they're
+// left out when comparing P.
+const STATUS_MASK: u8 = 0xCF;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct State {
+    pub pc: Address,
+    pub s: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    /// Address and value pairs; memory not listed is left as zero.
+    pub ram: Vec<(Address, u8)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// One bus cycle: address, data and direction.
+pub type Cycle = (Address, u8, Access);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TestCase {
+    pub name: String,
+    pub initial: State,
+    #[serde(rename = "final")]
+    pub final_state: State,
+    pub cycles: Vec<Cycle>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    Register {
+        name: &'static str,
+        expected: u16,
+        actual: u16,
+    },
+    Memory {
+        address: Address,
+        expected: u8,
+        actual: u8,
+    },
+    /// The instruction took a different number of cycles.
+    CycleCount {
+        expected: usize,
+        actual: usize,
+    },
+    /// The first bus cycle to differ. Until the core's accesses are exact
+    /// (see `pins`) this is expected for many instructions, so callers
+    /// checking only the outcome can filter it out.
+    BusCycle {
+        index: usize,
+        expected: Option<Cycle>,
+        actual: Option<Cycle>,
+    },
+    UnknownOpcode(u8),
+}
+
+struct Recorder {
+    ram: Ram,
+    cycles: Vec<Cycle>,
+}
+
+impl PinBus for Recorder {
+    fn clock(&mut self, pins: Pins) -> u8 {
+        let data = if pins.read {
+            self.ram.read_u8(pins.address)
+        } else {
+            self.ram.write_u8(pins.address, pins.data);
+            pins.data
+        };
+        let access = if pins.read {
+            Access::Read
+        } else {
+            Access::Write
+        };
+        self.cycles.push((pins.address, data, access));
+        data
+    }
+}
+
+impl TestCase {
+    /// Applies the initial state, runs one instruction on a CPU of
+    /// `variant` and returns everything that differs from the final state.
+    pub fn run(&self, variant: Variant) -> Vec<Difference> {
+        let initial = &self.initial;
+        let mut cpu = Cpu::new();
+        cpu.variant = variant;
+        cpu.pc = initial.pc;
+        cpu.sp = initial.s;
+        cpu.acc = initial.a;
+        cpu.x = initial.x;
+        cpu.y = initial.y;
+        cpu.status.set(initial.p);
+        let mut bus = Recorder {
+            ram: Ram::new(),
+            cycles: Vec::new(),
+        };
+        for &(address, data) in &initial.ram {
+            bus.ram.write_u8(address, data);
+        }
+        let mut differences = Vec::new();
+        let cycles = match cpu.step_pins(&mut bus) {
+            Ok(cycles) => cycles as usize,
+            Err(error) => {
+                differences.push(Difference::UnknownOpcode(error.0));
+                return differences;
+            }
+        };
+        let expected = &self.final_state;
+        let registers = [
+            ("pc", expected.pc, cpu.pc),
+            ("s", expected.s as u16, cpu.sp as u16),
+            ("a", expected.a as u16, cpu.acc as u16),
+            ("x", expected.x as u16, cpu.x as u16),
+            ("y", expected.y as u16, cpu.y as u16),
+            (
+                "p",
+                (expected.p & STATUS_MASK) as u16,
+                (u8::from(cpu.status) & STATUS_MASK) as u16,
+            ),
+        ];
+        for (name, expected, actual) in registers {
+            if expected != actual {
+                differences.push(Difference::Register {
+                    name,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        for &(address, expected) in &expected.ram {
+            let actual = bus.ram.read_u8(address);
+            if expected != actual {
+                differences.push(Difference::Memory {
+                    address,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        if cycles != self.cycles.len() {
+            differences.push(Difference::CycleCount {
+                expected: self.cycles.len(),
+                actual: cycles,
+            });
+        }
+        let count = self.cycles.len().max(bus.cycles.len());
+        if let Some(index) =
+            (0..count).find(|&index| self.cycles.get(index) != bus.cycles.get(index))
+        {
+            differences.push(Difference::BusCycle {
+                index,
+                expected: self.cycles.get(index).copied(),
+                actual: bus.cycles.get(index).copied(),
+            });
+        }
+        differences
+    }
+}
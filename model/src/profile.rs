@@ -0,0 +1,176 @@
+//! Attributes the cycles a `Machine` runs to the addresses of the
+//! instructions that took them, and to routines, once enabled with
+//! `Machine::enable_profiling`.
+//!
+//! Routines are found with a call stack kept alongside the real one: `JSR`,
+//! `BRK` and interrupts push their target, and `RTS` and `RTI` pop it.
+//! Code which leaves a routine some other way, for example by adjusting the
+//! stack and jumping, confuses it, so the routine figures are a guide
+//! rather than exact. Cycles run with nothing on the stack belong to no
+//! routine.
+//!
+//! Names come from the bookmarks of an `Annotations`, such as the labels of
+//! an assembled program:
+//!
+//! ```ignore
+//! machine.enable_profiling();
+//! machine.run(Fuel::cycles(10_000_000))?;
+//! let profile = machine.take_profile().unwrap();
+//! for (name, routine) in profile.routines_named(&assembled.annotations()).iter().take(10) {
+//!     println!("{:20} {:10} {:10}", name, routine.total_cycles, routine.self_cycles);
+//! }
+//! ```
+use crate::annotation::Annotations;
+use crate::{opcode, Address};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RoutineCounts {
+    pub calls: u64,
+    /// Cycles spent in the routine itself.
+    pub self_cycles: u64,
+    /// Cycles spent in the routine and everything it called. A recursive
+    /// routine's cycles are only counted once.
+    pub total_cycles: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    addresses: BTreeMap<Address, Counts>,
+    routines: BTreeMap<Address, RoutineCounts>,
+    stack: Vec<Address>,
+    total: Counts,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    fn charge(&mut self, cycles: u64) {
+        self.total.cycles += cycles;
+        if let Some(&current) = self.stack.last() {
+            self.routines.entry(current).or_default().self_cycles += cycles;
+        }
+        for (depth, &routine) in self.stack.iter().enumerate() {
+            if !self.stack[..depth].contains(&routine) {
+                self.routines.entry(routine).or_default().total_cycles += cycles;
+            }
+        }
+    }
+    fn call(&mut self, routine: Address) {
+        self.routines.entry(routine).or_default().calls += 1;
+        self.stack.push(routine);
+    }
+    pub(crate) fn record_instruction(
+        &mut self,
+        pc: Address,
+        opcode: u8,
+        cycles: u8,
+        next: Address,
+    ) {
+        let counts = self.addresses.entry(pc).or_default();
+        counts.instructions += 1;
+        counts.cycles += cycles as u64;
+        self.total.instructions += 1;
+        self.charge(cycles as u64);
+        match opcode {
+            opcode::jsr::ABSOLUTE | opcode::brk::IMPLIED => self.call(next),
+            opcode::rts::IMPLIED | opcode::rti::IMPLIED => {
+                self.stack.pop();
+            }
+            _ => (),
+        }
+    }
+    pub(crate) fn record_interrupt(&mut self, handler: Address, cycles: u8) {
+        self.call(handler);
+        self.charge(cycles as u64);
+    }
+    /// Instructions and cycles run in all.
+    pub fn total(&self) -> Counts {
+        self.total
+    }
+    /// Every address an instruction ran at, most cycles first.
+    pub fn addresses(&self) -> Vec<(Address, Counts)> {
+        let mut addresses = self
+            .addresses
+            .iter()
+            .map(|(&address, &counts)| (address, counts))
+            .collect::<Vec<_>>();
+        addresses.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(&b.0)));
+        addresses
+    }
+    /// Every routine called, by entry address, most total cycles first.
+    pub fn routines(&self) -> Vec<(Address, RoutineCounts)> {
+        let mut routines = self
+            .routines
+            .iter()
+            .map(|(&address, &counts)| (address, counts))
+            .collect::<Vec<_>>();
+        routines.sort_by(|a, b| b.1.total_cycles.cmp(&a.1.total_cycles).then(a.0.cmp(&b.0)));
+        routines
+    }
+    /// `routines`, named as by `name`.
+    pub fn routines_named(&self, symbols: &Annotations) -> Vec<(String, RoutineCounts)> {
+        self.routines()
+            .into_iter()
+            .map(|(address, counts)| (name(symbols, address), counts))
+            .collect()
+    }
+    /// Cycles grouped by the nearest bookmark at or before each address,
+    /// most first. Addresses before any bookmark are grouped under `$0000`.
+    pub fn by_label(&self, symbols: &Annotations) -> Vec<(String, Counts)> {
+        let mut labels = BTreeMap::<String, Counts>::new();
+        for (&address, counts) in &self.addresses {
+            let label = symbols
+                .bookmarks()
+                .take_while(|&(bookmark, _)| bookmark <= address)
+                .last()
+                .map_or_else(|| String::from("$0000"), |(_, name)| String::from(name));
+            let total = labels.entry(label).or_default();
+            total.instructions += counts.instructions;
+            total.cycles += counts.cycles;
+        }
+        let mut labels = labels.into_iter().collect::<Vec<_>>();
+        labels.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then_with(|| a.0.cmp(&b.0)));
+        labels
+    }
+    /// Adds the counts from `other`, e.g. from another run.
+    pub fn merge(&mut self, other: &Profile) {
+        for (&address, counts) in &other.addresses {
+            let ours = self.addresses.entry(address).or_default();
+            ours.instructions += counts.instructions;
+            ours.cycles += counts.cycles;
+        }
+        for (&address, counts) in &other.routines {
+            let ours = self.routines.entry(address).or_default();
+            ours.calls += counts.calls;
+            ours.self_cycles += counts.self_cycles;
+            ours.total_cycles += counts.total_cycles;
+        }
+        self.total.instructions += other.total.instructions;
+        self.total.cycles += other.total.cycles;
+    }
+}
+
+/// The bookmark at `address`, or the nearest one before it with the
+/// offset, as `label+3`, or else the address in hex.
+pub fn name(symbols: &Annotations, address: Address) -> String {
+    match symbols
+        .bookmarks()
+        .take_while(|&(bookmark, _)| bookmark <= address)
+        .last()
+    {
+        Some((bookmark, name)) if bookmark == address => String::from(name),
+        Some((bookmark, name)) => format!("{}+{}", name, address - bookmark),
+        None => format!("${:04X}", address),
+    }
+}
@@ -0,0 +1,217 @@
+//! A static recompiler from a 6502 image to Rust source. Code reachable
+//! from the entry points is found with `control_flow::Analysis`, and each
+//! basic block becomes an arm of a `match` on the program counter which
+//! runs its instructions in turn, so nothing is fetched or decoded at run
+//! time. The common documented instructions become calls into
+//! `specialised` with their operand and the address of the next
+//! instruction written in; the rest run their dispatch table entry, with
+//! the fetch served from their bytes. Anywhere else, such as after an
+//! indirect jump or a return into code that wasn't found, the generated
+//! function falls back to `Cpu::step`.
+//!
+//! Code found needn't be mapped when the generated function runs, though
+//! data the program reads from the image, and any code it runs which
+//! wasn't found, must be. It assumes the image doesn't change. Blocks run
+//! with the NMOS 6502's instruction set, whatever `Cpu::variant` says.
+//!
+//! ```ignore
+//! let source = Recompiler::new(0xC000, &rom)
+//!     .entry_point(0xC000)
+//!     .function_name("run_rom")
+//!     .emit();
+//! std::fs::write(out_dir.join("rom.rs"), source)?;
+//! ```
+use crate::control_flow::Analysis;
+use crate::debug::{AddressingMode, InstructionType, InstructionWithOperand};
+use crate::Address;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write as _;
+
+pub struct Recompiler<'a> {
+    origin: Address,
+    image: &'a [u8],
+    entry_points: Vec<Address>,
+    function_name: String,
+    crate_path: String,
+}
+
+impl<'a> Recompiler<'a> {
+    /// Recompiles `image`, which is mapped at `origin`.
+    pub fn new(origin: Address, image: &'a [u8]) -> Self {
+        Self {
+            origin,
+            image,
+            entry_points: Vec::new(),
+            function_name: String::from("run"),
+            crate_path: String::from("portal_solutions_mos6502_model"),
+        }
+    }
+    /// Adds an address code is known to start at, such as the reset vector
+    /// or an interrupt handler.
+    pub fn entry_point(mut self, address: Address) -> Self {
+        self.entry_points.push(address);
+        self
+    }
+    /// The generated function's name, `run` by default.
+    pub fn function_name(mut self, name: &str) -> Self {
+        self.function_name = String::from(name);
+        self
+    }
+    /// The path this crate is used by in the generated code, for when it's
+    /// renamed or re-exported.
+    pub fn crate_path(mut self, path: &str) -> Self {
+        self.crate_path = String::from(path);
+        self
+    }
+    /// Returns the source of a function which runs the machine until at
+    /// least the given number of cycles have passed:
+    ///
+    /// ```ignore
+    /// pub fn run<M: Memory>(cpu: &mut Cpu, memory: &mut M, cycles: u64) -> Result<u64, UnknownOpcode>
+    /// ```
+    pub fn emit(&self) -> String {
+        let analysis = Analysis::new(self.origin, self.image, &self.entry_points);
+        let mut source = String::new();
+        // Writing to a `String` can't fail.
+        let _ = self.write(&mut source, &analysis);
+        source
+    }
+    fn write(&self, out: &mut String, analysis: &Analysis) -> core::fmt::Result {
+        let path = &self.crate_path;
+        writeln!(
+            out,
+            "// Recompiled from {} bytes at ${:04X}.",
+            self.image.len(),
+            self.origin
+        )?;
+        writeln!(out, "use {}::machine::{{Cpu, Memory}};", path)?;
+        writeln!(out, "use {}::specialised::*;", path)?;
+        writeln!(out, "use {}::UnknownOpcode;", path)?;
+        writeln!(out)?;
+        writeln!(
+            out,
+            "pub fn {}<M: Memory>(cpu: &mut Cpu, memory: &mut M, cycles: u64) -> Result<u64, UnknownOpcode> {{",
+            self.function_name
+        )?;
+        writeln!(out, "    let mut elapsed = 0;")?;
+        writeln!(out, "    while elapsed < cycles {{")?;
+        writeln!(out, "        elapsed += match cpu.pc {{")?;
+        for block in analysis.blocks() {
+            writeln!(out, "            0x{:04X} => {{", block.start)?;
+            writeln!(out, "                let mut cycles = 0;")?;
+            for instruction in &block.instructions {
+                let mut comment = String::new();
+                write!(comment, "{}", instruction)?;
+                let call = specialise(instruction).unwrap_or_else(|| {
+                    let bytes = instruction.encode();
+                    let bytes = bytes.iter().map(|byte| format!("0x{:02X}", byte));
+                    format!(
+                        "interpret(cpu, memory, &[{}])?",
+                        bytes.collect::<Vec<_>>().join(", ")
+                    )
+                });
+                writeln!(
+                    out,
+                    "                cycles += {} as u64; // {}",
+                    call,
+                    comment.trim_end()
+                )?;
+            }
+            writeln!(out, "                cycles")?;
+            writeln!(out, "            }}")?;
+        }
+        writeln!(out, "            _ => cpu.step(memory)? as u64,")?;
+        writeln!(out, "        }};")?;
+        writeln!(out, "    }}")?;
+        writeln!(out, "    Ok(elapsed)")?;
+        writeln!(out, "}}")
+    }
+}
+
+// The addressing mode type for an instruction's operand, if it has one.
+fn mode(instruction: &InstructionWithOperand) -> Option<String> {
+    let byte = instruction.operand().first().copied().unwrap_or(0);
+    let word = instruction.operand_u16_le().unwrap_or(0);
+    let mode = match instruction.instruction().addressing_mode() {
+        AddressingMode::Immediate => format!("Immediate(0x{:02X})", byte),
+        AddressingMode::ZeroPage => format!("ZeroPage(0x{:02X})", byte),
+        AddressingMode::ZeroPageXIndexed => format!("ZeroPageIndexed(0x{:02X}, X)", byte),
+        AddressingMode::ZeroPageYIndexed => format!("ZeroPageIndexed(0x{:02X}, Y)", byte),
+        AddressingMode::Absolute => format!("Absolute(0x{:04X})", word),
+        AddressingMode::AbsoluteXIndexed => format!("AbsoluteIndexed(0x{:04X}, X)", word),
+        AddressingMode::AbsoluteYIndexed => format!("AbsoluteIndexed(0x{:04X}, Y)", word),
+        AddressingMode::XIndexedIndirect => format!("XIndexedIndirect(0x{:02X})", byte),
+        AddressingMode::IndirectYIndexed => format!("IndirectYIndexed(0x{:02X})", byte),
+        _ => return None,
+    };
+    Some(mode)
+}
+
+// A call running the instruction through `specialised`, as
+// `threaded::specialise` builds, if it has one.
+fn specialise(instruction: &InstructionWithOperand) -> Option<String> {
+    use InstructionType::*;
+    let addressing_mode = instruction.instruction().addressing_mode();
+    let next = instruction
+        .address()
+        .wrapping_add(instruction.instruction().size() as Address);
+    let cycles = instruction.instruction().cycles().0;
+    let name = format!("{:?}", instruction.instruction().instruction_type()).to_lowercase();
+    let call = |shape: &str, argument: &str| {
+        Some(format!(
+            "{}(cpu, memory, {}, 0x{:04X}, {}, {})",
+            shape,
+            mode(instruction)?,
+            next,
+            cycles,
+            argument
+        ))
+    };
+    let implied = |apply: &str| {
+        format!(
+            "implied(cpu, memory, 0x{:04X}, {}, {})",
+            next, cycles, apply
+        )
+    };
+    let branch = |taken: &str| {
+        let offset = instruction.operand().first().copied().unwrap_or(0) as i8;
+        format!("branch(cpu, memory, 0x{:04X}, {}, {})", next, offset, taken)
+    };
+    match instruction.instruction().instruction_type() {
+        Lda | Ldx | Ldy | And | Ora | Eor | Adc | Sbc | Cmp | Cpx | Cpy | Bit => {
+            call("read", &name)
+        }
+        Sta => call("write", "cpu.acc"),
+        Stx => call("write", "cpu.x"),
+        Sty => call("write", "cpu.y"),
+        Asl | Lsr | Rol | Ror | Inc | Dec => match addressing_mode {
+            AddressingMode::Accumulator => {
+                Some(implied(&format!("|cpu| cpu.acc = {}(cpu, cpu.acc)", name)))
+            }
+            AddressingMode::ZeroPage
+            | AddressingMode::ZeroPageXIndexed
+            | AddressingMode::Absolute
+            | AddressingMode::AbsoluteXIndexed => call("modify", &name),
+            _ => None,
+        },
+        Inx | Iny | Dex | Dey | Tax | Tay | Txa | Tya | Tsx | Txs | Clc | Sec | Cld | Sed | Clv => {
+            Some(implied(&name))
+        }
+        Nop if addressing_mode == AddressingMode::Implied => Some(implied(&name)),
+        Bcc => Some(branch("!cpu.status.carry()")),
+        Bcs => Some(branch("cpu.status.carry()")),
+        Bne => Some(branch("!cpu.status.zero()")),
+        Beq => Some(branch("cpu.status.zero()")),
+        Bpl => Some(branch("!cpu.status.negative()")),
+        Bmi => Some(branch("cpu.status.negative()")),
+        Bvc => Some(branch("!cpu.status.overflow()")),
+        Bvs => Some(branch("cpu.status.overflow()")),
+        Jmp if addressing_mode == AddressingMode::Absolute => Some(format!(
+            "jump(cpu, 0x{:04X})",
+            instruction.operand_u16_le()?
+        )),
+        _ => None,
+    }
+}
@@ -0,0 +1,180 @@
+//! Recording of everything nondeterministic fed into a `Machine`, so a run
+//! can be reproduced exactly. Once `Machine::record_inputs` is called, the
+//! values read from I/O (every peripheral already added, and any ranges
+//! given, such as `MemoryMap::io` handlers) are logged with the cycle the
+//! instruction reading them started at, along with each call to
+//! `set_irq`, `request_nmi`, `request_nmi_at`, `stall` and `reset`.
+//!
+//! `Machine::replay` plays a recording back on a machine started from the
+//! same state: I/O is still read, so devices see the same accesses, but the
+//! CPU gets the recorded values, and the recorded calls are made at the
+//! same points while calls from the host are ignored. If the CPU reads I/O
+//! other than as recorded, replay stops and `Status::Diverged` says where.
+//! Once the recording is used up, the machine runs on as normal.
+//!
+//! ```ignore
+//! machine.record_inputs(&[0x4016..=0x4017]);
+//! frontend.run(&mut machine);
+//! let recording = machine.take_recording().unwrap();
+//!
+//! let mut machine = fresh_machine();
+//! machine.replay(recording);
+//! machine.run(Fuel::cycles(cycles))?;
+//! assert_eq!(machine.replay_status(), Some(Status::Finished));
+//! ```
+use crate::Address;
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Read { address: Address, data: u8 },
+    Irq(bool),
+    Nmi { asserted_at: u64 },
+    Stall(u32),
+    Reset,
+}
+
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    pub cycle: u64,
+    pub input: Input,
+}
+
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    /// The ranges reads were recorded from.
+    pub io: Vec<RangeInclusive<Address>>,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Recording,
+    Replaying {
+        remaining: usize,
+    },
+    Finished,
+    /// The run stopped matching the recording at `cycle`, where `expected`
+    /// was next.
+    Diverged {
+        cycle: u64,
+        expected: Option<Entry>,
+    },
+}
+
+pub(crate) struct Inputs {
+    recording: Recording,
+    replaying: bool,
+    next: usize,
+    diverged: Option<(u64, Option<Entry>)>,
+}
+
+impl Inputs {
+    pub(crate) fn record(io: Vec<RangeInclusive<Address>>) -> Self {
+        Self {
+            recording: Recording {
+                io,
+                entries: Vec::new(),
+            },
+            replaying: false,
+            next: 0,
+            diverged: None,
+        }
+    }
+    pub(crate) fn replay(recording: Recording) -> Self {
+        Self {
+            recording,
+            replaying: true,
+            next: 0,
+            diverged: None,
+        }
+    }
+    pub(crate) fn into_recording(self) -> Recording {
+        self.recording
+    }
+    pub(crate) fn status(&self) -> Status {
+        match self.diverged {
+            Some((cycle, expected)) => Status::Diverged { cycle, expected },
+            None if !self.replaying => Status::Recording,
+            None if self.next == self.recording.entries.len() => Status::Finished,
+            None => Status::Replaying {
+                remaining: self.recording.entries.len() - self.next,
+            },
+        }
+    }
+    // Whether recorded entries are still being played back.
+    fn playing(&self) -> bool {
+        self.replaying && self.diverged.is_none()
+    }
+    fn diverge(&mut self, cycle: u64) {
+        self.diverged = Some((cycle, self.recording.entries.get(self.next).copied()));
+    }
+    /// Notes a call from the host, returning whether it should be acted on,
+    /// which it isn't while replaying.
+    pub(crate) fn host(&mut self, cycle: u64, input: Input) -> bool {
+        if self.replaying {
+            return !self.playing() || self.next == self.recording.entries.len();
+        }
+        self.recording.entries.push(Entry { cycle, input });
+        true
+    }
+    /// The value the CPU should see for a read of `data` from `address` by
+    /// the instruction which started at `cycle`.
+    pub(crate) fn read(&mut self, cycle: u64, address: Address, data: u8) -> u8 {
+        if !self
+            .recording
+            .io
+            .iter()
+            .any(|range| range.contains(&address))
+        {
+            return data;
+        }
+        if !self.replaying {
+            let input = Input::Read { address, data };
+            self.recording.entries.push(Entry { cycle, input });
+            return data;
+        }
+        if !self.playing() || self.next == self.recording.entries.len() {
+            return data;
+        }
+        match self.recording.entries.get(self.next) {
+            Some(&Entry {
+                cycle: recorded,
+                input: Input::Read { address: at, data },
+            }) if recorded == cycle && at == address => {
+                self.next += 1;
+                data
+            }
+            _ => {
+                self.diverge(cycle);
+                data
+            }
+        }
+    }
+    /// The next recorded call due by `cycle`, if replaying.
+    pub(crate) fn due(&mut self, cycle: u64) -> Option<Input> {
+        if !self.playing() {
+            return None;
+        }
+        let entry = *self.recording.entries.get(self.next)?;
+        match entry.input {
+            // A read which should have happened by now.
+            Input::Read { .. } if entry.cycle < cycle => {
+                self.diverge(cycle);
+                None
+            }
+            Input::Read { .. } => None,
+            input if entry.cycle <= cycle => {
+                self.next += 1;
+                Some(input)
+            }
+            _ => None,
+        }
+    }
+}
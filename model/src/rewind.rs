@@ -0,0 +1,156 @@
+//! Reverse execution for a `Machine`, by taking a snapshot every so many
+//! instructions and, to go back, restoring the nearest one before the
+//! target and running forward again to it.
+//!
+//! This relies on the machine being deterministic: anything the host feeds
+//! it between steps, such as `set_irq` or peripheral input, must be fed again
+//! the same way, and isn't covered here. Events and traps aren't part of a
+//! snapshot, so they must be set up the same way too. Going back drops the
+//! checkpoints after the new position, in case the host then does something
+//! else.
+//!
+//! ```ignore
+//! let mut rewind = Rewind::new(&machine, 1000).limit(256);
+//! rewind.run(&mut machine, 50_000)?;
+//! rewind.step_back(&mut machine, 10)?;
+//! ```
+use crate::machine::{Fuel, Machine, Memory, RunReport, Snapshot, StepError, Stopped};
+use crate::peripheral::InvalidState;
+use alloc::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy)]
+pub enum RewindError {
+    Step(StepError),
+    /// A checkpoint couldn't be restored, as the machine's peripherals have
+    /// changed since it was taken.
+    InvalidState(InvalidState),
+}
+
+impl From<StepError> for RewindError {
+    fn from(error: StepError) -> Self {
+        Self::Step(error)
+    }
+}
+
+impl From<InvalidState> for RewindError {
+    fn from(error: InvalidState) -> Self {
+        Self::InvalidState(error)
+    }
+}
+
+pub struct Rewind<M> {
+    interval: u64,
+    limit: usize,
+    // Oldest first, keyed by position.
+    checkpoints: VecDeque<(u64, Snapshot<M>)>,
+    position: u64,
+}
+
+impl<M: Memory + Clone> Rewind<M> {
+    /// Starts at position 0 with `machine` as it is now, and takes a
+    /// checkpoint every `interval` instructions after that.
+    pub fn new(machine: &Machine<M>, interval: u64) -> Self {
+        let mut checkpoints = VecDeque::new();
+        checkpoints.push_back((0, machine.snapshot()));
+        Self {
+            interval: interval.max(1),
+            limit: usize::MAX,
+            checkpoints,
+            position: 0,
+        }
+    }
+    /// Keeps at most `checkpoints` checkpoints, dropping the oldest, so
+    /// how far back can be gone is limited but so is the memory used.
+    pub fn limit(mut self, checkpoints: usize) -> Self {
+        self.limit = checkpoints.max(1);
+        self
+    }
+    /// Instructions run since the start.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+    /// The furthest back `step_back` can go.
+    pub fn earliest(&self) -> u64 {
+        self.checkpoints
+            .front()
+            .map_or(0, |&(position, _)| position)
+    }
+    fn checkpoint(&mut self, machine: &Machine<M>) {
+        let latest = self.checkpoints.back().map(|&(position, _)| position);
+        if self.position.is_multiple_of(self.interval) && latest != Some(self.position) {
+            if self.checkpoints.len() == self.limit {
+                self.checkpoints.pop_front();
+            }
+            self.checkpoints
+                .push_back((self.position, machine.snapshot()));
+        }
+    }
+    /// Runs one instruction, and any interrupts or stall before it, as
+    /// `Machine::run` with `Fuel::instructions(1)` would.
+    pub fn step(&mut self, machine: &mut Machine<M>) -> Result<RunReport, StepError> {
+        self.checkpoint(machine);
+        let report = machine.run(Fuel::instructions(1))?;
+        self.position += report.instructions as u64;
+        Ok(report)
+    }
+    /// Steps up to `instructions` times, stopping early if the machine
+    /// halts or a trap stops it.
+    pub fn run(
+        &mut self,
+        machine: &mut Machine<M>,
+        instructions: u64,
+    ) -> Result<RunReport, StepError> {
+        let mut total = RunReport {
+            instructions: 0,
+            cycles: 0,
+            stopped: Stopped::OutOfFuel,
+        };
+        for _ in 0..instructions {
+            let report = self.step(machine)?;
+            total.instructions += report.instructions;
+            total.cycles += report.cycles;
+            if !matches!(report.stopped, Stopped::OutOfFuel) {
+                total.stopped = report.stopped;
+                break;
+            }
+        }
+        Ok(total)
+    }
+    /// Goes back to the state before the last `instructions` instructions,
+    /// or as far as the checkpoints allow, returning how far it went.
+    pub fn step_back(
+        &mut self,
+        machine: &mut Machine<M>,
+        instructions: u64,
+    ) -> Result<u64, RewindError> {
+        self.seek(machine, self.position.saturating_sub(instructions))
+    }
+    /// Goes to `position`, before or after the current one, returning how
+    /// many instructions back that was. Going back stops at the earliest
+    /// checkpoint.
+    pub fn seek(&mut self, machine: &mut Machine<M>, position: u64) -> Result<u64, RewindError> {
+        let from = self.position;
+        let target = position.max(self.earliest());
+        if target < from {
+            while self
+                .checkpoints
+                .back()
+                .is_some_and(|&(position, _)| position > target)
+            {
+                self.checkpoints.pop_back();
+            }
+            let Some((position, snapshot)) = self.checkpoints.back() else {
+                return Ok(0);
+            };
+            machine.restore(snapshot)?;
+            self.position = *position;
+        }
+        while self.position < target {
+            let report = self.step(machine)?;
+            if report.instructions == 0 {
+                break;
+            }
+        }
+        Ok(from.saturating_sub(self.position))
+    }
+}
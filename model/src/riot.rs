@@ -0,0 +1,306 @@
+//! A 6532 RIOT: 128 bytes of RAM, two 8-bit I/O ports and an interval
+//! timer. Offsets are decoded from the chip's address pins as wired in the
+//! Atari 2600, where bit 7 selects the chip and bit 9 picks the I/O
+//! registers over RAM, so for the 2600's layout it can be mapped over
+//! $0000-$02FF after the TIA. The PA7 edge detector isn't modelled.
+use crate::peripheral::{InvalidState, Peripheral};
+use crate::Address;
+use alloc::{boxed::Box, vec::Vec};
+
+const CHIP_SELECT: Address = 0x0080;
+const IO_SELECT: Address = 0x0200;
+
+/// Offsets of the I/O registers, with `IO_SELECT` and `CHIP_SELECT` set.
+pub const PORT_A: Address = 0x0280;
+pub const DDR_A: Address = 0x0281;
+pub const PORT_B: Address = 0x0282;
+pub const DDR_B: Address = 0x0283;
+/// Reading gives the timer, and writing with the low two bits selecting a
+/// prescaler of 1, 8, 64 or 1024 cycles starts it. Either also sets the
+/// timer interrupt enable from bit 3.
+pub const TIMER: Address = 0x0294;
+/// Reading gives the interrupt flags; bit 7 is set once the timer expires.
+pub const INTERRUPT_FLAGS: Address = 0x0285;
+
+pub const FLAG_TIMER: u8 = 0x80;
+
+const PRESCALERS: [u16; 4] = [1, 8, 64, 1024];
+
+pub struct Riot {
+    ram: [u8; 128],
+    port_a: Box<dyn FnMut() -> u8>,
+    port_b: Box<dyn FnMut() -> u8>,
+    output_a: u8,
+    ddr_a: u8,
+    output_b: u8,
+    ddr_b: u8,
+    timer: u8,
+    prescaler: u16,
+    // Cycles until the timer next decrements.
+    countdown: u16,
+    // Once the timer passes zero it decrements every cycle.
+    expired: bool,
+    timer_flag: bool,
+    timer_irq_enabled: bool,
+}
+
+impl Default for Riot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Riot {
+    /// A RIOT with nothing driving either port, so pins configured as
+    /// inputs read as 1.
+    pub fn new() -> Self {
+        Self::with_ports(|| 0xFF, || 0xFF)
+    }
+    /// A RIOT whose input pins are read from `port_a` and `port_b`, e.g.
+    /// the joysticks and console switches.
+    pub fn with_ports<A, B>(port_a: A, port_b: B) -> Self
+    where
+        A: FnMut() -> u8 + 'static,
+        B: FnMut() -> u8 + 'static,
+    {
+        Self {
+            ram: [0; 128],
+            port_a: Box::new(port_a),
+            port_b: Box::new(port_b),
+            output_a: 0,
+            ddr_a: 0,
+            output_b: 0,
+            ddr_b: 0,
+            timer: 0,
+            prescaler: 1024,
+            countdown: 1024,
+            expired: false,
+            timer_flag: false,
+            timer_irq_enabled: false,
+        }
+    }
+    pub fn ram(&self) -> &[u8; 128] {
+        &self.ram
+    }
+    // Reading or writing the timer clears its flag.
+    fn access_timer(&mut self, offset: Address) {
+        self.timer_flag = false;
+        self.timer_irq_enabled = offset & 0x08 != 0;
+    }
+}
+
+impl Peripheral for Riot {
+    fn read(&mut self, offset: Address) -> u8 {
+        if offset & CHIP_SELECT == 0 {
+            return 0;
+        }
+        if offset & IO_SELECT == 0 {
+            return self.ram[(offset & 0x7F) as usize];
+        }
+        if offset & 0x04 == 0 {
+            return match offset & 0x03 {
+                0 => (self.output_a & self.ddr_a) | ((self.port_a)() & !self.ddr_a),
+                1 => self.ddr_a,
+                2 => (self.output_b & self.ddr_b) | ((self.port_b)() & !self.ddr_b),
+                _ => self.ddr_b,
+            };
+        }
+        if offset & 0x01 == 0 {
+            self.access_timer(offset);
+            self.timer
+        } else if self.timer_flag {
+            FLAG_TIMER
+        } else {
+            0
+        }
+    }
+    fn write(&mut self, offset: Address, data: u8) {
+        if offset & CHIP_SELECT == 0 {
+            return;
+        }
+        if offset & IO_SELECT == 0 {
+            self.ram[(offset & 0x7F) as usize] = data;
+            return;
+        }
+        if offset & 0x04 == 0 {
+            match offset & 0x03 {
+                0 => self.output_a = data,
+                1 => self.ddr_a = data,
+                2 => self.output_b = data,
+                _ => self.ddr_b = data,
+            }
+        } else if offset & 0x10 != 0 {
+            self.access_timer(offset);
+            self.timer = data;
+            self.prescaler = PRESCALERS[(offset & 0x03) as usize];
+            self.countdown = self.prescaler;
+            self.expired = false;
+        }
+    }
+    fn tick(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.countdown -= 1;
+            if self.countdown > 0 {
+                continue;
+            }
+            let (timer, passed_zero) = self.timer.overflowing_sub(1);
+            self.timer = timer;
+            if passed_zero {
+                self.expired = true;
+                self.timer_flag = true;
+            }
+            self.countdown = if self.expired { 1 } else { self.prescaler };
+        }
+    }
+    fn irq(&self) -> bool {
+        self.timer_flag && self.timer_irq_enabled
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = self.ram.to_vec();
+        state.extend_from_slice(&[
+            self.output_a,
+            self.ddr_a,
+            self.output_b,
+            self.ddr_b,
+            self.timer,
+        ]);
+        state.extend_from_slice(&self.prescaler.to_le_bytes());
+        state.extend_from_slice(&self.countdown.to_le_bytes());
+        state.push(
+            self.expired as u8 | (self.timer_flag as u8) << 1 | (self.timer_irq_enabled as u8) << 2,
+        );
+        state
+    }
+    fn load_state(&mut self, state: &[u8]) -> Result<(), InvalidState> {
+        let (ram, rest) = state.split_at_checked(128).ok_or(InvalidState)?;
+        let &[output_a, ddr_a, output_b, ddr_b, timer, p0, p1, c0, c1, flags] = rest else {
+            return Err(InvalidState);
+        };
+        let prescaler = u16::from_le_bytes([p0, p1]);
+        let countdown = u16::from_le_bytes([c0, c1]);
+        if !PRESCALERS.contains(&prescaler) || countdown == 0 || countdown > prescaler {
+            return Err(InvalidState);
+        }
+        self.ram.copy_from_slice(ram);
+        self.output_a = output_a;
+        self.ddr_a = ddr_a;
+        self.output_b = output_b;
+        self.ddr_b = ddr_b;
+        self.timer = timer;
+        self.prescaler = prescaler;
+        self.countdown = countdown;
+        self.expired = flags & 1 != 0;
+        self.timer_flag = flags & 2 != 0;
+        self.timer_irq_enabled = flags & 4 != 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTIM: Address = 0x0284;
+
+    fn tick(riot: &mut Riot, cycles: usize) {
+        for _ in 0..cycles {
+            riot.tick(1);
+        }
+    }
+
+    #[test]
+    fn timer_counts_down_at_each_prescaler() {
+        for (index, &prescaler) in PRESCALERS.iter().enumerate() {
+            let prescaler = prescaler as usize;
+            let mut riot = Riot::new();
+            riot.write(TIMER | index as Address, 2);
+            tick(&mut riot, prescaler - 1);
+            assert_eq!(riot.read(INTIM), 2);
+            tick(&mut riot, 1);
+            assert_eq!(riot.read(INTIM), 1);
+            tick(&mut riot, prescaler);
+            assert_eq!(riot.read(INTIM), 0);
+            assert_eq!(riot.read(INTERRUPT_FLAGS), 0);
+            tick(&mut riot, prescaler);
+            assert_eq!(riot.read(INTERRUPT_FLAGS), FLAG_TIMER);
+            // Past zero it counts down every cycle.
+            assert_eq!(riot.read(INTIM), 0xFF);
+            tick(&mut riot, 3);
+            assert_eq!(riot.read(INTIM), 0xFC);
+            // Reading the timer cleared the flag.
+            assert_eq!(riot.read(INTERRUPT_FLAGS), 0);
+        }
+    }
+
+    #[test]
+    fn timer_interrupt_is_enabled_by_bit_3() {
+        let mut riot = Riot::new();
+        // TIM1T, with the interrupt enabled.
+        riot.write(TIMER | 0x08, 0);
+        assert!(!riot.irq());
+        riot.tick(1);
+        assert!(riot.irq());
+        // Reading without bit 3 clears the flag and disables it.
+        riot.read(INTIM);
+        riot.tick(1);
+        assert_eq!(riot.read(INTERRUPT_FLAGS), 0);
+        assert!(!riot.irq());
+    }
+
+    #[test]
+    fn save_and_load_state() {
+        let mut riot = Riot::new();
+        riot.write(0x0080, 0x12);
+        riot.write(0x00FF, 0x34);
+        riot.write(DDR_A, 0xF0);
+        riot.write(PORT_A, 0x5A);
+        riot.write(DDR_B, 0x0F);
+        riot.write(PORT_B, 0xA5);
+        // TIM64T, part way to the next decrement.
+        riot.write(TIMER | 0x0A, 3);
+        tick(&mut riot, 100);
+        let state = riot.save_state();
+
+        let mut loaded = Riot::new();
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.save_state(), state);
+        assert_eq!(loaded.ram(), riot.ram());
+        for offset in [PORT_A, DDR_A, PORT_B, DDR_B] {
+            assert_eq!(loaded.read(offset), riot.read(offset));
+        }
+        for _ in 0..4 {
+            tick(&mut riot, 64);
+            tick(&mut loaded, 64);
+            assert_eq!(loaded.irq(), riot.irq());
+            assert_eq!(loaded.read(INTERRUPT_FLAGS), riot.read(INTERRUPT_FLAGS));
+            assert_eq!(loaded.read(TIMER | 0x08), riot.read(TIMER | 0x08));
+        }
+    }
+
+    #[test]
+    fn load_state_rejects_invalid_state() {
+        let state = Riot::new().save_state();
+        let prescaler = 128 + 5;
+        let countdown = prescaler + 2;
+        let mut invalid = Vec::new();
+        invalid.push(state[..state.len() - 1].to_vec());
+        invalid.push([&state[..], &[0]].concat());
+        let mut bad_prescaler = state.clone();
+        bad_prescaler[prescaler..prescaler + 2].copy_from_slice(&3u16.to_le_bytes());
+        invalid.push(bad_prescaler);
+        let mut no_countdown = state.clone();
+        no_countdown[countdown..countdown + 2].fill(0);
+        invalid.push(no_countdown);
+        let mut long_countdown = state.clone();
+        long_countdown[countdown..countdown + 2].copy_from_slice(&1025u16.to_le_bytes());
+        invalid.push(long_countdown);
+
+        let mut riot = Riot::new();
+        riot.write(0x0080, 0x12);
+        let before = riot.save_state();
+        for state in invalid {
+            assert!(riot.load_state(&state).is_err());
+            assert_eq!(riot.save_state(), before);
+        }
+    }
+}
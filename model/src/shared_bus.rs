@@ -0,0 +1,261 @@
+//! Several CPUs on one bus, such as a main CPU and a sound or disk
+//! co-processor. Each is a `Machine` over a `Shared` handle to the bus,
+//! with its own interrupts, peripherals and traps, and a `Scheduler`
+//! interleaves them on a common clock.
+//!
+//! Time is counted in units of the caller's choosing, and each CPU takes
+//! `period` units per cycle, so CPUs at different rates can be mixed: with
+//! units of 1/(985248 * 1000000) seconds, say, a 985248Hz CPU has a period
+//! of 1000000 and a 1MHz one of 985248. The scheduler always steps the CPU
+//! furthest behind, a whole instruction at a time, so CPUs only see each
+//! other's accesses at instruction boundaries.
+//!
+//! ```ignore
+//! let bus = Shared::new(MemoryMap::new());
+//! let mut scheduler = Scheduler::new();
+//! let main = scheduler.add(Machine::new(Cpu::new(), bus.handle()), 1);
+//! let sound = scheduler.add(Machine::new(Cpu::new(), bus.handle()), 2);
+//! scheduler.run_for(1_000_000)?;
+//! ```
+use crate::machine::{Fault, Fuel, Machine, Memory, MemoryReadOnly, StepError, Stopped};
+use crate::Address;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::{Ref, RefCell, RefMut};
+
+/// A handle to a bus shared by several machines. Handles don't implement
+/// `Clone`, as a `Snapshot` of a machine over one would share the bus
+/// rather than copy it; `handle` makes another.
+pub struct Shared<M>(Rc<RefCell<M>>);
+
+impl<M> Shared<M> {
+    pub fn new(memory: M) -> Self {
+        Self(Rc::new(RefCell::new(memory)))
+    }
+    /// Another handle to the same bus.
+    pub fn handle(&self) -> Self {
+        Self(Rc::clone(&self.0))
+    }
+    pub fn borrow(&self) -> Ref<'_, M> {
+        self.0.borrow()
+    }
+    pub fn borrow_mut(&self) -> RefMut<'_, M> {
+        self.0.borrow_mut()
+    }
+}
+
+/// Faults raised by the bus are taken by whichever CPU checks first,
+/// which is the one which caused them unless the bus is used from outside
+/// the machines.
+impl<M: Memory> Memory for Shared<M> {
+    fn read_u8(&mut self, address: Address) -> u8 {
+        self.0.borrow_mut().read_u8(address)
+    }
+    fn write_u8(&mut self, address: Address, data: u8) {
+        self.0.borrow_mut().write_u8(address, data)
+    }
+    fn read_block(&mut self, address: Address, buffer: &mut [u8]) {
+        self.0.borrow_mut().read_block(address, buffer)
+    }
+    fn write_block(&mut self, address: Address, data: &[u8]) {
+        self.0.borrow_mut().write_block(address, data)
+    }
+    fn load(&mut self, address: Address, data: &[u8]) {
+        self.0.borrow_mut().load(address, data)
+    }
+    fn take_fault(&mut self) -> Option<Fault> {
+        self.0.borrow_mut().take_fault()
+    }
+    fn set_mapping_register(&mut self, index: u8, bank: u8) {
+        self.0.borrow_mut().set_mapping_register(index, bank)
+    }
+}
+
+impl<M: MemoryReadOnly> MemoryReadOnly for Shared<M> {
+    fn read_u8_read_only(&self, address: Address) -> u8 {
+        self.0.borrow().read_u8_read_only(address)
+    }
+}
+
+/// An error from one of the CPUs, which is left as it was when it
+/// happened. A trap handler returning `TrapAction::Stop` gives
+/// `StepError::TrapStop`.
+#[derive(Debug, Clone, Copy)]
+pub struct Error {
+    pub cpu: usize,
+    pub error: StepError,
+}
+
+struct Core<M> {
+    machine: Machine<M>,
+    period: u64,
+    // The time the machine's cycle count was `base` at.
+    start: u64,
+    base: u64,
+}
+
+impl<M: Memory> Core<M> {
+    // The machine's cycle count goes back if it's restored from an earlier
+    // snapshot, and so does its time, to no earlier than 0.
+    fn time(&self) -> u64 {
+        let cycles = self.machine.cycles() as i128 - self.base as i128;
+        (self.start as i128 + cycles * self.period as i128).max(0) as u64
+    }
+}
+
+pub struct Scheduler<M> {
+    cores: Vec<Core<M>>,
+    now: u64,
+}
+
+impl<M: Memory> Default for Scheduler<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: Memory> Scheduler<M> {
+    pub fn new() -> Self {
+        Self {
+            cores: Vec::new(),
+            now: 0,
+        }
+    }
+    /// Adds a CPU taking `period` time units per cycle, starting now, and
+    /// returns its index.
+    pub fn add(&mut self, machine: Machine<M>, period: u64) -> usize {
+        self.cores.push(Core {
+            base: machine.cycles(),
+            machine,
+            period: period.max(1),
+            start: self.now,
+        });
+        self.cores.len() - 1
+    }
+    pub fn len(&self) -> usize {
+        self.cores.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.cores.is_empty()
+    }
+    pub fn machine(&self, cpu: usize) -> &Machine<M> {
+        &self.cores[cpu].machine
+    }
+    /// Changes to the machine's cycle count, e.g. by restoring a snapshot,
+    /// move the CPU's time by as many cycles. One moved back is run to
+    /// catch up.
+    pub fn machine_mut(&mut self, cpu: usize) -> &mut Machine<M> {
+        &mut self.cores[cpu].machine
+    }
+    /// The time every CPU has been run up to.
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+    /// How far `cpu` has run, which may be past `now` by up to an
+    /// instruction.
+    pub fn time(&self, cpu: usize) -> u64 {
+        self.cores[cpu].time()
+    }
+    /// Runs every CPU up to `time`, taking interrupts and stalls as
+    /// `Machine::run` does. A halted CPU idles until reset.
+    pub fn run_until(&mut self, time: u64) -> Result<(), Error> {
+        while let Some((cpu, core)) = self
+            .cores
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, core)| core.time() < time)
+            .min_by_key(|(_, core)| core.time())
+        {
+            let report = core
+                .machine
+                .run(Fuel::instructions(1))
+                .map_err(|error| Error { cpu, error })?;
+            match report.stopped {
+                Stopped::Trap => {
+                    return Err(Error {
+                        cpu,
+                        error: StepError::TrapStop,
+                    })
+                }
+                Stopped::Halted if report.cycles == 0 => {
+                    core.start = time;
+                    core.base = core.machine.cycles();
+                }
+                _ => {}
+            }
+        }
+        self.now = self.now.max(time);
+        Ok(())
+    }
+    /// Runs every CPU for `duration` more time units.
+    pub fn run_for(&mut self, duration: u64) -> Result<(), Error> {
+        self.run_until(self.now + duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::{Cpu, Ram};
+    use crate::memory_map::MemoryMap;
+
+    // A CPU looping over `LDA $4000 + index; JMP start` at `start`.
+    fn looping(bus: &Shared<MemoryMap>, start: Address, index: u8) -> Machine<Shared<MemoryMap>> {
+        let [lo, hi] = start.to_le_bytes();
+        bus.borrow_mut()
+            .load(start, &[0xAD, index, 0x40, 0x4C, lo, hi]);
+        let mut machine = Machine::new(Cpu::new(), bus.handle());
+        machine.cpu.pc = start;
+        machine
+    }
+
+    #[test]
+    fn interleaves_by_period() {
+        let reads = Rc::new(RefCell::new(Vec::new()));
+        let read = {
+            let reads = reads.clone();
+            move |offset| {
+                reads.borrow_mut().push(offset);
+                0
+            }
+        };
+        let bus = Shared::new(MemoryMap::new().ram(0x0000..=0x3FFF).io(
+            0x4000..=0x4001,
+            read,
+            |_, _| (),
+        ));
+        let mut scheduler = Scheduler::new();
+        scheduler.add(looping(&bus, 0x0200, 0), 2);
+        scheduler.add(looping(&bus, 0x0300, 1), 3);
+        // Each loop takes 7 cycles: 14 time units on the first CPU and 21
+        // on the second.
+        scheduler.run_until(40).unwrap();
+        assert_eq!(*reads.borrow(), [0, 1, 0, 1, 0]);
+        assert_eq!(scheduler.now(), 40);
+        assert_eq!((scheduler.time(0), scheduler.time(1)), (42, 42));
+        // Running to a time already passed changes nothing.
+        scheduler.run_until(30).unwrap();
+        assert_eq!(scheduler.now(), 40);
+        scheduler.run_for(2).unwrap();
+        assert_eq!(scheduler.now(), 42);
+        assert_eq!(reads.borrow().len(), 5);
+    }
+
+    #[test]
+    fn restoring_an_earlier_snapshot_goes_back_in_time() {
+        let mut ram = Ram::new();
+        // NOP; JMP $0200
+        ram.load(0x0200, &[0xEA, 0x4C, 0x00, 0x02]);
+        let mut machine = Machine::new(Cpu::new(), ram);
+        machine.cpu.pc = 0x0200;
+        let snapshot = machine.snapshot();
+        let mut scheduler = Scheduler::new();
+        scheduler.add(machine, 4);
+        scheduler.run_until(100).unwrap();
+        let time = scheduler.time(0);
+        scheduler.machine_mut(0).restore(&snapshot).unwrap();
+        assert_eq!(scheduler.time(0), 0);
+        scheduler.run_until(100).unwrap();
+        assert_eq!(scheduler.time(0), time);
+    }
+}
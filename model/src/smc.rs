@@ -0,0 +1,96 @@
+//! Catching writes to code, whether self-modifying code doing so on
+//! purpose or a stray pointer corrupting it. A `CodeWatch` covers ranges
+//! given up front, and optionally every address executed since it was
+//! set up with `Machine::watch_code`, which calls a handler after each
+//! instruction or interrupt for every write it made to a watched address.
+//!
+//! ```ignore
+//! let watch = CodeWatch::new().range(0x8000..=0xBFFF).executed();
+//! machine.watch_code(watch, |machine, write| {
+//!     eprintln!("{}\n{}", write, machine.cpu);
+//! });
+//! ```
+use crate::coverage::AddressSet;
+use crate::Address;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeWrite {
+    /// The address of the instruction which wrote, or of the one an
+    /// interrupt was taken before.
+    pub pc: Address,
+    pub address: Address,
+    pub data: u8,
+    /// Whether `address` had been executed, rather than only being in a
+    /// watched range.
+    pub executed: bool,
+}
+
+/// As `$C012 wrote $60 to $8003 (executed)`.
+impl fmt::Display for CodeWrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "${:04X} wrote ${:02X} to ${:04X}",
+            self.pc, self.data, self.address
+        )?;
+        if self.executed {
+            write!(f, " (executed)")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CodeWatch {
+    ranges: Vec<RangeInclusive<Address>>,
+    executed: Option<AddressSet>,
+    pending: Vec<CodeWrite>,
+}
+
+impl CodeWatch {
+    /// Watches nothing until given ranges or `executed`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Watches `range`, as well as anything else given.
+    pub fn range(mut self, range: RangeInclusive<Address>) -> Self {
+        self.ranges.push(range);
+        self
+    }
+    /// Watches the bytes of every instruction executed from now on.
+    pub fn executed(mut self) -> Self {
+        self.executed.get_or_insert_with(AddressSet::new);
+        self
+    }
+    /// The instruction bytes executed so far, if they're being watched.
+    pub fn executed_addresses(&self) -> Option<&AddressSet> {
+        self.executed.as_ref()
+    }
+    pub(crate) fn record_write(&mut self, pc: Address, address: Address, data: u8) {
+        let executed = self
+            .executed
+            .as_ref()
+            .is_some_and(|executed| executed.contains(address));
+        if executed || self.ranges.iter().any(|range| range.contains(&address)) {
+            self.pending.push(CodeWrite {
+                pc,
+                address,
+                data,
+                executed,
+            });
+        }
+    }
+    pub(crate) fn record_executed(&mut self, pc: Address, size: usize) {
+        if let Some(executed) = &mut self.executed {
+            for offset in 0..size {
+                executed.insert(pc.wrapping_add(offset as Address));
+            }
+        }
+    }
+    pub(crate) fn take_pending(&mut self) -> Vec<CodeWrite> {
+        core::mem::take(&mut self.pending)
+    }
+}
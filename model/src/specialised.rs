@@ -0,0 +1,508 @@
+//! Instructions specialised to an operand known before they run, for code
+//! translated ahead of time. The threaded backend builds its closures from
+//! these, and the recompiler's output calls them. Each addressing mode type
+//! holds its operand and makes the accesses of the `addressing_mode` type of
+//! the same name, less the fetch. Each instruction function is given the
+//! address of the next instruction and the fewest cycles it takes, leaves
+//! the program counter at the next instruction (or the branch target), and
+//! returns the cycles taken.
+//!
+//! ```ignore
+//! // LDA $1234,X at $C000.
+//! let cycles = read(cpu, memory, AbsoluteIndexed(0x1234, X), 0xC003, 4, lda);
+//! ```
+use crate::addressing_mode::{
+    index_address, read_zero_page_pointer, zero_page_address, zero_page_indexed,
+};
+use crate::dispatch::Table;
+use crate::instruction::{adc_with_mode, branch_next_pc_with_cycles, sbc_with_mode};
+use crate::machine::{Cpu, Fault, Memory};
+use crate::{Address, UnknownOpcode};
+
+/// The register an indexed mode adds.
+pub trait Index: Copy {
+    fn of(cpu: &Cpu) -> u8;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct X;
+impl Index for X {
+    fn of(cpu: &Cpu) -> u8 {
+        cpu.x
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Y;
+impl Index for Y {
+    fn of(cpu: &Cpu) -> u8 {
+        cpu.y
+    }
+}
+
+pub trait ReadData: Copy {
+    /// The data, and whether indexing crossed a page.
+    fn read_data<M: Memory>(self, cpu: &Cpu, memory: &mut M) -> (u8, bool);
+}
+
+pub trait WriteData: Copy {
+    fn write_address<M: Memory>(self, cpu: &Cpu, memory: &mut M) -> Address;
+    fn write_data<M: Memory>(self, cpu: &Cpu, memory: &mut M, data: u8) {
+        let address = self.write_address(cpu, memory);
+        memory.write_u8(address, data)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Immediate(pub u8);
+impl ReadData for Immediate {
+    fn read_data<M: Memory>(self, _: &Cpu, _: &mut M) -> (u8, bool) {
+        (self.0, false)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ZeroPage(pub u8);
+impl ReadData for ZeroPage {
+    fn read_data<M: Memory>(self, _: &Cpu, memory: &mut M) -> (u8, bool) {
+        (memory.read_u8_zero_page(self.0), false)
+    }
+}
+impl WriteData for ZeroPage {
+    fn write_address<M: Memory>(self, _: &Cpu, memory: &mut M) -> Address {
+        zero_page_address(memory, self.0 as Address)
+    }
+    fn write_data<M: Memory>(self, _: &Cpu, memory: &mut M, data: u8) {
+        memory.write_u8_zero_page(self.0, data)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ZeroPageIndexed<I>(pub u8, pub I);
+impl<I: Index> ZeroPageIndexed<I> {
+    fn offset<M: Memory>(self, cpu: &Cpu, memory: &mut M) -> Address {
+        memory.read_u8_dummy(zero_page_address(memory, self.0 as Address));
+        zero_page_indexed(cpu, self.0, I::of(cpu))
+    }
+}
+impl<I: Index> ReadData for ZeroPageIndexed<I> {
+    fn read_data<M: Memory>(self, cpu: &Cpu, memory: &mut M) -> (u8, bool) {
+        let data = match self.offset(cpu, memory) {
+            offset if cpu.quirks.zero_page_wrap => memory.read_u8_zero_page(offset as u8),
+            offset => memory.read_u8(zero_page_address(memory, offset)),
+        };
+        (data, false)
+    }
+}
+impl<I: Index> WriteData for ZeroPageIndexed<I> {
+    fn write_address<M: Memory>(self, cpu: &Cpu, memory: &mut M) -> Address {
+        let offset = self.offset(cpu, memory);
+        zero_page_address(memory, offset)
+    }
+    fn write_data<M: Memory>(self, cpu: &Cpu, memory: &mut M, data: u8) {
+        match self.offset(cpu, memory) {
+            offset if cpu.quirks.zero_page_wrap => memory.write_u8_zero_page(offset as u8, data),
+            offset => memory.write_u8(zero_page_address(memory, offset), data),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Absolute(pub Address);
+impl ReadData for Absolute {
+    fn read_data<M: Memory>(self, _: &Cpu, memory: &mut M) -> (u8, bool) {
+        (memory.read_u8(self.0), false)
+    }
+}
+impl WriteData for Absolute {
+    fn write_address<M: Memory>(self, _: &Cpu, _: &mut M) -> Address {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AbsoluteIndexed<I>(pub Address, pub I);
+impl<I: Index> ReadData for AbsoluteIndexed<I> {
+    fn read_data<M: Memory>(self, cpu: &Cpu, memory: &mut M) -> (u8, bool) {
+        let (address, crossed) = index_address(memory, self.0, I::of(cpu), false);
+        (memory.read_u8(address), crossed)
+    }
+}
+impl<I: Index> WriteData for AbsoluteIndexed<I> {
+    fn write_address<M: Memory>(self, cpu: &Cpu, memory: &mut M) -> Address {
+        index_address(memory, self.0, I::of(cpu), true).0
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct XIndexedIndirect(pub u8);
+impl ReadData for XIndexedIndirect {
+    fn read_data<M: Memory>(self, cpu: &Cpu, memory: &mut M) -> (u8, bool) {
+        let address = self.write_address(cpu, memory);
+        (memory.read_u8(address), false)
+    }
+}
+impl WriteData for XIndexedIndirect {
+    fn write_address<M: Memory>(self, cpu: &Cpu, memory: &mut M) -> Address {
+        memory.read_u8_dummy(zero_page_address(memory, self.0 as Address));
+        read_zero_page_pointer(cpu, memory, self.0, cpu.x)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IndirectYIndexed(pub u8);
+impl ReadData for IndirectYIndexed {
+    fn read_data<M: Memory>(self, cpu: &Cpu, memory: &mut M) -> (u8, bool) {
+        let base = read_zero_page_pointer(cpu, memory, self.0, 0);
+        let (address, crossed) = index_address(memory, base, cpu.y, false);
+        (memory.read_u8(address), crossed)
+    }
+}
+impl WriteData for IndirectYIndexed {
+    fn write_address<M: Memory>(self, cpu: &Cpu, memory: &mut M) -> Address {
+        let base = read_zero_page_pointer(cpu, memory, self.0, 0);
+        index_address(memory, base, cpu.y, true).0
+    }
+}
+
+/// An instruction which reads its data, such as `LDA` or `ADC`, with one
+/// more cycle if indexing crossed a page.
+#[inline(always)]
+pub fn read<M: Memory, A: ReadData>(
+    cpu: &mut Cpu,
+    memory: &mut M,
+    mode: A,
+    next: Address,
+    cycles: u8,
+    apply: impl FnOnce(&mut Cpu, u8),
+) -> u8 {
+    let (data, crossed) = mode.read_data(cpu, memory);
+    apply(cpu, data);
+    cpu.pc = next;
+    cycles + crossed as u8
+}
+
+/// A store of `data`.
+#[inline(always)]
+pub fn write<M: Memory, A: WriteData>(
+    cpu: &mut Cpu,
+    memory: &mut M,
+    mode: A,
+    next: Address,
+    cycles: u8,
+    data: u8,
+) -> u8 {
+    mode.write_data(cpu, memory, data);
+    cpu.pc = next;
+    cycles
+}
+
+/// A read-modify-write instruction, accessing memory as
+/// `ReadModifyWriteData::read_data_for_modify` does, and then writing back
+/// what `apply` makes of the data.
+#[inline(always)]
+pub fn modify<M: Memory, A: ReadData + WriteData>(
+    cpu: &mut Cpu,
+    memory: &mut M,
+    mode: A,
+    next: Address,
+    cycles: u8,
+    apply: impl FnOnce(&mut Cpu, u8) -> u8,
+) -> u8 {
+    let address = mode.write_address(cpu, memory);
+    let data = memory.read_u8(address);
+    if cpu.quirks.rmw_dummy_write {
+        memory.write_u8(address, data);
+    } else {
+        memory.read_u8_dummy(address);
+    }
+    let data = apply(cpu, data);
+    memory.write_u8(address, data);
+    cpu.pc = next;
+    cycles
+}
+
+/// An implied or accumulator instruction, with the dummy read of the byte
+/// after the opcode, which is at `next`.
+#[inline(always)]
+pub fn implied<M: Memory>(
+    cpu: &mut Cpu,
+    memory: &mut M,
+    next: Address,
+    cycles: u8,
+    apply: impl FnOnce(&mut Cpu),
+) -> u8 {
+    memory.read_u8_dummy(next);
+    apply(cpu);
+    cpu.pc = next;
+    cycles
+}
+
+#[inline(always)]
+pub fn branch<M: Memory>(
+    cpu: &mut Cpu,
+    memory: &mut M,
+    next: Address,
+    offset: i8,
+    taken: bool,
+) -> u8 {
+    if taken {
+        let (pc, cycles) = branch_next_pc_with_cycles(memory, next, offset);
+        cpu.pc = pc;
+        cycles
+    } else {
+        cpu.pc = next;
+        2
+    }
+}
+
+/// `JMP` to an absolute address.
+#[inline(always)]
+pub fn jump(cpu: &mut Cpu, target: Address) -> u8 {
+    cpu.pc = target;
+    3
+}
+
+// Memory as seen by an instruction run through its handler: the fetch
+// comes from the bytes given.
+struct Fetched<'a, M> {
+    memory: &'a mut M,
+    pc: Address,
+    bytes: &'a [u8],
+}
+
+impl<M: Memory> Memory for Fetched<'_, M> {
+    fn read_u8(&mut self, address: Address) -> u8 {
+        match self.bytes.get(address.wrapping_sub(self.pc) as usize) {
+            Some(&byte) => byte,
+            None => self.memory.read_u8(address),
+        }
+    }
+    fn read_u8_dummy(&mut self, address: Address) {
+        self.memory.read_u8_dummy(address)
+    }
+    fn write_u8(&mut self, address: Address, data: u8) {
+        self.memory.write_u8(address, data)
+    }
+    fn read_u8_zero_page(&mut self, address: u8) -> u8 {
+        self.memory.read_u8_zero_page(address)
+    }
+    fn write_u8_zero_page(&mut self, address: u8, data: u8) {
+        self.memory.write_u8_zero_page(address, data)
+    }
+    fn read_u8_stack(&mut self, stack_pointer: u8) -> u8 {
+        self.memory.read_u8_stack(stack_pointer)
+    }
+    fn write_u8_stack(&mut self, stack_pointer: u8, data: u8) {
+        self.memory.write_u8_stack(stack_pointer, data)
+    }
+    fn take_fault(&mut self) -> Option<Fault> {
+        self.memory.take_fault()
+    }
+    fn set_mapping_register(&mut self, index: u8, bank: u8) {
+        self.memory.set_mapping_register(index, bank)
+    }
+    fn zero_page_base(&self) -> Address {
+        self.memory.zero_page_base()
+    }
+}
+
+/// Runs any other instruction at the program counter through the NMOS
+/// dispatch table, fetching it from `bytes` (opcode first) rather than
+/// memory.
+#[inline(always)]
+pub fn interpret<M: Memory>(
+    cpu: &mut Cpu,
+    memory: &mut M,
+    bytes: &[u8],
+) -> Result<u8, UnknownOpcode> {
+    let mut fetched = Fetched {
+        memory,
+        pc: cpu.pc,
+        bytes,
+    };
+    Table::<Fetched<M>>::HANDLERS[bytes[0] as usize](cpu, &mut fetched)
+}
+
+fn set_zero_and_negative(cpu: &mut Cpu, value: u8) {
+    cpu.status.set_zero_from_value(value);
+    cpu.status.set_negative_from_value(value);
+}
+
+fn compare(cpu: &mut Cpu, register: u8, data: u8) {
+    let (difference, borrow) = register.overflowing_sub(data);
+    set_zero_and_negative(cpu, difference);
+    cpu.status.set_carry_to(!borrow);
+}
+
+// What `read` applies.
+
+pub fn lda(cpu: &mut Cpu, data: u8) {
+    cpu.acc = data;
+    set_zero_and_negative(cpu, data)
+}
+
+pub fn ldx(cpu: &mut Cpu, data: u8) {
+    cpu.x = data;
+    set_zero_and_negative(cpu, data)
+}
+
+pub fn ldy(cpu: &mut Cpu, data: u8) {
+    cpu.y = data;
+    set_zero_and_negative(cpu, data)
+}
+
+pub fn and(cpu: &mut Cpu, data: u8) {
+    cpu.acc &= data;
+    set_zero_and_negative(cpu, cpu.acc)
+}
+
+pub fn ora(cpu: &mut Cpu, data: u8) {
+    cpu.acc |= data;
+    set_zero_and_negative(cpu, cpu.acc)
+}
+
+pub fn eor(cpu: &mut Cpu, data: u8) {
+    cpu.acc ^= data;
+    set_zero_and_negative(cpu, cpu.acc)
+}
+
+pub fn adc(cpu: &mut Cpu, data: u8) {
+    adc_with_mode(cpu, data)
+}
+
+pub fn sbc(cpu: &mut Cpu, data: u8) {
+    sbc_with_mode(cpu, data)
+}
+
+pub fn cmp(cpu: &mut Cpu, data: u8) {
+    compare(cpu, cpu.acc, data)
+}
+
+pub fn cpx(cpu: &mut Cpu, data: u8) {
+    compare(cpu, cpu.x, data)
+}
+
+pub fn cpy(cpu: &mut Cpu, data: u8) {
+    compare(cpu, cpu.y, data)
+}
+
+pub fn bit(cpu: &mut Cpu, data: u8) {
+    cpu.status.set_zero_from_value(cpu.acc & data);
+    cpu.status.set_negative_from_value(data);
+    cpu.status.set_overflow_to(data & (1 << 6) != 0);
+}
+
+// What `modify` applies, also applied to the accumulator through
+// `implied`.
+
+pub fn asl(cpu: &mut Cpu, data: u8) -> u8 {
+    cpu.status.set_carry_to(data & (1 << 7) != 0);
+    let data = data.wrapping_shl(1);
+    set_zero_and_negative(cpu, data);
+    data
+}
+
+pub fn lsr(cpu: &mut Cpu, data: u8) -> u8 {
+    cpu.status.set_carry_to(data & 1 != 0);
+    let data = data.wrapping_shr(1);
+    set_zero_and_negative(cpu, data);
+    data
+}
+
+pub fn rol(cpu: &mut Cpu, data: u8) -> u8 {
+    let carry = data & (1 << 7) != 0;
+    let data = data.wrapping_shl(1) | cpu.status.carry_value();
+    cpu.status.set_carry_to(carry);
+    set_zero_and_negative(cpu, data);
+    data
+}
+
+pub fn ror(cpu: &mut Cpu, data: u8) -> u8 {
+    let carry = data & 1 != 0;
+    let data = data.wrapping_shr(1) | cpu.status.carry_value().wrapping_shl(7);
+    cpu.status.set_carry_to(carry);
+    set_zero_and_negative(cpu, data);
+    data
+}
+
+pub fn inc(cpu: &mut Cpu, data: u8) -> u8 {
+    let data = data.wrapping_add(1);
+    set_zero_and_negative(cpu, data);
+    data
+}
+
+pub fn dec(cpu: &mut Cpu, data: u8) -> u8 {
+    let data = data.wrapping_sub(1);
+    set_zero_and_negative(cpu, data);
+    data
+}
+
+// What `implied` applies.
+
+pub fn inx(cpu: &mut Cpu) {
+    cpu.x = inc(cpu, cpu.x)
+}
+
+pub fn iny(cpu: &mut Cpu) {
+    cpu.y = inc(cpu, cpu.y)
+}
+
+pub fn dex(cpu: &mut Cpu) {
+    cpu.x = dec(cpu, cpu.x)
+}
+
+pub fn dey(cpu: &mut Cpu) {
+    cpu.y = dec(cpu, cpu.y)
+}
+
+pub fn tax(cpu: &mut Cpu) {
+    cpu.x = cpu.acc;
+    set_zero_and_negative(cpu, cpu.x)
+}
+
+pub fn tay(cpu: &mut Cpu) {
+    cpu.y = cpu.acc;
+    set_zero_and_negative(cpu, cpu.y)
+}
+
+pub fn txa(cpu: &mut Cpu) {
+    cpu.acc = cpu.x;
+    set_zero_and_negative(cpu, cpu.acc)
+}
+
+pub fn tya(cpu: &mut Cpu) {
+    cpu.acc = cpu.y;
+    set_zero_and_negative(cpu, cpu.acc)
+}
+
+pub fn tsx(cpu: &mut Cpu) {
+    cpu.x = cpu.sp;
+    set_zero_and_negative(cpu, cpu.x)
+}
+
+pub fn txs(cpu: &mut Cpu) {
+    cpu.sp = cpu.x
+}
+
+pub fn clc(cpu: &mut Cpu) {
+    cpu.status.clear_carry()
+}
+
+pub fn sec(cpu: &mut Cpu) {
+    cpu.status.set_carry()
+}
+
+pub fn cld(cpu: &mut Cpu) {
+    cpu.status.clear_decimal()
+}
+
+pub fn sed(cpu: &mut Cpu) {
+    cpu.status.set_decimal()
+}
+
+pub fn clv(cpu: &mut Cpu) {
+    cpu.status.clear_overflow()
+}
+
+pub fn nop(_: &mut Cpu) {}
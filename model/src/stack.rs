@@ -0,0 +1,129 @@
+//! A view of the hardware stack at $0100-$01FF, and a best-effort call
+//! stack from it. `JSR` pushes the address of its own last byte, so a pair
+//! of bytes on the stack is taken as a return address when the three bytes
+//! ending there are a `JSR`. Data pushed with `PHA` can look like one, and
+//! a routine which pulls its return address to use it as a pointer hides
+//! its frame, so the result is a guide rather than the truth.
+//!
+//! ```ignore
+//! let frames = stack::call_stack(&machine.cpu, &machine.memory);
+//! let mut out = String::new();
+//! stack::write_call_stack(&mut out, &frames, Some(&symbols))?;
+//! ```
+use crate::annotation::Annotations;
+use crate::machine::{Cpu, MemoryReadOnly};
+use crate::profile::name;
+use crate::{opcode, Address};
+use alloc::vec::Vec;
+use core::fmt;
+
+const STACK_PAGE: Address = 0x0100;
+
+/// The bytes in use on the stack, from the top (the last pushed) down to
+/// $01FF.
+pub fn bytes<M: MemoryReadOnly>(cpu: &Cpu, memory: &M) -> Vec<u8> {
+    (cpu.sp as Address + 1..=0xFF)
+        .map(|offset| memory.read_u8_read_only(STACK_PAGE + offset))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// Where the return address's low byte is on the stack.
+    pub at: Address,
+    /// The `JSR` which made the call.
+    pub call_site: Address,
+    /// The routine called, as the `JSR` gives it.
+    pub routine: Address,
+    /// Where the routine returns to, after the `JSR`.
+    pub return_address: Address,
+}
+
+/// The frames found on the stack, innermost first.
+pub fn call_stack<M: MemoryReadOnly>(cpu: &Cpu, memory: &M) -> Vec<Frame> {
+    let mut frames = Vec::new();
+    let mut offset = cpu.sp as Address + 1;
+    while offset < 0xFF {
+        let at = STACK_PAGE + offset;
+        let pushed = memory.read_u16_le_read_only(at);
+        let call_site = pushed.wrapping_sub(2);
+        if memory.read_u8_read_only(call_site) == opcode::jsr::ABSOLUTE {
+            frames.push(Frame {
+                at,
+                call_site,
+                routine: memory.read_u16_le_read_only(call_site.wrapping_add(1)),
+                return_address: pushed.wrapping_add(1),
+            });
+            offset += 2;
+        } else {
+            offset += 1;
+        }
+    }
+    frames
+}
+
+/// Writes one frame per line, innermost first, as
+/// `$01FC  print called from main+16`, naming addresses by the nearest
+/// bookmark in `symbols` if given, as `profile::name` does.
+pub fn write_call_stack<W: fmt::Write>(
+    out: &mut W,
+    frames: &[Frame],
+    symbols: Option<&Annotations>,
+) -> fmt::Result {
+    let empty = Annotations::new();
+    let symbols = symbols.unwrap_or(&empty);
+    for frame in frames {
+        writeln!(
+            out,
+            "${:04X}  {} called from {}",
+            frame.at,
+            name(symbols, frame.routine),
+            name(symbols, frame.call_site)
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::Ram;
+
+    #[test]
+    fn call_site_at_end_of_address_space() {
+        let mut ram = Ram::new();
+        let bytes = ram.as_mut_slice();
+        bytes[0x01FE..0x0200].copy_from_slice(&[0x00, 0x00]);
+        bytes[0xFFFE] = opcode::jsr::ABSOLUTE;
+        bytes[0xFFFF] = 0x34;
+        bytes[0x0000] = 0x12;
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xFD;
+        let frames = call_stack(&cpu, &ram);
+        assert_eq!(
+            frames,
+            [Frame {
+                at: 0x01FE,
+                call_site: 0xFFFE,
+                routine: 0x1234,
+                return_address: 0x0001,
+            }]
+        );
+    }
+
+    #[test]
+    fn finds_nested_calls() {
+        let mut ram = Ram::new();
+        let bytes = ram.as_mut_slice();
+        bytes[0x8000..0x8003].copy_from_slice(&[opcode::jsr::ABSOLUTE, 0x00, 0x90]);
+        bytes[0x9000..0x9003].copy_from_slice(&[opcode::jsr::ABSOLUTE, 0x00, 0xA0]);
+        bytes[0x01FC..0x0200].copy_from_slice(&[0x02, 0x90, 0x02, 0x80]);
+        let mut cpu = Cpu::new();
+        cpu.sp = 0xFB;
+        let routines: Vec<_> = call_stack(&cpu, &ram)
+            .iter()
+            .map(|frame| (frame.call_site, frame.routine))
+            .collect();
+        assert_eq!(routines, [(0x9000, 0xA000), (0x8000, 0x9000)]);
+    }
+}
@@ -24,11 +24,37 @@ pub mod flag {
 }
 const MASK: u8 = !(flag::BRK | flag::EXPANSION);
 
+/// The processor status. Only the six real flags are stored: B and the
+/// unused bit only exist on the stack, so they are added by
+/// `masked_with_brk_and_expansion` and `masked_with_expansion` when pushing,
+/// and dropped by `set` and `From<u8>` when pulling.
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Register {
     raw: u8,
 }
+
+/// The same type as `Register`, under the name used elsewhere in the crate.
+pub type Status = Register;
+
+impl From<u8> for Register {
+    fn from(value: u8) -> Self {
+        Self { raw: value & MASK }
+    }
+}
+
+/// The flags with the unused bit set and B clear, as an interrupt pushes
+/// them.
+impl From<Register> for u8 {
+    fn from(status: Register) -> Self {
+        status.masked_with_expansion()
+    }
+}
+impl Default for Register {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl Register {
     pub fn new() -> Self {
         Self {
@@ -38,6 +64,9 @@ impl Register {
     pub fn masked_with_brk_and_expansion(&self) -> u8 {
         self.raw | flag::BRK | flag::EXPANSION
     }
+    pub fn masked_with_expansion(&self) -> u8 {
+        self.raw | flag::EXPANSION
+    }
     pub fn set(&mut self, value: u8) {
         self.raw = value & MASK;
     }
@@ -50,7 +79,7 @@ impl Register {
     pub fn set_carry_to(&mut self, value: bool) {
         self.raw = ((value as u8) << bit::CARRY) | (self.raw & !flag::CARRY);
     }
-    pub fn is_carry(&self) -> bool {
+    pub fn carry(&self) -> bool {
         self.raw & flag::CARRY != 0
     }
     pub fn carry_value(&self) -> u8 {
@@ -62,19 +91,25 @@ impl Register {
     pub fn clear_decimal(&mut self) {
         self.raw &= !flag::DECIMAL;
     }
-    pub fn is_decimal(&self) -> bool {
+    pub fn decimal(&self) -> bool {
         self.raw & flag::DECIMAL != 0
     }
+    pub fn set_decimal_to(&mut self, value: bool) {
+        self.set_flag_to(flag::DECIMAL, value);
+    }
+    pub fn set_zero_to(&mut self, value: bool) {
+        self.set_flag_to(flag::ZERO, value);
+    }
     pub fn set_zero_from_value(&mut self, value: u8) {
         self.raw = (((value == 0) as u8) << bit::ZERO) | (self.raw & !flag::ZERO);
     }
-    pub fn is_zero(&self) -> bool {
+    pub fn zero(&self) -> bool {
         self.raw & flag::ZERO != 0
     }
     pub fn clear_overflow(&mut self) {
         self.raw &= !flag::OVERFLOW;
     }
-    pub fn is_overflow(&self) -> bool {
+    pub fn overflow(&self) -> bool {
         self.raw & flag::OVERFLOW != 0
     }
     pub fn set_overflow_to(&mut self, value: bool) {
@@ -83,9 +118,12 @@ impl Register {
     pub fn clear_negative(&mut self) {
         self.raw &= !flag::NEGATIVE;
     }
-    pub fn is_negative(&self) -> bool {
+    pub fn negative(&self) -> bool {
         self.raw & flag::NEGATIVE != 0
     }
+    pub fn set_negative_to(&mut self, value: bool) {
+        self.set_flag_to(flag::NEGATIVE, value);
+    }
     pub fn set_negative_from_value(&mut self, value: u8) {
         self.raw = (value & flag::NEGATIVE) | (self.raw & !flag::NEGATIVE);
     }
@@ -95,9 +133,43 @@ impl Register {
     pub fn clear_interrupt_disable(&mut self) {
         self.raw &= !flag::INTERRUPT_DISABLE;
     }
-    pub fn is_interrupt_disable(&self) -> bool {
+    pub fn set_interrupt_disable_to(&mut self, value: bool) {
+        self.set_flag_to(flag::INTERRUPT_DISABLE, value);
+    }
+    pub fn interrupt_disable(&self) -> bool {
         self.raw & flag::INTERRUPT_DISABLE != 0
     }
+    #[deprecated(note = "use `carry`")]
+    pub fn is_carry(&self) -> bool {
+        self.carry()
+    }
+    #[deprecated(note = "use `zero`")]
+    pub fn is_zero(&self) -> bool {
+        self.zero()
+    }
+    #[deprecated(note = "use `interrupt_disable`")]
+    pub fn is_interrupt_disable(&self) -> bool {
+        self.interrupt_disable()
+    }
+    #[deprecated(note = "use `decimal`")]
+    pub fn is_decimal(&self) -> bool {
+        self.decimal()
+    }
+    #[deprecated(note = "use `overflow`")]
+    pub fn is_overflow(&self) -> bool {
+        self.overflow()
+    }
+    #[deprecated(note = "use `negative`")]
+    pub fn is_negative(&self) -> bool {
+        self.negative()
+    }
+    fn set_flag_to(&mut self, flag: u8, value: bool) {
+        if value {
+            self.raw |= flag;
+        } else {
+            self.raw &= !flag;
+        }
+    }
 }
 use core::fmt;
 impl fmt::Debug for Register {
@@ -105,12 +177,36 @@ impl fmt::Debug for Register {
         write!(
             f,
             "[N={:?},V={:?},D={:?},I:{:?},Z:{:?},C:{:?}]",
-            self.is_negative() as u8,
-            self.is_overflow() as u8,
-            self.is_decimal() as u8,
-            self.is_interrupt_disable() as u8,
-            self.is_zero() as u8,
-            self.is_carry() as u8,
+            self.negative() as u8,
+            self.overflow() as u8,
+            self.decimal() as u8,
+            self.interrupt_disable() as u8,
+            self.zero() as u8,
+            self.carry() as u8,
+        )
+    }
+}
+
+/// The flags as `NV-BDIZC`, with set flags in upper case and clear ones in
+/// lower case. B is always shown clear.
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let letter = |set: bool, letter: char| {
+            if set {
+                letter
+            } else {
+                letter.to_ascii_lowercase()
+            }
+        };
+        write!(
+            f,
+            "{}{}-b{}{}{}{}",
+            letter(self.negative(), 'N'),
+            letter(self.overflow(), 'V'),
+            letter(self.decimal(), 'D'),
+            letter(self.interrupt_disable(), 'I'),
+            letter(self.zero(), 'Z'),
+            letter(self.carry(), 'C'),
         )
     }
 }
@@ -0,0 +1,117 @@
+//! Running a `Machine` a slice at a time, so it can share a thread with a
+//! UI or an event loop instead of blocking it. A `Stepper` is polled from a
+//! timer or animation frame callback; `Machine::run_async` wraps one in a
+//! future which yields to the executor after each slice.
+//!
+//! ```ignore
+//! // In an async task:
+//! let report = machine.run_async(Fuel::cycles(1_789_773)).slice(29_830).await?;
+//!
+//! // Or from a callback:
+//! match stepper.poll(&mut machine)? {
+//!     Poll::Ready(report) => finished(report),
+//!     Poll::Pending => request_animation_frame(again),
+//! }
+//! ```
+use crate::machine::{Fuel, Machine, Memory, RunReport, StepError, Stopped};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+/// Cycles a `RunFor` runs per poll unless told otherwise: about 10ms of a
+/// 1MHz CPU.
+pub const DEFAULT_SLICE: usize = 10_000;
+
+/// Runs until `fuel` runs out, `slice` cycles per call to `poll`.
+#[derive(Debug, Clone)]
+pub struct Stepper {
+    fuel: Fuel,
+    slice: usize,
+    progress: RunReport,
+}
+
+impl Stepper {
+    pub fn new(fuel: Fuel, slice: usize) -> Self {
+        Self {
+            fuel,
+            slice: slice.max(1),
+            progress: RunReport {
+                instructions: 0,
+                cycles: 0,
+                stopped: Stopped::OutOfFuel,
+            },
+        }
+    }
+    /// Everything run so far.
+    pub fn progress(&self) -> &RunReport {
+        &self.progress
+    }
+    /// Whether the last poll returned `Poll::Ready`, or would have.
+    pub fn is_done(&self) -> bool {
+        self.progress.instructions >= self.fuel.max_instructions
+            || self.progress.cycles >= self.fuel.max_cycles
+            || !matches!(self.progress.stopped, Stopped::OutOfFuel)
+    }
+    /// Runs the next slice, returning the whole run's report once the fuel
+    /// runs out, or the machine halts or a trap stops it. The last
+    /// instruction of a slice may take it a few cycles over, which comes
+    /// out of the next.
+    pub fn poll<M: Memory>(
+        &mut self,
+        machine: &mut Machine<M>,
+    ) -> Result<Poll<RunReport>, StepError> {
+        if !self.is_done() {
+            let cycles = self.fuel.max_cycles - self.progress.cycles;
+            let fuel = Fuel::new(
+                self.fuel.max_instructions - self.progress.instructions,
+                cycles.min(self.slice),
+            );
+            let report = machine.run(fuel)?;
+            self.progress.instructions += report.instructions;
+            self.progress.cycles += report.cycles;
+            self.progress.stopped = report.stopped;
+        }
+        Ok(if self.is_done() {
+            Poll::Ready(self.progress.clone())
+        } else {
+            Poll::Pending
+        })
+    }
+}
+
+/// The future from `Machine::run_async`.
+pub struct RunFor<'a, M> {
+    machine: &'a mut Machine<M>,
+    stepper: Stepper,
+}
+
+impl<'a, M> RunFor<'a, M> {
+    pub(crate) fn new(machine: &'a mut Machine<M>, fuel: Fuel) -> Self {
+        Self {
+            machine,
+            stepper: Stepper::new(fuel, DEFAULT_SLICE),
+        }
+    }
+    /// Runs `cycles` cycles before each yield instead of `DEFAULT_SLICE`.
+    pub fn slice(mut self, cycles: usize) -> Self {
+        self.stepper.slice = cycles.max(1);
+        self
+    }
+}
+
+impl<M: Memory> Future for RunFor<'_, M> {
+    type Output = Result<RunReport, StepError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        match this.stepper.poll(this.machine) {
+            Ok(Poll::Ready(report)) => Poll::Ready(Ok(report)),
+            Ok(Poll::Pending) => {
+                // Nothing outside is awaited, so ask to be polled again
+                // straight away, after the executor's other tasks.
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            Err(error) => Poll::Ready(Err(error)),
+        }
+    }
+}
@@ -0,0 +1,665 @@
+//! A closure-threaded backend, behind the `threaded` feature. Straight-line
+//! runs of code are translated once into blocks of closures, one per
+//! instruction, and then run as a chain without fetching or decoding. The
+//! common documented instructions are specialised as they are translated,
+//! with their operand and the address of the next instruction built in.
+//! The rest run the interpreter's handler for their opcode, with the fetch
+//! served from the bytes captured. Apart from the fetch, every instruction
+//! makes the accesses `Cpu::step` makes, dummy ones included.
+//!
+//! A block ends at the first instruction which can jump, or change the I
+//! flag, so interrupts only need checking between blocks. Nothing here
+//! takes them, and a caller checking between blocks takes an IRQ up to
+//! `MAX_BLOCK_INSTRUCTIONS` (32) instructions later than it would between
+//! calls to `Cpu::step`.
+//!
+//! Writes the CPU makes to translated code throw away the blocks covering
+//! it, and the block being run stops after the write. As with
+//! `decode_cache`, code changed by anything else must be invalidated by
+//! hand.
+//!
+//! Only the NMOS 6502 and 2A03 are translated; other variants are stepped
+//! one instruction at a time. On the loop in `benches/interpreter.rs` it
+//! has measured at 1.1-1.2x the speed of `Cpu::step` out of `Ram`, and
+//! 2.0-2.5x out of a `MemoryMap`, where not fetching through memory saves
+//! the most; there `decode_cache` manages 1.6-2.1x. Both vary from run to
+//! run.
+//!
+//! ```ignore
+//! let mut threaded = Threaded::new();
+//! let elapsed = threaded.run_cycles(&mut cpu, &mut memory, 1_000_000)?;
+//! ```
+use crate::addressing_mode::zero_page_address;
+use crate::debug::{AddressingMode, Instruction, InstructionType};
+use crate::machine::{Cpu, Fault, Memory, Variant};
+use crate::specialised::*;
+use crate::status::flag;
+use crate::{address, Address, UnknownOpcode};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+// Longest block translated, in instructions and so at most three times
+// that in bytes.
+const MAX_BLOCK_INSTRUCTIONS: usize = 32;
+const MAX_BLOCK_BYTES: Address = 3 * MAX_BLOCK_INSTRUCTIONS as Address;
+
+// Runs one instruction, leaving the program counter at the next.
+type Op<M> = Box<dyn Fn(&mut Cpu, &mut Context<M>) -> Result<u8, UnknownOpcode>>;
+
+struct Block<M> {
+    ops: Vec<Op<M>>,
+    // One past the last byte of the block.
+    end: Address,
+}
+
+// What a translated instruction runs against: memory, which bytes are
+// covered by some block, and the ones of those it wrote to.
+struct Context<'a, M> {
+    memory: &'a mut M,
+    code: &'a [bool; 0x10000],
+    written: &'a mut Vec<Address>,
+}
+
+impl<M: Memory> Context<'_, M> {
+    #[inline(always)]
+    fn wrote(&mut self, address: Address) {
+        if self.code[address as usize] {
+            self.written.push(address);
+        }
+    }
+}
+
+impl<M: Memory> Memory for Context<'_, M> {
+    #[inline(always)]
+    fn read_u8(&mut self, address: Address) -> u8 {
+        self.memory.read_u8(address)
+    }
+    #[inline(always)]
+    fn read_u8_dummy(&mut self, address: Address) {
+        self.memory.read_u8_dummy(address)
+    }
+    #[inline(always)]
+    fn write_u8(&mut self, address: Address, data: u8) {
+        self.wrote(address);
+        self.memory.write_u8(address, data)
+    }
+    #[inline(always)]
+    fn read_u8_zero_page(&mut self, address: u8) -> u8 {
+        self.memory.read_u8_zero_page(address)
+    }
+    #[inline(always)]
+    fn write_u8_zero_page(&mut self, address: u8, data: u8) {
+        self.wrote(zero_page_address(self.memory, address as Address));
+        self.memory.write_u8_zero_page(address, data)
+    }
+    fn read_u8_stack(&mut self, stack_pointer: u8) -> u8 {
+        self.memory.read_u8_stack(stack_pointer)
+    }
+    fn write_u8_stack(&mut self, stack_pointer: u8, data: u8) {
+        self.wrote(address::from_u8_lo_hi(stack_pointer, 0x01));
+        self.memory.write_u8_stack(stack_pointer, data)
+    }
+    fn take_fault(&mut self) -> Option<Fault> {
+        self.memory.take_fault()
+    }
+    fn set_mapping_register(&mut self, index: u8, bank: u8) {
+        self.memory.set_mapping_register(index, bank)
+    }
+    fn zero_page_base(&self) -> Address {
+        self.memory.zero_page_base()
+    }
+}
+
+// The specialised op for an instruction, falling back on its handler with
+// the fetch served from the bytes captured.
+fn specialise<M: Memory>(instruction: Instruction, pc: Address, bytes: [u8; 3]) -> Op<M> {
+    use InstructionType::*;
+    let byte = bytes[1];
+    let word = address::from_u8_lo_hi(bytes[1], bytes[2]);
+    let size = instruction.size();
+    let next = pc.wrapping_add(size as Address);
+    let cycles = instruction.cycles().0;
+    let handled: Op<M> = Box::new(move |cpu, context| interpret(cpu, context, &bytes[..size]));
+    macro_rules! op {
+        ($body:expr) => {
+            Box::new(move |cpu: &mut Cpu, context: &mut Context<M>| Ok($body(cpu, context)))
+        };
+    }
+    macro_rules! read {
+        ($apply:expr) => {
+            match instruction.addressing_mode() {
+                AddressingMode::Immediate => {
+                    op!(|cpu, context| read(cpu, context, Immediate(byte), next, cycles, $apply))
+                }
+                AddressingMode::ZeroPage => {
+                    op!(|cpu, context| read(cpu, context, ZeroPage(byte), next, cycles, $apply))
+                }
+                AddressingMode::ZeroPageXIndexed => op!(|cpu, context| read(
+                    cpu,
+                    context,
+                    ZeroPageIndexed(byte, X),
+                    next,
+                    cycles,
+                    $apply
+                )),
+                AddressingMode::ZeroPageYIndexed => op!(|cpu, context| read(
+                    cpu,
+                    context,
+                    ZeroPageIndexed(byte, Y),
+                    next,
+                    cycles,
+                    $apply
+                )),
+                AddressingMode::Absolute => {
+                    op!(|cpu, context| read(cpu, context, Absolute(word), next, cycles, $apply))
+                }
+                AddressingMode::AbsoluteXIndexed => op!(|cpu, context| read(
+                    cpu,
+                    context,
+                    AbsoluteIndexed(word, X),
+                    next,
+                    cycles,
+                    $apply
+                )),
+                AddressingMode::AbsoluteYIndexed => op!(|cpu, context| read(
+                    cpu,
+                    context,
+                    AbsoluteIndexed(word, Y),
+                    next,
+                    cycles,
+                    $apply
+                )),
+                AddressingMode::XIndexedIndirect => op!(|cpu, context| read(
+                    cpu,
+                    context,
+                    XIndexedIndirect(byte),
+                    next,
+                    cycles,
+                    $apply
+                )),
+                AddressingMode::IndirectYIndexed => op!(|cpu, context| read(
+                    cpu,
+                    context,
+                    IndirectYIndexed(byte),
+                    next,
+                    cycles,
+                    $apply
+                )),
+                _ => handled,
+            }
+        };
+    }
+    macro_rules! write {
+        ($register:ident) => {
+            match instruction.addressing_mode() {
+                AddressingMode::ZeroPage => op!(|cpu: &mut Cpu, context| {
+                    let data = cpu.$register;
+                    write(cpu, context, ZeroPage(byte), next, cycles, data)
+                }),
+                AddressingMode::ZeroPageXIndexed => op!(|cpu: &mut Cpu, context| {
+                    let data = cpu.$register;
+                    write(cpu, context, ZeroPageIndexed(byte, X), next, cycles, data)
+                }),
+                AddressingMode::ZeroPageYIndexed => op!(|cpu: &mut Cpu, context| {
+                    let data = cpu.$register;
+                    write(cpu, context, ZeroPageIndexed(byte, Y), next, cycles, data)
+                }),
+                AddressingMode::Absolute => op!(|cpu: &mut Cpu, context| {
+                    let data = cpu.$register;
+                    write(cpu, context, Absolute(word), next, cycles, data)
+                }),
+                AddressingMode::AbsoluteXIndexed => op!(|cpu: &mut Cpu, context| {
+                    let data = cpu.$register;
+                    write(cpu, context, AbsoluteIndexed(word, X), next, cycles, data)
+                }),
+                AddressingMode::AbsoluteYIndexed => op!(|cpu: &mut Cpu, context| {
+                    let data = cpu.$register;
+                    write(cpu, context, AbsoluteIndexed(word, Y), next, cycles, data)
+                }),
+                AddressingMode::XIndexedIndirect => op!(|cpu: &mut Cpu, context| {
+                    let data = cpu.$register;
+                    write(cpu, context, XIndexedIndirect(byte), next, cycles, data)
+                }),
+                AddressingMode::IndirectYIndexed => op!(|cpu: &mut Cpu, context| {
+                    let data = cpu.$register;
+                    write(cpu, context, IndirectYIndexed(byte), next, cycles, data)
+                }),
+                _ => handled,
+            }
+        };
+    }
+    macro_rules! modify {
+        ($apply:expr) => {
+            match instruction.addressing_mode() {
+                AddressingMode::Accumulator => {
+                    implied!(|cpu: &mut Cpu| cpu.acc = $apply(cpu, cpu.acc))
+                }
+                AddressingMode::ZeroPage => {
+                    op!(|cpu, context| modify(cpu, context, ZeroPage(byte), next, cycles, $apply))
+                }
+                AddressingMode::ZeroPageXIndexed => op!(|cpu, context| modify(
+                    cpu,
+                    context,
+                    ZeroPageIndexed(byte, X),
+                    next,
+                    cycles,
+                    $apply
+                )),
+                AddressingMode::Absolute => {
+                    op!(|cpu, context| modify(cpu, context, Absolute(word), next, cycles, $apply))
+                }
+                AddressingMode::AbsoluteXIndexed => op!(|cpu, context| modify(
+                    cpu,
+                    context,
+                    AbsoluteIndexed(word, X),
+                    next,
+                    cycles,
+                    $apply
+                )),
+                _ => handled,
+            }
+        };
+    }
+    macro_rules! implied {
+        ($apply:expr) => {
+            op!(|cpu, context| implied(cpu, context, next, cycles, $apply))
+        };
+    }
+    macro_rules! branch {
+        ($taken:expr) => {
+            op!(|cpu: &mut Cpu, context| {
+                let taken = $taken(&*cpu);
+                branch(cpu, context, next, byte as i8, taken)
+            })
+        };
+    }
+    match instruction.instruction_type() {
+        Lda => read!(lda),
+        Ldx => read!(ldx),
+        Ldy => read!(ldy),
+        And => read!(and),
+        Ora => read!(ora),
+        Eor => read!(eor),
+        Adc => read!(adc),
+        Sbc => read!(sbc),
+        Cmp => read!(cmp),
+        Cpx => read!(cpx),
+        Cpy => read!(cpy),
+        Bit => read!(bit),
+        Sta => write!(acc),
+        Stx => write!(x),
+        Sty => write!(y),
+        Asl => modify!(asl),
+        Lsr => modify!(lsr),
+        Rol => modify!(rol),
+        Ror => modify!(ror),
+        Inc => modify!(inc),
+        Dec => modify!(dec),
+        Inx => implied!(inx),
+        Iny => implied!(iny),
+        Dex => implied!(dex),
+        Dey => implied!(dey),
+        Tax => implied!(tax),
+        Tay => implied!(tay),
+        Txa => implied!(txa),
+        Tya => implied!(tya),
+        Tsx => implied!(tsx),
+        Txs => implied!(txs),
+        Clc => implied!(clc),
+        Sec => implied!(sec),
+        Cld => implied!(cld),
+        Sed => implied!(sed),
+        Clv => implied!(clv),
+        Nop if instruction.addressing_mode() == AddressingMode::Implied => implied!(nop),
+        Bcc => branch!(|cpu: &Cpu| !cpu.status.carry()),
+        Bcs => branch!(|cpu: &Cpu| cpu.status.carry()),
+        Bne => branch!(|cpu: &Cpu| !cpu.status.zero()),
+        Beq => branch!(|cpu: &Cpu| cpu.status.zero()),
+        Bpl => branch!(|cpu: &Cpu| !cpu.status.negative()),
+        Bmi => branch!(|cpu: &Cpu| cpu.status.negative()),
+        Bvc => branch!(|cpu: &Cpu| !cpu.status.overflow()),
+        Bvs => branch!(|cpu: &Cpu| cpu.status.overflow()),
+        Jmp if instruction.addressing_mode() == AddressingMode::Absolute => {
+            op!(|cpu, _| jump(cpu, word))
+        }
+        _ => handled,
+    }
+}
+
+/// What one call to `Threaded::run_block` did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockReport {
+    pub instructions: usize,
+    pub cycles: usize,
+}
+
+pub struct Threaded<M> {
+    // Indexed by start address.
+    blocks: Vec<Option<Block<M>>>,
+    translated: usize,
+    // Which bytes are covered by some block.
+    code: Box<[bool; 0x10000]>,
+    // The bytes of code the instruction being run wrote to.
+    written: Vec<Address>,
+}
+
+impl<M: Memory> Default for Threaded<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: Memory> Threaded<M> {
+    pub fn new() -> Self {
+        let Ok(code) = alloc::vec![false; 0x10000].into_boxed_slice().try_into() else {
+            unreachable!()
+        };
+        Self {
+            blocks: (0..0x10000).map(|_| None).collect(),
+            translated: 0,
+            code,
+            written: Vec::new(),
+        }
+    }
+    // Translates the block starting at `start`, which is empty if the first
+    // opcode isn't valid.
+    fn translate(&mut self, start: Address, memory: &mut M) -> Block<M> {
+        let mut ops = Vec::new();
+        let mut pc = start;
+        while ops.len() < MAX_BLOCK_INSTRUCTIONS {
+            let opcode = memory.read_u8(pc);
+            let Ok(instruction) = Instruction::from_opcode(opcode) else {
+                break;
+            };
+            let size = instruction.size() as Address;
+            // Blocks don't wrap past $FFFF.
+            let Some(next) = pc.checked_add(size) else {
+                break;
+            };
+            let mut bytes = [opcode, 0, 0];
+            for (i, byte) in bytes.iter_mut().enumerate().take(size as usize).skip(1) {
+                *byte = memory.read_u8(pc + i as Address);
+            }
+            ops.push(specialise(instruction, pc, bytes));
+            self.code[pc as usize..next as usize].fill(true);
+            pc = next;
+            let instruction_type = instruction.instruction_type();
+            if instruction_type.is_control_flow()
+                || instruction_type.flags_modified() & flag::INTERRUPT_DISABLE != 0
+            {
+                break;
+            }
+        }
+        Block { ops, end: pc }
+    }
+    /// Runs the block at the program counter, translating it first if
+    /// needed.
+    pub fn run_block(
+        &mut self,
+        cpu: &mut Cpu,
+        memory: &mut M,
+    ) -> Result<BlockReport, UnknownOpcode> {
+        if !matches!(cpu.variant, Variant::Nmos6502 | Variant::Ricoh2A03) {
+            let cycles = cpu.step(memory)? as usize;
+            return Ok(BlockReport {
+                instructions: 1,
+                cycles,
+            });
+        }
+        let start = cpu.pc as usize;
+        if self.blocks[start].is_none() {
+            let block = self.translate(cpu.pc, memory);
+            if block.ops.is_empty() {
+                // Let the interpreter report the bad opcode.
+                let cycles = cpu.step(memory)? as usize;
+                return Ok(BlockReport {
+                    instructions: 1,
+                    cycles,
+                });
+            }
+            self.blocks[start] = Some(block);
+            self.translated += 1;
+        }
+        let Some(block) = &self.blocks[start] else {
+            unreachable!()
+        };
+        let mut context = Context {
+            memory,
+            code: &self.code,
+            written: &mut self.written,
+        };
+        let mut report = BlockReport::default();
+        let mut result = Ok(());
+        for op in &block.ops {
+            match op(cpu, &mut context) {
+                Ok(cycles) => report.cycles += cycles as usize,
+                Err(error) => {
+                    result = Err(error);
+                    break;
+                }
+            }
+            report.instructions += 1;
+            if !context.written.is_empty() {
+                break;
+            }
+        }
+        let written = core::mem::take(&mut self.written);
+        for &address in &written {
+            self.invalidate(address);
+        }
+        // Keep the allocation for next time.
+        self.written = written;
+        self.written.clear();
+        result.map(|()| report)
+    }
+    /// Runs blocks until at least `cycles` cycles have passed, returning
+    /// the exact count.
+    pub fn run_cycles(
+        &mut self,
+        cpu: &mut Cpu,
+        memory: &mut M,
+        cycles: usize,
+    ) -> Result<usize, UnknownOpcode> {
+        let mut elapsed = 0;
+        while elapsed < cycles {
+            elapsed += self.run_block(cpu, memory)?.cycles;
+        }
+        Ok(elapsed)
+    }
+    /// Throws away every block which includes the byte at `address`.
+    pub fn invalidate(&mut self, address: Address) {
+        let first = address.saturating_sub(MAX_BLOCK_BYTES);
+        let mut end = address;
+        for start in first..=address {
+            let block = &mut self.blocks[start as usize];
+            if let Some(removed) = block.take_if(|block| block.end > address) {
+                self.translated -= 1;
+                self.code[start as usize..removed.end as usize].fill(false);
+                end = end.max(removed.end);
+            }
+        }
+        // Blocks which overlap the ones thrown away still cover some of
+        // their bytes.
+        let first = first.saturating_sub(MAX_BLOCK_BYTES);
+        for start in first..end {
+            if let Some(block) = &self.blocks[start as usize] {
+                self.code[start as usize..block.end as usize].fill(true);
+            }
+        }
+    }
+    pub fn clear(&mut self) {
+        self.blocks.fill_with(|| None);
+        self.translated = 0;
+        self.code.fill(false);
+    }
+    /// The number of blocks currently translated.
+    pub fn blocks(&self) -> usize {
+        self.translated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::Ram;
+    use crate::opcode;
+
+    #[test]
+    fn invalidate_stops_watching_for_writes() {
+        let mut ram = Ram::new();
+        // LDA #1; STA $0300; STA $0300; RTS, and a block of NOPs at $0300.
+        ram.write_block(
+            0x0200,
+            &[0xA9, 0x01, 0x8D, 0x00, 0x03, 0x8D, 0x00, 0x03, 0x60],
+        );
+        ram.write_block(0x0300, &[0xEA, 0x60]);
+        let mut threaded = Threaded::new();
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x0300;
+        threaded.run_block(&mut cpu, &mut ram).unwrap();
+        threaded.invalidate(0x0300);
+        assert_eq!(threaded.blocks(), 0);
+        // $0300 isn't code any more, so the stores don't stop the block.
+        cpu.pc = 0x0200;
+        assert_eq!(
+            threaded.run_block(&mut cpu, &mut ram).unwrap().instructions,
+            4
+        );
+    }
+
+    #[test]
+    fn invalidate_keeps_overlapping_blocks_watched() {
+        let mut ram = Ram::new();
+        // Two blocks sharing their last bytes: NOP; NOP; RTS from $0300
+        // and $0301.
+        ram.write_block(0x0300, &[0xEA, 0xEA, 0x60]);
+        // STA $0302; STA $0300; RTS.
+        ram.write_block(0x0200, &[0x8D, 0x02, 0x03, 0x8D, 0x00, 0x03, 0x60]);
+        let mut threaded = Threaded::new();
+        let mut cpu = Cpu::new();
+        for pc in [0x0300, 0x0301] {
+            cpu.pc = pc;
+            threaded.run_block(&mut cpu, &mut ram).unwrap();
+        }
+        threaded.invalidate(0x0300);
+        assert_eq!(threaded.blocks(), 1);
+        // $0302 is still covered by the block at $0301.
+        cpu.pc = 0x0200;
+        assert_eq!(
+            threaded.run_block(&mut cpu, &mut ram).unwrap().instructions,
+            1
+        );
+    }
+
+    #[test]
+    fn writing_code_retranslates_it() {
+        let mut ram = Ram::new();
+        // INC $0204; LDA #0; RTS: the INC turns the LDA into LDA #1.
+        ram.write_block(0x0200, &[0xEE, 0x04, 0x02, 0xA9, 0x00, 0x60]);
+        let mut threaded = Threaded::new();
+        let mut cpu = Cpu::new();
+        for _ in 0..2 {
+            cpu.pc = 0x0200;
+            threaded.run_block(&mut cpu, &mut ram).unwrap();
+            threaded.run_block(&mut cpu, &mut ram).unwrap();
+        }
+        assert_eq!(cpu.acc, 2);
+    }
+
+    // Ram which logs every access but the reads of `code`.
+    struct Logged {
+        ram: Ram,
+        code: Vec<bool>,
+        log: Vec<(Address, u8, bool)>,
+    }
+
+    impl Memory for Logged {
+        fn read_u8(&mut self, address: Address) -> u8 {
+            let data = self.ram.read_u8(address);
+            if !self.code[address as usize] {
+                self.log.push((address, data, false));
+            }
+            data
+        }
+        fn write_u8(&mut self, address: Address, data: u8) {
+            self.log.push((address, data, true));
+            self.ram.write_u8(address, data)
+        }
+    }
+
+    // Runs random code both ways, from the same state, and checks they end
+    // up in the same state having made the same accesses.
+    #[test]
+    fn runs_as_the_interpreter_does() {
+        let mut seed = 0x2545_F491_4F6C_DD1Du64;
+        let mut random = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as u8
+        };
+        for _ in 0..500 {
+            let mut program = Vec::new();
+            while program.len() < 0x80 {
+                let opcode = random();
+                if let Ok(instruction) = Instruction::from_opcode(opcode) {
+                    program.push(opcode);
+                    for _ in 1..instruction.size() {
+                        program.push(random());
+                    }
+                }
+            }
+            // Jams, so that translation never reads past the program.
+            program.extend_from_slice(&[opcode::kil::unofficial0::IMPLIED; 3]);
+            let mut ram = Ram::new();
+            for address in 0..0x100 {
+                ram.write_u8(address, random());
+            }
+            ram.write_block(0x0200, &program);
+            let mut cpu = Cpu::new();
+            cpu.pc = 0x0200;
+            cpu.acc = random();
+            cpu.x = random();
+            cpu.y = random();
+            cpu.sp = random();
+            cpu.status.set(random());
+            cpu.quirks.rmw_dummy_write = random() & 1 != 0;
+            cpu.quirks.zero_page_wrap = random() & 1 != 0;
+
+            // The program's bytes read the same either way, apart from the
+            // fetches, so its reads are left out of the logs.
+            let mut code = alloc::vec![false; 0x10000];
+            code[0x0200..0x0200 + program.len()].fill(true);
+            let logged = |ram: &Ram| Logged {
+                ram: ram.clone(),
+                code: code.clone(),
+                log: Vec::new(),
+            };
+            let (mut our_memory, mut their_memory) = (logged(&ram), logged(&ram));
+            let mut threaded = Threaded::new();
+            let (mut ours, mut theirs) = (cpu.clone(), cpu);
+            // Branches can leave the program, where the bytes read as code
+            // would be logged one way and not the other.
+            for _ in 0..64 {
+                if !code[ours.pc as usize] {
+                    break;
+                }
+                let Ok(report) = threaded.run_block(&mut ours, &mut our_memory) else {
+                    break;
+                };
+                let mut cycles = 0;
+                for _ in 0..report.instructions {
+                    cycles += theirs.step(&mut their_memory).unwrap() as usize;
+                }
+                assert_eq!(cycles, report.cycles, "{:02X?}", program);
+                assert_eq!(
+                    alloc::format!("{:?}", ours),
+                    alloc::format!("{:?}", theirs),
+                    "{:02X?}",
+                    program
+                );
+                assert_eq!(our_memory.log, their_memory.log, "{:02X?}", program);
+            }
+        }
+    }
+}
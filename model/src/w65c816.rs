@@ -0,0 +1,366 @@
+//! The 65C816 in emulation mode, used as `Variant::W65C816`. On top of the
+//! NMOS 6502 instruction set this adds the 65C816's own instructions that
+//! make sense with 8-bit registers, the direct page register, and a few of
+//! the 65C02 additions: `BRA`, `STZ`, `TSB`, `TRB`, `INC A`, `DEC A` and
+//! the `X` and `Y` pushes and pulls. The 65C02's `(zp)` addressing and the
+//! new modes of `BIT` and `JMP`, `MVN`, `MVP` and `WAI` aren't there yet,
+//! and nor are the long addressing modes other than `JSL`, `JML` and
+//! `RTL`.
+//!
+//! Memory only sees 16-bit addresses, so the bank registers are kept but
+//! don't affect accesses. `XCE` can clear the E flag, but everything keeps
+//! running as in emulation mode, and `REP` and `SEP` can't touch the M and
+//! X bits, just as they can't in emulation mode.
+use crate::addressing_mode::{
+    Absolute, AbsoluteXIndexed, ReadData, WriteData, ZeroPage, ZeroPageXIndexed,
+};
+use crate::huc6280::Relocated;
+use crate::machine::{Cpu, Memory};
+use crate::{address, Address, UnknownOpcode};
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+/// The vector `COP` jumps through in emulation mode.
+pub const COP_LO: Address = 0xFFF4;
+
+pub const COP: u8 = 0x02;
+pub const TSB_ZERO_PAGE: u8 = 0x04;
+pub const PHD: u8 = 0x0B;
+pub const TSB_ABSOLUTE: u8 = 0x0C;
+pub const TRB_ZERO_PAGE: u8 = 0x14;
+pub const INC_ACCUMULATOR: u8 = 0x1A;
+pub const TCS: u8 = 0x1B;
+pub const TRB_ABSOLUTE: u8 = 0x1C;
+pub const JSL: u8 = 0x22;
+pub const PLD: u8 = 0x2B;
+pub const DEC_ACCUMULATOR: u8 = 0x3A;
+pub const TSC: u8 = 0x3B;
+pub const WDM: u8 = 0x42;
+pub const PHK: u8 = 0x4B;
+pub const PHY: u8 = 0x5A;
+pub const TCD: u8 = 0x5B;
+pub const JML: u8 = 0x5C;
+pub const PER: u8 = 0x62;
+pub const STZ_ZERO_PAGE: u8 = 0x64;
+pub const RTL: u8 = 0x6B;
+pub const STZ_ZERO_PAGE_X_INDEXED: u8 = 0x74;
+pub const PLY: u8 = 0x7A;
+pub const TDC: u8 = 0x7B;
+pub const BRA: u8 = 0x80;
+pub const BRL: u8 = 0x82;
+pub const PHB: u8 = 0x8B;
+pub const TXY: u8 = 0x9B;
+pub const STZ_ABSOLUTE: u8 = 0x9C;
+pub const STZ_ABSOLUTE_X_INDEXED: u8 = 0x9E;
+pub const PLB: u8 = 0xAB;
+pub const TYX: u8 = 0xBB;
+pub const REP: u8 = 0xC2;
+pub const PEI: u8 = 0xD4;
+pub const PHX: u8 = 0xDA;
+/// Stops the clock, which `Machine` treats as a halt.
+pub const STP: u8 = 0xDB;
+pub const SEP: u8 = 0xE2;
+pub const XBA: u8 = 0xEB;
+pub const PEA: u8 = 0xF4;
+pub const PLX: u8 = 0xFA;
+pub const XCE: u8 = 0xFB;
+
+/// The registers the 65C816 has beyond those of the 6502.
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    /// The E flag, which `XCE` swaps with carry.
+    pub emulation: bool,
+    /// The high byte of the accumulator, swapped in by `XBA`.
+    pub b: u8,
+    pub direct: u16,
+    pub data_bank: u8,
+    pub program_bank: u8,
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self {
+            emulation: true,
+            b: 0,
+            direct: 0,
+            data_bank: 0,
+            program_bank: 0,
+        }
+    }
+}
+
+fn operand_u8<M: Memory>(cpu: &Cpu, memory: &mut M) -> u8 {
+    memory.read_u8(cpu.pc.wrapping_add(1))
+}
+
+fn operand_u16<M: Memory>(cpu: &Cpu, memory: &mut M) -> u16 {
+    memory.read_u16_le(cpu.pc.wrapping_add(1))
+}
+
+impl Cpu {
+    fn push_u16<M: Memory>(&mut self, memory: &mut M, value: u16) {
+        self.push_stack_u8(memory, address::hi(value));
+        self.push_stack_u8(memory, address::lo(value));
+    }
+    fn pop_u16<M: Memory>(&mut self, memory: &mut M) -> u16 {
+        let lo = self.pop_stack_u8(memory);
+        let hi = self.pop_stack_u8(memory);
+        address::from_u8_lo_hi(lo, hi)
+    }
+    fn set_flags_from_u8(&mut self, value: u8) {
+        self.status.set_negative_from_value(value);
+        self.status.set_zero_from_value(value);
+    }
+    fn set_flags_from_u16(&mut self, value: u16) {
+        self.status.set_negative_from_value(address::hi(value));
+        self.status.set_zero_from_value((value != 0) as u8);
+    }
+    // `TSB` and `TRB`: Z from the bits in common with the accumulator, then
+    // those bits are set or cleared.
+    fn test_bits<A: ReadData + WriteData, M: Memory>(&mut self, memory: &mut M, set: bool) {
+        let data = A::read_data(self, memory);
+        self.status.set_zero_from_value(data & self.acc);
+        let data = if set {
+            data | self.acc
+        } else {
+            data & !self.acc
+        };
+        A::write_data(self, memory, data);
+    }
+    pub(crate) fn step_w65c816<M: Memory>(
+        &mut self,
+        opcode: u8,
+        memory: &mut M,
+    ) -> Result<u8, UnknownOpcode> {
+        let memory = &mut Relocated(memory, self.w65c816.direct);
+        let (bytes, cycles) = match opcode {
+            XCE => {
+                let carry = self.status.carry();
+                self.status.set_carry_to(self.w65c816.emulation);
+                self.w65c816.emulation = carry;
+                (1, 2)
+            }
+            REP => {
+                let mask = operand_u8(self, memory);
+                self.status.set(self.status.masked_with_expansion() & !mask);
+                (2, 3)
+            }
+            SEP => {
+                let mask = operand_u8(self, memory);
+                self.status.set(self.status.masked_with_expansion() | mask);
+                (2, 3)
+            }
+            XBA => {
+                core::mem::swap(&mut self.acc, &mut self.w65c816.b);
+                self.set_flags_from_u8(self.acc);
+                (1, 3)
+            }
+            TCD => {
+                self.w65c816.direct = address::from_u8_lo_hi(self.acc, self.w65c816.b);
+                self.set_flags_from_u16(self.w65c816.direct);
+                (1, 2)
+            }
+            TDC => {
+                let direct = self.w65c816.direct;
+                self.acc = address::lo(direct);
+                self.w65c816.b = address::hi(direct);
+                self.set_flags_from_u16(direct);
+                (1, 2)
+            }
+            TCS => {
+                self.sp = self.acc;
+                (1, 2)
+            }
+            TSC => {
+                self.acc = self.sp;
+                self.w65c816.b = 0x01;
+                self.set_flags_from_u16(address::from_u8_lo_hi(self.sp, 0x01));
+                (1, 2)
+            }
+            TXY => {
+                self.y = self.x;
+                self.set_flags_from_u8(self.y);
+                (1, 2)
+            }
+            TYX => {
+                self.x = self.y;
+                self.set_flags_from_u8(self.x);
+                (1, 2)
+            }
+            PHB => {
+                self.push_stack_u8(memory, self.w65c816.data_bank);
+                (1, 3)
+            }
+            PLB => {
+                self.w65c816.data_bank = self.pop_stack_u8(memory);
+                self.set_flags_from_u8(self.w65c816.data_bank);
+                (1, 4)
+            }
+            PHK => {
+                self.push_stack_u8(memory, self.w65c816.program_bank);
+                (1, 3)
+            }
+            PHD => {
+                self.push_u16(memory, self.w65c816.direct);
+                (1, 4)
+            }
+            PLD => {
+                self.w65c816.direct = self.pop_u16(memory);
+                self.set_flags_from_u16(self.w65c816.direct);
+                (1, 5)
+            }
+            PHX => {
+                self.push_stack_u8(memory, self.x);
+                (1, 3)
+            }
+            PHY => {
+                self.push_stack_u8(memory, self.y);
+                (1, 3)
+            }
+            PLX => {
+                self.x = self.pop_stack_u8(memory);
+                self.set_flags_from_u8(self.x);
+                (1, 4)
+            }
+            PLY => {
+                self.y = self.pop_stack_u8(memory);
+                self.set_flags_from_u8(self.y);
+                (1, 4)
+            }
+            PEA => {
+                let value = operand_u16(self, memory);
+                self.push_u16(memory, value);
+                (3, 5)
+            }
+            PEI => {
+                let pointer = operand_u8(self, memory);
+                let value = memory.read_u16_le_zero_page(pointer);
+                self.push_u16(memory, value);
+                (2, 6)
+            }
+            PER => {
+                let offset = operand_u16(self, memory);
+                let value = self.pc.wrapping_add(3).wrapping_add(offset);
+                self.push_u16(memory, value);
+                (3, 6)
+            }
+            WDM => (2, 2),
+            STZ_ZERO_PAGE => {
+                ZeroPage::write_data(self, memory, 0);
+                (2, 3)
+            }
+            STZ_ZERO_PAGE_X_INDEXED => {
+                ZeroPageXIndexed::write_data(self, memory, 0);
+                (2, 4)
+            }
+            STZ_ABSOLUTE => {
+                Absolute::write_data(self, memory, 0);
+                (3, 4)
+            }
+            STZ_ABSOLUTE_X_INDEXED => {
+                AbsoluteXIndexed::write_data(self, memory, 0);
+                (3, 5)
+            }
+            TSB_ZERO_PAGE => {
+                self.test_bits::<ZeroPage, _>(memory, true);
+                (2, 5)
+            }
+            TSB_ABSOLUTE => {
+                self.test_bits::<Absolute, _>(memory, true);
+                (3, 6)
+            }
+            TRB_ZERO_PAGE => {
+                self.test_bits::<ZeroPage, _>(memory, false);
+                (2, 5)
+            }
+            TRB_ABSOLUTE => {
+                self.test_bits::<Absolute, _>(memory, false);
+                (3, 6)
+            }
+            INC_ACCUMULATOR => {
+                self.acc = self.acc.wrapping_add(1);
+                self.set_flags_from_u8(self.acc);
+                (1, 2)
+            }
+            DEC_ACCUMULATOR => {
+                self.acc = self.acc.wrapping_sub(1);
+                self.set_flags_from_u8(self.acc);
+                (1, 2)
+            }
+            BRA => {
+                let next = self.pc.wrapping_add(2);
+                let target = next.wrapping_add(operand_u8(self, memory) as i8 as Address);
+                self.pc = target;
+                return Ok(3 + address::on_different_pages(next, target) as u8);
+            }
+            BRL => {
+                let offset = operand_u16(self, memory);
+                self.pc = self.pc.wrapping_add(3).wrapping_add(offset);
+                return Ok(4);
+            }
+            JML => {
+                let target = operand_u16(self, memory);
+                self.w65c816.program_bank = memory.read_u8(self.pc.wrapping_add(3));
+                self.pc = target;
+                return Ok(4);
+            }
+            JSL => {
+                let target = operand_u16(self, memory);
+                let bank = memory.read_u8(self.pc.wrapping_add(3));
+                self.push_stack_u8(memory, self.w65c816.program_bank);
+                self.push_u16(memory, self.pc.wrapping_add(3));
+                self.w65c816.program_bank = bank;
+                self.pc = target;
+                return Ok(8);
+            }
+            RTL => {
+                self.pc = self.pop_u16(memory).wrapping_add(1);
+                self.w65c816.program_bank = self.pop_stack_u8(memory);
+                return Ok(6);
+            }
+            COP => {
+                self.push_u16(memory, self.pc.wrapping_add(2));
+                self.push_stack_u8(memory, self.status.masked_with_brk_and_expansion());
+                self.status.set_interrupt_disable();
+                self.status.clear_decimal();
+                self.pc = memory.read_u16_le(COP_LO);
+                return Ok(7);
+            }
+            STP => return Err(UnknownOpcode(opcode)),
+            _ => return self.execute(opcode, memory),
+        };
+        self.pc = self.pc.wrapping_add(bytes);
+        Ok(cycles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::{Ram, Variant};
+    use crate::opcode;
+
+    #[test]
+    fn read_modify_write_uses_the_direct_page() {
+        let mut ram = Ram::new();
+        #[rustfmt::skip]
+        ram.write_block(0x8000, &[
+            opcode::inc::ZERO_PAGE, 0x10,
+            opcode::rol::ZERO_PAGE_X_INDEXED, 0x10,
+            opcode::lda::ZERO_PAGE, 0x10,
+        ]);
+        ram.write_block(0x0310, &[0x41, 0x42]);
+        let mut cpu = Cpu::new();
+        cpu.variant = Variant::W65C816;
+        cpu.w65c816.direct = 0x0300;
+        cpu.pc = 0x8000;
+        cpu.x = 1;
+        for _ in 0..3 {
+            cpu.step(&mut ram).unwrap();
+        }
+        assert_eq!(cpu.acc, 0x42);
+        assert_eq!(&ram.as_slice()[0x0310..0x0312], &[0x42, 0x84]);
+        assert_eq!(&ram.as_slice()[0x0010..0x0012], &[0, 0]);
+    }
+}
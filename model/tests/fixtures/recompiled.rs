@@ -0,0 +1,51 @@
+// Recompiled from 272 bytes at $C000.
+use portal_solutions_mos6502_model::machine::{Cpu, Memory};
+use portal_solutions_mos6502_model::specialised::*;
+use portal_solutions_mos6502_model::UnknownOpcode;
+
+pub fn run<M: Memory>(cpu: &mut Cpu, memory: &mut M, cycles: u64) -> Result<u64, UnknownOpcode> {
+    let mut elapsed = 0;
+    while elapsed < cycles {
+        elapsed += match cpu.pc {
+            0xC000 => {
+                let mut cycles = 0;
+                cycles += read(cpu, memory, Immediate(0x00), 0xC002, 2, ldx) as u64; // C000  Ldx(Immediate) 00
+                cycles += read(cpu, memory, Immediate(0x10), 0xC004, 2, ldy) as u64; // C002  Ldy(Immediate) 10
+                cycles += implied(cpu, memory, 0xC005, 2, clc) as u64; // C004  Clc(Implied)
+                cycles
+            }
+            0xC005 => {
+                let mut cycles = 0;
+                cycles += read(cpu, memory, AbsoluteIndexed(0xC100, X), 0xC008, 4, lda) as u64; // C005  Lda(AbsoluteXIndexed) C100
+                cycles += read(cpu, memory, Immediate(0x07), 0xC00A, 2, adc) as u64; // C008  Adc(Immediate) 07
+                cycles += write(cpu, memory, AbsoluteIndexed(0x0200, X), 0xC00D, 5, cpu.acc) as u64; // C00A  Sta(AbsoluteXIndexed) 0200
+                cycles += modify(cpu, memory, AbsoluteIndexed(0x0200, X), 0xC010, 7, asl) as u64; // C00D  Asl(AbsoluteXIndexed) 0200
+                cycles += interpret(cpu, memory, &[0x20, 0x30, 0xC0])? as u64; // C010  Jsr(Absolute) C030
+                cycles
+            }
+            0xC013 => {
+                let mut cycles = 0;
+                cycles += implied(cpu, memory, 0xC014, 2, inx) as u64; // C013  Inx(Implied)
+                cycles += implied(cpu, memory, 0xC015, 2, dey) as u64; // C014  Dey(Implied)
+                cycles += branch(cpu, memory, 0xC017, -18, !cpu.status.zero()) as u64; // C015  Bne(Relative) EE
+                cycles
+            }
+            0xC017 => {
+                let mut cycles = 0;
+                cycles += jump(cpu, 0xC000) as u64; // C017  Jmp(Absolute) C000
+                cycles
+            }
+            0xC030 => {
+                let mut cycles = 0;
+                cycles += write(cpu, memory, ZeroPage(0x10), 0xC032, 3, cpu.acc) as u64; // C030  Sta(ZeroPage) 10
+                cycles += modify(cpu, memory, ZeroPage(0x11), 0xC034, 5, inc) as u64; // C032  Inc(ZeroPage) 11
+                cycles += read(cpu, memory, ZeroPage(0x11), 0xC036, 3, lda) as u64; // C034  Lda(ZeroPage) 11
+                cycles += implied(cpu, memory, 0xC037, 2, |cpu| cpu.acc = lsr(cpu, cpu.acc)) as u64; // C036  Lsr(Accumulator)
+                cycles += interpret(cpu, memory, &[0x60])? as u64; // C037  Rts(Implied)
+                cycles
+            }
+            _ => cpu.step(memory)? as u64,
+        };
+    }
+    Ok(elapsed)
+}
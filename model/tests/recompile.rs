@@ -0,0 +1,106 @@
+//! Compiles the recompiler's output for a small image, checked in under
+//! `fixtures/`, and runs it against `Cpu::step`. Run with
+//! `UPDATE_FIXTURES=1` to regenerate the fixture after changing the
+//! recompiler.
+#![cfg(feature = "alloc")]
+
+use portal_solutions_mos6502_model::machine::{Cpu, Memory, Ram};
+use portal_solutions_mos6502_model::recompile::Recompiler;
+
+mod recompiled {
+    include!("fixtures/recompiled.rs");
+}
+
+const ORIGIN: u16 = 0xC000;
+// Where `IMAGE` keeps the data the program reads.
+const DATA: u16 = 0xC100;
+
+#[rustfmt::skip]
+const IMAGE: [u8; 0x110] = {
+    let mut image = [0; 0x110];
+    let code = [
+        0xA2, 0x00,       // $C000 LDX #$00
+        0xA0, 0x10,       // $C002 LDY #$10
+        0x18,             // $C004 CLC
+        0xBD, 0x00, 0xC1, // $C005 LDA $C100,X
+        0x69, 0x07,       // $C008 ADC #$07
+        0x9D, 0x00, 0x02, // $C00A STA $0200,X
+        0x1E, 0x00, 0x02, // $C00D ASL $0200,X
+        0x20, 0x30, 0xC0, // $C010 JSR $C030
+        0xE8,             // $C013 INX
+        0x88,             // $C014 DEY
+        0xD0, 0xEE,       // $C015 BNE $C005
+        0x4C, 0x00, 0xC0, // $C017 JMP $C000
+    ];
+    let subroutine = [
+        0x85, 0x10, // $C030 STA $10
+        0xE6, 0x11, // $C032 INC $11
+        0xA5, 0x11, // $C034 LDA $11
+        0x4A,       // $C036 LSR A
+        0x60,       // $C037 RTS
+    ];
+    let mut i = 0;
+    while i < code.len() {
+        image[i] = code[i];
+        i += 1;
+    }
+    let mut i = 0;
+    while i < subroutine.len() {
+        image[0x30 + i] = subroutine[i];
+        i += 1;
+    }
+    let mut i = 0;
+    while i < 0x10 {
+        image[(DATA - ORIGIN) as usize + i] = (i as u8).wrapping_mul(0x1D);
+        i += 1;
+    }
+    image
+};
+
+fn emit() -> String {
+    Recompiler::new(ORIGIN, &IMAGE)
+        .entry_point(ORIGIN)
+        .function_name("run")
+        .emit()
+}
+
+#[test]
+fn fixture_is_up_to_date() {
+    let source = emit();
+    if std::env::var_os("UPDATE_FIXTURES").is_some() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/recompiled.rs");
+        std::fs::write(path, &source).unwrap();
+    }
+    assert_eq!(
+        source,
+        include_str!("fixtures/recompiled.rs"),
+        "rerun with UPDATE_FIXTURES=1"
+    );
+}
+
+#[test]
+fn recompiled_code_runs_as_the_interpreter_does() {
+    let mut cpu = Cpu::new();
+    cpu.pc = ORIGIN;
+    cpu.sp = 0xFF;
+    // The recompiled code is never fetched, so only the data is mapped.
+    let mut ours = Ram::new();
+    ours.write_block(DATA, &IMAGE[(DATA - ORIGIN) as usize..]);
+    let mut theirs = Ram::new();
+    theirs.write_block(ORIGIN, &IMAGE);
+    let (mut our_cpu, mut their_cpu) = (cpu.clone(), cpu);
+
+    let elapsed = recompiled::run(&mut our_cpu, &mut ours, 10_000).unwrap();
+    let mut their_elapsed = 0;
+    while their_elapsed < elapsed {
+        their_elapsed += their_cpu.step(&mut theirs).unwrap() as u64;
+    }
+    assert_eq!(elapsed, their_elapsed);
+    assert_eq!(format!("{:?}", our_cpu), format!("{:?}", their_cpu));
+    let (mut our_bytes, mut their_bytes) = ([0; 0x400], [0; 0x400]);
+    ours.read_block(0, &mut our_bytes);
+    theirs.read_block(0, &mut their_bytes);
+    assert_eq!(our_bytes, their_bytes);
+    // The loop stored something.
+    assert_ne!(our_bytes[0x0200..0x0210], [0; 0x10]);
+}
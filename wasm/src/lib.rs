@@ -0,0 +1,282 @@
+//! `wasm-bindgen` bindings: a `Machine` over flat RAM, and a `Block` which
+//! assembles a line of text at a time.
+//!
+//! ```ignore
+//! import init, { Block, Machine } from "./pkg/portal_solutions_mos6502_wasm.js";
+//! await init();
+//! const block = new Block();
+//! block.label("start");
+//! block.inst("LDX #$05");
+//! block.label("loop");
+//! block.inst("DEX");
+//! block.inst("BNE loop");
+//! block.inst("BRK");
+//! const program = block.assemble(0x0600, 0x100);
+//! const machine = new Machine();
+//! machine.load(0x0600, program.bytes);
+//! machine.pc = program.label("start");
+//! console.log(machine.monitor("g"));
+//! ```
+use portal_solutions_mos6502_assembler as assembler;
+use portal_solutions_mos6502_model::debug::{self, TraceFormat};
+use portal_solutions_mos6502_model::machine::{self, Cpu, Memory, Ram};
+use portal_solutions_mos6502_model::monitor::{self, Monitor};
+use portal_solutions_mos6502_model::{opcode, status, Address};
+use wasm_bindgen::prelude::*;
+
+fn error<E: core::fmt::Debug>(error: E) -> JsError {
+    JsError::new(&format!("{:?}", error))
+}
+
+#[wasm_bindgen]
+pub struct Machine {
+    inner: machine::Machine<Ram>,
+    monitor: Monitor,
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl Machine {
+    /// A machine with 64KB of RAM, all zero, and the CPU at $0000.
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self {
+            inner: machine::Machine::new(Cpu::new(), Ram::new()),
+            monitor: Monitor::new(),
+        }
+    }
+    pub fn load(&mut self, address: Address, bytes: &[u8]) {
+        self.inner.memory.load(address, bytes);
+    }
+    pub fn read(&mut self, address: Address) -> u8 {
+        self.inner.memory.read_u8(address)
+    }
+    pub fn write(&mut self, address: Address, data: u8) {
+        self.inner.memory.write_u8(address, data);
+    }
+    /// A copy of the whole address space.
+    pub fn memory(&self) -> Vec<u8> {
+        self.inner.memory.as_slice().to_vec()
+    }
+    /// Runs one instruction, returning the cycles it took.
+    pub fn step(&mut self) -> Result<u8, JsError> {
+        self.inner.step().map_err(error)
+    }
+    /// Runs until at least `cycles` cycles have passed, taking interrupts,
+    /// and returns the exact count.
+    pub fn run_cycles(&mut self, cycles: usize) -> Result<usize, JsError> {
+        Ok(self.inner.run_cycles(cycles).map_err(error)?.cycles)
+    }
+    pub fn reset(&mut self) {
+        self.inner.reset();
+    }
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.inner.set_irq(asserted);
+    }
+    pub fn request_nmi(&mut self) {
+        self.inner.request_nmi();
+    }
+    pub fn cycles(&self) -> u64 {
+        self.inner.cycles()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn pc(&self) -> Address {
+        self.inner.cpu.pc
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_pc(&mut self, pc: Address) {
+        self.inner.cpu.pc = pc;
+    }
+    #[wasm_bindgen(getter)]
+    pub fn a(&self) -> u8 {
+        self.inner.cpu.acc
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_a(&mut self, a: u8) {
+        self.inner.cpu.acc = a;
+    }
+    #[wasm_bindgen(getter)]
+    pub fn x(&self) -> u8 {
+        self.inner.cpu.x
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_x(&mut self, x: u8) {
+        self.inner.cpu.x = x;
+    }
+    #[wasm_bindgen(getter)]
+    pub fn y(&self) -> u8 {
+        self.inner.cpu.y
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_y(&mut self, y: u8) {
+        self.inner.cpu.y = y;
+    }
+    #[wasm_bindgen(getter)]
+    pub fn sp(&self) -> u8 {
+        self.inner.cpu.sp
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_sp(&mut self, sp: u8) {
+        self.inner.cpu.sp = sp;
+    }
+    /// The status as an interrupt would push it.
+    #[wasm_bindgen(getter)]
+    pub fn p(&self) -> u8 {
+        u8::from(self.inner.cpu.status)
+    }
+    #[wasm_bindgen(setter)]
+    pub fn set_p(&mut self, p: u8) {
+        self.inner.cpu.status = status::Register::from(p);
+    }
+    /// The next instruction and the registers, in nestest.log format.
+    pub fn trace(&self) -> String {
+        TraceFormat::default().line(&self.inner.cpu, &self.inner.memory, self.inner.cycles())
+    }
+    /// `lines` lines of disassembly from `address`.
+    pub fn disassemble(&self, address: Address, lines: usize) -> String {
+        let lines = debug::disassemble_window(&self.inner.memory, address, 0, lines.max(1) - 1);
+        let mut out = String::new();
+        let _ = debug::write_disassembly(&mut out, &lines, None);
+        out
+    }
+    /// Runs a command of the machine-language monitor, returning its
+    /// output.
+    pub fn monitor(&mut self, line: &str) -> Result<String, JsError> {
+        self.monitor
+            .execute(&mut self.inner, line)
+            .map_err(|error| JsError::new(&error.to_string()))
+    }
+}
+
+// The label an operand refers to, if it isn't a number or `A`.
+fn operand_label(operand: &str) -> Option<&str> {
+    let core = operand
+        .trim_start_matches(['#', '(', '<', '>'])
+        .split([',', ')'])
+        .next()?
+        .trim();
+    let first = core.chars().next()?;
+    ((first.is_ascii_alphabetic() || first == '_') && !core.eq_ignore_ascii_case("A"))
+        .then_some(core)
+}
+
+#[wasm_bindgen]
+pub struct Block {
+    inner: assembler::Block,
+}
+
+impl Default for Block {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl Block {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self {
+            inner: assembler::Block::new(),
+        }
+    }
+    pub fn label(&mut self, name: &str) {
+        self.inner.label(name);
+    }
+    pub fn constant(&mut self, name: &str, value: Address) {
+        self.inner.constant(name, value);
+    }
+    pub fn set_offset(&mut self, offset: Address) {
+        self.inner.set_offset(offset);
+    }
+    pub fn byte(&mut self, byte: u8) {
+        self.inner.literal_byte(byte);
+    }
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.inner.literal_byte(byte);
+        }
+    }
+    pub fn word(&mut self, word: u16) {
+        self.inner.literal_offset_le(word);
+    }
+    /// Adds one instruction written as text, e.g. `LDA #$01`, `STA $0200,X`
+    /// or `BNE loop`. Operands can be labels or constants: `#<name` and
+    /// `#>name` for the low and high bytes, `(name),Y` and `(name,X)` for a
+    /// zero page pointer, and any other form for a 16-bit address. Numeric
+    /// branch targets are taken as offsets within the block.
+    pub fn inst(&mut self, text: &str) -> Result<(), JsError> {
+        let text = text.trim();
+        let (mnemonic, operand) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let operand = operand.trim();
+        let assemble = |operand: &str| {
+            monitor::assemble(0, &format!("{} {}", mnemonic, operand))
+                .map_err(|error| JsError::new(&error.to_string()))
+        };
+        let Some(label) = operand_label(operand) else {
+            let bytes = monitor::assemble(self.inner.current_offset(), text)
+                .map_err(|error| JsError::new(&error.to_string()))?;
+            self.bytes(&bytes);
+            return Ok(());
+        };
+        let is_branch = monitor::assemble(0, &format!("{} $00", mnemonic))
+            .ok()
+            .and_then(|bytes| opcode::info(bytes[0]))
+            .is_some_and(|info| {
+                info.addressing_mode == debug::AddressingMode::Relative
+            });
+        let placeholder = |value: &str| operand.replacen(label, value, 1);
+        if is_branch {
+            let opcode = assemble("$0002")?[0];
+            self.inner.literal_byte(opcode);
+            self.inner.label_relative_offset(label);
+        } else if let Some(rest) = operand.strip_prefix("#<") {
+            self.inner.literal_byte(assemble(&format!("#$00{}", &rest[label.len()..]))?[0]);
+            self.inner.label_offset_lo(label);
+        } else if let Some(rest) = operand.strip_prefix("#>") {
+            self.inner.literal_byte(assemble(&format!("#$00{}", &rest[label.len()..]))?[0]);
+            self.inner.label_offset_hi(label);
+        } else if operand.ends_with("),Y") || operand.ends_with(",X)") {
+            self.inner.literal_byte(assemble(&placeholder("$00"))?[0]);
+            self.inner.label_offset_lo(label);
+        } else {
+            self.inner.literal_byte(assemble(&placeholder("$FFFF"))?[0]);
+            self.inner.label_offset_le(label);
+        }
+        Ok(())
+    }
+    /// Assembles the block at `base` into an image of `size` bytes.
+    pub fn assemble(&self, base: Address, size: usize) -> Result<Assembled, JsError> {
+        let mut bytes = Vec::new();
+        let block = self.inner.assemble(base, size, &mut bytes).map_err(error)?;
+        Ok(Assembled { bytes, block })
+    }
+}
+
+#[wasm_bindgen]
+pub struct Assembled {
+    bytes: Vec<u8>,
+    block: assembler::AssembledBlock,
+}
+
+#[wasm_bindgen]
+impl Assembled {
+    #[wasm_bindgen(getter)]
+    pub fn bytes(&self) -> Vec<u8> {
+        self.bytes.clone()
+    }
+    pub fn label(&self, name: &str) -> Option<Address> {
+        self.block.address_of_label(name)
+    }
+    /// Every label, as `name address` lines in hex.
+    pub fn labels(&self) -> String {
+        self.block
+            .labels()
+            .map(|(name, address)| format!("{} {:04X}\n", name, address))
+            .collect()
+    }
+}